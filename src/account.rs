@@ -1,11 +1,265 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fmt::Display;
+
 use rust_decimal::Decimal;
+#[cfg(feature = "csv")]
+use serde::{Deserialize, Serialize};
+
+use crate::currency::Currency;
+use crate::ids::{ClientId, TxId};
+
+/// Number of decimal places used for balances unless configured per client.
+pub const DEFAULT_SCALE: u32 = 4;
 
+/// Balances are serialized as strings so their scale is preserved.
+///
+/// `available`, `held` and `total` are the balances in the engine's base
+/// currency; balances in any other currency are kept in `currencies`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "csv", derive(Serialize, Deserialize))]
 pub struct Account {
-    pub client: u16,
+    pub client: ClientId,
+    #[cfg_attr(feature = "csv", serde(with = "crate::serde_decimal"))]
     pub available: Decimal,
+    #[cfg_attr(feature = "csv", serde(with = "crate::serde_decimal"))]
     pub held: Decimal,
+    #[cfg_attr(feature = "csv", serde(with = "crate::serde_decimal"))]
     pub total: Decimal,
     pub locked: bool,
+    /// Decimal places used when formatting this account's balances.
+    pub scale: u32,
+    /// Number of chargebacks applied to this account.
+    #[cfg_attr(feature = "csv", serde(default))]
+    pub chargebacks: u32,
+    /// Why the account was locked, if it is.
+    #[cfg_attr(
+        feature = "csv",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub lock_reason: Option<LockReason>,
+    /// Balances in currencies other than the base currency.
+    #[cfg_attr(
+        feature = "csv",
+        serde(default, skip_serializing_if = "BTreeMap::is_empty")
+    )]
+    pub currencies: BTreeMap<Currency, Balances>,
+}
+
+/// Balances of an account in a single currency.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "csv", derive(Serialize, Deserialize))]
+pub struct Balances {
+    #[cfg_attr(feature = "csv", serde(with = "crate::serde_decimal"))]
+    pub available: Decimal,
+    #[cfg_attr(feature = "csv", serde(with = "crate::serde_decimal"))]
+    pub held: Decimal,
+    #[cfg_attr(feature = "csv", serde(with = "crate::serde_decimal"))]
+    pub total: Decimal,
+}
+
+/// The event that locked an account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "csv",
+    derive(Serialize, Deserialize),
+    serde(tag = "type", rename_all = "lowercase")
+)]
+pub enum LockReason {
+    /// Locked by the chargeback of `tx_id`; `sequence` places the chargeback
+    /// among the transactions' sequence numbers.
+    Chargeback { tx_id: TxId, sequence: u64 },
+    /// Frozen by a `freeze` row; only an `unfreeze` row lifts it.
+    Manual,
+}
+
+impl Display for LockReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LockReason::Chargeback { tx_id, sequence } => {
+                write!(f, "chargeback of tx {} (sequence {})", tx_id, sequence)
+            }
+            LockReason::Manual => f.write_str("manual freeze"),
+        }
+    }
+}
+
+/// Whether an account is locked and by whom, as told by `Account::lock_state`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockState {
+    Unlocked,
+    /// Locked by a chargeback, for good.
+    ChargebackLocked,
+    /// Frozen with `LockReason::Manual`, until `PaymentEngine::unfreeze_account`
+    /// lifts it.
+    AdminLocked,
+}
+
+impl Account {
+    /// Creates an unlocked account with zero balances.
+    pub fn new(client: ClientId) -> Self {
+        Account {
+            client,
+            available: Decimal::ZERO,
+            held: Decimal::ZERO,
+            total: Decimal::ZERO,
+            locked: false,
+            scale: DEFAULT_SCALE,
+            chargebacks: 0,
+            lock_reason: None,
+            currencies: BTreeMap::new(),
+        }
+    }
+
+    /// Whether the account is locked for any reason, as in the report's
+    /// `locked` column.
+    #[inline]
+    pub fn locked(&self) -> bool {
+        self.locked
+    }
+
+    /// Tells chargeback locks from admin freezes. A locked account without a
+    /// `lock_reason`, e.g. read from a file written before reasons were kept,
+    /// counts as locked by a chargeback, the only lock there was then.
+    pub fn lock_state(&self) -> LockState {
+        match (self.locked, self.lock_reason) {
+            (false, _) => LockState::Unlocked,
+            (true, Some(LockReason::Manual)) => LockState::AdminLocked,
+            (true, _) => LockState::ChargebackLocked,
+        }
+    }
+
+    /// Balances in `currency`, `None` being the base currency. Zero for a
+    /// currency the account never held.
+    pub fn balances(&self, currency: Option<Currency>) -> Balances {
+        match currency {
+            None => Balances {
+                available: self.available,
+                held: self.held,
+                total: self.total,
+            },
+            Some(currency) => self.currencies.get(&currency).copied().unwrap_or_default(),
+        }
+    }
+
+    pub(crate) fn set_balances(&mut self, currency: Option<Currency>, balances: Balances) {
+        match currency {
+            None => {
+                self.available = balances.available;
+                self.held = balances.held;
+                self.total = balances.total;
+            }
+            Some(currency) => {
+                self.currencies.insert(currency, balances);
+            }
+        }
+    }
+
+    /// Balances in every currency the account holds, the base currency first
+    /// and then by currency code.
+    pub fn all_balances(&self) -> impl Iterator<Item = (Option<Currency>, Balances)> + '_ {
+        std::iter::once((None, self.balances(None))).chain(
+            self.currencies
+                .iter()
+                .map(|(&currency, &balances)| (Some(currency), balances)),
+        )
+    }
 }
 
-pub type Accounts = std::collections::HashMap<u16, Account>;
+/// Copy of an account's balances at a point in time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccountView {
+    pub client: ClientId,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+}
+
+impl From<&Account> for AccountView {
+    fn from(account: &Account) -> Self {
+        AccountView {
+            client: account.client,
+            available: account.available,
+            held: account.held,
+            total: account.total,
+            locked: account.locked,
+        }
+    }
+}
+
+pub type Accounts = HashMap<ClientId, Account>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "csv")]
+    use rust_decimal::dec;
+
+    #[test]
+    fn test_new() {
+        let account = Account::new(ClientId(7));
+
+        assert_eq!(account.client, ClientId(7));
+        assert_eq!(account.total, Decimal::ZERO);
+        assert!(!account.locked);
+        assert_eq!(account.scale, DEFAULT_SCALE);
+    }
+
+    #[test]
+    fn test_lock_state() {
+        let mut account = Account::new(ClientId(1));
+        assert_eq!(account.lock_state(), LockState::Unlocked);
+
+        account.locked = true;
+        assert_eq!(account.lock_state(), LockState::ChargebackLocked);
+        account.lock_reason = Some(LockReason::Chargeback {
+            tx_id: TxId(2),
+            sequence: 5,
+        });
+        assert_eq!(account.lock_state(), LockState::ChargebackLocked);
+        account.lock_reason = Some(LockReason::Manual);
+        assert_eq!(account.lock_state(), LockState::AdminLocked);
+        assert!(account.locked());
+    }
+
+    #[cfg(feature = "csv")]
+    #[test]
+    fn test_serde_preserves_scale() {
+        let account = Account {
+            available: dec!(1.50),
+            held: dec!(0.2500),
+            total: dec!(1.7500),
+            ..Account::new(ClientId(3))
+        };
+
+        let json = serde_json::to_string(&account).unwrap();
+        assert_eq!(
+            json,
+            r#"{"client":3,"available":"1.50","held":"0.2500","total":"1.7500","locked":false,"scale":4,"chargebacks":0}"#
+        );
+
+        let decoded: Account = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, account);
+        assert_eq!(decoded.available.scale(), 2);
+    }
+
+    #[cfg(feature = "csv")]
+    #[test]
+    fn test_serde_lock_reason() {
+        let account = Account {
+            locked: true,
+            lock_reason: Some(LockReason::Chargeback {
+                tx_id: TxId(5),
+                sequence: 9,
+            }),
+            ..Account::new(ClientId(3))
+        };
+
+        let json = serde_json::to_string(&account).unwrap();
+        assert!(json.ends_with(r#""lock_reason":{"type":"chargeback","tx_id":5,"sequence":9}}"#));
+
+        let decoded: Account = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, account);
+    }
+}
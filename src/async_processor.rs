@@ -0,0 +1,189 @@
+use csv::StringRecord;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio_stream::{Stream, StreamExt};
+
+use crate::config::CsvOptions;
+use crate::entry::TransactionEntry;
+use crate::error::ProcessorError;
+use crate::payments_engine::PaymentEngine;
+use crate::processor::{
+    ErrorSink, ProcessingSummary, StderrSink, check_headers, csv_reader, is_blank,
+    process_and_record, unsupported_version, version_column,
+};
+
+/// Asynchronous counterpart of `process_csv_stream`.
+///
+/// Records are split on line boundaries, so partial records spanning several
+/// reads are buffered until the line is complete. Like the synchronous reader,
+/// fields may be quoted and are trimmed, and `#` comment lines and blank lines
+/// are skipped; a quoted field cannot span lines. A row of an unsupported
+/// version stops processing with `UnsupportedVersion`.
+pub async fn process_csv_stream_async(
+    engine: &mut PaymentEngine,
+    reader: impl AsyncRead + Unpin,
+) -> Result<ProcessingSummary, ProcessorError> {
+    let mut lines = BufReader::new(reader).lines();
+    let mut headers: Option<StringRecord> = None;
+    let mut version = None;
+    let mut summary = ProcessingSummary::default();
+    let mut sink = StderrSink::default();
+    let mut row = 0;
+
+    loop {
+        let line = match lines.next_line().await {
+            Ok(Some(line)) => line,
+            Ok(None) => break,
+            Err(e) => {
+                sink.parse_error(None, &e);
+                summary.record_parse_error();
+                break;
+            }
+        };
+        if line.starts_with('#') {
+            continue;
+        }
+        let record = split_record(&line);
+        if is_blank(&record) {
+            continue;
+        }
+        let Some(headers) = &headers else {
+            check_headers(&record)?;
+            version = version_column(&record);
+            headers = Some(record);
+            continue;
+        };
+        if let Some(unsupported) = unsupported_version(&record, version) {
+            return Err(ProcessorError::UnsupportedVersion(unsupported));
+        }
+
+        row += 1;
+        sink.set_row(row);
+        match record.deserialize::<TransactionEntry>(Some(headers)) {
+            Ok(entry) => {
+                process_and_record(engine, entry, &mut summary, &mut sink);
+            }
+            Err(e) => {
+                sink.parse_error(Some(&record), &e);
+                summary.record_parse_error();
+            }
+        }
+    }
+    Ok(summary)
+}
+
+pub async fn process_stream_async(
+    engine: &mut PaymentEngine,
+    mut stream: impl Stream<Item = TransactionEntry> + Unpin,
+) -> ProcessingSummary {
+    let mut summary = ProcessingSummary::default();
+    let mut sink = StderrSink::default();
+    let mut row = 0;
+    while let Some(entry) = stream.next().await {
+        row += 1;
+        sink.set_row(row);
+        process_and_record(engine, entry, &mut summary, &mut sink);
+    }
+    summary
+}
+
+#[inline]
+fn split_record(line: &str) -> StringRecord {
+    if !line.contains('"') {
+        return line.split(',').map(str::trim).collect();
+    }
+    let options = CsvOptions {
+        has_headers: false,
+        ..CsvOptions::default()
+    };
+    csv_reader(line.as_bytes(), &options)
+        .into_records()
+        .next()
+        .and_then(Result::ok)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ids::{ClientId, TxId};
+    use rust_decimal::dec;
+    use tokio::io::AsyncWriteExt;
+
+    #[tokio::test]
+    async fn test_process_csv_stream_async_in_chunks() {
+        let data = "type, client, tx, amount\n\
+                    deposit, 1, 1, 100.0\n\
+                    withdrawal, 1, 2, 25.25\n\
+                    \n\
+                    # second client\n\
+                    deposit, 2, 3, 5.0\n\
+                    deposit, x, 4, 1.0\n\
+                    dispute, 2, 3,\n\
+                    chargeback, 2, 3";
+        let (mut writer, reader) = tokio::io::duplex(8);
+
+        let write = async move {
+            for chunk in data.as_bytes().chunks(5) {
+                writer.write_all(chunk).await.unwrap();
+            }
+        };
+        let mut engine = PaymentEngine::new();
+        let (_, summary) = tokio::join!(write, process_csv_stream_async(&mut engine, reader));
+        let summary = summary.unwrap();
+
+        assert_eq!(summary.processed, 5);
+        assert_eq!(summary.parse_errors, 1);
+        assert_eq!(
+            engine.accounts.get(&ClientId(1)).unwrap().available,
+            dec!(74.75)
+        );
+        assert!(engine.accounts.get(&ClientId(2)).unwrap().locked);
+    }
+
+    #[tokio::test]
+    async fn test_process_csv_stream_async_quoted() {
+        let data = "type,client,tx,amount,description\n\
+                    \"deposit\",\"1\",\"1\",\" 10.0 \",\"by wire, twice\"\n\
+                    deposit, 1, 2, 5.0, cash\n\
+                    \"dispute\",\"1\",\"2\",\"\",\"\"";
+        let mut engine = PaymentEngine::new();
+
+        let summary = process_csv_stream_async(&mut engine, data.as_bytes())
+            .await
+            .unwrap();
+
+        assert_eq!((summary.processed, summary.parse_errors), (3, 0));
+        let account = engine.accounts.get(&ClientId(1)).unwrap();
+        assert_eq!((account.available, account.held), (dec!(10.0), dec!(5.0)));
+    }
+
+    #[tokio::test]
+    async fn test_process_csv_stream_async_version() {
+        let data = "version,type,client,tx,amount\n\
+                    1,deposit,1,1,10.0\n\
+                    2,deposit,1,2,5.0\n\
+                    1,deposit,1,3,1.0\n";
+        let mut engine = PaymentEngine::new();
+
+        let result = process_csv_stream_async(&mut engine, data.as_bytes()).await;
+
+        assert!(matches!(result, Err(ProcessorError::UnsupportedVersion(2))));
+        assert_eq!(engine.accounts[&ClientId(1)].available, dec!(10.0));
+    }
+
+    #[tokio::test]
+    async fn test_process_stream_async() {
+        let entries = vec![
+            TransactionEntry::deposit(ClientId(1), TxId(1), dec!(10.0)),
+            TransactionEntry::withdrawal(ClientId(1), TxId(2), dec!(20.0)),
+            TransactionEntry::dispute(ClientId(1), TxId(1)),
+        ];
+        let mut engine = PaymentEngine::new();
+
+        let summary = process_stream_async(&mut engine, tokio_stream::iter(entries)).await;
+
+        assert_eq!(summary.processed, 2);
+        assert_eq!(summary.rejected, 1);
+        assert_eq!(engine.accounts.get(&ClientId(1)).unwrap().held, dec!(10.0));
+    }
+}
@@ -0,0 +1,69 @@
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::PathBuf;
+
+use anyhow::Context;
+use clap::Parser;
+
+use transaction::testgen::{GeneratorConfig, write_csv};
+
+/// Generates a reproducible transaction CSV, e.g. for benchmarks.
+#[derive(Parser, Debug)]
+#[command(version)]
+struct Cli {
+    /// Number of rows, not counting the header
+    #[arg(long, default_value = "1000")]
+    rows: u64,
+
+    /// Number of clients, numbered from 1
+    #[arg(long, default_value = "100", value_parser = clap::value_parser!(u16).range(1..))]
+    clients: u16,
+
+    /// Share of deposits among deposits and withdrawals
+    #[arg(long, value_name = "RATIO", default_value = "0.7", value_parser = probability)]
+    deposit_ratio: f64,
+
+    /// Probability of a row disputing a recent deposit or settling a dispute
+    #[arg(long, value_name = "P", default_value = "0.05", value_parser = probability)]
+    dispute_probability: f64,
+
+    /// Probability of a row repeating a recent deposit's tx id
+    #[arg(long, value_name = "P", default_value = "0.01", value_parser = probability)]
+    duplicate_probability: f64,
+
+    /// Seed of the generator; the same seed gives the same rows
+    #[arg(long, default_value = "1")]
+    seed: u64,
+
+    /// Write the rows to this file instead of stdout
+    #[arg(short, long, value_name = "PATH")]
+    output: Option<PathBuf>,
+}
+
+fn probability(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(p) if (0.0..=1.0).contains(&p) => Ok(p),
+        _ => Err(format!("`{}` is not a number between 0 and 1", s)),
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+    let config = GeneratorConfig {
+        rows: cli.rows,
+        clients: cli.clients,
+        deposit_ratio: cli.deposit_ratio,
+        dispute_probability: cli.dispute_probability,
+        duplicate_probability: cli.duplicate_probability,
+        seed: cli.seed,
+    };
+    match &cli.output {
+        Some(path) => {
+            let file = File::create(path)
+                .with_context(|| format!("failed to create {}", path.display()))?;
+            write_csv(&config, BufWriter::new(file))
+                .with_context(|| format!("failed to write {}", path.display()))
+        }
+        None => write_csv(&config, io::stdout().lock()).context("failed to write stdout"),
+    }
+}
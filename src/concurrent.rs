@@ -0,0 +1,204 @@
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+use crate::account::Account;
+#[cfg(feature = "csv")]
+use crate::account::AccountView;
+use crate::config::EngineConfig;
+#[cfg(feature = "csv")]
+use crate::entry::TransactionEntry;
+use crate::error::PaymentError;
+use crate::ids::{ClientId, TxId};
+use crate::payments_engine::PaymentEngine;
+use crate::transaction::Transaction;
+
+/// Payment engine shared between threads.
+///
+/// Clients are split over `shards` independent engines by `client % shards`,
+/// each behind its own mutex, so operations on the same client are serialized
+/// while clients of different shards proceed in parallel. Every shard uses the
+/// same configuration, except that `max_accounts` bounds the accounts of all
+/// shards together.
+pub struct ConcurrentPaymentEngine {
+    shards: Vec<Mutex<PaymentEngine>>,
+    max_accounts: Option<usize>,
+    /// Accounts across all shards, counted only when `max_accounts` is set.
+    accounts: AtomicUsize,
+}
+
+impl ConcurrentPaymentEngine {
+    pub fn new(shards: NonZeroUsize) -> Self {
+        Self::with_config(shards, EngineConfig::default())
+    }
+
+    pub fn with_config(shards: NonZeroUsize, config: EngineConfig) -> Self {
+        let shard_config = EngineConfig {
+            max_accounts: None,
+            ..config
+        };
+        ConcurrentPaymentEngine {
+            shards: (0..shards.get())
+                .map(|_| Mutex::new(PaymentEngine::with_config(shard_config.clone())))
+                .collect(),
+            max_accounts: config.max_accounts,
+            accounts: AtomicUsize::new(0),
+        }
+    }
+
+    #[inline]
+    fn shard(&self, client: ClientId) -> MutexGuard<'_, PaymentEngine> {
+        // A panic while holding the lock cannot leave a shard half-updated in a
+        // way later operations would trip over, so poisoning is ignored.
+        self.shards[usize::from(client.0) % self.shards.len()]
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Runs `operation` on the client's shard. With `max_accounts` set, a slot
+    /// is reserved before an operation that may open the client's account and
+    /// given back if it did not, so an operation on a new client is refused
+    /// with `AccountLimitExceeded` once all slots are taken.
+    fn with_shard<T>(
+        &self,
+        client: ClientId,
+        operation: impl FnOnce(&mut PaymentEngine) -> Result<T, PaymentError>,
+    ) -> Result<T, PaymentError> {
+        let mut shard = self.shard(client);
+        let Some(max_accounts) = self.max_accounts else {
+            return operation(&mut shard);
+        };
+        let existed = shard.accounts.contains_key(&client);
+        if !existed {
+            self.accounts
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |accounts| {
+                    (accounts < max_accounts).then_some(accounts + 1)
+                })
+                .map_err(|_| PaymentError::AccountLimitExceeded(client))?;
+        }
+        let result = operation(&mut shard);
+        if !shard.accounts.contains_key(&client) {
+            self.accounts.fetch_sub(1, Ordering::SeqCst);
+        }
+        result
+    }
+
+    pub fn process_transaction(&self, transaction: Transaction) -> Result<(), PaymentError> {
+        self.with_shard(transaction.account_id, |shard| {
+            shard.process_transaction(transaction)
+        })
+    }
+
+    pub fn process_dispute(&self, account_id: ClientId, tx_id: TxId) -> Result<(), PaymentError> {
+        self.with_shard(account_id, |shard| shard.process_dispute(account_id, tx_id))
+    }
+
+    pub fn process_resolve(&self, account_id: ClientId, tx_id: TxId) -> Result<(), PaymentError> {
+        self.with_shard(account_id, |shard| shard.process_resolve(account_id, tx_id))
+    }
+
+    pub fn process_chargeback(
+        &self,
+        account_id: ClientId,
+        tx_id: TxId,
+    ) -> Result<(), PaymentError> {
+        self.with_shard(account_id, |shard| {
+            shard.process_chargeback(account_id, tx_id)
+        })
+    }
+
+    /// See `PaymentEngine::apply`.
+    #[cfg(feature = "csv")]
+    pub fn apply(&self, entry: TransactionEntry) -> Result<AccountView, PaymentError> {
+        self.with_shard(entry.account_id, |shard| shard.apply(entry))
+    }
+
+    /// Returns a copy of the client's account.
+    pub fn account(&self, client: ClientId) -> Option<Account> {
+        self.shard(client).account(client).cloned()
+    }
+
+    /// Merges the shards into a single engine, e.g. to write the report. The
+    /// engine gets back the configured `max_accounts`.
+    pub fn into_engine(self) -> PaymentEngine {
+        let mut shards = self
+            .shards
+            .into_iter()
+            .map(|shard| shard.into_inner().unwrap_or_else(|e| e.into_inner()));
+        let mut engine = shards.next().unwrap_or_default();
+        for shard in shards {
+            // Shards hold disjoint clients and no account limit of their own,
+            // so there is nothing for the merge to refuse.
+            engine.merge(shard).expect("shards hold disjoint clients");
+        }
+        engine.config.max_accounts = self.max_accounts;
+        engine
+    }
+}
+
+#[cfg(all(test, feature = "csv"))]
+mod tests {
+    use super::*;
+    use rust_decimal::dec;
+
+    #[test]
+    fn test_routes_clients_to_shards() {
+        let engine = ConcurrentPaymentEngine::new(NonZeroUsize::new(3).unwrap());
+        for client in 1..=5 {
+            engine
+                .apply(TransactionEntry::deposit(
+                    ClientId(client),
+                    TxId(client.into()),
+                    dec!(10.0),
+                ))
+                .unwrap();
+        }
+        engine.process_dispute(ClientId(2), TxId(2)).unwrap();
+        engine.process_chargeback(ClientId(2), TxId(2)).unwrap();
+
+        assert!(engine.account(ClientId(2)).unwrap().locked);
+        assert!(engine.account(ClientId(6)).is_none());
+        assert!(matches!(
+            engine.process_dispute(ClientId(1), TxId(2)),
+            Err(PaymentError::TransactionNotFound { .. })
+        ));
+
+        let merged = engine.into_engine();
+        assert_eq!(merged.accounts.len(), 5);
+        assert_eq!(merged.accounts[&ClientId(2)].total, dec!(0.0));
+    }
+
+    #[test]
+    fn test_max_accounts_across_shards() {
+        let engine = ConcurrentPaymentEngine::with_config(
+            NonZeroUsize::new(2).unwrap(),
+            EngineConfig {
+                max_accounts: Some(1),
+                ..EngineConfig::default()
+            },
+        );
+        // A rejected operation on a new client gives its slot back.
+        engine
+            .apply(TransactionEntry::withdrawal(
+                ClientId(2),
+                TxId(1),
+                dec!(1.0),
+            ))
+            .unwrap_err();
+        engine
+            .apply(TransactionEntry::deposit(ClientId(1), TxId(2), dec!(1.0)))
+            .unwrap();
+        assert!(matches!(
+            engine.apply(TransactionEntry::deposit(ClientId(2), TxId(3), dec!(1.0))),
+            Err(PaymentError::AccountLimitExceeded(ClientId(2)))
+        ));
+        engine
+            .apply(TransactionEntry::deposit(ClientId(1), TxId(4), dec!(1.0)))
+            .unwrap();
+
+        let merged = engine.into_engine();
+        assert_eq!(merged.accounts.len(), 1);
+        assert_eq!(merged.accounts[&ClientId(1)].total, dec!(2.0));
+        assert_eq!(merged.config.max_accounts, Some(1));
+    }
+}
@@ -0,0 +1,347 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use rust_decimal::Decimal;
+
+use crate::currency::Currency;
+use crate::ids::ClientId;
+use crate::transaction::MAX_AMOUNT_SCALE;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EngineConfig {
+    pub withdrawal_policy: WithdrawalPolicy,
+    pub duplicate_policy: DuplicatePolicy,
+    /// Whether a tx id already used by another client is accepted. The format
+    /// allows it, as tx ids are only unique per client.
+    pub cross_client_tx_ids: CrossClientTxIdPolicy,
+    /// Mark disputes still open when a chargeback locks the account as `Frozen`,
+    /// so they can no longer be resolved or charged back.
+    pub freeze_disputes_on_lock: bool,
+    /// Whether deposits into locked accounts are accepted; withdrawals and
+    /// disputes are rejected either way.
+    pub locked_account_deposits: LockedAccountDeposits,
+    /// Same as `locked_account_deposits: LockedAccountDeposits::Allow` when
+    /// set.
+    #[deprecated(note = "use `locked_account_deposits` instead")]
+    pub allow_deposits_when_locked: bool,
+    /// Let withdrawals be disputed as well as deposits. The withdrawn funds
+    /// are already gone, so a dispute credits them back as held, raising the
+    /// total; a resolve removes them again and a chargeback releases them to
+    /// available.
+    pub allow_withdrawal_disputes: bool,
+    /// Let a transaction of an existing account that was rejected for
+    /// insufficient funds be retried later under the same tx id with the same
+    /// type and amount. Rejected attempts are remembered per client up to the
+    /// `settled_retention` or `RetentionPolicy::Window` bound, whichever is
+    /// smaller. When off, the tx id of such an attempt cannot be reused. On
+    /// by default.
+    pub allow_retry_of_failed_tx: bool,
+    /// Reject withdrawals, disputes, resolves and chargebacks for a client
+    /// without an account with `AccountNotFound`, so that only deposits open
+    /// accounts. When off, such a withdrawal fails for insufficient funds.
+    pub reject_unknown_clients: bool,
+    /// Caps on single transactions and on cumulative withdrawals; none are
+    /// set by default.
+    pub limits: Limits,
+    /// Upper bound on the number of accounts; unlimited when `None`.
+    pub max_accounts: Option<usize>,
+    /// Decimal places per client for formatting balances, e.g. 0 for JPY or 3
+    /// for BHD. Clients without an entry use `DEFAULT_SCALE`.
+    pub account_scales: HashMap<ClientId, u32>,
+    /// Number of chargebacks after which the account is locked.
+    pub chargebacks_before_lock: u32,
+    /// Deposits and withdrawals with more decimal places are rejected with
+    /// `TooManyDecimals`; any scale is accepted when `None`.
+    pub max_amount_scale: Option<u32>,
+    /// Charged to the account on every chargeback, even if it drives the
+    /// balance negative. Zero by default.
+    pub chargeback_fee: Decimal,
+    /// Number of charged back transactions kept per account. Older ones are
+    /// evicted, in the order they were charged back, and their tx ids cannot
+    /// be reused; all are kept when `None`. As under `RetentionPolicy::Window`,
+    /// any unknown tx id below the highest evicted one is treated as evicted.
+    /// Resolved transactions are kept, since they may be disputed again.
+    pub settled_retention: Option<usize>,
+    /// Whether deposits and withdrawals that were never disputed are kept
+    /// for the whole run.
+    pub retention_policy: RetentionPolicy,
+    /// Record every applied operation, so that `PaymentEngine::statement`
+    /// can list them. Off by default, as the record grows with the input.
+    pub record_statements: bool,
+    /// Decimal places of every balance in the reports and statements, capped
+    /// at `MAX_PRECISION`; each account's scale is used when `None`.
+    pub report_precision: Option<u32>,
+    /// How balances are rounded to the report scale, banker's rounding by
+    /// default.
+    pub rounding: RoundingStrategy,
+    /// Currency of rows without a `currency` column or value. Reports only
+    /// name currencies once another one is present. `USD` by default.
+    pub base_currency: Currency,
+}
+
+impl EngineConfig {
+    /// Whether deposits into locked accounts are accepted, through either
+    /// `locked_account_deposits` or its deprecated alias.
+    #[allow(deprecated)]
+    pub fn accepts_locked_deposits(&self) -> bool {
+        self.locked_account_deposits == LockedAccountDeposits::Allow
+            || self.allow_deposits_when_locked
+    }
+}
+
+#[allow(deprecated)]
+impl Default for EngineConfig {
+    fn default() -> Self {
+        EngineConfig {
+            withdrawal_policy: WithdrawalPolicy::default(),
+            duplicate_policy: DuplicatePolicy::default(),
+            cross_client_tx_ids: CrossClientTxIdPolicy::default(),
+            freeze_disputes_on_lock: false,
+            locked_account_deposits: LockedAccountDeposits::default(),
+            allow_deposits_when_locked: false,
+            allow_withdrawal_disputes: false,
+            allow_retry_of_failed_tx: true,
+            reject_unknown_clients: false,
+            limits: Limits::default(),
+            max_accounts: None,
+            account_scales: HashMap::new(),
+            chargebacks_before_lock: 1,
+            max_amount_scale: Some(MAX_AMOUNT_SCALE),
+            chargeback_fee: Decimal::ZERO,
+            settled_retention: None,
+            retention_policy: RetentionPolicy::default(),
+            record_statements: false,
+            report_precision: None,
+            rounding: RoundingStrategy::default(),
+            base_currency: Currency::USD,
+        }
+    }
+}
+
+/// Basic risk controls. Amounts are compared in the transaction's own
+/// currency, and a transaction exactly at a limit is accepted.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Limits {
+    /// Deposits and withdrawals above this amount are rejected with
+    /// `LimitExceeded`.
+    pub max_tx_amount: Option<Decimal>,
+    /// Withdrawals that would take the client's withdrawals in the run, per
+    /// currency, above this amount are rejected with `LimitExceeded`.
+    pub max_total_withdrawal_per_client: Option<Decimal>,
+    /// Withdrawals above this amount are rejected with
+    /// `WithdrawalLimitExceeded`, even when they would be clamped under
+    /// `WithdrawalPolicy::PartialClamp`.
+    pub max_withdrawal: Option<Decimal>,
+}
+
+/// How amounts are rounded to the number of decimal places of a report, for
+/// reporting standards that differ from banker's rounding. Maps onto
+/// `rust_decimal::RoundingStrategy`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum RoundingStrategy {
+    /// Round half to even, so `0.00005` becomes `0.0000` and `0.00015`
+    /// becomes `0.0002`.
+    #[default]
+    HalfEven,
+    /// Round half away from zero.
+    HalfUp,
+    /// Round half toward zero.
+    HalfDown,
+    /// Truncate the extra places.
+    TowardZero,
+    /// Round any extra places away from zero.
+    AwayFromZero,
+}
+
+impl From<RoundingStrategy> for rust_decimal::RoundingStrategy {
+    fn from(strategy: RoundingStrategy) -> Self {
+        match strategy {
+            RoundingStrategy::HalfEven => rust_decimal::RoundingStrategy::MidpointNearestEven,
+            RoundingStrategy::HalfUp => rust_decimal::RoundingStrategy::MidpointAwayFromZero,
+            RoundingStrategy::HalfDown => rust_decimal::RoundingStrategy::MidpointTowardZero,
+            RoundingStrategy::TowardZero => rust_decimal::RoundingStrategy::ToZero,
+            RoundingStrategy::AwayFromZero => rust_decimal::RoundingStrategy::AwayFromZero,
+        }
+    }
+}
+
+/// How transaction CSV input is read and processed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CsvOptions {
+    /// The first row names the columns. Without it every row is read in the
+    /// fixed `type, client, tx, amount, currency, timestamp` order.
+    pub has_headers: bool,
+    /// Field separator, e.g. `b'\t'` for TSV or `b';'` for exports using the
+    /// comma as decimal separator. A comma by default.
+    pub delimiter: u8,
+    /// Fields may be enclosed in double quotes, e.g. to hold the delimiter;
+    /// whitespace inside the quotes is trimmed like any other. Off, quotes
+    /// are read as part of the value.
+    pub quoting: bool,
+    /// Stop at the first rejected row with `ProcessorError::Rejected` instead
+    /// of reporting it and going on; the rows before it stay applied.
+    pub strict: bool,
+    /// Data rows to pass over before applying anything, to resume an
+    /// interrupted run. The engine must already hold the state those rows
+    /// produced, e.g. restored from a copy saved when the run stopped;
+    /// skipping rows it never saw, or not skipping rows it already applied,
+    /// silently yields wrong balances. Rows keep their numbers in errors.
+    pub skip_rows: usize,
+    /// Also accept amounts decorated with a currency symbol and thousands
+    /// separators, e.g. `"$1,234.56"`. `None`, the default, only accepts
+    /// plain decimals.
+    pub amount_format: Option<AmountFormat>,
+    /// Lines starting with this byte are ignored, e.g. `# fixture notes` in
+    /// hand-maintained files. `Some(b'#')` by default.
+    pub comment_char: Option<u8>,
+    /// Ignore rows whose fields are all empty, such as lines of whitespace or
+    /// bare delimiters, rather than reporting them as parse errors. Empty
+    /// lines are always ignored. On by default.
+    pub skip_blank_lines: bool,
+    /// Stop reading, with `ProcessingSummary::aborted_due_to_errors` set, as
+    /// soon as the rows that failed to parse or were rejected outnumber this.
+    /// Unlimited when `None`, the default.
+    pub max_errors: Option<u64>,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        CsvOptions {
+            has_headers: true,
+            delimiter: b',',
+            quoting: true,
+            strict: false,
+            skip_rows: 0,
+            amount_format: None,
+            comment_char: Some(b'#'),
+            skip_blank_lines: true,
+            max_errors: None,
+        }
+    }
+}
+
+/// Decoration stripped from amounts that are not plain decimals, see
+/// `CsvOptions::amount_format`. The decimal separator is always `.`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AmountFormat {
+    /// Symbol allowed in front of the digits, after any minus sign.
+    pub currency_symbol: Option<char>,
+    /// Separator allowed between groups of three digits of the integer part.
+    pub thousands_separator: Option<char>,
+}
+
+impl Default for AmountFormat {
+    fn default() -> Self {
+        AmountFormat {
+            currency_symbol: Some('$'),
+            thousands_separator: Some(','),
+        }
+    }
+}
+
+impl AmountFormat {
+    /// Parses `raw` without its currency symbol and thousands separators;
+    /// `None` if what remains is not a decimal or the separators do not split
+    /// the integer part in groups of three digits.
+    pub fn parse(&self, raw: &str) -> Option<Decimal> {
+        let raw = raw.trim();
+        let (sign, unsigned) = raw.strip_prefix('-').map_or(("", raw), |rest| ("-", rest));
+        let unsigned = match self.currency_symbol {
+            Some(symbol) => unsigned.strip_prefix(symbol).unwrap_or(unsigned),
+            None => unsigned,
+        };
+        let (integer, fraction) = unsigned
+            .split_once('.')
+            .map_or((unsigned, None), |(integer, fraction)| {
+                (integer, Some(fraction))
+            });
+
+        let mut plain = sign.to_string();
+        match self.thousands_separator {
+            Some(separator) if integer.contains(separator) => {
+                let mut groups = integer.split(separator);
+                let first = groups.next().unwrap_or_default();
+                if !(1..=3).contains(&first.len()) || groups.clone().any(|group| group.len() != 3) {
+                    return None;
+                }
+                plain.push_str(first);
+                plain.extend(groups);
+            }
+            _ => plain.push_str(integer),
+        }
+        if let Some(fraction) = fraction {
+            plain.push('.');
+            plain.push_str(fraction);
+        }
+        Decimal::from_str(&plain).ok()
+    }
+}
+
+/// What to do with a withdrawal that exceeds the available balance.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum WithdrawalPolicy {
+    /// Reject the withdrawal with `InsufficientFunds`.
+    #[default]
+    Reject,
+    /// Withdraw whatever is available and record the clamped amount, reported
+    /// to `EngineObserver::on_clamp`. With nothing available, e.g. after a
+    /// chargeback fee, the withdrawal is rejected with `InsufficientFunds`.
+    PartialClamp,
+}
+
+/// How deposits into an account locked by a chargeback or `freeze_account`
+/// are handled.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum LockedAccountDeposits {
+    /// Reject them with `AccountLocked`, like every other operation.
+    #[default]
+    Reject,
+    /// Credit them to the available and total balances, e.g. so that a
+    /// client can pay down a negative balance after a chargeback.
+    Allow,
+}
+
+/// How long deposits and withdrawals that were never disputed are kept.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum RetentionPolicy {
+    /// Keep every transaction, so any of them can be disputed later.
+    #[default]
+    KeepAll,
+    /// Evict a completed transaction once this many further operations have
+    /// been applied after it, for inputs where disputes always follow their
+    /// deposit closely. A dispute citing it is then rejected with
+    /// `TransactionEvicted`. Only the highest evicted tx id of each client is
+    /// remembered, so tx ids are expected to increase per client: any unknown
+    /// tx id below it is treated as evicted.
+    Window(usize),
+}
+
+/// How a deposit or withdrawal reusing an already stored tx id is handled.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum DuplicatePolicy {
+    /// Reject every reused tx id with `TransactionAlreadyExists`.
+    #[default]
+    Reject,
+    /// Skip exact replays (same type and amount) without applying them and
+    /// reject reused tx ids with a different amount.
+    SkipIdentical,
+    /// Reject exact replays with `TransactionAlreadyExists` and reused tx ids with
+    /// a different amount with `DuplicateWithDifferentAmount`.
+    RejectMismatch,
+}
+
+/// How a deposit or withdrawal reusing the tx id of another client's deposit
+/// or withdrawal is handled. Unless `Allow`, the engine remembers the client
+/// of every applied tx id, including evicted ones; a `ConcurrentPaymentEngine`
+/// only compares clients of the same shard.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum CrossClientTxIdPolicy {
+    /// Tx ids are only unique per client.
+    #[default]
+    Allow,
+    /// Apply the transaction and record the reuse; see
+    /// `PaymentEngine::reused_tx_ids`.
+    Report,
+    /// Reject the transaction with `TxIdReusedAcrossClients`.
+    Reject,
+}
@@ -0,0 +1,82 @@
+use std::fmt;
+use std::fmt::Display;
+use std::str::FromStr;
+
+#[cfg(feature = "csv")]
+use serde::de::Error as _;
+#[cfg(feature = "csv")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use thiserror::Error;
+
+/// Three-letter currency code such as `USD` or `EUR`, stored upper-case.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Currency([u8; 3]);
+
+impl Currency {
+    pub const USD: Currency = Currency(*b"USD");
+
+    pub fn as_str(&self) -> &str {
+        // Only ASCII letters are ever stored.
+        std::str::from_utf8(&self.0).unwrap_or_default()
+    }
+}
+
+#[derive(Error, Debug, Clone, PartialEq)]
+#[error("Invalid currency code `{0}`, expected three letters")]
+pub struct ParseCurrencyError(String);
+
+/// Accepts three ASCII letters in any case.
+impl FromStr for Currency {
+    type Err = ParseCurrencyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let code: [u8; 3] = s
+            .as_bytes()
+            .try_into()
+            .ok()
+            .filter(|code: &[u8; 3]| code.iter().all(u8::is_ascii_alphabetic))
+            .ok_or_else(|| ParseCurrencyError(s.to_string()))?;
+        Ok(Currency(code.map(|letter| letter.to_ascii_uppercase())))
+    }
+}
+
+impl Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(self.as_str())
+    }
+}
+
+#[cfg(feature = "csv")]
+impl Serialize for Currency {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+#[cfg(feature = "csv")]
+impl<'de> Deserialize<'de> for Currency {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let raw = String::deserialize(deserializer)?;
+        raw.parse().map_err(D::Error::custom)
+    }
+}
+
+#[cfg(all(test, feature = "csv"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("eur".parse::<Currency>().unwrap().to_string(), "EUR");
+        assert_eq!("USD".parse::<Currency>().unwrap(), Currency::USD);
+        for invalid in ["", "EU", "EURO", "E1R", "€UR"] {
+            assert!(invalid.parse::<Currency>().is_err(), "{invalid}");
+        }
+        assert_eq!(
+            serde_json::from_str::<Currency>("\"chf\"")
+                .unwrap()
+                .as_str(),
+            "CHF"
+        );
+    }
+}
@@ -0,0 +1,154 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fmt::Display;
+
+use crate::account::AccountView;
+use crate::ids::ClientId;
+use crate::payments_engine::PaymentEngine;
+
+/// An account that differs between two engines, as found by
+/// `PaymentEngine::diff`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccountDiff {
+    pub client: ClientId,
+    /// The account in the engine `diff` was called on, `None` if only the
+    /// other engine has it.
+    pub before: Option<AccountView>,
+    /// The account in the other engine, `None` if it is missing there.
+    pub after: Option<AccountView>,
+}
+
+impl Display for AccountDiff {
+    /// One line per account: the fields that changed, or the whole account
+    /// when it is only on one side.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let view = |f: &mut fmt::Formatter, view: &AccountView| {
+            write!(
+                f,
+                "available {}, held {}, total {}, locked {}",
+                view.available, view.held, view.total, view.locked
+            )
+        };
+        write!(f, "client {}: ", self.client)?;
+        match (&self.before, &self.after) {
+            (Some(before), Some(after)) => {
+                let mut changes = Vec::new();
+                for (field, before, after) in [
+                    ("available", before.available, after.available),
+                    ("held", before.held, after.held),
+                    ("total", before.total, after.total),
+                ] {
+                    if before != after {
+                        changes.push(format!("{} {} -> {}", field, before, after));
+                    }
+                }
+                if before.locked != after.locked {
+                    changes.push(format!("locked {} -> {}", before.locked, after.locked));
+                }
+                f.write_str(&changes.join(", "))
+            }
+            (Some(before), None) => {
+                f.write_str("removed (")?;
+                view(f, before)?;
+                f.write_str(")")
+            }
+            (None, Some(after)) => {
+                f.write_str("added (")?;
+                view(f, after)?;
+                f.write_str(")")
+            }
+            (None, None) => f.write_str("unchanged"),
+        }
+    }
+}
+
+impl PaymentEngine {
+    /// Accounts whose base currency balances or lock flag differ in `other`,
+    /// and accounts present in only one of the engines, ordered by client.
+    /// Balances compare by value, so `1.5` and `1.5000` are equal; balances in
+    /// other currencies are not compared.
+    pub fn diff(&self, other: &PaymentEngine) -> Vec<AccountDiff> {
+        let clients: BTreeSet<ClientId> = self
+            .accounts
+            .keys()
+            .chain(other.accounts.keys())
+            .copied()
+            .collect();
+        clients
+            .into_iter()
+            .filter_map(|client| {
+                let before = self.accounts.get(&client).map(AccountView::from);
+                let after = other.accounts.get(&client).map(AccountView::from);
+                (before != after).then_some(AccountDiff {
+                    client,
+                    before,
+                    after,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ids::TxId;
+    use crate::transaction::{Transaction, TransactionType};
+    use rust_decimal::{Decimal, dec};
+
+    fn engine(withdrawn: Decimal, second_client: bool) -> PaymentEngine {
+        let mut engine = PaymentEngine::new();
+        let mut apply = |tx_type, client, tx_id, amount| {
+            engine
+                .process_transaction(Transaction::new(
+                    tx_type,
+                    ClientId(client),
+                    TxId(tx_id),
+                    amount,
+                ))
+                .unwrap();
+        };
+        apply(TransactionType::Deposit, 1, 1, dec!(10.0));
+        apply(TransactionType::Withdrawal, 1, 2, withdrawn);
+        if second_client {
+            apply(TransactionType::Deposit, 2, 3, dec!(1.5));
+        }
+        engine
+    }
+
+    #[test]
+    fn test_diff() {
+        let before = engine(dec!(2.5), true);
+        assert_eq!(before.diff(&engine(dec!(2.5), true)), vec![]);
+
+        let after = engine(dec!(3.0), false);
+        let diff = before.diff(&after);
+        assert_eq!(diff.len(), 2);
+        assert_eq!(diff[0].client, ClientId(1));
+        let (changed_before, changed_after) = (diff[0].before.unwrap(), diff[0].after.unwrap());
+        assert_eq!(
+            (changed_before.available, changed_after.available),
+            (dec!(7.5), dec!(7.0))
+        );
+        assert_eq!(
+            (changed_before.total, changed_after.total),
+            (dec!(7.5), dec!(7.0))
+        );
+        assert_eq!(changed_before.held, changed_after.held);
+        assert_eq!(
+            diff[0].to_string(),
+            "client 1: available 7.5 -> 7.0, total 7.5 -> 7.0"
+        );
+        assert_eq!(diff[1].client, ClientId(2));
+        assert_eq!(diff[1].before.unwrap().total, dec!(1.5));
+        assert_eq!(diff[1].after, None);
+        assert_eq!(
+            diff[1].to_string(),
+            "client 2: removed (available 1.5, held 0, total 1.5, locked false)"
+        );
+
+        let reversed = after.diff(&before);
+        assert_eq!(reversed[1].before, None);
+        assert!(reversed[1].to_string().starts_with("client 2: added ("));
+    }
+}
@@ -0,0 +1,120 @@
+use sha2::{Digest, Sha256};
+
+use crate::account::DEFAULT_SCALE;
+use crate::payments_engine::{PaymentEngine, format_amount};
+use crate::transaction::{Transaction, TransactionType};
+
+impl PaymentEngine {
+    /// SHA-256 of a canonical rendering of the final state, for checking that
+    /// two runs over the same input agree without comparing whole reports.
+    ///
+    /// Accounts are hashed by client with their balances in every currency and
+    /// lock flag, then transactions and fees by client and tx id with their
+    /// amounts and statuses. Amounts are rendered with four decimal places, and
+    /// sequence numbers are left out, so the digest depends neither on map
+    /// iteration order nor on the order independent clients were processed in.
+    pub fn state_digest(&self) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+
+        let mut accounts: Vec<_> = self.accounts.values().collect();
+        accounts.sort_unstable_by_key(|account| account.client);
+        for account in accounts {
+            for (currency, balances) in account.all_balances() {
+                hasher.update(format!(
+                    "account,{},{},{},{},{},{}\n",
+                    account.client,
+                    self.currency_or_base(currency),
+                    format_amount(balances.available, DEFAULT_SCALE),
+                    format_amount(balances.held, DEFAULT_SCALE),
+                    format_amount(balances.total, DEFAULT_SCALE),
+                    account.locked
+                ));
+            }
+        }
+
+        let mut transactions: Vec<&Transaction> = self
+            .transactions
+            .values()
+            .flat_map(|account_transactions| account_transactions.values())
+            .chain(self.fees.values().flatten())
+            .collect();
+        // A fee carries the tx id of the chargeback that incurred it.
+        transactions
+            .sort_unstable_by_key(|t| (t.account_id, t.tx_id, t.tx_type == TransactionType::Fee));
+        for transaction in transactions {
+            hasher.update(format!(
+                "transaction,{},{},{},{},{},{},{}\n",
+                transaction.account_id,
+                transaction.tx_id,
+                transaction.tx_type,
+                self.currency_or_base(transaction.currency),
+                format_amount(transaction.amount, DEFAULT_SCALE),
+                format_amount(transaction.disputed_amount, DEFAULT_SCALE),
+                transaction.status
+            ));
+        }
+
+        hasher.finalize().into()
+    }
+}
+
+/// Lower-case hexadecimal rendering of a digest.
+pub fn to_hex(digest: &[u8]) -> String {
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "csv")]
+    use crate::entry::TransactionEntry;
+    #[cfg(feature = "csv")]
+    use crate::ids::{ClientId, TxId};
+    #[cfg(feature = "csv")]
+    use crate::processor::{NullSink, process_stream_with_sink};
+    #[cfg(feature = "csv")]
+    use rust_decimal::dec;
+
+    #[cfg(feature = "csv")]
+    fn entries() -> Vec<TransactionEntry> {
+        vec![
+            TransactionEntry::deposit(ClientId(1), TxId(1), dec!(10.0)),
+            TransactionEntry::deposit(ClientId(2), TxId(2), dec!(5)),
+            TransactionEntry::withdrawal(ClientId(1), TxId(3), dec!(2.5)),
+            TransactionEntry::deposit(ClientId(3), TxId(4), dec!(1.0)),
+            TransactionEntry::dispute(ClientId(2), TxId(2)),
+            TransactionEntry::chargeback(ClientId(2), TxId(2)),
+        ]
+    }
+
+    #[cfg(feature = "csv")]
+    #[test]
+    fn test_state_digest_ignores_order() {
+        let mut forward = PaymentEngine::new();
+        process_stream_with_sink(&mut forward, entries().into_iter(), &mut NullSink);
+
+        // Clients in reverse order, each client's own rows still in order.
+        let mut reversed = PaymentEngine::new();
+        let mut by_client = entries();
+        by_client.sort_by_key(|entry| std::cmp::Reverse(entry.account_id));
+        process_stream_with_sink(&mut reversed, by_client.into_iter(), &mut NullSink);
+
+        assert_eq!(forward.state_digest(), reversed.state_digest());
+        assert_eq!(forward.state_digest(), forward.state_digest());
+
+        reversed
+            .apply(TransactionEntry::deposit(
+                ClientId(3),
+                TxId(5),
+                dec!(0.0001),
+            ))
+            .unwrap();
+        assert_ne!(forward.state_digest(), reversed.state_digest());
+    }
+
+    #[test]
+    fn test_to_hex() {
+        assert_eq!(to_hex(&[0x00, 0x0f, 0xab]), "000fab");
+        assert_eq!(to_hex(&PaymentEngine::new().state_digest()).len(), 64);
+    }
+}
@@ -1,19 +1,131 @@
+use std::fmt;
+use std::fmt::Display;
+use std::str::FromStr;
+
 use rust_decimal::Decimal;
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer};
+
+use crate::config::AmountFormat;
+use crate::currency::Currency;
+use crate::ids::{ClientId, TxId};
+use crate::transaction::{ConvertionError, MAX_AMOUNT_SCALE, Transaction, TransactionType};
+
+/// Versions of the row format this crate reads. Rows without a `version` value
+/// are version 1, the format without the column.
+pub const SUPPORTED_VERSIONS: [u32; 1] = [1];
 
 #[derive(Debug, Clone, PartialEq, Deserialize)]
 pub struct TransactionEntry {
     #[serde(rename = "type")]
     pub entry_type: TransactionEntryType,
     #[serde(rename = "client")]
-    pub account_id: u16,
+    pub account_id: ClientId,
     #[serde(rename = "tx")]
-    pub tx_id: u32,
-    #[serde(deserialize_with = "csv::invalid_option")]
-    pub amount: Option<Decimal>,
+    pub tx_id: TxId,
+    #[serde(default)]
+    pub amount: AmountField,
+    /// Optional `currency` column; the engine's base currency when absent.
+    #[serde(default)]
+    pub currency: Option<Currency>,
+    /// Optional `timestamp` column, in seconds since the Unix epoch. Only
+    /// carried along for ordering and reporting.
+    #[serde(default)]
+    pub timestamp: Option<u64>,
+    /// Optional `version` column naming the format of the row; a row of a
+    /// version missing from `SUPPORTED_VERSIONS` fails to deserialize.
+    #[serde(default, deserialize_with = "supported_version")]
+    pub version: Option<u32>,
 }
 
-#[derive(Debug, Clone, PartialEq, Deserialize)]
+fn supported_version<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u32>, D::Error> {
+    match Option::<u32>::deserialize(deserializer)? {
+        Some(version) if !SUPPORTED_VERSIONS.contains(&version) => Err(serde::de::Error::custom(
+            format!("unsupported format version {}", version),
+        )),
+        version => Ok(version),
+    }
+}
+
+impl TransactionEntry {
+    pub fn deposit(account_id: ClientId, tx_id: TxId, amount: Decimal) -> Self {
+        Self::with_amount(TransactionEntryType::Deposit, account_id, tx_id, amount)
+    }
+
+    pub fn withdrawal(account_id: ClientId, tx_id: TxId, amount: Decimal) -> Self {
+        Self::with_amount(TransactionEntryType::Withdrawal, account_id, tx_id, amount)
+    }
+
+    pub fn dispute(account_id: ClientId, tx_id: TxId) -> Self {
+        Self::without_amount(TransactionEntryType::Dispute, account_id, tx_id)
+    }
+
+    pub fn resolve(account_id: ClientId, tx_id: TxId) -> Self {
+        Self::without_amount(TransactionEntryType::Resolve, account_id, tx_id)
+    }
+
+    /// Resolve row carrying the part of the disputed amount to release.
+    pub fn partial_resolve(account_id: ClientId, tx_id: TxId, amount: Decimal) -> Self {
+        Self::with_amount(TransactionEntryType::Resolve, account_id, tx_id, amount)
+    }
+
+    pub fn chargeback(account_id: ClientId, tx_id: TxId) -> Self {
+        Self::without_amount(TransactionEntryType::Chargeback, account_id, tx_id)
+    }
+
+    /// Freeze row; `tx` is not used.
+    pub fn freeze(account_id: ClientId) -> Self {
+        Self::without_amount(TransactionEntryType::Freeze, account_id, TxId(0))
+    }
+
+    pub fn unfreeze(account_id: ClientId) -> Self {
+        Self::without_amount(TransactionEntryType::Unfreeze, account_id, TxId(0))
+    }
+
+    /// Sets the currency of the row.
+    pub fn in_currency(mut self, currency: Currency) -> Self {
+        self.currency = Some(currency);
+        self
+    }
+
+    /// Sets the timestamp of the row.
+    pub fn at(mut self, timestamp: u64) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    #[inline]
+    fn with_amount(
+        entry_type: TransactionEntryType,
+        account_id: ClientId,
+        tx_id: TxId,
+        amount: Decimal,
+    ) -> Self {
+        TransactionEntry {
+            entry_type,
+            account_id,
+            tx_id,
+            amount: AmountField::Value(amount),
+            currency: None,
+            timestamp: None,
+            version: None,
+        }
+    }
+
+    #[inline]
+    fn without_amount(entry_type: TransactionEntryType, account_id: ClientId, tx_id: TxId) -> Self {
+        TransactionEntry {
+            entry_type,
+            account_id,
+            tx_id,
+            amount: AmountField::Missing,
+            currency: None,
+            timestamp: None,
+            version: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum TransactionEntryType {
     Deposit,
@@ -21,4 +133,214 @@ pub enum TransactionEntryType {
     Dispute,
     Resolve,
     Chargeback,
+    /// Locks the account until an `Unfreeze` row; carries no amount.
+    Freeze,
+    Unfreeze,
+}
+
+impl Display for TransactionEntryType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            TransactionEntryType::Deposit => "deposit",
+            TransactionEntryType::Withdrawal => "withdrawal",
+            TransactionEntryType::Dispute => "dispute",
+            TransactionEntryType::Resolve => "resolve",
+            TransactionEntryType::Chargeback => "chargeback",
+            TransactionEntryType::Freeze => "freeze",
+            TransactionEntryType::Unfreeze => "unfreeze",
+        })
+    }
+}
+
+/// Raw `amount` column, keeping the original text when it is not a valid decimal.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum AmountField {
+    #[default]
+    Missing,
+    Invalid(String),
+    Value(Decimal),
+}
+
+/// Writes the amount as it appeared in the input; `Missing` is empty.
+impl Display for AmountField {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AmountField::Missing => Ok(()),
+            AmountField::Invalid(raw) => f.write_str(raw),
+            AmountField::Value(amount) => write!(f, "{}", amount),
+        }
+    }
+}
+
+impl AmountField {
+    /// Reads an amount that is not a plain decimal again with `format`, e.g.
+    /// `$1,234.56`; it stays `Invalid` if that fails too.
+    pub fn reparse(&mut self, format: &AmountFormat) {
+        if let AmountField::Invalid(raw) = self
+            && let Some(amount) = format.parse(raw)
+        {
+            *self = AmountField::Value(amount);
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for AmountField {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let raw = Option::<String>::deserialize(deserializer)?;
+        Ok(match raw.as_deref().map(str::trim) {
+            None | Some("") => AmountField::Missing,
+            Some(raw) => Decimal::from_str(raw)
+                .or_else(|_| Decimal::from_scientific(raw))
+                .map_or_else(
+                    |_| AmountField::Invalid(raw.to_string()),
+                    AmountField::Value,
+                ),
+        })
+    }
+}
+
+impl Transaction {
+    /// Converts an entry, rejecting amounts with more than `max_scale` decimal
+    /// places; trailing zeros are not counted. `None` accepts any scale.
+    pub fn from_entry(
+        value: TransactionEntry,
+        max_scale: Option<u32>,
+    ) -> Result<Self, ConvertionError> {
+        let tx_type = value.entry_type.try_into()?;
+        let amount = match value.amount {
+            AmountField::Value(amount) => amount,
+            AmountField::Missing => return Err(ConvertionError::MissingAmount),
+            AmountField::Invalid(raw) => return Err(ConvertionError::UnparseableAmount(raw)),
+        };
+        if max_scale.is_some_and(|max_scale| amount.normalize().scale() > max_scale) {
+            return Err(ConvertionError::TooManyDecimals(amount));
+        }
+
+        let mut transaction = Transaction::new(tx_type, value.account_id, value.tx_id, amount);
+        transaction.currency = value.currency;
+        transaction.timestamp = value.timestamp;
+        Ok(transaction)
+    }
+}
+
+impl TryFrom<TransactionEntry> for Transaction {
+    type Error = ConvertionError;
+
+    fn try_from(value: TransactionEntry) -> Result<Self, Self::Error> {
+        Transaction::from_entry(value, Some(MAX_AMOUNT_SCALE))
+    }
+}
+
+impl TryFrom<TransactionEntryType> for TransactionType {
+    type Error = ConvertionError;
+
+    fn try_from(value: TransactionEntryType) -> Result<Self, Self::Error> {
+        match value {
+            TransactionEntryType::Deposit => Ok(TransactionType::Deposit),
+            TransactionEntryType::Withdrawal => Ok(TransactionType::Withdrawal),
+            _ => Err(ConvertionError::InvalidTransactionType),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::TransactionStatus;
+    use csv::{ReaderBuilder, Trim};
+    use rust_decimal::dec;
+
+    fn parse(data: &str) -> Vec<TransactionEntry> {
+        ReaderBuilder::new()
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(data.as_bytes())
+            .deserialize()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_amount_field() {
+        let entries = parse(
+            "type, client, tx, amount\n\
+             deposit, 1, 1, 1.5\n\
+             deposit, 1, 2, abc\n\
+             dispute, 1, 1,\n\
+             dispute, 1, 1",
+        );
+
+        assert_eq!(entries[0].amount, AmountField::Value(dec!(1.5)));
+        assert_eq!(entries[1].amount, AmountField::Invalid("abc".to_string()));
+        assert_eq!(entries[2].amount, AmountField::Missing);
+        assert_eq!(entries[3].amount, AmountField::Missing);
+    }
+
+    #[test]
+    fn test_try_from_entry() {
+        let transaction = Transaction::try_from(TransactionEntry::withdrawal(
+            ClientId(3),
+            TxId(7),
+            dec!(1.5),
+        ))
+        .expect("Withdrawal entry should convert");
+
+        assert_eq!(transaction.tx_type, TransactionType::Withdrawal);
+        assert_eq!(transaction.account_id, ClientId(3));
+        assert_eq!(transaction.tx_id, TxId(7));
+        assert_eq!(transaction.amount, dec!(1.5));
+        assert_eq!(transaction.status, TransactionStatus::Completed);
+    }
+
+    #[test]
+    fn test_try_from_entry_errors() {
+        assert!(matches!(
+            Transaction::try_from(TransactionEntry::dispute(ClientId(1), TxId(1))),
+            Err(ConvertionError::InvalidTransactionType)
+        ));
+
+        let mut entry = TransactionEntry::deposit(ClientId(1), TxId(1), dec!(1.0));
+        entry.amount = AmountField::Missing;
+        assert!(matches!(
+            Transaction::try_from(entry.clone()),
+            Err(ConvertionError::MissingAmount)
+        ));
+
+        entry.amount = AmountField::Invalid("abc".to_string());
+        assert!(matches!(
+            Transaction::try_from(entry),
+            Err(ConvertionError::UnparseableAmount(raw)) if raw == "abc"
+        ));
+    }
+
+    #[test]
+    fn test_too_many_decimals() {
+        assert!(matches!(
+            Transaction::try_from(TransactionEntry::deposit(ClientId(1), TxId(1), dec!(0.00001))),
+            Err(ConvertionError::TooManyDecimals(amount)) if amount == dec!(0.00001)
+        ));
+        assert!(
+            Transaction::try_from(TransactionEntry::deposit(
+                ClientId(1),
+                TxId(1),
+                dec!(0.0001)
+            ))
+            .is_ok()
+        );
+        assert!(
+            Transaction::try_from(TransactionEntry::deposit(
+                ClientId(1),
+                TxId(1),
+                dec!(1.500000)
+            ))
+            .is_ok()
+        );
+        assert!(
+            Transaction::from_entry(
+                TransactionEntry::deposit(ClientId(1), TxId(1), dec!(0.00001)),
+                None
+            )
+            .is_ok()
+        );
+    }
 }
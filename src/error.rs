@@ -1,33 +1,318 @@
+use std::fmt;
+use std::fmt::Display;
+use std::io;
+
+use rust_decimal::Decimal;
 use thiserror::Error;
 
-use crate::transaction::ConvertionError;
+use crate::account::LockReason;
+use crate::currency::Currency;
+#[cfg(feature = "csv")]
+use crate::entry::TransactionEntry;
+use crate::ids::{ClientId, TxId};
+use crate::transaction::{ConvertionError, TransactionStatus, TransitionError};
 
-#[derive(Error, Debug)]
+/// Reason an operation was rejected. `code` gives a stable identifier for
+/// each kind of failure; new variants may be added.
+#[derive(Error, Debug, Clone, PartialEq)]
+#[non_exhaustive]
 pub enum PaymentError {
-    #[error("Insufficient funds for transaction")]
-    InsufficientFunds,
-    #[error("Insufficient hold funds for transaction")]
-    InsufficientHoldFunds,
-    #[error("Account is locked: {0}")]
-    AccountLocked(u16),
+    #[error("Insufficient funds for transaction {tx_id}")]
+    InsufficientFunds { client: ClientId, tx_id: TxId },
+    #[error("Insufficient hold funds for transaction {tx_id}")]
+    InsufficientHoldFunds { client: ClientId, tx_id: TxId },
+    /// An open dispute without a recorded hold, which only an inconsistent
+    /// state can produce.
+    #[error("No funds recorded as held for disputed transaction {tx_id}")]
+    MissingHold { client: ClientId, tx_id: TxId },
+    #[error("Invalid amount: {0}")]
+    InvalidAmount(Decimal),
+    #[error(
+        "Account is locked: {client}{}",
+        reason.map(|reason| format!(" by {}", reason)).unwrap_or_default()
+    )]
+    AccountLocked {
+        client: ClientId,
+        reason: Option<LockReason>,
+    },
     #[error("Account not found: {0}")]
-    AccountNotFound(u16),
-    #[error("Transaction not found")]
-    TransactionNotFound,
-    #[error("Invalid transaction type for operation")]
-    InvalidTransactionType,
-    #[error("Transaction already exists")]
-    TransactionAlreadyExists,
-    #[error("Transaction already disputed")]
-    TransactionAlreadyDisputed,
-    #[error("Transaction is not disputed")]
-    TransactionIsNotDisputed,
-    #[error("Invalid entry for transaction conversion")]
+    AccountNotFound(ClientId),
+    #[error("Account is not frozen: {0}")]
+    AccountNotFrozen(ClientId),
+    #[error("Account limit exceeded, cannot create account: {0}")]
+    AccountLimitExceeded(ClientId),
+    #[error("Transaction not found: {tx_id}")]
+    TransactionNotFound { client: ClientId, tx_id: TxId },
+    #[error("Transaction evicted from memory: {0}")]
+    TransactionEvicted(TxId),
+    #[error("Transaction belongs to client {expected}, not client {actual}")]
+    AccountMismatch {
+        expected: ClientId,
+        actual: ClientId,
+    },
+    #[error("Transaction {tx_id} is in {expected}, not {actual}")]
+    CurrencyMismatch {
+        tx_id: TxId,
+        expected: Currency,
+        actual: Currency,
+    },
+    #[error("Invalid transaction type for operation: {0}")]
+    InvalidTransactionType(TxId),
+    #[error("Transaction already exists: {tx_id}")]
+    TransactionAlreadyExists { client: ClientId, tx_id: TxId },
+    #[error(
+        "Transaction {tx_id} of client {second_client} was already used by client {first_client}"
+    )]
+    TxIdReusedAcrossClients {
+        tx_id: TxId,
+        first_client: ClientId,
+        second_client: ClientId,
+    },
+    #[error("Transaction {tx_id} already exists with amount {stored}, got {incoming}")]
+    DuplicateWithDifferentAmount {
+        tx_id: TxId,
+        stored: Decimal,
+        incoming: Decimal,
+    },
+    #[error("Transaction {0} has no amount to dispute at the account's scale")]
+    ZeroAmountDispute(TxId),
+    #[error("Transaction already disputed: {0}")]
+    TransactionAlreadyDisputed(TxId),
+    #[error("Transaction is not disputed: {0}")]
+    TransactionIsNotDisputed(TxId),
+    #[error("Transaction already resolved: {0}")]
+    TransactionAlreadyResolved(TxId),
+    #[error("Transaction already charged back: {0}")]
+    TransactionAlreadyChargedBack(TxId),
+    #[error("Transaction frozen by account lock: {0}")]
+    TransactionFrozen(TxId),
+    #[error("Transaction {tx_id} cannot go from {from} to {to}")]
+    InvalidTransition {
+        tx_id: TxId,
+        from: TransactionStatus,
+        to: TransactionStatus,
+    },
+    #[error("Transaction {tx_id} exceeds the {kind} limit of {limit}: {attempted}")]
+    LimitExceeded {
+        tx_id: TxId,
+        kind: LimitKind,
+        limit: Decimal,
+        attempted: Decimal,
+    },
+    #[error("Withdrawal {tx_id} of {attempted} exceeds the per-withdrawal limit of {limit}")]
+    WithdrawalLimitExceeded {
+        client: ClientId,
+        tx_id: TxId,
+        limit: Decimal,
+        attempted: Decimal,
+    },
+    #[error(
+        "Transaction {tx_id} would take a balance of client {client} past the decimal precision"
+    )]
+    PrecisionLoss { client: ClientId, tx_id: TxId },
+    #[error("Invalid entry for transaction conversion: {0}")]
     InvalidEntryForConversion(ConvertionError),
 }
 
+/// Limit of `config::Limits` that rejected an operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitKind {
+    /// `max_tx_amount`; `attempted` is the transaction's amount.
+    TransactionAmount,
+    /// `max_total_withdrawal_per_client`; `attempted` is the client's total
+    /// withdrawals including this one.
+    TotalWithdrawal,
+}
+
+impl Display for LimitKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            LimitKind::TransactionAmount => "transaction amount",
+            LimitKind::TotalWithdrawal => "total withdrawal",
+        })
+    }
+}
+
+/// Reason `PaymentEngine::merge` refused to combine two engines. Nothing is
+/// merged when it fails.
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum MergeError {
+    /// Both engines hold state for the client, whose histories cannot be
+    /// interleaved after the fact; the lowest such client is named.
+    #[error("Both engines hold state for client {0}")]
+    ClientOverlap(ClientId),
+    #[error("Transaction {tx_id} of client {client} exists in both engines")]
+    TransactionOverlap { client: ClientId, tx_id: TxId },
+    #[error("Account limit exceeded, cannot merge account: {0}")]
+    AccountLimitExceeded(ClientId),
+    /// A balance of the client would overflow or need rounding once added up;
+    /// see `PaymentError::PrecisionLoss`.
+    #[error("Balances of client {0} cannot be added up exactly")]
+    PrecisionLoss(ClientId),
+}
+
+/// Failure of a whole input, as opposed to a single rejected row.
+#[derive(Error, Debug)]
+pub enum ProcessorError {
+    #[error("Unusable CSV header: {0}")]
+    BadHeader(String),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// The first rejected row in strict mode; `row` counts the data rows read,
+    /// from 1.
+    #[error("Row {row} rejected: {error}")]
+    Rejected { row: u64, error: PaymentError },
+    /// A row names a format version missing from `entry::SUPPORTED_VERSIONS`;
+    /// the rows before it stay applied and none after it are read.
+    #[error("Unsupported format version: {0}")]
+    UnsupportedVersion(u32),
+}
+
+/// A rejection with the row and entry it came from, e.g. `row 42: dispute tx
+/// 17 client 3: Transaction not found: 17`, as the processor logs it.
+#[cfg(feature = "csv")]
+#[derive(Error, Debug, Clone, PartialEq)]
+#[error(
+    "row {row}: {} tx {} client {}: {source}",
+    entry.entry_type,
+    entry.tx_id,
+    entry.account_id
+)]
+pub struct ContextualError {
+    /// Number of the row among the data rows read, from 1.
+    pub row: u64,
+    pub entry: TransactionEntry,
+    pub source: PaymentError,
+}
+
+/// Coarse classification of a rejected operation, so callers can tell rejections
+/// expected from an account lock apart from genuinely invalid input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectionReason {
+    AccountLocked,
+    Invalid,
+}
+
+impl PaymentError {
+    /// Stable upper-case identifier of the kind of failure, for mapping
+    /// rejections to API error codes without parsing messages.
+    pub fn code(&self) -> &'static str {
+        match self {
+            PaymentError::InsufficientFunds { .. } => "INSUFFICIENT_FUNDS",
+            PaymentError::InsufficientHoldFunds { .. } => "INSUFFICIENT_HELD_FUNDS",
+            PaymentError::MissingHold { .. } => "MISSING_HOLD",
+            PaymentError::InvalidAmount(_) => "INVALID_AMOUNT",
+            PaymentError::AccountLocked { .. } => "ACCOUNT_LOCKED",
+            PaymentError::AccountNotFound(_) => "ACCOUNT_NOT_FOUND",
+            PaymentError::AccountNotFrozen(_) => "ACCOUNT_NOT_FROZEN",
+            PaymentError::AccountLimitExceeded(_) => "ACCOUNT_LIMIT_EXCEEDED",
+            PaymentError::TransactionNotFound { .. } => "TX_NOT_FOUND",
+            PaymentError::TransactionEvicted(_) => "TX_EVICTED",
+            PaymentError::AccountMismatch { .. } => "ACCOUNT_MISMATCH",
+            PaymentError::CurrencyMismatch { .. } => "CURRENCY_MISMATCH",
+            PaymentError::InvalidTransactionType(_) => "INVALID_TX_TYPE",
+            PaymentError::TransactionAlreadyExists { .. } => "TX_ALREADY_EXISTS",
+            PaymentError::DuplicateWithDifferentAmount { .. } => "TX_DUPLICATE_MISMATCH",
+            PaymentError::TxIdReusedAcrossClients { .. } => "TX_ID_REUSED",
+            PaymentError::ZeroAmountDispute(_) => "ZERO_AMOUNT_DISPUTE",
+            PaymentError::TransactionAlreadyDisputed(_) => "TX_ALREADY_DISPUTED",
+            PaymentError::TransactionIsNotDisputed(_) => "TX_NOT_DISPUTED",
+            PaymentError::TransactionAlreadyResolved(_) => "TX_ALREADY_RESOLVED",
+            PaymentError::TransactionAlreadyChargedBack(_) => "TX_ALREADY_CHARGED_BACK",
+            PaymentError::TransactionFrozen(_) => "TX_FROZEN",
+            PaymentError::InvalidTransition { .. } => "TX_INVALID_TRANSITION",
+            PaymentError::LimitExceeded { .. } => "LIMIT_EXCEEDED",
+            PaymentError::WithdrawalLimitExceeded { .. } => "WITHDRAWAL_LIMIT_EXCEEDED",
+            PaymentError::PrecisionLoss { .. } => "PRECISION_LOSS",
+            PaymentError::InvalidEntryForConversion(error) => match error {
+                ConvertionError::InvalidTransactionType => "INVALID_ENTRY_TYPE",
+                ConvertionError::MissingAmount => "MISSING_AMOUNT",
+                ConvertionError::UnparseableAmount(_) => "UNPARSEABLE_AMOUNT",
+                ConvertionError::TooManyDecimals(_) => "TOO_MANY_DECIMALS",
+            },
+        }
+    }
+
+    /// Error for a rejected status change of `tx_id`.
+    pub(crate) fn transition(tx_id: TxId, error: TransitionError) -> Self {
+        match error {
+            TransitionError::AlreadyDisputed => PaymentError::TransactionAlreadyDisputed(tx_id),
+            TransitionError::NotDisputed => PaymentError::TransactionIsNotDisputed(tx_id),
+            TransitionError::AlreadyResolved => PaymentError::TransactionAlreadyResolved(tx_id),
+            TransitionError::AlreadyChargedBack => {
+                PaymentError::TransactionAlreadyChargedBack(tx_id)
+            }
+            TransitionError::Frozen => PaymentError::TransactionFrozen(tx_id),
+            TransitionError::Illegal { from, to } => {
+                PaymentError::InvalidTransition { tx_id, from, to }
+            }
+        }
+    }
+
+    pub fn rejection_reason(&self) -> RejectionReason {
+        match self {
+            PaymentError::AccountLocked { .. } => RejectionReason::AccountLocked,
+            _ => RejectionReason::Invalid,
+        }
+    }
+}
+
 impl From<ConvertionError> for PaymentError {
     fn from(error: ConvertionError) -> Self {
         Self::InvalidEntryForConversion(error)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::dec;
+
+    #[test]
+    fn test_codes() {
+        let errors = [
+            PaymentError::InsufficientFunds {
+                client: ClientId(1),
+                tx_id: TxId(2),
+            },
+            PaymentError::TransactionNotFound {
+                client: ClientId(1),
+                tx_id: TxId(2),
+            },
+            PaymentError::TransactionAlreadyDisputed(TxId(2)),
+            ConvertionError::TooManyDecimals(dec!(0.00001)).into(),
+        ];
+        let codes: Vec<&str> = errors.iter().map(PaymentError::code).collect();
+        assert_eq!(
+            codes,
+            vec![
+                "INSUFFICIENT_FUNDS",
+                "TX_NOT_FOUND",
+                "TX_ALREADY_DISPUTED",
+                "TOO_MANY_DECIMALS"
+            ]
+        );
+        assert_eq!(
+            errors[0].to_string(),
+            "Insufficient funds for transaction 2"
+        );
+    }
+
+    #[cfg(feature = "csv")]
+    #[test]
+    fn test_contextual_error() {
+        let error = ContextualError {
+            row: 42,
+            entry: TransactionEntry::dispute(ClientId(3), TxId(17)),
+            source: PaymentError::TransactionNotFound {
+                client: ClientId(3),
+                tx_id: TxId(17),
+            },
+        };
+        assert_eq!(
+            error.to_string(),
+            "row 42: dispute tx 17 client 3: Transaction not found: 17"
+        );
+        assert!(std::error::Error::source(&error).is_some());
+    }
+}
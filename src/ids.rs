@@ -0,0 +1,71 @@
+use std::fmt;
+use std::fmt::Display;
+use std::num::ParseIntError;
+use std::str::FromStr;
+
+#[cfg(feature = "csv")]
+use serde::{Deserialize, Serialize};
+
+/// Identifier of a client account, the `client` column of the input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "csv", derive(Serialize, Deserialize), serde(transparent))]
+pub struct ClientId(pub u16);
+
+/// Identifier of a deposit or withdrawal, the `tx` column of the input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "csv", derive(Serialize, Deserialize), serde(transparent))]
+pub struct TxId(pub u32);
+
+impl Display for ClientId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Display for TxId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FromStr for ClientId {
+    type Err = ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(ClientId)
+    }
+}
+
+impl FromStr for TxId {
+    type Err = ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(TxId)
+    }
+}
+
+impl From<u16> for ClientId {
+    fn from(id: u16) -> Self {
+        ClientId(id)
+    }
+}
+
+impl From<u32> for TxId {
+    fn from(id: u32) -> Self {
+        TxId(id)
+    }
+}
+
+#[cfg(all(test, feature = "csv"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ids_serialize_as_numbers() {
+        assert_eq!(serde_json::to_string(&ClientId(7)).unwrap(), "7");
+        assert_eq!(serde_json::from_str::<TxId>("42").unwrap(), TxId(42));
+        assert_eq!("65535".parse::<ClientId>().unwrap(), ClientId(u16::MAX));
+        assert!("65536".parse::<ClientId>().is_err());
+        assert_eq!(format!("{:>4}", TxId(3)), "   3");
+    }
+}
@@ -0,0 +1,156 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Detects the compression format from the file extension, falling back to
+    /// the magic bytes at the start of the stream.
+    pub fn detect(path: &Path, header: &[u8]) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("gz") => return Compression::Gzip,
+            Some("zst") | Some("zstd") => return Compression::Zstd,
+            _ => {}
+        }
+
+        if header.starts_with(&GZIP_MAGIC) {
+            Compression::Gzip
+        } else if header.starts_with(&ZSTD_MAGIC) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Compression::None => "plain",
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+        }
+    }
+}
+
+/// Opens an input file, transparently decompressing `.gz` and `.zst` files.
+/// `-` reads standard input.
+pub fn open_input(path: &Path) -> io::Result<Box<dyn Read>> {
+    if path == Path::new("-") {
+        return open_stdin();
+    }
+    let mut reader = BufReader::new(File::open(path)?);
+    let compression = Compression::detect(path, reader.fill_buf()?);
+    decompress(reader, compression)
+}
+
+/// Reads standard input, detecting compression from the magic bytes.
+pub fn open_stdin() -> io::Result<Box<dyn Read>> {
+    let mut reader = io::stdin().lock();
+    let compression = Compression::detect(Path::new("-"), reader.fill_buf()?);
+    decompress(reader, compression)
+}
+
+pub fn decompress<R: BufRead + 'static>(
+    reader: R,
+    compression: Compression,
+) -> io::Result<Box<dyn Read>> {
+    match compression {
+        Compression::None => Ok(Box::new(reader)),
+        #[cfg(feature = "gzip")]
+        Compression::Gzip => Ok(Box::new(CorruptArchive::new(
+            flate2::bufread::MultiGzDecoder::new(reader),
+            compression,
+        ))),
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => Ok(Box::new(CorruptArchive::new(
+            zstd::Decoder::with_buffer(reader)?,
+            compression,
+        ))),
+        #[allow(unreachable_patterns)]
+        _ => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!(
+                "{} input requires the `{}` feature",
+                compression.name(),
+                compression.name()
+            ),
+        )),
+    }
+}
+
+/// Labels decoder failures so a truncated or corrupt archive is reported as such
+/// instead of as a generic I/O error.
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+struct CorruptArchive<R> {
+    inner: R,
+    compression: Compression,
+}
+
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+impl<R> CorruptArchive<R> {
+    fn new(inner: R, compression: Compression) -> Self {
+        CorruptArchive { inner, compression }
+    }
+}
+
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+impl<R: Read> Read for CorruptArchive<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("corrupt {} archive: {}", self.compression.name(), e),
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_by_extension() {
+        assert_eq!(
+            Compression::detect(Path::new("tx.csv.gz"), b""),
+            Compression::Gzip
+        );
+        assert_eq!(
+            Compression::detect(Path::new("tx.csv.zst"), b""),
+            Compression::Zstd
+        );
+        assert_eq!(
+            Compression::detect(Path::new("tx.csv"), b"type"),
+            Compression::None
+        );
+    }
+
+    #[test]
+    fn test_detect_by_magic_bytes() {
+        assert_eq!(
+            Compression::detect(Path::new("tx"), &[0x1f, 0x8b, 0x08]),
+            Compression::Gzip
+        );
+        assert_eq!(
+            Compression::detect(Path::new("tx"), &[0x28, 0xb5, 0x2f, 0xfd, 0x00]),
+            Compression::Zstd
+        );
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn test_corrupt_gzip_is_reported() {
+        let data: &'static [u8] = &[0x1f, 0x8b, 0x08, 0x00, 0xff, 0xff];
+        let mut reader = decompress(data, Compression::Gzip).unwrap();
+        let error = reader.read_to_end(&mut Vec::new()).unwrap_err();
+        assert!(error.to_string().starts_with("corrupt gzip archive"));
+    }
+}
@@ -0,0 +1,418 @@
+use rust_decimal::Decimal;
+use thiserror::Error;
+
+use crate::currency::Currency;
+use crate::ids::ClientId;
+use crate::payments_engine::PaymentEngine;
+use crate::transaction::{TransactionStatus, TransactionType};
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum InvariantViolation {
+    #[error(
+        "client {client}{}: available {available} + held {held} != total {total}",
+        in_currency(.currency)
+    )]
+    BalanceMismatch {
+        client: ClientId,
+        currency: Option<Currency>,
+        available: Decimal,
+        held: Decimal,
+        total: Decimal,
+    },
+    #[error("client {client}{}: negative available {available}", in_currency(.currency))]
+    NegativeAvailable {
+        client: ClientId,
+        currency: Option<Currency>,
+        available: Decimal,
+    },
+    #[error("client {client}{}: negative held {held}", in_currency(.currency))]
+    NegativeHeld {
+        client: ClientId,
+        currency: Option<Currency>,
+        held: Decimal,
+    },
+    #[error("client {client}{}: negative total {total}", in_currency(.currency))]
+    NegativeTotal {
+        client: ClientId,
+        currency: Option<Currency>,
+        total: Decimal,
+    },
+    #[error(
+        "client {client}{}: disputed transactions hold {disputed}, account holds {held}",
+        in_currency(.currency)
+    )]
+    DisputedNotHeld {
+        client: ClientId,
+        currency: Option<Currency>,
+        disputed: Decimal,
+        held: Decimal,
+    },
+}
+
+/// Names a foreign currency after the client; nothing for the base currency.
+fn in_currency(currency: &Option<Currency>) -> String {
+    currency
+        .map(|currency| format!(" in {}", currency))
+        .unwrap_or_default()
+}
+
+/// Balance field of an account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BalanceField {
+    Available,
+    Held,
+    Total,
+}
+
+/// A stored balance that differs from the one recomputed from the transaction
+/// history.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditDiscrepancy {
+    pub client: ClientId,
+    /// `None` for the base currency.
+    pub currency: Option<Currency>,
+    pub field: BalanceField,
+    pub stored: Decimal,
+    pub expected: Decimal,
+}
+
+impl PaymentEngine {
+    /// Checks the internal consistency of every account in each of its
+    /// currencies, returning all violations ordered by client.
+    pub fn verify_invariants(&self) -> Result<(), Vec<InvariantViolation>> {
+        let mut clients: Vec<ClientId> = self.accounts.keys().copied().collect();
+        clients.sort_unstable();
+
+        let mut violations = Vec::new();
+        for client in clients {
+            for (currency, balances) in self.accounts[&client].all_balances() {
+                // Fees may drive the balance negative, by at most their sum.
+                let floor = -self.fees_charged(client, currency);
+
+                if balances.available + balances.held != balances.total {
+                    violations.push(InvariantViolation::BalanceMismatch {
+                        client,
+                        currency,
+                        available: balances.available,
+                        held: balances.held,
+                        total: balances.total,
+                    });
+                }
+                if balances.available < floor {
+                    violations.push(InvariantViolation::NegativeAvailable {
+                        client,
+                        currency,
+                        available: balances.available,
+                    });
+                }
+                if balances.held < Decimal::ZERO {
+                    violations.push(InvariantViolation::NegativeHeld {
+                        client,
+                        currency,
+                        held: balances.held,
+                    });
+                }
+                if balances.total < floor {
+                    violations.push(InvariantViolation::NegativeTotal {
+                        client,
+                        currency,
+                        total: balances.total,
+                    });
+                }
+
+                let disputed: Decimal = self
+                    .transactions
+                    .get(&client)
+                    .into_iter()
+                    .flat_map(|transactions| transactions.values())
+                    .filter(|transaction| {
+                        transaction.currency == currency
+                            && matches!(
+                                transaction.status,
+                                TransactionStatus::Disputed
+                                    | TransactionStatus::PartiallyResolved
+                                    | TransactionStatus::Frozen
+                            )
+                    })
+                    .map(|transaction| transaction.disputed_amount)
+                    .sum();
+                if disputed != balances.held {
+                    violations.push(InvariantViolation::DisputedNotHeld {
+                        client,
+                        currency,
+                        disputed,
+                        held: balances.held,
+                    });
+                }
+            }
+        }
+
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+
+    /// Recomputes every account's balances in each of its currencies from its
+    /// transactions and returns those differing from the stored values,
+    /// ordered by client.
+    ///
+    /// Deposits add to the total and withdrawals and fees subtract from it,
+    /// except for whatever was charged back, and open disputes are held; a
+    /// disputed withdrawal is held on top of the total. Transactions
+    /// evicted under `settled_retention` or the retention window count with
+    /// their settled amounts.
+    pub fn audit(&self) -> Vec<AuditDiscrepancy> {
+        let mut clients: Vec<ClientId> = self.accounts.keys().copied().collect();
+        clients.sort_unstable();
+
+        let mut discrepancies = Vec::new();
+        for client in clients {
+            for (currency, balances) in self.accounts[&client].all_balances() {
+                let mut total = self
+                    .evicted
+                    .get(&(client, currency))
+                    .copied()
+                    .unwrap_or_default();
+                let mut held = Decimal::ZERO;
+                for transaction in self
+                    .transactions
+                    .get(&client)
+                    .into_iter()
+                    .flat_map(|transactions| transactions.values())
+                    .chain(self.fees.get(&client).into_iter().flatten())
+                    .filter(|transaction| transaction.currency == currency)
+                {
+                    total += transaction.net_amount();
+                    if matches!(
+                        transaction.status,
+                        TransactionStatus::Disputed
+                            | TransactionStatus::PartiallyResolved
+                            | TransactionStatus::Frozen
+                    ) {
+                        held += transaction.disputed_amount;
+                        // A disputed withdrawal is credited back while held.
+                        if transaction.tx_type == TransactionType::Withdrawal {
+                            total += transaction.disputed_amount;
+                        }
+                    }
+                }
+
+                for (field, stored, expected) in [
+                    (BalanceField::Available, balances.available, total - held),
+                    (BalanceField::Held, balances.held, held),
+                    (BalanceField::Total, balances.total, total),
+                ] {
+                    if stored != expected {
+                        discrepancies.push(AuditDiscrepancy {
+                            client,
+                            currency,
+                            field,
+                            stored,
+                            expected,
+                        });
+                    }
+                }
+            }
+        }
+        discrepancies
+    }
+
+    #[inline]
+    fn fees_charged(&self, client: ClientId, currency: Option<Currency>) -> Decimal {
+        self.fees
+            .get(&client)
+            .into_iter()
+            .flatten()
+            .filter(|fee| fee.currency == currency)
+            .map(|fee| fee.amount)
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "csv")]
+    use crate::config::EngineConfig;
+    #[cfg(feature = "csv")]
+    use crate::entry::TransactionEntry;
+    use crate::ids::TxId;
+    #[cfg(feature = "csv")]
+    use crate::processor::process_stream;
+    use crate::transaction::{Transaction, TransactionType};
+    use rust_decimal::dec;
+
+    fn engine_with_dispute() -> PaymentEngine {
+        let mut engine = PaymentEngine::new();
+        let deposit = |client, tx_id, amount| {
+            Transaction::new(
+                TransactionType::Deposit,
+                ClientId(client),
+                TxId(tx_id),
+                amount,
+            )
+        };
+        engine
+            .process_transaction(deposit(1, 1, dec!(100.0)))
+            .unwrap();
+        engine
+            .process_transaction(deposit(1, 2, dec!(40.0)))
+            .unwrap();
+        engine.process_dispute(ClientId(1), TxId(2)).unwrap();
+        engine
+            .process_transaction(deposit(2, 3, dec!(5.0)))
+            .unwrap();
+        engine
+    }
+
+    #[test]
+    fn test_healthy_engine() {
+        assert_eq!(engine_with_dispute().verify_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn test_balance_mismatch() {
+        let mut engine = engine_with_dispute();
+        engine.accounts.get_mut(&ClientId(2)).unwrap().total = dec!(6.0);
+
+        assert_eq!(
+            engine.verify_invariants(),
+            Err(vec![InvariantViolation::BalanceMismatch {
+                client: ClientId(2),
+                currency: None,
+                available: dec!(5.0),
+                held: dec!(0.0),
+                total: dec!(6.0),
+            }])
+        );
+    }
+
+    #[test]
+    fn test_negative_balances() {
+        let mut engine = engine_with_dispute();
+        let account = engine.accounts.get_mut(&ClientId(2)).unwrap();
+        account.available = dec!(-1.0);
+        account.total = dec!(-1.0);
+
+        let violations = engine.verify_invariants().unwrap_err();
+        assert_eq!(
+            violations,
+            vec![
+                InvariantViolation::NegativeAvailable {
+                    client: ClientId(2),
+                    currency: None,
+                    available: dec!(-1.0),
+                },
+                InvariantViolation::NegativeTotal {
+                    client: ClientId(2),
+                    currency: None,
+                    total: dec!(-1.0),
+                },
+            ]
+        );
+
+        let account = engine.accounts.get_mut(&ClientId(2)).unwrap();
+        account.available = dec!(6.0);
+        account.held = dec!(-1.0);
+        account.total = dec!(5.0);
+
+        let violations = engine.verify_invariants().unwrap_err();
+        assert!(violations.contains(&InvariantViolation::NegativeHeld {
+            client: ClientId(2),
+            currency: None,
+            held: dec!(-1.0),
+        }));
+    }
+
+    #[test]
+    fn test_disputed_not_held() {
+        let mut engine = engine_with_dispute();
+        let account = engine.accounts.get_mut(&ClientId(1)).unwrap();
+        account.held = dec!(30.0);
+        account.available = dec!(110.0);
+
+        assert_eq!(
+            engine.verify_invariants(),
+            Err(vec![InvariantViolation::DisputedNotHeld {
+                client: ClientId(1),
+                currency: None,
+                disputed: dec!(40.0),
+                held: dec!(30.0),
+            }])
+        );
+    }
+
+    #[cfg(feature = "csv")]
+    #[test]
+    fn test_audit_healthy_engine() {
+        let mut engine = engine_with_dispute();
+        let entries = vec![
+            TransactionEntry::withdrawal(ClientId(1), TxId(4), dec!(25.0)),
+            TransactionEntry::deposit(ClientId(3), TxId(5), dec!(50.0)),
+            TransactionEntry::dispute(ClientId(3), TxId(5)),
+            TransactionEntry::partial_resolve(ClientId(3), TxId(5), dec!(20.0)),
+            TransactionEntry::chargeback(ClientId(3), TxId(5)),
+        ];
+        process_stream(&mut engine, entries.into_iter());
+
+        assert_eq!(engine.audit(), vec![]);
+    }
+
+    #[cfg(feature = "csv")]
+    #[test]
+    fn test_fees_allow_negative_balance() {
+        let mut engine = PaymentEngine::with_config(EngineConfig {
+            chargeback_fee: dec!(15.0),
+            ..EngineConfig::default()
+        });
+        let entries = vec![
+            TransactionEntry::deposit(ClientId(1), TxId(1), dec!(10.0)),
+            TransactionEntry::dispute(ClientId(1), TxId(1)),
+            TransactionEntry::chargeback(ClientId(1), TxId(1)),
+        ];
+        process_stream(&mut engine, entries.into_iter());
+
+        assert_eq!(engine.accounts[&ClientId(1)].total, dec!(-15.0));
+        assert_eq!(engine.verify_invariants(), Ok(()));
+        assert_eq!(engine.audit(), vec![]);
+
+        engine.accounts.get_mut(&ClientId(1)).unwrap().available = dec!(-20.0);
+        assert!(engine.verify_invariants().unwrap_err().contains(
+            &InvariantViolation::NegativeAvailable {
+                client: ClientId(1),
+                currency: None,
+                available: dec!(-20.0),
+            }
+        ));
+    }
+
+    #[test]
+    fn test_audit_drift() {
+        let mut engine = engine_with_dispute();
+        let account = engine.accounts.get_mut(&ClientId(1)).unwrap();
+        account.held = dec!(30.0);
+        account.available = dec!(110.0);
+
+        assert_eq!(
+            engine.audit(),
+            vec![
+                AuditDiscrepancy {
+                    client: ClientId(1),
+                    currency: None,
+                    field: BalanceField::Available,
+                    stored: dec!(110.0),
+                    expected: dec!(100.0),
+                },
+                AuditDiscrepancy {
+                    client: ClientId(1),
+                    currency: None,
+                    field: BalanceField::Held,
+                    stored: dec!(30.0),
+                    expected: dec!(40.0),
+                },
+            ]
+        );
+    }
+}
@@ -1,6 +1,33 @@
 pub mod account;
+#[cfg(feature = "async")]
+pub mod async_processor;
+pub mod concurrent;
+pub mod config;
+pub mod currency;
+pub mod diff;
+#[cfg(feature = "digest")]
+pub mod digest;
+#[cfg(feature = "csv")]
 pub mod entry;
 pub mod error;
+pub mod ids;
+pub mod input;
+pub mod invariants;
+pub mod observer;
 pub mod payments_engine;
+#[cfg(feature = "csv")]
 pub mod processor;
+#[cfg(feature = "csv")]
+pub mod rejects;
+#[cfg(feature = "csv")]
+pub mod serde_decimal;
+pub mod statement;
+#[cfg(feature = "csv")]
+pub mod stats;
+#[cfg(feature = "csv")]
+pub mod testgen;
+#[cfg(feature = "testing")]
+pub mod testing;
 pub mod transaction;
+#[cfg(feature = "csv")]
+pub mod validate;
@@ -1,22 +1,811 @@
-use std::fs::File;
-use std::iter::Iterator;
-use std::{env, io::BufReader};
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
 
-use transaction::payments_engine::PaymentEngine;
-use transaction::processor::process_csv_stream;
+use anyhow::Context;
+use clap::{Parser, ValueEnum};
 
+use transaction::config::{AmountFormat, CsvOptions, EngineConfig};
+use transaction::currency::Currency;
+use transaction::digest::to_hex;
+use transaction::error::ProcessorError;
+use transaction::ids::ClientId;
+use transaction::input::open_input;
+use transaction::payments_engine::{MAX_PRECISION, PaymentEngine};
+use transaction::processor::{
+    EntryFilter, ErrorSink, FileStderrSink, FileSummary, NullSink, StderrSink, process_csv_files,
+};
+use transaction::rejects::CsvRejectsSink;
+use transaction::statement::Statement;
+use transaction::stats::Stats;
+use transaction::validate::validate_csv_stream_with_options;
+
+/// Processes a CSV file of transactions and prints the final account balances.
+#[derive(Parser, Debug)]
+#[command(version)]
+struct Cli {
+    /// Input CSV files, optionally gzip or zstd compressed, processed in order;
+    /// `-` reads stdin, and a directory stands for the CSV files inside it,
+    /// sorted by name
+    #[arg(required = true)]
+    inputs: Vec<PathBuf>,
+
+    /// Write the report to this file instead of stdout
+    #[arg(short, long, value_name = "PATH")]
+    output: Option<PathBuf>,
+
+    /// Overwrite the `--output` file if it already exists
+    #[arg(long)]
+    force: bool,
+
+    /// Report format
+    #[arg(long, value_enum, default_value_t = Format::Csv)]
+    format: Format,
+
+    /// Decimal places in the report, at most 10; defaults to each account's
+    /// scale (4 unless configured)
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(..=MAX_PRECISION as i64))]
+    precision: Option<u32>,
+
+    /// Stop at the first rejected row without writing a report, and exit with
+    /// a non-zero status if any row failed to parse
+    #[arg(long)]
+    strict: bool,
+
+    /// Do not report failed rows on stderr
+    #[arg(short, long)]
+    quiet: bool,
+
+    /// Check the ledger invariants before writing the report
+    #[arg(long)]
+    verify: bool,
+
+    /// Also write every transaction with its final status to this CSV file
+    #[arg(long, value_name = "PATH")]
+    dump_transactions: Option<PathBuf>,
+
+    /// Only check the inputs and list the problems found, without writing a
+    /// report; exits with status 2 if there are any
+    #[arg(long)]
+    check: bool,
+
+    /// Process these inputs into a second engine and print the accounts that
+    /// differ from those of the main inputs, without writing a report; exits
+    /// with status 1 if there are any
+    #[arg(long, value_name = "INPUT", num_args = 1.., conflicts_with = "check")]
+    diff: Vec<PathBuf>,
+
+    /// Write every row that failed to parse or was rejected to this CSV file,
+    /// with an extra `error` column
+    #[arg(long, value_name = "PATH")]
+    rejects: Option<PathBuf>,
+
+    /// Print the statement of this client, listing every operation with the
+    /// running available balance, instead of the account report
+    #[arg(long, value_name = "CLIENT")]
+    statement: Option<ClientId>,
+
+    /// Currency of rows without a `currency` value; reports gain a currency
+    /// column once another currency is present
+    #[arg(long, value_name = "CODE", default_value = "USD")]
+    base_currency: Currency,
+
+    /// Only process the rows of this client; may be repeated. Rows of other
+    /// clients are skipped and their accounts left out of the report
+    #[arg(long = "client", value_name = "ID")]
+    clients: Vec<ClientId>,
+
+    /// Print a SHA-256 digest of the final state on stderr, to compare runs
+    #[arg(long)]
+    digest: bool,
+
+    /// Print a table of the applied rows by type, the rejections by error
+    /// code and the amounts moved on stderr
+    #[arg(long)]
+    stats: bool,
+
+    /// Field separator of the inputs: `,`, `;` or `\t` for TSV
+    #[arg(long, value_name = "CHAR", default_value = ",", value_parser = parse_delimiter)]
+    delimiter: u8,
+
+    /// Also accept amounts with a `$` sign and `,` thousands separators, such
+    /// as `"$1,234.56"`
+    #[arg(long)]
+    lenient_amounts: bool,
+
+    /// Stop without writing a report once more than N rows, across all
+    /// inputs, failed to parse or were rejected
+    #[arg(long, value_name = "N")]
+    max_errors: Option<u64>,
+}
+
+/// Accepts `\t` or `tab` as well as a literal tab for TSV input.
+fn parse_delimiter(value: &str) -> Result<u8, String> {
+    match value {
+        "," => Ok(b','),
+        ";" => Ok(b';'),
+        "\t" | "\\t" | "tab" => Ok(b'\t'),
+        _ => Err(format!(
+            "unsupported delimiter `{}`, expected `,`, `;` or `\\t`",
+            value
+        )),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+enum Format {
+    Csv,
+    Json,
+}
+
+/// Exit status of a run stopped by Ctrl-C, as shells report it.
+const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+/// Set by the first Ctrl-C; processing stops before the next row and the
+/// report is written for the rows applied so far.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Exits with status 2 on usage errors, reported by clap, 1 when an input
+/// or output cannot be read or written, and 130 when interrupted.
 fn main() {
-    let mut args = env::args();
-    if args.len() != 2 {
-        eprintln!("Usage: {} transactions.csv", args.next().unwrap());
-        return;
+    let cli = Cli::parse();
+    // A second Ctrl-C exits at once, e.g. while blocked reading stdin.
+    if let Err(e) = ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::Relaxed) {
+            process::exit(INTERRUPTED_EXIT_CODE);
+        }
+    }) {
+        eprintln!("Warning: cannot handle Ctrl-C: {}", e);
+    }
+    match run(cli) {
+        Ok(code) => process::exit(code),
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            process::exit(1);
+        }
+    }
+}
+
+fn run(cli: Cli) -> anyhow::Result<i32> {
+    if cli.check {
+        return check(&cli);
+    }
+    if !cli.diff.is_empty() {
+        return diff(&cli);
+    }
+
+    // Refuse an existing report before spending time on the inputs.
+    if let Some(path) = &cli.output
+        && !cli.force
+        && path.exists()
+    {
+        anyhow::bail!(
+            "{} already exists, pass --force to overwrite it",
+            path.display()
+        );
+    }
+    let inputs = expand_inputs(&cli.inputs, cli.quiet)?;
+    // Failed rows are labeled with their file and line when there are
+    // several files to tell apart.
+    let labeled = inputs.len() > 1 || cli.inputs.iter().any(|input| input.is_dir());
+
+    let mut engine = PaymentEngine::with_config(EngineConfig {
+        base_currency: cli.base_currency,
+        record_statements: cli.statement.is_some(),
+        ..EngineConfig::default()
+    });
+    let mut rejects = match &cli.rejects {
+        Some(path) => {
+            let file = File::create(path)
+                .with_context(|| format!("failed to create {}", path.display()))?;
+            Some(CsvRejectsSink::new(BufWriter::new(file))?)
+        }
+        None => None,
+    };
+
+    let options = CsvOptions {
+        delimiter: cli.delimiter,
+        strict: cli.strict,
+        amount_format: cli.lenient_amounts.then(AmountFormat::default),
+        // The threshold is for the whole run.
+        max_errors: cli.max_errors,
+        ..CsvOptions::default()
+    };
+    let filter = entry_filter(&cli);
+
+    let mut file_sink;
+    let report: &mut dyn ErrorSink = if cli.quiet {
+        &mut NullSink
+    } else if labeled {
+        file_sink = FileStderrSink::new(PathBuf::new(), options.has_headers);
+        &mut file_sink
+    } else {
+        &mut StderrSink::default()
+    };
+    let results = match &mut rejects {
+        Some(rejects) => process_csv_files(
+            &mut engine,
+            &inputs,
+            &options,
+            &filter,
+            &INTERRUPTED,
+            cli.strict,
+            &mut (&mut *report, rejects),
+        ),
+        None => process_csv_files(
+            &mut engine,
+            &inputs,
+            &options,
+            &filter,
+            &INTERRUPTED,
+            cli.strict,
+            report,
+        ),
+    };
+
+    let mut failed = 0;
+    let mut read_any = false;
+    let mut stats = Stats::default();
+    for (input, result) in inputs.iter().zip(results) {
+        match result {
+            Ok(FileSummary { summary, .. }) => {
+                if (inputs.len() > 1 || summary.interrupted) && !cli.quiet {
+                    eprintln!("{}: {}", input.display(), summary);
+                }
+                failed += summary.errors();
+                if summary.aborted_due_to_errors {
+                    anyhow::bail!(
+                        "{}: aborted at row {}, more than {} rows failed",
+                        input.display(),
+                        summary.rows(),
+                        cli.max_errors.unwrap_or_default()
+                    );
+                }
+                stats.merge(&summary.stats);
+                read_any = true;
+            }
+            Err(e) => {
+                let context = match e {
+                    ProcessorError::Io(_) => format!("failed to open {}", input.display()),
+                    ProcessorError::BadHeader(_) | ProcessorError::UnsupportedVersion(_) => {
+                        format!("failed to read {}", input.display())
+                    }
+                    ProcessorError::Rejected { .. } => {
+                        return Err(e).context(format!("failed to process {}", input.display()));
+                    }
+                };
+                if cli.strict || inputs.len() == 1 {
+                    return Err(e).context(context);
+                }
+                eprintln!("Error: {}: {}", context, e);
+                failed += 1;
+            }
+        }
+    }
+    if !read_any {
+        anyhow::bail!("none of the input files could be opened");
+    }
+
+    if let (Some(rejects), Some(path)) = (rejects, &cli.rejects) {
+        rejects
+            .finish()
+            .with_context(|| format!("failed to write {}", path.display()))?;
+    }
+
+    if cli.verify
+        && let Err(violations) = engine.verify_invariants()
+    {
+        for violation in violations {
+            eprintln!("Invariant violation: {}", violation);
+        }
+        return Ok(1);
+    }
+
+    if let Some(path) = &cli.dump_transactions {
+        let file =
+            File::create(path).with_context(|| format!("failed to create {}", path.display()))?;
+        let mut writer = BufWriter::new(file);
+        engine.write_transactions_csv(&mut writer)?;
+        writer.flush()?;
+    }
+
+    let statement = cli
+        .statement
+        .map(|client| {
+            engine
+                .statement(client)
+                .with_context(|| format!("no account for client {}", client))
+        })
+        .transpose()?;
+
+    match &cli.output {
+        Some(path) => {
+            let file = create_output(path, cli.force)?;
+            write_report(&engine, &cli, statement.as_ref(), BufWriter::new(file))?;
+        }
+        None => write_report(&engine, &cli, statement.as_ref(), io::stdout().lock())?,
+    }
+    if cli.digest {
+        eprintln!("digest: {}", to_hex(&engine.state_digest()));
+    }
+    if cli.stats {
+        eprintln!("{}", stats);
+    }
+
+    Ok(if INTERRUPTED.load(Ordering::Relaxed) {
+        INTERRUPTED_EXIT_CODE
+    } else if cli.strict && failed > 0 {
+        1
+    } else {
+        0
+    })
+}
+
+fn check(cli: &Cli) -> anyhow::Result<i32> {
+    let mut engine = PaymentEngine::with_config(EngineConfig {
+        base_currency: cli.base_currency,
+        ..EngineConfig::default()
+    });
+    let options = CsvOptions {
+        delimiter: cli.delimiter,
+        amount_format: cli.lenient_amounts.then(AmountFormat::default),
+        ..CsvOptions::default()
+    };
+    let inputs = expand_inputs(&cli.inputs, cli.quiet)?;
+    let mut clean = true;
+    for input in &inputs {
+        let reader =
+            open_input(input).with_context(|| format!("failed to open {}", input.display()))?;
+        let report = validate_csv_stream_with_options(&mut engine, reader, &options);
+        if inputs.len() > 1 {
+            println!("{}:", input.display());
+        }
+        println!("{}", report);
+        clean &= report.is_clean();
+    }
+    Ok(if clean { 0 } else { 2 })
+}
+
+/// Lists the accounts that differ between the engines built from the main
+/// inputs and from the `--diff` ones, each processed like a normal run.
+fn diff(cli: &Cli) -> anyhow::Result<i32> {
+    let before = load(cli, &cli.inputs)?;
+    let after = load(cli, &cli.diff)?;
+    let diffs = before.diff(&after);
+    for diff in &diffs {
+        println!("{}", diff);
+    }
+    Ok(if diffs.is_empty() { 0 } else { 1 })
+}
+
+/// Processes `inputs` into a new engine, failing on the first input that
+/// cannot be read.
+fn load(cli: &Cli, inputs: &[PathBuf]) -> anyhow::Result<PaymentEngine> {
+    let inputs = expand_inputs(inputs, cli.quiet)?;
+    let mut engine = PaymentEngine::with_config(EngineConfig {
+        base_currency: cli.base_currency,
+        ..EngineConfig::default()
+    });
+    let options = CsvOptions {
+        delimiter: cli.delimiter,
+        amount_format: cli.lenient_amounts.then(AmountFormat::default),
+        ..CsvOptions::default()
+    };
+    let report: &mut dyn ErrorSink = if cli.quiet {
+        &mut NullSink
+    } else {
+        &mut StderrSink::default()
+    };
+    let results = process_csv_files(
+        &mut engine,
+        &inputs,
+        &options,
+        &entry_filter(cli),
+        &INTERRUPTED,
+        true,
+        report,
+    );
+    for (input, result) in inputs.iter().zip(results) {
+        result.with_context(|| format!("failed to read {}", input.display()))?;
+    }
+    Ok(engine)
+}
+
+fn entry_filter(cli: &Cli) -> EntryFilter {
+    if cli.clients.is_empty() {
+        EntryFilter::default()
+    } else {
+        EntryFilter::clients(cli.clients.iter().copied())
+    }
+}
+
+/// Replaces every directory among the inputs with the CSV files directly in
+/// it, sorted by name, so part files are processed in order. Hidden files,
+/// subdirectories and other extensions are skipped with a notice unless
+/// `quiet`; a directory without any CSV file is an error.
+fn expand_inputs(inputs: &[PathBuf], quiet: bool) -> anyhow::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for input in inputs {
+        if !input.is_dir() {
+            files.push(input.clone());
+            continue;
+        }
+        let mut paths = fs::read_dir(input)
+            .and_then(|entries| {
+                entries
+                    .map(|entry| entry.map(|entry| entry.path()))
+                    .collect::<io::Result<Vec<_>>>()
+            })
+            .with_context(|| format!("failed to read directory {}", input.display()))?;
+        paths.sort();
+
+        let found = files.len();
+        for path in paths {
+            let name = path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_ascii_lowercase();
+            let skipped = if name.starts_with('.') {
+                Some("hidden file")
+            } else if path.is_dir() {
+                Some("directory")
+            } else if ![".csv", ".csv.gz", ".csv.zst"]
+                .iter()
+                .any(|extension| name.ends_with(extension))
+            {
+                Some("not a CSV file")
+            } else {
+                None
+            };
+            match skipped {
+                Some(reason) if !quiet => eprintln!("Skipping {}: {}", path.display(), reason),
+                Some(_) => {}
+                None => files.push(path),
+            }
+        }
+        if files.len() == found {
+            anyhow::bail!("no CSV files in {}", input.display());
+        }
+    }
+    Ok(files)
+}
+
+/// Opens the report file, failing if it exists unless `force` is set.
+fn create_output(path: &Path, force: bool) -> anyhow::Result<File> {
+    let mut options = OpenOptions::new();
+    options.write(true);
+    if force {
+        options.create(true).truncate(true);
+    } else {
+        options.create_new(true);
+    }
+    options.open(path).map_err(|e| match e.kind() {
+        io::ErrorKind::AlreadyExists => anyhow::anyhow!(
+            "{} already exists, pass --force to overwrite it",
+            path.display()
+        ),
+        io::ErrorKind::PermissionDenied => {
+            anyhow::anyhow!("permission denied writing {}", path.display())
+        }
+        _ => anyhow::Error::new(e).context(format!("failed to create {}", path.display())),
+    })
+}
+
+fn write_report(
+    engine: &PaymentEngine,
+    cli: &Cli,
+    statement: Option<&Statement>,
+    mut writer: impl Write,
+) -> io::Result<()> {
+    match (statement, cli.format) {
+        (Some(statement), _) => write!(writer, "{}", statement)?,
+        (None, Format::Csv) => engine.write_csv(&mut writer, cli.precision)?,
+        (None, Format::Json) => engine.write_json(&mut writer, cli.precision)?,
+    }
+    writeln!(writer)?;
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("transaction-{}-{}", process::id(), name))
+    }
+
+    fn write_input(name: &str, data: &str) -> PathBuf {
+        let path = temp_path(name);
+        fs::write(&path, data).unwrap();
+        path
     }
 
-    let filename = args.nth(1).expect("No filename provided");
-    let mut engine = PaymentEngine::new();
+    #[test]
+    fn test_parse_defaults() {
+        let cli = Cli::try_parse_from(["transaction", "transactions.csv"]).unwrap();
+
+        assert_eq!(cli.inputs, vec![PathBuf::from("transactions.csv")]);
+        assert_eq!(cli.output, None);
+        assert!(!cli.force);
+        assert_eq!(cli.format, Format::Csv);
+        assert!(!cli.strict && !cli.quiet && !cli.verify);
+        assert_eq!(cli.precision, None);
+    }
+
+    #[test]
+    fn test_parse_flags() {
+        let cli = Cli::try_parse_from([
+            "transaction",
+            "--format",
+            "json",
+            "--output",
+            "report.json",
+            "--strict",
+            "-q",
+            "--precision",
+            "2",
+            "-",
+        ])
+        .unwrap();
+
+        assert_eq!(cli.inputs, vec![PathBuf::from("-")]);
+        assert_eq!(cli.output, Some(PathBuf::from("report.json")));
+        assert_eq!(cli.format, Format::Json);
+        assert!(cli.strict && cli.quiet);
+        assert_eq!(cli.precision, Some(2));
+    }
+
+    #[test]
+    fn test_parse_rejects_invalid_flags() {
+        assert!(Cli::try_parse_from(["transaction"]).is_err());
+        assert!(Cli::try_parse_from(["transaction", "--format", "xml", "in.csv"]).is_err());
+        assert!(Cli::try_parse_from(["transaction", "--precision", "11", "in.csv"]).is_err());
+    }
 
-    let reader = BufReader::new(File::open(&filename).expect("Failed to open file"));
-    process_csv_stream(&mut engine, reader);
+    #[test]
+    fn test_run_writes_json_output() {
+        let input = write_input("json.csv", "type, client, tx, amount\ndeposit, 1, 1, 2.5\n");
+        let output = temp_path("json.out");
+        let cli = Cli::try_parse_from([
+            "transaction",
+            "--format",
+            "json",
+            "--output",
+            output.to_str().unwrap(),
+            input.to_str().unwrap(),
+        ])
+        .unwrap();
 
-    println!("{}", engine);
+        assert_eq!(run(cli).unwrap(), 0);
+        assert_eq!(
+            fs::read_to_string(&output).unwrap(),
+            "[{\"client\":1,\"available\":\"2.5000\",\"held\":\"0.0000\",\"total\":\"2.5000\",\"locked\":false}]\n"
+        );
+        fs::remove_file(input).unwrap();
+        fs::remove_file(output).unwrap();
+    }
+
+    #[test]
+    fn test_run_strict_exit_code() {
+        let input = write_input(
+            "strict.csv",
+            "type, client, tx, amount\ndeposit, 1, 1, 2.5\nwithdrawal, 1, 2, 5.0\n",
+        );
+        let output = temp_path("strict.out");
+        let args = [
+            "transaction",
+            "-q",
+            "--force",
+            "-o",
+            output.to_str().unwrap(),
+        ];
+
+        let lenient = Cli::try_parse_from(args.iter().copied().chain([input.to_str().unwrap()]));
+        assert_eq!(run(lenient.unwrap()).unwrap(), 0);
+
+        let strict = Cli::try_parse_from(
+            args.iter()
+                .copied()
+                .chain(["--strict", input.to_str().unwrap()]),
+        );
+        let error = run(strict.unwrap()).unwrap_err();
+        assert!(
+            format!("{:#}", error).contains("Row 2 rejected: "),
+            "{error:#}"
+        );
+        fs::remove_file(input).unwrap();
+        fs::remove_file(output).unwrap();
+    }
+
+    #[test]
+    fn test_run_max_errors() {
+        let input = write_input(
+            "max_errors.csv",
+            "type, client, tx, amount
+             withdrawal, 1, 1, 1.0
+             deposit, 1, 2, 2.5
+             deposit, x, 3, 1.0
+             withdrawal, 1, 4, 5.0
+             deposit, 1, 5, 1.0
+",
+        );
+        let output = temp_path("max_errors.out");
+        let args = |max_errors: &'static str| {
+            Cli::try_parse_from([
+                "transaction",
+                "-q",
+                "--max-errors",
+                max_errors,
+                "--force",
+                "-o",
+                output.to_str().unwrap(),
+                input.to_str().unwrap(),
+            ])
+            .unwrap()
+        };
+
+        assert_eq!(run(args("3")).unwrap(), 0);
+        let error = run(args("2")).unwrap_err();
+        assert!(
+            format!("{:#}", error).ends_with("aborted at row 4, more than 2 rows failed"),
+            "{error:#}"
+        );
+        fs::remove_file(input).unwrap();
+        fs::remove_file(output).unwrap();
+    }
+
+    #[test]
+    fn test_run_statement() {
+        let input = write_input(
+            "statement.csv",
+            "type, client, tx, amount\ndeposit, 1, 1, 2.5\ndispute, 1, 1,\n",
+        );
+        let output = temp_path("statement.out");
+        let args = |client: &'static str| {
+            Cli::try_parse_from([
+                "transaction",
+                "--statement",
+                client,
+                "--force",
+                "-o",
+                output.to_str().unwrap(),
+                input.to_str().unwrap(),
+            ])
+            .unwrap()
+        };
+
+        assert_eq!(run(args("1")).unwrap(), 0);
+        let statement = fs::read_to_string(&output).unwrap();
+        assert!(statement.starts_with("Statement for client 1\n"));
+        assert!(statement.ends_with("available 0.0000, held 2.5000, total 2.5000\n"));
+
+        let error = run(args("2")).unwrap_err();
+        assert!(error.to_string().contains("no account for client 2"));
+        fs::remove_file(input).unwrap();
+        fs::remove_file(output).unwrap();
+    }
+
+    #[test]
+    fn test_run_bad_header() {
+        let input = write_input(
+            "header.csv",
+            "typ, client, tx, amount\ndeposit, 1, 1, 2.5\n",
+        );
+        let cli = Cli::try_parse_from(["transaction", input.to_str().unwrap()]).unwrap();
+
+        let error = format!("{:#}", run(cli).unwrap_err());
+        assert!(error.contains("failed to read"));
+        assert!(error.contains("missing column `type`"));
+        fs::remove_file(input).unwrap();
+    }
+
+    #[test]
+    fn test_run_missing_input() {
+        let cli = Cli::try_parse_from(["transaction", "/nonexistent/transactions.csv"]).unwrap();
+
+        let error = run(cli).unwrap_err();
+        assert!(error.to_string().contains("failed to open"));
+    }
+
+    #[test]
+    fn test_run_existing_output() {
+        let input = write_input(
+            "existing.csv",
+            "type, client, tx, amount\ndeposit, 1, 1, 2.5\n",
+        );
+        let output = write_input("existing.out", "keep me\n");
+        let args = ["transaction", "-o", output.to_str().unwrap()];
+
+        let cli = Cli::try_parse_from(args.iter().copied().chain([input.to_str().unwrap()]));
+        let error = run(cli.unwrap()).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "{} already exists, pass --force to overwrite it",
+                output.display()
+            )
+        );
+        assert_eq!(fs::read_to_string(&output).unwrap(), "keep me\n");
+
+        let cli = Cli::try_parse_from(
+            args.iter()
+                .copied()
+                .chain(["--force", input.to_str().unwrap()]),
+        );
+        assert_eq!(run(cli.unwrap()).unwrap(), 0);
+        assert!(
+            fs::read_to_string(&output)
+                .unwrap()
+                .contains("1, 2.5000, 0.0000, 2.5000, false")
+        );
+        fs::remove_file(input).unwrap();
+        fs::remove_file(output).unwrap();
+    }
+
+    #[test]
+    fn test_create_output_errors() {
+        let missing = temp_path("no-such-dir").join("report.csv");
+        let error = create_output(&missing, true).unwrap_err();
+        assert!(
+            format!("{:#}", error).starts_with("failed to create"),
+            "{error:#}"
+        );
+
+        let dir = temp_path("output-dir");
+        fs::create_dir_all(&dir).unwrap();
+        let error = create_output(&dir, false).unwrap_err();
+        assert!(error.to_string().contains("already exists"), "{error:#}");
+        fs::remove_dir(dir).unwrap();
+    }
+
+    #[test]
+    fn test_run_multiple_inputs() {
+        let first = write_input(
+            "first.csv",
+            "type, client, tx, amount\ndeposit, 1, 1, 2.5\n",
+        );
+        let second = write_input(
+            "second.csv",
+            "type, client, tx, amount\nwithdrawal, 1, 2, 1.0\n",
+        );
+        let missing = temp_path("missing.csv");
+        let output = temp_path("multiple.out");
+        let args = |strict: bool| {
+            let mut args = vec![
+                "transaction",
+                "-q",
+                "--force",
+                "-o",
+                output.to_str().unwrap(),
+            ];
+            if strict {
+                args.push("--strict");
+            }
+            args.extend([
+                first.to_str().unwrap(),
+                missing.to_str().unwrap(),
+                second.to_str().unwrap(),
+            ]);
+            Cli::try_parse_from(args).unwrap()
+        };
+
+        assert_eq!(run(args(false)).unwrap(), 0);
+        assert!(
+            fs::read_to_string(&output)
+                .unwrap()
+                .contains("1, 1.5000, 0.0000, 1.5000, false")
+        );
+
+        let error = run(args(true)).unwrap_err();
+        assert!(error.to_string().contains("missing.csv"));
+        fs::remove_file(first).unwrap();
+        fs::remove_file(second).unwrap();
+        fs::remove_file(output).unwrap();
+    }
 }
@@ -0,0 +1,126 @@
+use rust_decimal::Decimal;
+
+use crate::account::LockReason;
+use crate::error::PaymentError;
+use crate::ids::{ClientId, TxId};
+
+/// Hooks called by `PaymentEngine` as operations are applied, e.g. to export
+/// metrics. Every hook does nothing by default.
+///
+/// Hooks run after the operation took effect, so an observer only sees
+/// successful operations through them; every rejected operation is reported
+/// to `on_reject` instead.
+pub trait EngineObserver: Send {
+    fn on_deposit(&mut self, _client: ClientId, _tx_id: TxId, _amount: Decimal) {}
+
+    /// `amount` is the amount actually withdrawn, after any clamping.
+    fn on_withdrawal(&mut self, _client: ClientId, _tx_id: TxId, _amount: Decimal) {}
+
+    /// Called before `on_withdrawal` when `WithdrawalPolicy::PartialClamp`
+    /// cut a withdrawal of `requested` down to the available `amount`.
+    fn on_clamp(&mut self, _client: ClientId, _tx_id: TxId, _requested: Decimal, _amount: Decimal) {
+    }
+
+    fn on_dispute(&mut self, _client: ClientId, _tx_id: TxId, _amount: Decimal) {}
+
+    /// Called for full and partial resolves with the amount released.
+    fn on_resolve(&mut self, _client: ClientId, _tx_id: TxId, _amount: Decimal) {}
+
+    fn on_chargeback(&mut self, _client: ClientId, _tx_id: TxId, _amount: Decimal) {}
+
+    /// Called after `on_chargeback` when the chargeback locked the account,
+    /// and when a `freeze` row locks it.
+    fn on_lock(&mut self, _client: ClientId, _reason: LockReason) {}
+
+    fn on_reject(&mut self, _error: &PaymentError) {}
+}
+
+#[cfg(all(test, feature = "csv"))]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::entry::TransactionEntry;
+    use crate::payments_engine::PaymentEngine;
+    use crate::processor::{NullSink, process_stream_with_sink};
+    use rust_decimal::dec;
+
+    #[derive(Default, Clone)]
+    struct CountingObserver {
+        calls: Arc<Mutex<HashMap<&'static str, Decimal>>>,
+    }
+
+    impl CountingObserver {
+        fn count(&self, hook: &'static str, amount: Decimal) {
+            *self.calls.lock().unwrap().entry(hook).or_default() += amount;
+        }
+    }
+
+    impl EngineObserver for CountingObserver {
+        fn on_deposit(&mut self, _client: ClientId, _tx_id: TxId, amount: Decimal) {
+            self.count("deposit", amount);
+        }
+
+        fn on_withdrawal(&mut self, _client: ClientId, _tx_id: TxId, amount: Decimal) {
+            self.count("withdrawal", amount);
+        }
+
+        fn on_dispute(&mut self, _client: ClientId, _tx_id: TxId, amount: Decimal) {
+            self.count("dispute", amount);
+        }
+
+        fn on_resolve(&mut self, _client: ClientId, _tx_id: TxId, amount: Decimal) {
+            self.count("resolve", amount);
+        }
+
+        fn on_chargeback(&mut self, _client: ClientId, _tx_id: TxId, amount: Decimal) {
+            self.count("chargeback", amount);
+        }
+
+        fn on_lock(&mut self, _client: ClientId, _reason: LockReason) {
+            self.count("lock", Decimal::ONE);
+        }
+
+        fn on_reject(&mut self, _error: &PaymentError) {
+            self.count("reject", Decimal::ONE);
+        }
+    }
+
+    #[test]
+    fn test_observer_hooks() {
+        let observer = CountingObserver::default();
+        let mut engine = PaymentEngine::new();
+        engine.set_observer(Box::new(observer.clone()));
+
+        let entries = vec![
+            TransactionEntry::deposit(ClientId(1), TxId(1), dec!(10.0)),
+            TransactionEntry::deposit(ClientId(1), TxId(2), dec!(5.0)),
+            TransactionEntry::withdrawal(ClientId(1), TxId(3), dec!(2.0)),
+            TransactionEntry::withdrawal(ClientId(1), TxId(4), dec!(100.0)),
+            TransactionEntry::dispute(ClientId(1), TxId(1)),
+            TransactionEntry::partial_resolve(ClientId(1), TxId(1), dec!(4.0)),
+            TransactionEntry::resolve(ClientId(1), TxId(1)),
+            TransactionEntry::resolve(ClientId(1), TxId(1)),
+            TransactionEntry::dispute(ClientId(1), TxId(2)),
+            TransactionEntry::chargeback(ClientId(1), TxId(2)),
+            TransactionEntry::deposit(ClientId(1), TxId(5), dec!(1.0)),
+            TransactionEntry::dispute(ClientId(2), TxId(9)),
+        ];
+        process_stream_with_sink(&mut engine, entries.into_iter(), &mut NullSink);
+
+        let calls = observer.calls.lock().unwrap().clone();
+        let expected: HashMap<&'static str, Decimal> = [
+            ("deposit", dec!(15.0)),
+            ("withdrawal", dec!(2.0)),
+            ("dispute", dec!(15.0)),
+            ("resolve", dec!(10.0)),
+            ("chargeback", dec!(5.0)),
+            ("lock", dec!(1)),
+            ("reject", dec!(4)),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(calls, expected);
+    }
+}
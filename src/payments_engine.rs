@@ -1,23 +1,211 @@
-use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, VecDeque};
 use std::fmt;
 use std::fmt::Display;
+use std::io;
+use std::io::Write;
 
 use rust_decimal::Decimal;
 use rust_decimal::dec;
+#[cfg(feature = "csv")]
+use serde::Serialize;
 
-use crate::account::Account;
-use crate::error::PaymentError;
+#[cfg(feature = "csv")]
+use crate::account::AccountView;
+pub use crate::account::Accounts;
+use crate::account::{Account, Balances, DEFAULT_SCALE, LockReason, LockState};
+use crate::config::{
+    CrossClientTxIdPolicy, DuplicatePolicy, EngineConfig, RetentionPolicy, RoundingStrategy,
+    WithdrawalPolicy,
+};
+use crate::currency::Currency;
+#[cfg(feature = "csv")]
+use crate::entry::TransactionEntry;
+use crate::error::{LimitKind, MergeError, PaymentError};
+use crate::ids::{ClientId, TxId};
+use crate::observer::EngineObserver;
+#[cfg(feature = "csv")]
+use crate::processor::process_entry;
+use crate::statement::{LedgerEntry, LedgerKind};
+use crate::transaction::StatusHistory;
 use crate::transaction::Transaction;
 use crate::transaction::TransactionStatus;
 use crate::transaction::TransactionType;
 
-pub type Accounts = HashMap<u16, Account>;
-pub type AccountTransactions = HashMap<u32, Transaction>;
-pub type Transactions = HashMap<u16, HashMap<u32, Transaction>>;
+pub type AccountTransactions = HashMap<TxId, Transaction>;
+pub type Transactions = HashMap<ClientId, HashMap<TxId, Transaction>>;
 
 pub struct PaymentEngine {
     pub accounts: Accounts,
     pub transactions: Transactions,
+    /// Fees charged to each client, as synthetic transactions carrying the tx id
+    /// of the chargeback that incurred them.
+    pub fees: HashMap<ClientId, Vec<Transaction>>,
+    /// Net effect on each client's total, per currency, of the transactions
+    /// evicted under `settled_retention` or the retention window, so that the
+    /// audit still adds up.
+    pub(crate) evicted: HashMap<(ClientId, Option<Currency>), Decimal>,
+    /// Charged back tx ids per client in the order they were charged back,
+    /// kept only while `settled_retention` is set.
+    settled: HashMap<ClientId, VecDeque<TxId>>,
+    /// Deposits and withdrawals in the order they were applied, with their
+    /// sequence numbers, kept only under `RetentionPolicy::Window`.
+    window: VecDeque<(u64, ClientId, TxId)>,
+    /// Highest tx id evicted per client under `settled_retention` or the
+    /// retention window. Every unknown tx id up to it counts as evicted, so
+    /// that evictions are reported as such and their ids not reused while
+    /// memory stays bounded by the number of clients.
+    evicted_up_to: HashMap<ClientId, TxId>,
+    /// Withdrawals rejected for insufficient funds and never applied, per
+    /// client with an account; see `allow_retry_of_failed_tx`.
+    failed: HashMap<ClientId, FailedAttempts>,
+    /// Sum of each client's applied withdrawals per currency, checked against
+    /// `max_total_withdrawal_per_client`.
+    withdrawn: HashMap<(ClientId, Option<Currency>), Decimal>,
+    /// Client that first applied each deposit or withdrawal tx id, kept only
+    /// when `cross_client_tx_ids` is not `Allow`.
+    tx_owners: HashMap<TxId, ClientId>,
+    /// Applied transactions that reused another client's tx id, under
+    /// `CrossClientTxIdPolicy::Report`.
+    reused_tx_ids: Vec<TxIdReuse>,
+    pub config: EngineConfig,
+    /// Balance movements per client, in application order, kept only when
+    /// `record_statements` is set; see `statement`.
+    pub(crate) ledger: HashMap<ClientId, Vec<LedgerEntry>>,
+    next_sequence: u64,
+    /// Notified of every applied or rejected operation; see `set_observer`.
+    observer: Option<Box<dyn EngineObserver>>,
+}
+
+/// A tx id applied for `second_client` after `first_client` had used it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TxIdReuse {
+    pub tx_id: TxId,
+    pub first_client: ClientId,
+    pub second_client: ClientId,
+}
+
+/// Step of a dispute, each moving the held funds of the disputed transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DisputeStep {
+    /// Dispute: the amount becomes held.
+    Hold,
+    /// Resolve: the held amount is released, in full or in part.
+    Release,
+    /// Chargeback: the held amount is reversed and the dispute closed.
+    Reverse,
+}
+
+impl DisputeStep {
+    fn ledger_kind(self) -> LedgerKind {
+        match self {
+            DisputeStep::Hold => LedgerKind::Dispute,
+            DisputeStep::Release => LedgerKind::Resolve,
+            DisputeStep::Reverse => LedgerKind::Chargeback,
+        }
+    }
+}
+
+/// Balance changes of one dispute step; `available + held` always moves by
+/// `total`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct BalanceDelta {
+    available: Decimal,
+    held: Decimal,
+    total: Decimal,
+}
+
+impl BalanceDelta {
+    /// A disputed deposit is held out of the available funds, and a chargeback
+    /// takes it off the total. A disputed withdrawal is credited back as held,
+    /// so resolving it takes the total down again while a chargeback returns
+    /// the funds to the client.
+    fn dispute(step: DisputeStep, withdrawal: bool, amount: Decimal) -> Self {
+        let zero = Decimal::ZERO;
+        let (available, held, total) = match (step, withdrawal) {
+            (DisputeStep::Hold, false) => (-amount, amount, zero),
+            (DisputeStep::Hold, true) => (zero, amount, amount),
+            (DisputeStep::Release, false) => (amount, -amount, zero),
+            (DisputeStep::Release, true) => (zero, -amount, -amount),
+            (DisputeStep::Reverse, false) => (zero, -amount, -amount),
+            (DisputeStep::Reverse, true) => (amount, -amount, zero),
+        };
+        BalanceDelta {
+            available,
+            held,
+            total,
+        }
+    }
+}
+
+/// Outcome of `apply_dispute_transition`.
+struct DisputeTransition {
+    amount: Decimal,
+    currency: Option<Currency>,
+    sequence: u64,
+}
+
+/// Last rejected attempt of each tx id of one client, bounded by
+/// `PaymentEngine::failed_retention`.
+#[derive(Debug, Clone, Default)]
+struct FailedAttempts {
+    attempts: HashMap<TxId, Transaction>,
+    /// Tx ids oldest first, kept only while the attempts are bounded.
+    order: VecDeque<TxId>,
+}
+
+impl FailedAttempts {
+    fn get(&self, tx_id: TxId) -> Option<&Transaction> {
+        self.attempts.get(&tx_id)
+    }
+
+    /// Keeps `attempt`, evicting the oldest attempts beyond `bound`.
+    fn insert(&mut self, attempt: Transaction, bound: Option<usize>) {
+        let tx_id = attempt.tx_id;
+        if self.attempts.insert(tx_id, attempt).is_some() {
+            return;
+        }
+        let Some(bound) = bound else {
+            return;
+        };
+        self.order.push_back(tx_id);
+        while self.order.len() > bound {
+            if let Some(evicted) = self.order.pop_front() {
+                self.attempts.remove(&evicted);
+            }
+        }
+    }
+
+    fn remove(&mut self, tx_id: TxId) {
+        if self.attempts.remove(&tx_id).is_some() {
+            self.order.retain(|&failed| failed != tx_id);
+        }
+    }
+
+    /// Adds the attempts of `other` after the ones already kept.
+    fn extend(&mut self, other: FailedAttempts, bound: Option<usize>) {
+        let FailedAttempts {
+            mut attempts,
+            order,
+        } = other;
+        for tx_id in order {
+            if let Some(attempt) = attempts.remove(&tx_id) {
+                self.insert(attempt, bound);
+            }
+        }
+        for attempt in attempts.into_values() {
+            self.insert(attempt, bound);
+        }
+    }
+}
+
+/// What checking a deposit or withdrawal found: how it moves the balances, the
+/// client that first used its tx id, and the requested amount if it was
+/// clamped.
+struct Admission {
+    delta: BalanceDelta,
+    first_client: Option<ClientId>,
+    clamped_from: Option<Decimal>,
 }
 
 impl Default for PaymentEngine {
@@ -28,560 +216,4808 @@ impl Default for PaymentEngine {
 
 impl PaymentEngine {
     pub fn new() -> Self {
+        Self::with_config(EngineConfig::default())
+    }
+
+    pub fn with_config(config: EngineConfig) -> Self {
         PaymentEngine {
             accounts: Accounts::new(),
             transactions: Transactions::new(),
+            fees: HashMap::new(),
+            evicted: HashMap::new(),
+            settled: HashMap::new(),
+            window: VecDeque::new(),
+            evicted_up_to: HashMap::new(),
+            failed: HashMap::new(),
+            withdrawn: HashMap::new(),
+            tx_owners: HashMap::new(),
+            reused_tx_ids: Vec::new(),
+            config,
+            ledger: HashMap::new(),
+            next_sequence: 0,
+            observer: None,
         }
     }
 
+    /// Reports every operation applied from now on to `observer`, replacing
+    /// any previous one.
+    pub fn set_observer(&mut self, observer: Box<dyn EngineObserver>) {
+        self.observer = Some(observer);
+    }
+
+    #[inline]
+    fn observe(&mut self, hook: impl FnOnce(&mut dyn EngineObserver)) {
+        if let Some(observer) = self.observer.as_deref_mut() {
+            hook(observer);
+        }
+    }
+
+    /// Reports a rejected operation to the observer and hands the error back.
+    #[inline]
+    pub(crate) fn rejected(&mut self, error: PaymentError) -> PaymentError {
+        #[cfg(feature = "tracing")]
+        tracing::warn!(code = error.code(), "rejected: {}", error);
+        self.observe(|observer| observer.on_reject(&error));
+        error
+    }
+
+    /// Ends an operation: evicts the transactions that left the retention
+    /// window and reports a rejection to the observer.
+    #[inline]
+    fn complete(&mut self, result: Result<(), PaymentError>) -> Result<(), PaymentError> {
+        self.evict_window();
+        result.map_err(|error| self.rejected(error))
+    }
+
     #[inline]
     fn update_account_balance(
         &mut self,
-        account_id: u16,
+        account_id: ClientId,
+        tx_id: TxId,
+        currency: Option<Currency>,
         available_delta: Decimal,
         held_delta: Decimal,
         total_delta: Decimal,
     ) -> Result<(), PaymentError> {
-        if let Some(account) = self.accounts.get_mut(&account_id) {
-            if (account.available + available_delta) < dec!(0)
-                || (account.held + held_delta) < dec!(0)
-                || (account.total + total_delta) < dec!(0)
-            {
-                return Err(PaymentError::InsufficientFunds);
-            }
-            account.available += available_delta;
-            account.held += held_delta;
-            account.total += total_delta;
-            Ok(())
-        } else {
-            Err(PaymentError::AccountNotFound(account_id))
+        let account = self
+            .accounts
+            .get_mut(&account_id)
+            .ok_or(PaymentError::AccountNotFound(account_id))?;
+        Self::apply_balance_delta(
+            account,
+            tx_id,
+            currency,
+            available_delta,
+            held_delta,
+            total_delta,
+        )
+    }
+
+    /// Moves the balances of `account` in `currency`, on an account the caller
+    /// already looked up.
+    #[inline]
+    fn apply_balance_delta(
+        account: &mut Account,
+        tx_id: TxId,
+        currency: Option<Currency>,
+        available_delta: Decimal,
+        held_delta: Decimal,
+        total_delta: Decimal,
+    ) -> Result<(), PaymentError> {
+        let mut balances = account.balances(currency);
+        // The total follows from available and held; it is not checked on its
+        // own since fees may legitimately drive it negative. A balance already
+        // negative because of a fee may still increase.
+        if (available_delta < dec!(0) && balances.available < -available_delta)
+            || balances.held < -held_delta
+        {
+            return Err(PaymentError::InsufficientFunds {
+                client: account.client,
+                tx_id,
+            });
         }
+        let precision_loss = || PaymentError::PrecisionLoss {
+            client: account.client,
+            tx_id,
+        };
+        balances = exact_add_balances(balances, available_delta, held_delta, total_delta)
+            .ok_or_else(precision_loss)?;
+        account.set_balances(currency, balances);
+        Ok(())
     }
 
     #[inline]
     fn update_transaction_status(
         &mut self,
-        account_id: u16,
-        tx_id: u32,
+        account_id: ClientId,
+        tx_id: TxId,
         new_status: TransactionStatus,
     ) -> Result<(), PaymentError> {
-        let account_transactions = self
-            .transactions
-            .get_mut(&account_id)
-            .ok_or(PaymentError::TransactionNotFound)?;
+        let account_transactions =
+            self.transactions
+                .get_mut(&account_id)
+                .ok_or(PaymentError::TransactionNotFound {
+                    client: account_id,
+                    tx_id,
+                })?;
 
         if let Some(existing_transaction) = account_transactions.get_mut(&tx_id) {
-            existing_transaction.status = new_status;
+            existing_transaction.set_status(new_status);
             Ok(())
         } else {
-            Err(PaymentError::TransactionNotFound)
+            Err(PaymentError::TransactionNotFound {
+                client: account_id,
+                tx_id,
+            })
         }
     }
 
+    #[inline]
+    fn update_disputed_amount(
+        &mut self,
+        account_id: ClientId,
+        tx_id: TxId,
+        disputed_amount: Decimal,
+    ) -> Result<(), PaymentError> {
+        let existing_transaction = self
+            .transactions
+            .get_mut(&account_id)
+            .and_then(|a| a.get_mut(&tx_id))
+            .ok_or(PaymentError::TransactionNotFound {
+                client: account_id,
+                tx_id,
+            })?;
+
+        existing_transaction.disputed_amount = disputed_amount;
+        Ok(())
+    }
+
     #[inline]
     pub fn get_deposit_transaction_status(
         &self,
-        account_id: u16,
-        tx_id: u32,
+        account_id: ClientId,
+        tx_id: TxId,
     ) -> Result<&Transaction, PaymentError> {
-        let account_transactions = self
-            .transactions
-            .get(&account_id)
-            .ok_or(PaymentError::TransactionNotFound)?;
+        let Some(transaction) = self.get_transaction(account_id, tx_id) else {
+            return Err(self.transaction_not_found(account_id, tx_id));
+        };
 
-        if let Some(transaction) = account_transactions.get(&tx_id) {
-            if transaction.tx_type != TransactionType::Deposit {
-                return Err(PaymentError::InvalidTransactionType);
-            }
-            Ok(transaction)
-        } else {
-            Err(PaymentError::TransactionNotFound)
+        if transaction.tx_type != TransactionType::Deposit {
+            return Err(PaymentError::InvalidTransactionType(tx_id));
+        }
+        Ok(transaction)
+    }
+
+    /// Deposit, or withdrawal under `allow_withdrawal_disputes`, that a
+    /// dispute, resolve or chargeback refers to.
+    #[inline]
+    fn get_disputable_transaction(
+        &self,
+        account_id: ClientId,
+        tx_id: TxId,
+    ) -> Result<&Transaction, PaymentError> {
+        if !self.config.allow_withdrawal_disputes {
+            return self.get_deposit_transaction_status(account_id, tx_id);
+        }
+        let Some(transaction) = self.get_transaction(account_id, tx_id) else {
+            return Err(self.transaction_not_found(account_id, tx_id));
+        };
+        if transaction.tx_type == TransactionType::Fee {
+            return Err(PaymentError::InvalidTransactionType(tx_id));
+        }
+        Ok(transaction)
+    }
+
+    /// Tells a transaction stored under another client apart from one that
+    /// does not exist at all.
+    #[inline]
+    fn transaction_not_found(&self, account_id: ClientId, tx_id: TxId) -> PaymentError {
+        if self.is_evicted(account_id, tx_id) {
+            return PaymentError::TransactionEvicted(tx_id);
         }
+        self.find_transaction(tx_id).map_or(
+            PaymentError::TransactionNotFound {
+                client: account_id,
+                tx_id,
+            },
+            |(&expected, _)| PaymentError::AccountMismatch {
+                expected,
+                actual: account_id,
+            },
+        )
     }
 
     #[inline]
-    fn check_transaction(&self, account_id: u16, tx_id: u32) -> bool {
+    fn get_transaction(&self, account_id: ClientId, tx_id: TxId) -> Option<&Transaction> {
         self.transactions
             .get(&account_id)
             .and_then(|a| a.get(&tx_id))
-            .is_some()
     }
 
     #[inline]
-    fn get_or_create_account(&mut self, account_id: u16) -> &Account {
-        (self.accounts.entry(account_id).or_insert(Account {
-            client: account_id,
-            available: Decimal::ZERO,
-            held: Decimal::ZERO,
-            total: Decimal::ZERO,
-            locked: false,
-        })) as _
+    fn check_duplicate(
+        &self,
+        stored: &Transaction,
+        incoming: &Transaction,
+    ) -> Result<(), PaymentError> {
+        let identical = stored.tx_type == incoming.tx_type
+            && stored.amount == incoming.amount
+            && stored.currency == incoming.currency;
+
+        let already_exists = PaymentError::TransactionAlreadyExists {
+            client: incoming.account_id,
+            tx_id: incoming.tx_id,
+        };
+        match self.config.duplicate_policy {
+            DuplicatePolicy::Reject => Err(already_exists),
+            DuplicatePolicy::SkipIdentical if identical => Ok(()),
+            DuplicatePolicy::RejectMismatch if identical => Err(already_exists),
+            _ => Err(PaymentError::DuplicateWithDifferentAmount {
+                tx_id: incoming.tx_id,
+                stored: stored.amount,
+                incoming: incoming.amount,
+            }),
+        }
+    }
+
+    /// Remembers a withdrawal rejected for insufficient funds, so that a retry
+    /// can be checked against it. Clients without an account, e.g. refused by
+    /// `max_accounts`, leave nothing behind.
+    fn record_failure(&mut self, attempt: Transaction, error: &PaymentError) {
+        if !matches!(error, PaymentError::InsufficientFunds { .. })
+            || !self.accounts.contains_key(&attempt.account_id)
+        {
+            return;
+        }
+        let bound = self.failed_retention();
+        self.failed
+            .entry(attempt.account_id)
+            .or_default()
+            .insert(attempt, bound);
     }
 
+    /// Number of failed attempts kept per client: the smaller of
+    /// `settled_retention` and the retention window, unbounded when neither is
+    /// set.
     #[inline]
-    fn insert_transaction(&mut self, transaction: Transaction) {
-        let account_transactions = self.transactions.entry(transaction.account_id).or_default();
-        account_transactions.insert(transaction.tx_id, transaction);
+    fn failed_retention(&self) -> Option<usize> {
+        let window = match self.config.retention_policy {
+            RetentionPolicy::Window(window) => Some(window),
+            RetentionPolicy::KeepAll => None,
+        };
+        match (self.config.settled_retention, window) {
+            (Some(settled), Some(window)) => Some(settled.min(window)),
+            (settled, window) => settled.or(window),
+        }
     }
 
+    /// A retry must repeat the rejected attempt exactly, and is only allowed
+    /// with `allow_retry_of_failed_tx`.
     #[inline]
-    fn lock_account(&mut self, account_id: u16) {
-        if let Some(account) = self.accounts.get_mut(&account_id) {
-            account.locked = true;
+    fn check_retry(&self, failed: &Transaction, retry: &Transaction) -> Result<(), PaymentError> {
+        if !self.config.allow_retry_of_failed_tx {
+            return Err(PaymentError::TransactionAlreadyExists {
+                client: retry.account_id,
+                tx_id: retry.tx_id,
+            });
         }
+        if failed.tx_type != retry.tx_type
+            || failed.amount != retry.amount
+            || failed.currency != retry.currency
+        {
+            return Err(PaymentError::DuplicateWithDifferentAmount {
+                tx_id: retry.tx_id,
+                stored: failed.amount,
+                incoming: retry.amount,
+            });
+        }
+        Ok(())
     }
 
+    /// Under `CrossClientTxIdPolicy::Reject`, rejects a deposit or withdrawal
+    /// reusing another client's tx id; otherwise returns that client, if any.
     #[inline]
-    fn is_account_locked(&self, account_id: u16) -> bool {
-        self.accounts
-            .get(&account_id)
-            .map(|a| a.locked)
-            .unwrap_or(false)
+    fn check_tx_owner(&self, transaction: &Transaction) -> Result<Option<ClientId>, PaymentError> {
+        let policy = self.config.cross_client_tx_ids;
+        if policy == CrossClientTxIdPolicy::Allow {
+            return Ok(None);
+        }
+        match self.tx_owners.get(&transaction.tx_id) {
+            Some(&first_client) if first_client != transaction.account_id => {
+                if policy == CrossClientTxIdPolicy::Reject {
+                    return Err(PaymentError::TxIdReusedAcrossClients {
+                        tx_id: transaction.tx_id,
+                        first_client,
+                        second_client: transaction.account_id,
+                    });
+                }
+                Ok(Some(first_client))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    #[inline]
+    fn check_account_limit(&self, account_id: ClientId) -> Result<(), PaymentError> {
+        if !self.accounts.contains_key(&account_id)
+            && self
+                .config
+                .max_accounts
+                .is_some_and(|max_accounts| self.accounts.len() >= max_accounts)
+        {
+            return Err(PaymentError::AccountLimitExceeded(account_id));
+        }
+        Ok(())
     }
 
-    pub fn process_transaction(&mut self, transaction: Transaction) -> Result<(), PaymentError> {
-        let account = self.get_or_create_account(transaction.account_id);
+    #[inline]
+    fn get_or_create_account(&mut self, account_id: ClientId) -> Result<&Account, PaymentError> {
+        self.check_account_limit(account_id)?;
 
-        let account_available = account.available;
+        let config = &self.config;
+        Ok(self
+            .accounts
+            .entry(account_id)
+            .or_insert_with(|| Self::new_account(config, account_id)))
+    }
 
-        if self.is_account_locked(transaction.account_id) {
-            return Err(PaymentError::AccountLocked(transaction.account_id));
+    /// Empty account at the client's configured scale.
+    #[inline]
+    fn new_account(config: &EngineConfig, account_id: ClientId) -> Account {
+        Account {
+            scale: config
+                .account_scales
+                .get(&account_id)
+                .copied()
+                .unwrap_or(DEFAULT_SCALE),
+            ..Account::new(account_id)
         }
+    }
+
+    #[inline]
+    fn take_sequence(&mut self) -> u64 {
+        let sequence = self.next_sequence;
+        self.next_sequence += 1;
+        sequence
+    }
 
-        if self.check_transaction(transaction.account_id, transaction.tx_id) {
-            return Err(PaymentError::TransactionAlreadyExists);
+    #[inline]
+    fn record_ledger(
+        &mut self,
+        account_id: ClientId,
+        kind: LedgerKind,
+        tx_id: TxId,
+        currency: Option<Currency>,
+        amount: Decimal,
+        available_delta: Decimal,
+    ) -> u64 {
+        let sequence = self.take_sequence();
+        if !self.config.record_statements {
+            return sequence;
         }
+        self.ledger
+            .entry(account_id)
+            .or_default()
+            .push(LedgerEntry {
+                sequence,
+                kind,
+                tx_id,
+                currency,
+                amount,
+                available_delta,
+            });
+        sequence
+    }
 
-        let (available_delta, held_delta, total_delta) = match transaction.tx_type {
-            TransactionType::Deposit => (transaction.amount, Decimal::ZERO, transaction.amount),
-            TransactionType::Withdrawal => {
-                if account_available >= transaction.amount {
-                    (-transaction.amount, Decimal::ZERO, -transaction.amount)
-                } else {
-                    return Err(PaymentError::InsufficientFunds);
-                }
-            }
+    #[inline]
+    fn insert_transaction(&mut self, mut transaction: Transaction) {
+        let kind = match transaction.tx_type {
+            TransactionType::Deposit => LedgerKind::Deposit,
+            TransactionType::Withdrawal => LedgerKind::Withdrawal,
+            TransactionType::Fee => LedgerKind::Fee,
         };
-
-        self.update_account_balance(
+        let available_delta = match transaction.tx_type {
+            TransactionType::Deposit => transaction.amount,
+            TransactionType::Withdrawal | TransactionType::Fee => -transaction.amount,
+        };
+        transaction.sequence = self.record_ledger(
             transaction.account_id,
+            kind,
+            transaction.tx_id,
+            transaction.currency,
+            transaction.amount,
             available_delta,
-            held_delta,
-            total_delta,
-        )?;
-        self.insert_transaction(transaction);
-        Ok(())
-    }
+        );
 
-    pub fn process_dispute(&mut self, account_id: u16, tx_id: u32) -> Result<(), PaymentError> {
-        if self.is_account_locked(account_id) {
-            return Err(PaymentError::AccountLocked(account_id));
+        if let RetentionPolicy::Window(_) = self.config.retention_policy {
+            self.window.push_back((
+                transaction.sequence,
+                transaction.account_id,
+                transaction.tx_id,
+            ));
         }
+        let account_transactions = self.transactions.entry(transaction.account_id).or_default();
+        account_transactions.insert(transaction.tx_id, transaction);
+    }
 
-        let existing_transaction = self.get_deposit_transaction_status(account_id, tx_id)?;
-        if existing_transaction.status == TransactionStatus::Completed {
-            let amount = existing_transaction.amount;
-            if let Some(account) = self.accounts.get(&account_id) {
-                if account.available < amount {
-                    return Err(PaymentError::InsufficientHoldFunds);
-                }
-            } else {
-                return Err(PaymentError::AccountNotFound(account_id));
+    /// Drops the completed and resolved transactions followed by more
+    /// operations than the retention window holds, counting from their last
+    /// resolve. Disputed ones stay until resolved; charged back ones until
+    /// `settle` evicts them.
+    fn evict_window(&mut self) {
+        let RetentionPolicy::Window(window) = self.config.retention_policy else {
+            return;
+        };
+        while let Some(&(sequence, account_id, tx_id)) = self.window.front() {
+            if self.next_sequence - sequence <= window as u64 {
+                break;
+            }
+            self.window.pop_front();
+            let Some(transactions) = self.transactions.get_mut(&account_id) else {
+                continue;
+            };
+            if transactions.get(&tx_id).is_none_or(|transaction| {
+                !matches!(
+                    transaction.status,
+                    TransactionStatus::Completed | TransactionStatus::Resolved
+                )
+            }) {
+                continue;
+            }
+            if let Some(transaction) = transactions.remove(&tx_id) {
+                *self
+                    .evicted
+                    .entry((account_id, transaction.currency))
+                    .or_default() += transaction.net_amount();
+                record_eviction(&mut self.evicted_up_to, account_id, tx_id);
             }
-
-            self.update_account_balance(account_id, -amount, amount, Decimal::ZERO)?;
-            self.update_transaction_status(account_id, tx_id, TransactionStatus::Disputed)?;
-            Ok(())
-        } else {
-            Err(PaymentError::TransactionAlreadyDisputed)
         }
     }
 
-    pub fn process_resolve(&mut self, account_id: u16, tx_id: u32) -> Result<(), PaymentError> {
-        if self.is_account_locked(account_id) {
-            return Err(PaymentError::AccountLocked(account_id));
-        }
-
-        let existing_transaction = self.get_deposit_transaction_status(account_id, tx_id)?;
+    /// Whether a tx id the client does not hold may have been evicted.
+    #[inline]
+    fn is_evicted(&self, account_id: ClientId, tx_id: TxId) -> bool {
+        self.evicted_up_to
+            .get(&account_id)
+            .is_some_and(|&highest| tx_id <= highest)
+    }
 
-        if existing_transaction.status != TransactionStatus::Disputed {
-            if existing_transaction.status == TransactionStatus::Resolved
-                || existing_transaction.status == TransactionStatus::Chargebacked
+    /// Records a charged back transaction, which can never be disputed again,
+    /// and evicts the oldest ones beyond `settled_retention`.
+    fn settle(&mut self, account_id: ClientId, tx_id: TxId) {
+        let Some(retention) = self.config.settled_retention else {
+            return;
+        };
+        let settled = self.settled.entry(account_id).or_default();
+        settled.push_back(tx_id);
+        while settled.len() > retention {
+            let Some(evicted_id) = settled.pop_front() else {
+                break;
+            };
+            if let Some(transaction) = self
+                .transactions
+                .get_mut(&account_id)
+                .and_then(|transactions| transactions.remove(&evicted_id))
             {
-                return Err(PaymentError::TransactionAlreadyDisputed);
-            } else {
-                return Err(PaymentError::TransactionIsNotDisputed);
+                *self
+                    .evicted
+                    .entry((account_id, transaction.currency))
+                    .or_default() += transaction.net_amount();
+                record_eviction(&mut self.evicted_up_to, account_id, evicted_id);
             }
         }
+    }
 
-        let amount = existing_transaction.amount;
-
-        if let Some(account) = self.accounts.get(&account_id) {
-            if account.held < amount {
-                return Err(PaymentError::InsufficientHoldFunds);
-            }
-        } else {
-            return Err(PaymentError::AccountNotFound(account_id));
+    /// Deducts the configured chargeback fee in the currency of the charged
+    /// back transaction, allowing a negative balance, and records it as a fee
+    /// transaction. Fails with `PrecisionLoss`, deducting nothing, if the
+    /// balances cannot hold the result exactly.
+    fn charge_fee(
+        &mut self,
+        account_id: ClientId,
+        tx_id: TxId,
+        currency: Option<Currency>,
+    ) -> Result<(), PaymentError> {
+        let fee = self.config.chargeback_fee;
+        if fee.is_zero() {
+            return Ok(());
         }
-
-        self.update_account_balance(account_id, amount, -amount, Decimal::ZERO)?;
-        self.update_transaction_status(account_id, tx_id, TransactionStatus::Resolved)?;
+        if let Some(account) = self.accounts.get_mut(&account_id) {
+            let balances =
+                exact_add_balances(account.balances(currency), -fee, Decimal::ZERO, -fee).ok_or(
+                    PaymentError::PrecisionLoss {
+                        client: account_id,
+                        tx_id,
+                    },
+                )?;
+            account.set_balances(currency, balances);
+        }
+        let sequence = self.record_ledger(account_id, LedgerKind::Fee, tx_id, currency, fee, -fee);
+        self.fees.entry(account_id).or_default().push(Transaction {
+            tx_type: TransactionType::Fee,
+            account_id,
+            tx_id,
+            amount: fee,
+            currency,
+            disputed_amount: Decimal::ZERO,
+            sequence,
+            timestamp: None,
+            status: TransactionStatus::Completed,
+            history: StatusHistory::default(),
+        });
         Ok(())
     }
 
-    pub fn process_chargeback(&mut self, account_id: u16, tx_id: u32) -> Result<(), PaymentError> {
-        if self.is_account_locked(account_id) {
-            return Err(PaymentError::AccountLocked(account_id));
+    /// Counts a chargeback against the account, returning the new count.
+    #[inline]
+    fn record_chargeback(&mut self, account_id: ClientId) -> u32 {
+        self.accounts
+            .get_mut(&account_id)
+            .map(|account| {
+                account.chargebacks += 1;
+                account.chargebacks
+            })
+            .unwrap_or(0)
+    }
+
+    #[inline]
+    fn lock_account(&mut self, account_id: ClientId, reason: LockReason) {
+        if let Some(account) = self.accounts.get_mut(&account_id) {
+            account.locked = true;
+            account.lock_reason = Some(reason);
         }
+        self.observe(|observer| observer.on_lock(account_id, reason));
+    }
 
-        let existing_transaction = self.get_deposit_transaction_status(account_id, tx_id)?;
+    #[inline]
+    fn account_locked(&self, account_id: ClientId) -> PaymentError {
+        PaymentError::AccountLocked {
+            client: account_id,
+            reason: self
+                .accounts
+                .get(&account_id)
+                .and_then(|account| account.lock_reason),
+        }
+    }
 
-        if existing_transaction.status != TransactionStatus::Disputed {
-            if existing_transaction.status == TransactionStatus::Resolved
-                || existing_transaction.status == TransactionStatus::Chargebacked
-            {
-                return Err(PaymentError::TransactionAlreadyDisputed);
-            } else {
-                return Err(PaymentError::TransactionIsNotDisputed);
-            }
+    /// Disputes opened before the account was locked must still be settled,
+    /// otherwise their funds stay held forever, unless they were frozen by the lock.
+    #[inline]
+    fn check_settlement_lock(&self, account_id: ClientId, tx_id: TxId) -> Result<(), PaymentError> {
+        if !self.is_account_locked(account_id) {
+            return Ok(());
         }
 
-        let amount = existing_transaction.amount;
+        match self.get_transaction(account_id, tx_id).map(|t| &t.status) {
+            Some(TransactionStatus::Disputed | TransactionStatus::PartiallyResolved) => Ok(()),
+            Some(TransactionStatus::Frozen) => Err(PaymentError::TransactionFrozen(tx_id)),
+            _ => Err(self.account_locked(account_id)),
+        }
+    }
 
-        if let Some(account) = self.accounts.get(&account_id) {
-            if account.held < amount {
-                return Err(PaymentError::InsufficientHoldFunds);
-            }
-        } else {
-            return Err(PaymentError::AccountNotFound(account_id));
+    #[inline]
+    fn freeze_open_disputes(&mut self, account_id: ClientId) {
+        if let Some(account_transactions) = self.transactions.get_mut(&account_id) {
+            account_transactions.values_mut().for_each(|t| {
+                if let Ok(status) = t.status.clone().transition(TransactionStatus::Frozen) {
+                    t.set_status(status);
+                }
+            });
         }
+    }
 
-        self.update_account_balance(account_id, Decimal::ZERO, -amount, -amount)?;
-        self.update_transaction_status(account_id, tx_id, TransactionStatus::Chargebacked)?;
-        self.lock_account(account_id);
+    /// Rejects settling an open dispute that has no hold recorded. Resolves and
+    /// chargebacks release exactly the transaction's recorded hold, never its
+    /// amount, so the holds of concurrent disputes cannot be mixed up.
+    #[inline]
+    fn check_hold(account_id: ClientId, transaction: &Transaction) -> Result<(), PaymentError> {
+        let open = matches!(
+            transaction.status,
+            TransactionStatus::Disputed | TransactionStatus::PartiallyResolved
+        );
+        if open && transaction.disputed_amount <= Decimal::ZERO {
+            return Err(PaymentError::MissingHold {
+                client: account_id,
+                tx_id: transaction.tx_id,
+            });
+        }
         Ok(())
     }
-}
-
-impl Display for PaymentEngine {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        writeln!(f, "client, available, held, total, locked")?;
 
-        for account in self.accounts.values() {
-            writeln!(
-                f,
-                "{}, {:.4}, {:.4}, {:.4}, {}",
-                account.client, account.available, account.held, account.total, account.locked
-            )?;
+    /// Under `reject_unknown_clients`, rejects operations other than deposits
+    /// for a client without an account.
+    #[inline]
+    fn check_known_client(&self, account_id: ClientId) -> Result<(), PaymentError> {
+        if self.config.reject_unknown_clients && !self.accounts.contains_key(&account_id) {
+            return Err(PaymentError::AccountNotFound(account_id));
         }
         Ok(())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use rust_decimal::dec;
+    #[inline]
+    fn is_account_locked(&self, account_id: ClientId) -> bool {
+        self.accounts
+            .get(&account_id)
+            .map(|a| a.locked)
+            .unwrap_or(false)
+    }
 
-    #[test]
-    fn test_payment_engine_display() {
-        let mut engine = PaymentEngine::new();
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(client = %transaction.account_id, tx = %transaction.tx_id, kind = ?transaction.tx_type)
+        )
+    )]
+    pub fn process_transaction(
+        &mut self,
+        mut transaction: Transaction,
+    ) -> Result<(), PaymentError> {
+        if transaction.currency == Some(self.config.base_currency) {
+            transaction.currency = None;
+        }
+        let result = self
+            .add_transaction(transaction)
+            .map_err(|(error, attempt)| {
+                self.record_failure(attempt, &error);
+                error
+            });
+        self.complete(result)
+    }
 
-        engine.accounts.insert(
-            1,
-            Account {
-                client: 1,
-                available: dec!(100.1234),
-                held: dec!(50.5678),
-                total: dec!(150.6912),
-                locked: false,
-            },
-        );
+    /// Applies a deposit or withdrawal, handing the transaction back with the
+    /// error when it is rejected.
+    fn add_transaction(
+        &mut self,
+        mut transaction: Transaction,
+    ) -> Result<(), (PaymentError, Transaction)> {
+        let Admission {
+            delta,
+            first_client,
+            clamped_from,
+        } = match self.admit_transaction(&mut transaction) {
+            Ok(Some(admission)) => admission,
+            // A replay of an applied transaction.
+            Ok(None) => return Ok(()),
+            Err(error) => return Err((error, transaction)),
+        };
 
-        engine.accounts.insert(
-            2,
-            Account {
-                client: 2,
-                available: dec!(0.0),
-                held: dec!(25.0),
-                total: dec!(25.0),
-                locked: true,
-            },
-        );
+        // A new account is only inserted once the delta applied to it.
+        let apply = |account: &mut Account| {
+            Self::apply_balance_delta(
+                account,
+                transaction.tx_id,
+                transaction.currency,
+                delta.available,
+                delta.held,
+                delta.total,
+            )
+        };
+        let applied = match self.accounts.entry(transaction.account_id) {
+            Entry::Occupied(entry) => apply(entry.into_mut()),
+            Entry::Vacant(entry) => {
+                let mut account = Self::new_account(&self.config, transaction.account_id);
+                apply(&mut account).map(|()| {
+                    entry.insert(account);
+                })
+            }
+        };
+        if let Err(error) = applied {
+            return Err((error, transaction));
+        }
+        let (client, tx_id, amount) = (
+            transaction.account_id,
+            transaction.tx_id,
+            transaction.amount,
+        );
+        let tx_type = transaction.tx_type.clone();
+        if tx_type == TransactionType::Withdrawal {
+            *self
+                .withdrawn
+                .entry((client, transaction.currency))
+                .or_default() += amount;
+        }
+        self.insert_transaction(transaction);
+        if let Some(failed) = self.failed.get_mut(&client) {
+            failed.remove(tx_id);
+        }
+        if self.config.cross_client_tx_ids != CrossClientTxIdPolicy::Allow {
+            self.tx_owners.entry(tx_id).or_insert(client);
+        }
+        if let Some(first_client) = first_client {
+            self.reused_tx_ids.push(TxIdReuse {
+                tx_id,
+                first_client,
+                second_client: client,
+            });
+        }
+        if let Some(requested) = clamped_from {
+            #[cfg(feature = "tracing")]
+            tracing::warn!(%client, tx = %tx_id, %requested, %amount, "withdrawal clamped");
+            self.observe(|observer| observer.on_clamp(client, tx_id, requested, amount));
+        }
+        self.observe(|observer| match tx_type {
+            TransactionType::Withdrawal => observer.on_withdrawal(client, tx_id, amount),
+            _ => observer.on_deposit(client, tx_id, amount),
+        });
+        Ok(())
+    }
+
+    /// Runs every check of a deposit or withdrawal without changing anything,
+    /// clamping the amount under `WithdrawalPolicy::PartialClamp`; `None` when
+    /// it repeats an applied transaction and is ignored.
+    fn admit_transaction(
+        &self,
+        transaction: &mut Transaction,
+    ) -> Result<Option<Admission>, PaymentError> {
+        if transaction.amount <= Decimal::ZERO {
+            return Err(PaymentError::InvalidAmount(transaction.amount));
+        }
+        // The account is only created once the transaction is known to apply, so
+        // that rejected rows do not leave empty accounts behind. Until then it
+        // is looked up once for every check, and once more to be updated.
+        let (account_available, locked) = match self.accounts.get(&transaction.account_id) {
+            Some(account) => (
+                account.balances(transaction.currency).available,
+                account.locked,
+            ),
+            None => {
+                self.check_account_limit(transaction.account_id)?;
+                if transaction.tx_type == TransactionType::Withdrawal {
+                    self.check_known_client(transaction.account_id)?;
+                }
+                (Decimal::ZERO, false)
+            }
+        };
+
+        let deposit_allowed = self.config.accepts_locked_deposits()
+            && transaction.tx_type == TransactionType::Deposit;
+        if locked && !deposit_allowed {
+            return Err(self.account_locked(transaction.account_id));
+        }
+
+        if let Some(stored) = self.get_transaction(transaction.account_id, transaction.tx_id) {
+            return self.check_duplicate(stored, transaction).map(|()| None);
+        }
+        if let Some(failed) = self
+            .failed
+            .get(&transaction.account_id)
+            .and_then(|failed| failed.get(transaction.tx_id))
+        {
+            self.check_retry(failed, transaction)?;
+        } else if self.is_evicted(transaction.account_id, transaction.tx_id) {
+            // Evicted ids cannot be compared, so they are never reused.
+            return Err(PaymentError::TransactionAlreadyExists {
+                client: transaction.account_id,
+                tx_id: transaction.tx_id,
+            });
+        }
+        let first_client = self.check_tx_owner(transaction)?;
+        if let Some(limit) = self.config.limits.max_tx_amount
+            && transaction.amount > limit
+        {
+            return Err(PaymentError::LimitExceeded {
+                tx_id: transaction.tx_id,
+                kind: LimitKind::TransactionAmount,
+                limit,
+                attempted: transaction.amount,
+            });
+        }
+        if transaction.tx_type == TransactionType::Withdrawal
+            && let Some(limit) = self.config.limits.max_withdrawal
+            && transaction.amount > limit
+        {
+            return Err(PaymentError::WithdrawalLimitExceeded {
+                client: transaction.account_id,
+                tx_id: transaction.tx_id,
+                limit,
+                attempted: transaction.amount,
+            });
+        }
+
+        let mut clamped_from = None;
+        let (available, held, total) = match transaction.tx_type {
+            TransactionType::Deposit => (transaction.amount, Decimal::ZERO, transaction.amount),
+            TransactionType::Withdrawal => {
+                if account_available < transaction.amount {
+                    // A chargeback fee may leave the available balance
+                    // negative; a withdrawal must never credit it.
+                    let clamped = account_available.max(Decimal::ZERO);
+                    if self.config.withdrawal_policy == WithdrawalPolicy::Reject
+                        || clamped <= Decimal::ZERO
+                    {
+                        return Err(PaymentError::InsufficientFunds {
+                            client: transaction.account_id,
+                            tx_id: transaction.tx_id,
+                        });
+                    }
+                    clamped_from = Some(transaction.amount);
+                    transaction.amount = clamped;
+                }
+                self.check_withdrawal_limit(transaction)?;
+                (-transaction.amount, Decimal::ZERO, -transaction.amount)
+            }
+            TransactionType::Fee => {
+                return Err(PaymentError::InvalidTransactionType(transaction.tx_id));
+            }
+        };
+        Ok(Some(Admission {
+            delta: BalanceDelta {
+                available,
+                held,
+                total,
+            },
+            first_client,
+            clamped_from,
+        }))
+    }
+
+    /// Rejects a withdrawal taking the client's total withdrawals in its
+    /// currency above `max_total_withdrawal_per_client`.
+    #[inline]
+    fn check_withdrawal_limit(&self, withdrawal: &Transaction) -> Result<(), PaymentError> {
+        let Some(limit) = self.config.limits.max_total_withdrawal_per_client else {
+            return Ok(());
+        };
+        let attempted = self
+            .withdrawn
+            .get(&(withdrawal.account_id, withdrawal.currency))
+            .copied()
+            .unwrap_or_default()
+            + withdrawal.amount;
+        if attempted > limit {
+            return Err(PaymentError::LimitExceeded {
+                tx_id: withdrawal.tx_id,
+                kind: LimitKind::TotalWithdrawal,
+                limit,
+                attempted,
+            });
+        }
+        Ok(())
+    }
+
+    /// Holds the amount of a completed deposit, or of a withdrawal under
+    /// `allow_withdrawal_disputes`. A resolved dispute may be reopened the same
+    /// way; only a charged back transaction is final. Amounts rounding to zero
+    /// at the account's scale are rejected with `ZeroAmountDispute`.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(client = %account_id, tx = %tx_id))
+    )]
+    pub fn process_dispute(
+        &mut self,
+        account_id: ClientId,
+        tx_id: TxId,
+    ) -> Result<(), PaymentError> {
+        let result = self.dispute(account_id, tx_id);
+        self.complete(result)
+    }
+
+    fn dispute(&mut self, account_id: ClientId, tx_id: TxId) -> Result<(), PaymentError> {
+        self.check_known_client(account_id)?;
+        if self.is_account_locked(account_id) {
+            return Err(self.account_locked(account_id));
+        }
+
+        let DisputeTransition { amount, .. } =
+            self.apply_dispute_transition(account_id, tx_id, DisputeStep::Hold, None)?;
+        self.observe(|observer| observer.on_dispute(account_id, tx_id, amount));
+        Ok(())
+    }
+
+    /// Moves the disputable transaction `tx_id` through `step`, for `amount`
+    /// or else its whole amount (`Hold`) or disputed amount, and shifts the
+    /// balances accordingly. Every check runs before anything changes, so a
+    /// rejected step leaves the engine untouched.
+    fn apply_dispute_transition(
+        &mut self,
+        account_id: ClientId,
+        tx_id: TxId,
+        step: DisputeStep,
+        amount: Option<Decimal>,
+    ) -> Result<DisputeTransition, PaymentError> {
+        let existing_transaction = self.get_disputable_transaction(account_id, tx_id)?;
+        if step != DisputeStep::Hold {
+            Self::check_hold(account_id, existing_transaction)?;
+        }
+        let withdrawal = existing_transaction.tx_type == TransactionType::Withdrawal;
+        let currency = existing_transaction.currency;
+        let disputed_amount = existing_transaction.disputed_amount;
+        let amount = match step {
+            DisputeStep::Hold => existing_transaction.amount,
+            DisputeStep::Release => amount.unwrap_or(disputed_amount),
+            DisputeStep::Reverse => disputed_amount,
+        };
+        let remaining = disputed_amount - amount;
+        let status = existing_transaction
+            .status
+            .clone()
+            .transition(match step {
+                DisputeStep::Hold => TransactionStatus::Disputed,
+                DisputeStep::Release if remaining > Decimal::ZERO => {
+                    TransactionStatus::PartiallyResolved
+                }
+                DisputeStep::Release => TransactionStatus::Resolved,
+                DisputeStep::Reverse => TransactionStatus::Chargebacked,
+            })
+            .map_err(|error| PaymentError::transition(tx_id, error))?;
+        if step == DisputeStep::Release && remaining < Decimal::ZERO {
+            return Err(PaymentError::InsufficientHoldFunds {
+                client: account_id,
+                tx_id,
+            });
+        }
+
+        let delta = BalanceDelta::dispute(step, withdrawal, amount);
+        let account = self
+            .accounts
+            .get(&account_id)
+            .ok_or(PaymentError::AccountNotFound(account_id))?;
+        // Holding nothing would only change the status, which hides
+        // degenerate input such as deposits below the account scale.
+        if step == DisputeStep::Hold
+            && amount
+                .round_dp_with_strategy(account.scale, self.config.rounding.into())
+                .is_zero()
+        {
+            return Err(PaymentError::ZeroAmountDispute(tx_id));
+        }
+        let balances = account.balances(currency);
+        if (delta.available < Decimal::ZERO && balances.available + delta.available < Decimal::ZERO)
+            || balances.held + delta.held < Decimal::ZERO
+        {
+            return Err(PaymentError::InsufficientHoldFunds {
+                client: account_id,
+                tx_id,
+            });
+        }
+        // The chargeback fee comes off after the reversal, which must not be
+        // left half done if the fee cannot be deducted exactly.
+        if step == DisputeStep::Reverse {
+            let fee = self.config.chargeback_fee;
+            exact_add_balances(balances, delta.available, delta.held, delta.total)
+                .and_then(|balances| exact_add_balances(balances, -fee, Decimal::ZERO, -fee))
+                .ok_or(PaymentError::PrecisionLoss {
+                    client: account_id,
+                    tx_id,
+                })?;
+        }
+
+        self.update_account_balance(
+            account_id,
+            tx_id,
+            currency,
+            delta.available,
+            delta.held,
+            delta.total,
+        )?;
+        match step {
+            DisputeStep::Hold => self.update_disputed_amount(account_id, tx_id, amount)?,
+            DisputeStep::Release => self.update_disputed_amount(account_id, tx_id, remaining)?,
+            DisputeStep::Reverse => {}
+        }
+        self.update_transaction_status(account_id, tx_id, status.clone())?;
+        let sequence = self.record_ledger(
+            account_id,
+            step.ledger_kind(),
+            tx_id,
+            currency,
+            amount,
+            delta.available,
+        );
+        Ok(DisputeTransition {
+            amount,
+            currency,
+            sequence,
+        })
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(client = %account_id, tx = %tx_id))
+    )]
+    pub fn process_resolve(
+        &mut self,
+        account_id: ClientId,
+        tx_id: TxId,
+    ) -> Result<(), PaymentError> {
+        let result = self.resolve(account_id, tx_id, None);
+        self.complete(result)
+    }
+
+    /// Releases part of the disputed amount. The transaction stays
+    /// `PartiallyResolved` until the whole disputed amount has been released.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(client = %account_id, tx = %tx_id))
+    )]
+    pub fn process_partial_resolve(
+        &mut self,
+        account_id: ClientId,
+        tx_id: TxId,
+        amount: Decimal,
+    ) -> Result<(), PaymentError> {
+        let result = if amount <= Decimal::ZERO {
+            Err(PaymentError::InvalidAmount(amount))
+        } else {
+            self.resolve(account_id, tx_id, Some(amount))
+        };
+        self.complete(result)
+    }
+
+    fn resolve(
+        &mut self,
+        account_id: ClientId,
+        tx_id: TxId,
+        amount: Option<Decimal>,
+    ) -> Result<(), PaymentError> {
+        self.check_known_client(account_id)?;
+        self.check_settlement_lock(account_id, tx_id)?;
+
+        let DisputeTransition {
+            amount, sequence, ..
+        } = self.apply_dispute_transition(account_id, tx_id, DisputeStep::Release, amount)?;
+        // A fully resolved transaction completes again and re-enters the
+        // retention window.
+        if let RetentionPolicy::Window(_) = self.config.retention_policy
+            && self.transactions[&account_id][&tx_id].status == TransactionStatus::Resolved
+        {
+            self.window.push_back((sequence, account_id, tx_id));
+        }
+        self.observe(|observer| observer.on_resolve(account_id, tx_id, amount));
+        Ok(())
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(client = %account_id, tx = %tx_id))
+    )]
+    pub fn process_chargeback(
+        &mut self,
+        account_id: ClientId,
+        tx_id: TxId,
+    ) -> Result<(), PaymentError> {
+        let result = self.chargeback(account_id, tx_id);
+        self.complete(result)
+    }
+
+    fn chargeback(&mut self, account_id: ClientId, tx_id: TxId) -> Result<(), PaymentError> {
+        self.check_known_client(account_id)?;
+        self.check_settlement_lock(account_id, tx_id)?;
+
+        let DisputeTransition {
+            amount,
+            currency,
+            sequence,
+            ..
+        } = self.apply_dispute_transition(account_id, tx_id, DisputeStep::Reverse, None)?;
+        self.charge_fee(account_id, tx_id, currency)?;
+        self.settle(account_id, tx_id);
+        self.observe(|observer| observer.on_chargeback(account_id, tx_id, amount));
+        if self.record_chargeback(account_id) >= self.config.chargebacks_before_lock {
+            self.lock_account(account_id, LockReason::Chargeback { tx_id, sequence });
+            if self.config.freeze_disputes_on_lock {
+                self.freeze_open_disputes(account_id);
+            }
+        }
+        Ok(())
+    }
+
+    /// Locks the account with `LockReason::Manual`, e.g. on a compliance
+    /// request, creating it with zero balances for an unknown client. An
+    /// account that is already locked keeps its reason. Unlike a chargeback
+    /// lock, open disputes can still be settled.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(client = %account_id))
+    )]
+    pub fn freeze_account(&mut self, account_id: ClientId) -> Result<(), PaymentError> {
+        let result = self.freeze(account_id);
+        self.complete(result)
+    }
+
+    fn freeze(&mut self, account_id: ClientId) -> Result<(), PaymentError> {
+        if !self.get_or_create_account(account_id)?.locked {
+            self.lock_account(account_id, LockReason::Manual);
+        }
+        Ok(())
+    }
+
+    /// Lifts a lock set by `freeze_account`. A chargeback lock is never
+    /// lifted: unfreezing such an account fails with `AccountLocked`, and
+    /// unfreezing an unlocked one with `AccountNotFrozen`.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(client = %account_id))
+    )]
+    pub fn unfreeze_account(&mut self, account_id: ClientId) -> Result<(), PaymentError> {
+        let result = self.unfreeze(account_id);
+        self.complete(result)
+    }
+
+    fn unfreeze(&mut self, account_id: ClientId) -> Result<(), PaymentError> {
+        let account = self
+            .accounts
+            .get_mut(&account_id)
+            .ok_or(PaymentError::AccountNotFound(account_id))?;
+        match account.lock_state() {
+            LockState::AdminLocked => {
+                account.locked = false;
+                account.lock_reason = None;
+                Ok(())
+            }
+            LockState::ChargebackLocked => Err(self.account_locked(account_id)),
+            LockState::Unlocked => Err(PaymentError::AccountNotFrozen(account_id)),
+        }
+    }
+
+    /// Rejects a dispute, resolve or chargeback row naming another currency
+    /// than the transaction it refers to. Rows without a currency always match.
+    #[cfg(feature = "csv")]
+    pub(crate) fn check_currency(
+        &self,
+        account_id: ClientId,
+        tx_id: TxId,
+        currency: Option<Currency>,
+    ) -> Result<(), PaymentError> {
+        let (Some(actual), Some(transaction)) = (currency, self.get_transaction(account_id, tx_id))
+        else {
+            return Ok(());
+        };
+        let expected = self.currency_or_base(transaction.currency);
+        if actual != expected {
+            return Err(PaymentError::CurrencyMismatch {
+                tx_id,
+                expected,
+                actual,
+            });
+        }
+        Ok(())
+    }
+
+    /// Applies an entry of any type and returns the client's account as it is
+    /// afterwards.
+    #[cfg(feature = "csv")]
+    pub fn apply(&mut self, entry: TransactionEntry) -> Result<AccountView, PaymentError> {
+        let client = entry.account_id;
+        process_entry(self, entry)?;
+        self.accounts
+            .get(&client)
+            .map(AccountView::from)
+            .ok_or(PaymentError::AccountNotFound(client))
+    }
+
+    /// Applies each entry in order and returns their outcomes by index, e.g.
+    /// to report which operations of a submitted batch succeeded. A rejected
+    /// entry does not stop the batch.
+    #[cfg(feature = "csv")]
+    pub fn process_entries(
+        &mut self,
+        entries: &[TransactionEntry],
+    ) -> Vec<Result<(), PaymentError>> {
+        entries
+            .iter()
+            .map(|entry| process_entry(self, entry.clone()))
+            .collect()
+    }
+
+    /// Moves another engine's accounts and transactions into this one, e.g. to
+    /// combine engines that processed disjoint shards of a feed split by
+    /// client. Fails with `ClientOverlap`, merging nothing, if both engines
+    /// hold state for the same client; see `merge_unchecked`.
+    pub fn merge(&mut self, other: PaymentEngine) -> Result<(), MergeError> {
+        if let Some(client) = other
+            .accounts
+            .keys()
+            .filter(|client| self.accounts.contains_key(client))
+            .min()
+        {
+            return Err(MergeError::ClientOverlap(*client));
+        }
+        self.merge_unchecked(other)
+    }
+
+    /// Like `merge`, but adds up the balances of clients present in both
+    /// engines; the account stays locked if either side locked it. Only sound
+    /// when the two histories of a client are independent. Nothing is merged
+    /// if a client has the same tx id in both engines, or if a sum of balances
+    /// would need rounding.
+    pub fn merge_unchecked(&mut self, other: PaymentEngine) -> Result<(), MergeError> {
+        for (client, other_transactions) in &other.transactions {
+            if let Some(transactions) = self.transactions.get(client)
+                && let Some(&tx_id) = other_transactions
+                    .keys()
+                    .find(|tx_id| transactions.contains_key(tx_id))
+            {
+                return Err(MergeError::TransactionOverlap {
+                    client: *client,
+                    tx_id,
+                });
+            }
+        }
+
+        if let Some(max_accounts) = self.config.max_accounts {
+            let mut new_clients: Vec<ClientId> = other
+                .accounts
+                .keys()
+                .filter(|client| !self.accounts.contains_key(client))
+                .copied()
+                .collect();
+            new_clients.sort_unstable();
+            if let Some(&client) = new_clients.get(max_accounts.saturating_sub(self.accounts.len()))
+            {
+                return Err(MergeError::AccountLimitExceeded(client));
+            }
+        }
+
+        let mut summed = HashMap::new();
+        for (client, other_account) in &other.accounts {
+            let Some(account) = self.accounts.get(client) else {
+                continue;
+            };
+            for (currency, other_balances) in other_account.all_balances() {
+                let balances = exact_add_balances(
+                    account.balances(currency),
+                    other_balances.available,
+                    other_balances.held,
+                    other_balances.total,
+                )
+                .ok_or(MergeError::PrecisionLoss(*client))?;
+                summed.insert((*client, currency), balances);
+            }
+        }
+
+        // The other engine's sequence numbers follow this engine's, keeping
+        // their relative order.
+        let offset = self.next_sequence;
+        for (client, other_account) in other.accounts {
+            let account = self.accounts.entry(client).or_insert_with(|| Account {
+                scale: other_account.scale,
+                ..Account::new(client)
+            });
+            for (currency, other_balances) in other_account.all_balances() {
+                let balances = summed.remove(&(client, currency)).unwrap_or(other_balances);
+                account.set_balances(currency, balances);
+            }
+            account.locked |= other_account.locked;
+            let other_reason = other_account.lock_reason.map(|reason| match reason {
+                LockReason::Chargeback { tx_id, sequence } => LockReason::Chargeback {
+                    tx_id,
+                    sequence: sequence + offset,
+                },
+                LockReason::Manual => LockReason::Manual,
+            });
+            account.lock_reason = account.lock_reason.or(other_reason);
+            account.chargebacks += other_account.chargebacks;
+        }
+
+        for (client, other_transactions) in other.transactions {
+            let transactions = self.transactions.entry(client).or_default();
+            for (tx_id, mut transaction) in other_transactions {
+                transaction.sequence += offset;
+                transactions.insert(tx_id, transaction);
+            }
+        }
+        for (client, other_evicted) in other.evicted {
+            *self.evicted.entry(client).or_default() += other_evicted;
+        }
+        self.window.extend(
+            other
+                .window
+                .into_iter()
+                .map(|(sequence, client, tx_id)| (sequence + offset, client, tx_id)),
+        );
+        for (client, other_highest) in other.evicted_up_to {
+            record_eviction(&mut self.evicted_up_to, client, other_highest);
+        }
+        let bound = self.failed_retention();
+        for (client, other_failed) in other.failed {
+            self.failed
+                .entry(client)
+                .or_default()
+                .extend(other_failed, bound);
+        }
+        for (key, other_withdrawn) in other.withdrawn {
+            *self.withdrawn.entry(key).or_default() += other_withdrawn;
+        }
+        for (tx_id, client) in other.tx_owners {
+            self.tx_owners.entry(tx_id).or_insert(client);
+        }
+        self.reused_tx_ids.extend(other.reused_tx_ids);
+        for (client, other_settled) in other.settled {
+            self.settled
+                .entry(client)
+                .or_default()
+                .extend(other_settled);
+        }
+        for (client, other_fees) in other.fees {
+            let fees = self.fees.entry(client).or_default();
+            fees.extend(other_fees.into_iter().map(|mut fee| {
+                fee.sequence += offset;
+                fee
+            }));
+        }
+        for (client, other_ledger) in other.ledger {
+            let ledger = self.ledger.entry(client).or_default();
+            ledger.extend(other_ledger.into_iter().map(|mut entry| {
+                entry.sequence += offset;
+                entry
+            }));
+        }
+        self.next_sequence += other.next_sequence;
+        Ok(())
+    }
+
+    /// Drops every account and transaction, leaving the engine as new but with
+    /// its configuration and observer.
+    pub fn clear(&mut self) {
+        self.accounts.clear();
+        self.transactions.clear();
+        self.fees.clear();
+        self.evicted.clear();
+        self.settled.clear();
+        self.window.clear();
+        self.evicted_up_to.clear();
+        self.failed.clear();
+        self.withdrawn.clear();
+        self.tx_owners.clear();
+        self.reused_tx_ids.clear();
+        self.ledger.clear();
+        self.next_sequence = 0;
+    }
+
+    /// Zeroes the client's balances and drops its transactions, fees and
+    /// statement lines. The account stays registered, unlocked and with its
+    /// scale. Returns `false` for an unknown client, which is left alone.
+    pub fn reset_account(&mut self, client: ClientId) -> bool {
+        let Some(account) = self.accounts.get_mut(&client) else {
+            return false;
+        };
+        *account = Account {
+            scale: account.scale,
+            ..Account::new(client)
+        };
+        self.transactions.remove(&client);
+        self.fees.remove(&client);
+        self.evicted
+            .retain(|&(evicted_client, _), _| evicted_client != client);
+        self.settled.remove(&client);
+        self.window
+            .retain(|&(_, window_client, _)| window_client != client);
+        self.evicted_up_to.remove(&client);
+        self.failed.remove(&client);
+        self.withdrawn
+            .retain(|&(withdrawn_client, _), _| withdrawn_client != client);
+        self.tx_owners.retain(|_, owner| *owner != client);
+        self.ledger.remove(&client);
+        true
+    }
+}
+
+impl PaymentEngine {
+    pub fn accounts_where<F: Fn(&Account) -> bool>(
+        &self,
+        pred: F,
+    ) -> impl Iterator<Item = &Account> {
+        self.accounts.values().filter(move |account| pred(account))
+    }
+
+    pub fn account(&self, client: ClientId) -> Option<&Account> {
+        self.accounts.get(&client)
+    }
+
+    /// Transactions applied under another client's tx id, in the order they
+    /// were applied; only recorded under `CrossClientTxIdPolicy::Report`.
+    pub fn reused_tx_ids(&self) -> &[TxIdReuse] {
+        &self.reused_tx_ids
+    }
+
+    /// Sum of the available balances of all accounts, in the base currency;
+    /// `None` if it does not fit in a `Decimal`.
+    pub fn total_available(&self) -> Option<Decimal> {
+        self.sum_balances(|account| account.available)
+    }
+
+    /// Sum of the held balances of all accounts, in the base currency; `None`
+    /// if it does not fit in a `Decimal`.
+    pub fn total_held(&self) -> Option<Decimal> {
+        self.sum_balances(|account| account.held)
+    }
+
+    /// Sum of the total balances of all accounts, in the base currency;
+    /// `None` if it does not fit in a `Decimal`.
+    pub fn total_balance(&self) -> Option<Decimal> {
+        self.sum_balances(|account| account.total)
+    }
+
+    #[inline]
+    fn sum_balances(&self, balance: impl Fn(&Account) -> Decimal) -> Option<Decimal> {
+        self.accounts
+            .values()
+            .try_fold(Decimal::ZERO, |sum, account| {
+                sum.checked_add(balance(account))
+            })
+    }
+
+    /// Finds a transaction by tx id alone, returning the client that owns it.
+    /// Tx ids are unique in well-formed input; if several clients use the same
+    /// id, the first one found is returned, in no particular order.
+    pub fn find_transaction(&self, tx_id: TxId) -> Option<(&ClientId, &Transaction)> {
+        self.transactions
+            .iter()
+            .find_map(|(client, account_transactions)| {
+                account_transactions
+                    .get(&tx_id)
+                    .map(|transaction| (client, transaction))
+            })
+    }
+
+    /// Statuses the client's transaction moved through, e.g. for compliance
+    /// reviews of repeated disputes; `None` if it is unknown or was evicted.
+    pub fn transaction_history(&self, client: ClientId, tx_id: TxId) -> Option<&StatusHistory> {
+        self.get_transaction(client, tx_id)
+            .map(|transaction| &transaction.history)
+    }
+
+    /// The currency code behind a transaction's or balance's `currency`.
+    #[inline]
+    pub fn currency_or_base(&self, currency: Option<Currency>) -> Currency {
+        currency.unwrap_or(self.config.base_currency)
+    }
+
+    /// Whether any account holds a currency other than the base currency, in
+    /// which case the reports name the currency of every row.
+    pub fn has_foreign_currencies(&self) -> bool {
+        self.accounts
+            .values()
+            .any(|account| !account.currencies.is_empty())
+    }
+
+    pub fn locked_accounts(&self) -> impl Iterator<Item = &Account> {
+        self.accounts_where(|account| account.locked)
+    }
+
+    /// Returns the client's transactions in the order they were applied.
+    pub fn history(&self, client: ClientId) -> impl Iterator<Item = &Transaction> {
+        let mut transactions: Vec<&Transaction> = self
+            .transactions
+            .get(&client)
+            .into_iter()
+            .flat_map(|account_transactions| account_transactions.values())
+            .collect();
+        transactions.sort_unstable_by_key(|t| t.sequence);
+        transactions.into_iter()
+    }
+
+    /// Latest timestamp among the client's stored deposits and withdrawals,
+    /// `None` when none of them carries one.
+    pub fn last_activity(&self, client: ClientId) -> Option<u64> {
+        self.transactions
+            .get(&client)?
+            .values()
+            .filter_map(|transaction| transaction.timestamp)
+            .max()
+    }
+
+    /// Writes every stored transaction with its final status, sorted by client
+    /// and tx id. Fees follow the transaction whose chargeback incurred them.
+    /// A `currency` column follows the amount when foreign currencies are held.
+    pub fn write_transactions_csv<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let currencies = self.has_foreign_currencies();
+        if currencies {
+            writeln!(writer, "client,tx,type,amount,currency,status")?;
+        } else {
+            writeln!(writer, "client,tx,type,amount,status")?;
+        }
+
+        let mut transactions: Vec<&Transaction> = self
+            .transactions
+            .values()
+            .flat_map(|account_transactions| account_transactions.values())
+            .chain(self.fees.values().flatten())
+            .collect();
+        transactions.sort_unstable_by_key(|t| (t.account_id, t.tx_id, t.sequence));
+
+        for transaction in transactions {
+            write!(
+                writer,
+                "{},{},{},{},",
+                transaction.account_id,
+                transaction.tx_id,
+                transaction.tx_type,
+                format_amount_with(transaction.amount, DEFAULT_SCALE, self.config.rounding),
+            )?;
+            if currencies {
+                write!(writer, "{},", self.currency_or_base(transaction.currency))?;
+            }
+            writeln!(writer, "{}", transaction.status)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the account report as CSV. Balances are rounded with the
+    /// configured `rounding` to `precision` decimal places, capped at `MAX_PRECISION`, or
+    /// when `None` to `report_precision` if configured and otherwise to each
+    /// account's scale.
+    pub fn write_csv<W: Write>(&self, mut writer: W, precision: Option<u32>) -> io::Result<()> {
+        write!(
+            writer,
+            "{}",
+            CsvReport {
+                engine: self,
+                precision
+            }
+        )
+    }
+
+    /// Writes the account report as a JSON array sorted by client, with balances
+    /// formatted like `write_csv`. When foreign currencies are held, there is an
+    /// object per client and currency, naming the currency. Accounts whose
+    /// transactions carry timestamps include the latest as `last_activity`.
+    #[cfg(feature = "csv")]
+    pub fn write_json<W: Write>(&self, writer: W, precision: Option<u32>) -> io::Result<()> {
+        let mut accounts: Vec<&Account> = self.accounts.values().collect();
+        accounts.sort_unstable_by_key(|account| account.client);
+
+        let currencies = self.has_foreign_currencies();
+        let report: Vec<AccountReport> = accounts
+            .into_iter()
+            .flat_map(|account| {
+                let scale = self.output_scale(account, precision);
+                let last_activity = self.last_activity(account.client);
+                account.all_balances().map(move |(currency, balances)| {
+                    let currency = currencies.then(|| self.currency_or_base(currency));
+                    let format = |amount| format_amount_with(amount, scale, self.config.rounding);
+                    AccountReport {
+                        last_activity,
+                        ..AccountReport::new(account, currency, balances, format)
+                    }
+                })
+            })
+            .collect();
+        serde_json::to_writer(writer, &report).map_err(io::Error::from)
+    }
+}
+
+/// Raises the client's highest evicted tx id to `tx_id`.
+#[inline]
+fn record_eviction(evicted_up_to: &mut HashMap<ClientId, TxId>, account_id: ClientId, tx_id: TxId) {
+    let highest = evicted_up_to.entry(account_id).or_insert(tx_id);
+    *highest = (*highest).max(tx_id);
+}
+
+/// Upper bound for the output precision.
+pub const MAX_PRECISION: u32 = 10;
+
+impl PaymentEngine {
+    /// Decimal places used to print the account's balances.
+    #[inline]
+    pub(crate) fn output_scale(&self, account: &Account, precision: Option<u32>) -> u32 {
+        precision
+            .or(self.config.report_precision)
+            .unwrap_or(account.scale)
+            .min(MAX_PRECISION)
+    }
+}
+
+/// `a + b`, or `None` where `Decimal` would overflow or have to round the sum
+/// to fit its 96-bit mantissa, which it otherwise does silently.
+#[inline]
+fn exact_add(a: Decimal, b: Decimal) -> Option<Decimal> {
+    let sum = a.checked_add(b)?;
+    // The sum is rounded to a smaller scale only when it does not fit; that
+    // loses nothing if neither operand had digits past that scale.
+    let scale = sum.scale();
+    if scale < a.scale().max(b.scale()) && (a.round_dp(scale) != a || b.round_dp(scale) != b) {
+        return None;
+    }
+    Some(sum)
+}
+
+/// `balances` moved by the given amounts, `None` if any sum would overflow or
+/// need rounding; see `exact_add`.
+fn exact_add_balances(
+    balances: Balances,
+    available: Decimal,
+    held: Decimal,
+    total: Decimal,
+) -> Option<Balances> {
+    Some(Balances {
+        available: exact_add(balances.available, available)?,
+        held: exact_add(balances.held, held)?,
+        total: exact_add(balances.total, total)?,
+    })
+}
+
+/// Formats an amount with exactly `scale` decimal places, whatever its stored
+/// scale, rounding with banker's rounding.
+#[inline]
+pub fn format_amount(amount: Decimal, scale: u32) -> String {
+    format_amount_with(amount, scale, RoundingStrategy::HalfEven)
+}
+
+/// Like `format_amount`, rounding with `rounding`. All reports go through it
+/// with the configured strategy.
+pub fn format_amount_with(amount: Decimal, scale: u32, rounding: RoundingStrategy) -> String {
+    let mut rounded = amount.round_dp_with_strategy(scale, rounding.into());
+    if rounded.is_zero() {
+        rounded.set_sign_positive(true);
+    }
+    // `{:.N}` overflows `Decimal`'s formatting buffer near `Decimal::MAX`, so
+    // the missing zeros are appended by hand.
+    let mut text = rounded.to_string();
+    if scale > 0 && rounded.scale() == 0 {
+        text.push('.');
+    }
+    text.extend(std::iter::repeat_n('0', (scale - rounded.scale()) as usize));
+    text
+}
+
+#[cfg(feature = "csv")]
+#[derive(Serialize)]
+struct AccountReport {
+    client: ClientId,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    currency: Option<Currency>,
+    available: String,
+    held: String,
+    total: String,
+    locked: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lock_reason: Option<LockReason>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_activity: Option<u64>,
+}
+
+#[cfg(feature = "csv")]
+impl AccountReport {
+    fn new(
+        account: &Account,
+        currency: Option<Currency>,
+        balances: Balances,
+        format: impl Fn(Decimal) -> String,
+    ) -> Self {
+        AccountReport {
+            client: account.client,
+            currency,
+            available: format(balances.available),
+            held: format(balances.held),
+            total: format(balances.total),
+            locked: account.locked,
+            lock_reason: account.lock_reason,
+            last_activity: None,
+        }
+    }
+}
+
+struct CsvReport<'a> {
+    engine: &'a PaymentEngine,
+    precision: Option<u32>,
+}
+
+/// Header row of the CSV account report, with a `currency` column when
+/// foreign currencies are held.
+#[inline]
+fn csv_header(currencies: bool) -> &'static str {
+    if currencies {
+        "client, currency, available, held, total, locked"
+    } else {
+        "client, available, held, total, locked"
+    }
+}
+
+impl PaymentEngine {
+    /// Writes the CSV report row of one of the account's currencies, without
+    /// the line break.
+    fn write_csv_row(
+        &self,
+        out: &mut impl fmt::Write,
+        account: &Account,
+        (currency, balances): (Option<Currency>, Balances),
+        precision: Option<u32>,
+        currencies: bool,
+    ) -> fmt::Result {
+        let scale = self.output_scale(account, precision);
+        let format = |amount| format_amount_with(amount, scale, self.config.rounding);
+        write!(out, "{}, ", account.client)?;
+        if currencies {
+            write!(out, "{}, ", self.currency_or_base(currency))?;
+        }
+        write!(
+            out,
+            "{}, {}, {}, {}",
+            format(balances.available),
+            format(balances.held),
+            format(balances.total),
+            account.locked
+        )
+    }
+
+    /// Lines of the CSV account report as rendered by `Display`, header
+    /// first, without line breaks. Each line is formatted only when the
+    /// iterator reaches it, so a large report can be streamed without
+    /// building it in memory.
+    pub fn into_csv_iter(mut self) -> impl Iterator<Item = String> {
+        let currencies = self.has_foreign_currencies();
+        let accounts = std::mem::take(&mut self.accounts);
+        std::iter::once(csv_header(currencies).to_string()).chain(accounts.into_values().flat_map(
+            move |account| {
+                account
+                    .all_balances()
+                    .map(|balances| {
+                        let mut line = String::new();
+                        // Writing to a `String` cannot fail.
+                        let _ = self.write_csv_row(&mut line, &account, balances, None, currencies);
+                        line
+                    })
+                    .collect::<Vec<_>>()
+            },
+        ))
+    }
+}
+
+impl Display for CsvReport<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let currencies = self.engine.has_foreign_currencies();
+        writeln!(f, "{}", csv_header(currencies))?;
+
+        for account in self.engine.accounts.values() {
+            for balances in account.all_balances() {
+                self.engine
+                    .write_csv_row(f, account, balances, self.precision, currencies)?;
+                writeln!(f)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Display for PaymentEngine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        CsvReport {
+            engine: self,
+            precision: None,
+        }
+        .fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "csv")]
+    use crate::config::{Limits, LockedAccountDeposits};
+    #[cfg(feature = "csv")]
+    use crate::processor::{NullSink, process_stream_with_sink};
+    #[cfg(feature = "csv")]
+    use crate::transaction::MAX_STATUS_HISTORY;
+    use rust_decimal::dec;
+
+    #[test]
+    fn test_payment_engine_display() {
+        let mut engine = PaymentEngine::new();
+
+        engine.accounts.insert(
+            ClientId(1),
+            Account {
+                available: dec!(100.1234),
+                held: dec!(50.5678),
+                total: dec!(150.6912),
+                ..Account::new(ClientId(1))
+            },
+        );
+
+        engine.accounts.insert(
+            ClientId(2),
+            Account {
+                available: dec!(0.0),
+                held: dec!(25.0),
+                total: dec!(25.0),
+                locked: true,
+                ..Account::new(ClientId(2))
+            },
+        );
+
+        engine.accounts.insert(
+            ClientId(3),
+            Account {
+                available: dec!(999.9999),
+                held: dec!(0.0001),
+                total: dec!(1000.0),
+                ..Account::new(ClientId(3))
+            },
+        );
+
+        let output = format!("{}", engine);
+
+        assert!(output.contains("client, available, held, total, locked"));
+        assert!(output.contains("1, 100.1234, 50.5678, 150.6912, false"));
+        assert!(output.contains("2, 0.0000, 25.0000, 25.0000, true"));
+        assert!(output.contains("3, 999.9999, 0.0001, 1000.0000, false"));
+
+        let lines: Vec<&str> = output.trim().split('\n').collect();
+        assert_eq!(lines.len(), 4);
+
+        for line in &lines[1..] {
+            let values: Vec<&str> = line.split(", ").collect();
+            assert_eq!(values.len(), 5);
+        }
+    }
+
+    #[test]
+    fn test_payment_engine_display_empty() {
+        let engine = PaymentEngine::new();
+        let output = format!("{}", engine);
+        assert_eq!(output.trim(), "client, available, held, total, locked");
+    }
+
+    #[test]
+    fn test_withdrawal_insufficient_funds() {
+        let mut engine = PaymentEngine::new();
+
+        let deposit = Transaction {
+            tx_type: TransactionType::Deposit,
+            account_id: ClientId(1),
+            tx_id: TxId(1),
+            amount: dec!(50.0),
+            currency: None,
+            disputed_amount: Decimal::ZERO,
+            sequence: 0,
+            timestamp: None,
+            status: TransactionStatus::Completed,
+            history: StatusHistory::default(),
+        };
+
+        engine.get_or_create_account(ClientId(1)).unwrap();
+        engine
+            .update_account_balance(
+                ClientId(1),
+                TxId(1),
+                None,
+                dec!(50.0),
+                dec!(0.0),
+                dec!(50.0),
+            )
+            .unwrap();
+        engine.insert_transaction(deposit);
+
+        assert_eq!(
+            engine.accounts.get(&ClientId(1)).unwrap().available,
+            dec!(50.0)
+        );
+        assert_eq!(engine.accounts.get(&ClientId(1)).unwrap().total, dec!(50.0));
+
+        let withdrawal = Transaction {
+            tx_type: TransactionType::Withdrawal,
+            account_id: ClientId(1),
+            tx_id: TxId(2),
+            amount: dec!(100.0),
+            currency: None,
+            disputed_amount: Decimal::ZERO,
+            sequence: 0,
+            timestamp: None,
+            status: TransactionStatus::Completed,
+            history: StatusHistory::default(),
+        };
+
+        let should_fail = engine.process_transaction(withdrawal);
+        assert!(should_fail.is_err(), "Should detect insufficient funds");
+
+        assert_eq!(
+            engine.accounts.get(&ClientId(1)).unwrap().available,
+            dec!(50.0)
+        );
+        assert_eq!(engine.accounts.get(&ClientId(1)).unwrap().total, dec!(50.0));
+        assert_eq!(engine.accounts.get(&ClientId(1)).unwrap().held, dec!(0.0));
+    }
+
+    #[test]
+    fn test_dispute_insufficient_available_balance() {
+        let mut engine = PaymentEngine::new();
+
+        let deposit = Transaction {
+            tx_type: TransactionType::Deposit,
+            account_id: ClientId(1),
+            tx_id: TxId(1),
+            amount: dec!(100.0),
+            currency: None,
+            disputed_amount: Decimal::ZERO,
+            sequence: 0,
+            timestamp: None,
+            status: TransactionStatus::Completed,
+            history: StatusHistory::default(),
+        };
+
+        engine.get_or_create_account(ClientId(1)).unwrap();
+        engine
+            .update_account_balance(
+                ClientId(1),
+                TxId(1),
+                None,
+                dec!(100.0),
+                dec!(0.0),
+                dec!(100.0),
+            )
+            .unwrap();
+        engine.insert_transaction(deposit);
+
+        let withdrawal = Transaction {
+            tx_type: TransactionType::Withdrawal,
+            account_id: ClientId(1),
+            tx_id: TxId(2),
+            amount: dec!(80.0),
+            currency: None,
+            disputed_amount: Decimal::ZERO,
+            sequence: 0,
+            timestamp: None,
+            status: TransactionStatus::Completed,
+            history: StatusHistory::default(),
+        };
+        engine
+            .process_transaction(withdrawal)
+            .expect("Withdrawal should succeed");
+
+        assert_eq!(
+            engine.accounts.get(&ClientId(1)).unwrap().available,
+            dec!(20.0)
+        );
+        assert_eq!(engine.accounts.get(&ClientId(1)).unwrap().total, dec!(20.0));
+
+        let result = engine.process_dispute(ClientId(1), TxId(1));
+        assert!(
+            result.is_err(),
+            "Dispute should fail due to insufficient available funds"
+        );
+
+        assert_eq!(
+            engine.accounts.get(&ClientId(1)).unwrap().available,
+            dec!(20.0)
+        );
+        assert_eq!(engine.accounts.get(&ClientId(1)).unwrap().total, dec!(20.0));
+        assert_eq!(engine.accounts.get(&ClientId(1)).unwrap().held, dec!(0.0));
+
+        assert_eq!(
+            engine
+                .transactions
+                .get(&ClientId(1))
+                .unwrap()
+                .get(&TxId(1))
+                .unwrap()
+                .status,
+            TransactionStatus::Completed
+        );
+    }
+
+    #[test]
+    fn test_withdrawal_exact_balance() {
+        let mut engine = PaymentEngine::new();
+
+        let deposit = Transaction {
+            tx_type: TransactionType::Deposit,
+            account_id: ClientId(1),
+            tx_id: TxId(1),
+            amount: dec!(50.0),
+            currency: None,
+            disputed_amount: Decimal::ZERO,
+            sequence: 0,
+            timestamp: None,
+            status: TransactionStatus::Completed,
+            history: StatusHistory::default(),
+        };
+
+        engine.get_or_create_account(ClientId(1)).unwrap();
+        engine
+            .update_account_balance(
+                ClientId(1),
+                TxId(1),
+                None,
+                dec!(50.0),
+                dec!(0.0),
+                dec!(50.0),
+            )
+            .unwrap();
+        engine.insert_transaction(deposit);
+
+        let withdrawal = Transaction {
+            tx_type: TransactionType::Withdrawal,
+            account_id: ClientId(1),
+            tx_id: TxId(2),
+            amount: dec!(50.0),
+            currency: None,
+            disputed_amount: Decimal::ZERO,
+            sequence: 0,
+            timestamp: None,
+            status: TransactionStatus::Completed,
+            history: StatusHistory::default(),
+        };
+        engine
+            .process_transaction(withdrawal)
+            .expect("Withdrawal should succeed");
+
+        assert_eq!(
+            engine.accounts.get(&ClientId(1)).unwrap().available,
+            dec!(0.0)
+        );
+        assert_eq!(engine.accounts.get(&ClientId(1)).unwrap().total, dec!(0.0));
+        assert_eq!(engine.accounts.get(&ClientId(1)).unwrap().held, dec!(0.0));
+    }
+
+    #[test]
+    fn test_withdrawal_process_with_insufficient_funds() {
+        let mut engine = PaymentEngine::new();
+
+        engine.get_or_create_account(ClientId(1)).unwrap();
+        engine
+            .update_account_balance(
+                ClientId(1),
+                TxId(1),
+                None,
+                dec!(50.0),
+                dec!(0.0),
+                dec!(50.0),
+            )
+            .unwrap();
+
+        let withdrawal = Transaction {
+            tx_type: TransactionType::Withdrawal,
+            account_id: ClientId(1),
+            tx_id: TxId(2),
+            amount: dec!(100.0),
+            currency: None,
+            disputed_amount: Decimal::ZERO,
+            sequence: 0,
+            timestamp: None,
+            status: TransactionStatus::Completed,
+            history: StatusHistory::default(),
+        };
+
+        let result = engine.process_transaction(withdrawal);
+
+        assert!(
+            result.is_err(),
+            "Should not have sufficient funds for withdrawal"
+        );
+        assert_eq!(
+            engine.accounts.get(&ClientId(1)).unwrap().available,
+            dec!(50.0)
+        );
+        assert_eq!(engine.accounts.get(&ClientId(1)).unwrap().total, dec!(50.0));
+    }
+
+    #[test]
+    fn test_dispute_process_with_insufficient_available_balance() {
+        let mut engine = PaymentEngine::new();
+
+        let deposit = Transaction {
+            tx_type: TransactionType::Deposit,
+            account_id: ClientId(1),
+            tx_id: TxId(1),
+            amount: dec!(100.0),
+            currency: None,
+            disputed_amount: Decimal::ZERO,
+            sequence: 0,
+            timestamp: None,
+            status: TransactionStatus::Completed,
+            history: StatusHistory::default(),
+        };
+
+        engine.get_or_create_account(ClientId(1)).unwrap();
+        engine
+            .update_account_balance(
+                ClientId(1),
+                TxId(1),
+                None,
+                dec!(100.0),
+                dec!(0.0),
+                dec!(100.0),
+            )
+            .unwrap();
+        engine.insert_transaction(deposit);
+
+        let withdrawal = Transaction {
+            tx_type: TransactionType::Withdrawal,
+            account_id: ClientId(1),
+            tx_id: TxId(2),
+            amount: dec!(80.0),
+            currency: None,
+            disputed_amount: Decimal::ZERO,
+            sequence: 0,
+            timestamp: None,
+            status: TransactionStatus::Completed,
+            history: StatusHistory::default(),
+        };
+        engine
+            .process_transaction(withdrawal)
+            .expect("Withdrawal should succeed");
+
+        assert_eq!(
+            engine.accounts.get(&ClientId(1)).unwrap().available,
+            dec!(20.0)
+        );
+        assert_eq!(engine.accounts.get(&ClientId(1)).unwrap().total, dec!(20.0));
+
+        let result = engine.process_dispute(ClientId(1), TxId(1));
+
+        assert!(
+            result.is_err(),
+            "Should not have sufficient available balance for dispute"
+        );
+        assert_eq!(
+            engine.accounts.get(&ClientId(1)).unwrap().available,
+            dec!(20.0)
+        );
+        assert_eq!(engine.accounts.get(&ClientId(1)).unwrap().total, dec!(20.0));
+        assert_eq!(engine.accounts.get(&ClientId(1)).unwrap().held, dec!(0.0));
+
+        assert_eq!(
+            engine
+                .transactions
+                .get(&ClientId(1))
+                .unwrap()
+                .get(&TxId(1))
+                .unwrap()
+                .status,
+            TransactionStatus::Completed
+        );
+    }
+
+    #[test]
+    fn test_successful_dispute_after_partial_withdrawal() {
+        let mut engine = PaymentEngine::new();
+
+        let deposit = Transaction {
+            tx_type: TransactionType::Deposit,
+            account_id: ClientId(1),
+            tx_id: TxId(1),
+            amount: dec!(30.0),
+            currency: None,
+            disputed_amount: Decimal::ZERO,
+            sequence: 0,
+            timestamp: None,
+            status: TransactionStatus::Completed,
+            history: StatusHistory::default(),
+        };
+
+        engine.get_or_create_account(ClientId(1)).unwrap();
+        engine
+            .update_account_balance(
+                ClientId(1),
+                TxId(1),
+                None,
+                dec!(100.0),
+                dec!(0.0),
+                dec!(100.0),
+            )
+            .unwrap();
+        engine.insert_transaction(deposit);
+
+        let withdrawal = Transaction {
+            tx_type: TransactionType::Withdrawal,
+            account_id: ClientId(1),
+            tx_id: TxId(2),
+            amount: dec!(50.0),
+            currency: None,
+            disputed_amount: Decimal::ZERO,
+            sequence: 0,
+            timestamp: None,
+            status: TransactionStatus::Completed,
+            history: StatusHistory::default(),
+        };
+
+        engine
+            .process_transaction(withdrawal)
+            .expect("Withdrawal should succeed");
+
+        assert_eq!(
+            engine.accounts.get(&ClientId(1)).unwrap().available,
+            dec!(50.0)
+        );
+
+        let result = engine.process_dispute(ClientId(1), TxId(1));
+        assert!(
+            result.is_ok(),
+            "Dispute should succeed when sufficient available balance"
+        );
+
+        assert_eq!(
+            engine.accounts.get(&ClientId(1)).unwrap().available,
+            dec!(20.0)
+        );
+        assert_eq!(engine.accounts.get(&ClientId(1)).unwrap().held, dec!(30.0));
+        assert_eq!(engine.accounts.get(&ClientId(1)).unwrap().total, dec!(50.0));
+
+        assert_eq!(
+            engine
+                .transactions
+                .get(&ClientId(1))
+                .unwrap()
+                .get(&TxId(1))
+                .unwrap()
+                .status,
+            TransactionStatus::Disputed
+        );
+    }
+
+    #[test]
+    fn test_disputed_amount_tracked_separately() {
+        let mut engine = PaymentEngine::new();
+
+        let deposit = Transaction {
+            tx_type: TransactionType::Deposit,
+            account_id: ClientId(1),
+            tx_id: TxId(1),
+            amount: dec!(40.0),
+            currency: None,
+            disputed_amount: Decimal::ZERO,
+            sequence: 0,
+            timestamp: None,
+            status: TransactionStatus::Completed,
+            history: StatusHistory::default(),
+        };
+        engine.process_transaction(deposit).unwrap();
+
+        engine.process_dispute(ClientId(1), TxId(1)).unwrap();
+        let transaction = engine
+            .transactions
+            .get(&ClientId(1))
+            .unwrap()
+            .get(&TxId(1))
+            .unwrap();
+        assert_eq!(transaction.amount, dec!(40.0));
+        assert_eq!(transaction.disputed_amount, dec!(40.0));
+
+        engine.process_resolve(ClientId(1), TxId(1)).unwrap();
+        let transaction = engine
+            .transactions
+            .get(&ClientId(1))
+            .unwrap()
+            .get(&TxId(1))
+            .unwrap();
+        assert_eq!(transaction.amount, dec!(40.0));
+        assert_eq!(transaction.disputed_amount, Decimal::ZERO);
+        assert_eq!(
+            engine.accounts.get(&ClientId(1)).unwrap().available,
+            dec!(40.0)
+        );
+        assert_eq!(engine.accounts.get(&ClientId(1)).unwrap().held, dec!(0.0));
+    }
+
+    #[test]
+    fn test_resolve_open_dispute_on_locked_account() {
+        let mut engine = PaymentEngine::new();
+
+        for (tx_id, amount) in [(1, dec!(30.0)), (2, dec!(20.0))] {
+            let deposit = Transaction {
+                tx_type: TransactionType::Deposit,
+                account_id: ClientId(1),
+                tx_id: TxId(tx_id),
+                amount,
+                currency: None,
+                disputed_amount: Decimal::ZERO,
+                sequence: 0,
+                timestamp: None,
+                status: TransactionStatus::Completed,
+                history: StatusHistory::default(),
+            };
+            engine.process_transaction(deposit).unwrap();
+        }
+
+        engine.process_dispute(ClientId(1), TxId(1)).unwrap();
+        engine.process_dispute(ClientId(1), TxId(2)).unwrap();
+        engine.process_chargeback(ClientId(1), TxId(1)).unwrap();
+
+        let account = engine.accounts.get(&ClientId(1)).unwrap();
+        assert!(account.locked);
+        assert_eq!(account.held, dec!(20.0));
+        assert_eq!(account.total, dec!(20.0));
+
+        engine
+            .process_resolve(ClientId(1), TxId(2))
+            .expect("Open dispute should be resolvable on a locked account");
+
+        let account = engine.accounts.get(&ClientId(1)).unwrap();
+        assert!(account.locked);
+        assert_eq!(account.available, dec!(20.0));
+        assert_eq!(account.held, dec!(0.0));
+        assert_eq!(account.total, dec!(20.0));
+
+        assert!(matches!(
+            engine.process_dispute(ClientId(1), TxId(2)),
+            Err(PaymentError::AccountLocked {
+                client: ClientId(1),
+                ..
+            })
+        ));
+        assert!(matches!(
+            engine.process_resolve(ClientId(1), TxId(2)),
+            Err(PaymentError::AccountLocked {
+                client: ClientId(1),
+                ..
+            })
+        ));
+    }
+
+    #[cfg(feature = "csv")]
+    #[test]
+    fn test_partial_clamp_after_chargeback_fee() {
+        #[derive(Default, Clone)]
+        struct Clamps(std::sync::Arc<std::sync::Mutex<Vec<(TxId, Decimal, Decimal)>>>);
+        impl EngineObserver for Clamps {
+            fn on_clamp(&mut self, _: ClientId, tx_id: TxId, requested: Decimal, amount: Decimal) {
+                self.0.lock().unwrap().push((tx_id, requested, amount));
+            }
+        }
+
+        let mut engine = PaymentEngine::with_config(EngineConfig {
+            withdrawal_policy: WithdrawalPolicy::PartialClamp,
+            chargebacks_before_lock: 2,
+            chargeback_fee: dec!(3),
+            ..EngineConfig::default()
+        });
+        let clamps = Clamps::default();
+        engine.set_observer(Box::new(clamps.clone()));
+        for entry in [
+            TransactionEntry::deposit(ClientId(1), TxId(1), dec!(1)),
+            TransactionEntry::deposit(ClientId(1), TxId(2), dec!(4)),
+            TransactionEntry::withdrawal(ClientId(1), TxId(3), dec!(10)),
+            TransactionEntry::deposit(ClientId(1), TxId(4), dec!(1)),
+            TransactionEntry::dispute(ClientId(1), TxId(4)),
+            TransactionEntry::chargeback(ClientId(1), TxId(4)),
+        ] {
+            engine.apply(entry).unwrap();
+        }
+        assert_eq!(engine.accounts[&ClientId(1)].available, dec!(-3));
+
+        // Clamping to a negative balance would credit the client.
+        assert_eq!(
+            engine
+                .apply(TransactionEntry::withdrawal(ClientId(1), TxId(5), dec!(5)))
+                .unwrap_err()
+                .code(),
+            "INSUFFICIENT_FUNDS"
+        );
+        let account = &engine.accounts[&ClientId(1)];
+        assert_eq!((account.available, account.total), (dec!(-3), dec!(-3)));
+        assert_eq!(
+            *clamps.0.lock().unwrap(),
+            vec![(TxId(3), dec!(10), dec!(5))]
+        );
+    }
+
+    #[test]
+    fn test_withdrawal_partial_clamp() {
+        let mut engine = PaymentEngine::with_config(EngineConfig {
+            withdrawal_policy: WithdrawalPolicy::PartialClamp,
+            ..EngineConfig::default()
+        });
+
+        let deposit = Transaction {
+            tx_type: TransactionType::Deposit,
+            account_id: ClientId(1),
+            tx_id: TxId(1),
+            amount: dec!(30.0),
+            currency: None,
+            disputed_amount: Decimal::ZERO,
+            sequence: 0,
+            timestamp: None,
+            status: TransactionStatus::Completed,
+            history: StatusHistory::default(),
+        };
+        engine.process_transaction(deposit).unwrap();
+
+        let withdrawal = Transaction {
+            tx_type: TransactionType::Withdrawal,
+            account_id: ClientId(1),
+            tx_id: TxId(2),
+            amount: dec!(100.0),
+            currency: None,
+            disputed_amount: Decimal::ZERO,
+            sequence: 0,
+            timestamp: None,
+            status: TransactionStatus::Completed,
+            history: StatusHistory::default(),
+        };
+        engine
+            .process_transaction(withdrawal)
+            .expect("Withdrawal should be clamped");
+
+        let account = engine.accounts.get(&ClientId(1)).unwrap();
+        assert_eq!(account.available, dec!(0.0));
+        assert_eq!(account.total, dec!(0.0));
+        assert_eq!(
+            engine
+                .transactions
+                .get(&ClientId(1))
+                .unwrap()
+                .get(&TxId(2))
+                .unwrap()
+                .amount,
+            dec!(30.0)
+        );
+
+        // Nothing is left to clamp to.
+        let withdrawal = Transaction {
+            tx_type: TransactionType::Withdrawal,
+            account_id: ClientId(1),
+            tx_id: TxId(3),
+            amount: dec!(1.0),
+            currency: None,
+            disputed_amount: Decimal::ZERO,
+            sequence: 0,
+            timestamp: None,
+            status: TransactionStatus::Completed,
+            history: StatusHistory::default(),
+        };
+        assert!(matches!(
+            engine.process_transaction(withdrawal),
+            Err(PaymentError::InsufficientFunds { .. })
+        ));
+        assert!(
+            !engine
+                .transactions
+                .get(&ClientId(1))
+                .unwrap()
+                .contains_key(&TxId(3))
+        );
+    }
+
+    #[test]
+    fn test_duplicate_policies() {
+        let deposit = |amount| Transaction {
+            tx_type: TransactionType::Deposit,
+            account_id: ClientId(1),
+            tx_id: TxId(1),
+            amount,
+            currency: None,
+            disputed_amount: Decimal::ZERO,
+            sequence: 0,
+            timestamp: None,
+            status: TransactionStatus::Completed,
+            history: StatusHistory::default(),
+        };
+
+        let mut engine = PaymentEngine::new();
+        engine.process_transaction(deposit(dec!(10.0))).unwrap();
+        assert!(matches!(
+            engine.process_transaction(deposit(dec!(10.0))),
+            Err(PaymentError::TransactionAlreadyExists { .. })
+        ));
+        assert!(matches!(
+            engine.process_transaction(deposit(dec!(11.0))),
+            Err(PaymentError::TransactionAlreadyExists { .. })
+        ));
+
+        let mut engine = PaymentEngine::with_config(EngineConfig {
+            duplicate_policy: DuplicatePolicy::SkipIdentical,
+            ..EngineConfig::default()
+        });
+        engine.process_transaction(deposit(dec!(10.0))).unwrap();
+        engine
+            .process_transaction(deposit(dec!(10.0)))
+            .expect("Identical replay should be skipped");
+        assert_eq!(engine.accounts.get(&ClientId(1)).unwrap().total, dec!(10.0));
+        assert!(matches!(
+            engine.process_transaction(deposit(dec!(11.0))),
+            Err(PaymentError::DuplicateWithDifferentAmount { tx_id: TxId(1), stored, incoming })
+                if stored == dec!(10.0) && incoming == dec!(11.0)
+        ));
+
+        let mut engine = PaymentEngine::with_config(EngineConfig {
+            duplicate_policy: DuplicatePolicy::RejectMismatch,
+            ..EngineConfig::default()
+        });
+        engine.process_transaction(deposit(dec!(10.0))).unwrap();
+        assert!(matches!(
+            engine.process_transaction(deposit(dec!(10.0))),
+            Err(PaymentError::TransactionAlreadyExists { .. })
+        ));
+        assert!(matches!(
+            engine.process_transaction(deposit(dec!(11.0))),
+            Err(PaymentError::DuplicateWithDifferentAmount { .. })
+        ));
+        assert_eq!(engine.accounts.get(&ClientId(1)).unwrap().total, dec!(10.0));
+    }
+
+    #[test]
+    fn test_freeze_open_disputes_on_lock() {
+        let mut engine = PaymentEngine::with_config(EngineConfig {
+            freeze_disputes_on_lock: true,
+            ..EngineConfig::default()
+        });
+
+        for (tx_id, amount) in [(1, dec!(30.0)), (2, dec!(20.0)), (3, dec!(10.0))] {
+            let deposit = Transaction {
+                tx_type: TransactionType::Deposit,
+                account_id: ClientId(1),
+                tx_id: TxId(tx_id),
+                amount,
+                currency: None,
+                disputed_amount: Decimal::ZERO,
+                sequence: 0,
+                timestamp: None,
+                status: TransactionStatus::Completed,
+                history: StatusHistory::default(),
+            };
+            engine.process_transaction(deposit).unwrap();
+        }
+
+        engine.process_dispute(ClientId(1), TxId(1)).unwrap();
+        engine.process_dispute(ClientId(1), TxId(2)).unwrap();
+        engine.process_chargeback(ClientId(1), TxId(1)).unwrap();
+
+        let transactions = engine.transactions.get(&ClientId(1)).unwrap();
+        assert_eq!(transactions[&TxId(2)].status, TransactionStatus::Frozen);
+        assert_eq!(transactions[&TxId(3)].status, TransactionStatus::Completed);
+        assert_eq!(engine.accounts.get(&ClientId(1)).unwrap().held, dec!(20.0));
+
+        assert!(matches!(
+            engine.process_resolve(ClientId(1), TxId(2)),
+            Err(PaymentError::TransactionFrozen(TxId(2)))
+        ));
+        assert!(matches!(
+            engine.process_chargeback(ClientId(1), TxId(2)),
+            Err(PaymentError::TransactionFrozen(TxId(2)))
+        ));
+        assert_eq!(engine.accounts.get(&ClientId(1)).unwrap().held, dec!(20.0));
+        assert_eq!(engine.verify_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn test_accounts_where() {
+        let mut engine = PaymentEngine::new();
+        for (client, held, locked) in [
+            (1, dec!(0.0), false),
+            (2, dec!(5.0), false),
+            (3, dec!(0.0), true),
+            (4, dec!(1.0), true),
+        ] {
+            engine.accounts.insert(
+                ClientId(client),
+                Account {
+                    available: dec!(10.0),
+                    held,
+                    total: dec!(10.0) + held,
+                    locked,
+                    ..Account::new(ClientId(client))
+                },
+            );
+        }
+
+        let mut locked: Vec<u16> = engine.locked_accounts().map(|a| a.client.0).collect();
+        locked.sort_unstable();
+        assert_eq!(locked, vec![3, 4]);
+
+        let mut held: Vec<u16> = engine
+            .accounts_where(|a| a.held > Decimal::ZERO)
+            .map(|a| a.client.0)
+            .collect();
+        held.sort_unstable();
+        assert_eq!(held, vec![2, 4]);
+
+        assert_eq!(engine.accounts_where(|a| a.total > dec!(100.0)).count(), 0);
+    }
+
+    #[test]
+    fn test_max_accounts() {
+        let mut engine = PaymentEngine::with_config(EngineConfig {
+            max_accounts: Some(2),
+            ..EngineConfig::default()
+        });
+
+        let deposit = |account_id, tx_id| Transaction {
+            tx_type: TransactionType::Deposit,
+            account_id: ClientId(account_id),
+            tx_id: TxId(tx_id),
+            amount: dec!(1.0),
+            currency: None,
+            disputed_amount: Decimal::ZERO,
+            sequence: 0,
+            timestamp: None,
+            status: TransactionStatus::Completed,
+            history: StatusHistory::default(),
+        };
+
+        engine.process_transaction(deposit(1, 1)).unwrap();
+        engine.process_transaction(deposit(2, 2)).unwrap();
+        assert!(matches!(
+            engine.process_transaction(deposit(3, 3)),
+            Err(PaymentError::AccountLimitExceeded(ClientId(3)))
+        ));
+        assert!(!engine.accounts.contains_key(&ClientId(3)));
+
+        engine
+            .process_transaction(deposit(1, 4))
+            .expect("Existing accounts keep processing");
+        assert_eq!(engine.accounts.len(), 2);
+        assert_eq!(engine.accounts.get(&ClientId(1)).unwrap().total, dec!(2.0));
+    }
+
+    #[test]
+    fn test_history_in_application_order() {
+        let mut engine = PaymentEngine::new();
+        let operations = [
+            (TransactionType::Deposit, 1, 10, dec!(5.0)),
+            (TransactionType::Deposit, 2, 3, dec!(7.0)),
+            (TransactionType::Withdrawal, 1, 2, dec!(1.0)),
+            (TransactionType::Deposit, 1, 7, dec!(2.0)),
+            (TransactionType::Withdrawal, 2, 1, dec!(3.0)),
+            (TransactionType::Withdrawal, 1, 4, dec!(6.0)),
+        ];
+
+        for (tx_type, account_id, tx_id, amount) in operations {
+            let transaction = Transaction {
+                tx_type,
+                account_id: ClientId(account_id),
+                tx_id: TxId(tx_id),
+                amount,
+                currency: None,
+                disputed_amount: Decimal::ZERO,
+                sequence: 0,
+                timestamp: None,
+                status: TransactionStatus::Completed,
+                history: StatusHistory::default(),
+            };
+            engine.process_transaction(transaction).unwrap();
+        }
+
+        let history: Vec<u32> = engine.history(ClientId(1)).map(|t| t.tx_id.0).collect();
+        assert_eq!(history, vec![10, 2, 7, 4]);
+        let history: Vec<u32> = engine.history(ClientId(2)).map(|t| t.tx_id.0).collect();
+        assert_eq!(history, vec![3, 1]);
+        assert_eq!(engine.history(ClientId(3)).count(), 0);
+    }
+
+    #[test]
+    fn test_display_uses_account_scale() {
+        let mut engine = PaymentEngine::with_config(EngineConfig {
+            account_scales: HashMap::from([(ClientId(1), 0), (ClientId(2), 3)]),
+            ..EngineConfig::default()
+        });
+
+        for (account_id, tx_id) in [(1, 1), (2, 2), (3, 3)] {
+            let deposit = Transaction {
+                tx_type: TransactionType::Deposit,
+                account_id: ClientId(account_id),
+                tx_id: TxId(tx_id),
+                amount: dec!(1500.25),
+                currency: None,
+                disputed_amount: Decimal::ZERO,
+                sequence: 0,
+                timestamp: None,
+                status: TransactionStatus::Completed,
+                history: StatusHistory::default(),
+            };
+            engine.process_transaction(deposit).unwrap();
+        }
+
+        let output = format!("{}", engine);
+        assert!(output.contains("1, 1500, 0, 1500, false"));
+        assert!(output.contains("2, 1500.250, 0.000, 1500.250, false"));
+        assert!(output.contains("3, 1500.2500, 0.0000, 1500.2500, false"));
+    }
+
+    #[cfg(feature = "csv")]
+    #[test]
+    fn test_write_json() {
+        let mut engine = PaymentEngine::with_config(EngineConfig {
+            account_scales: HashMap::from([(ClientId(2), 2)]),
+            ..EngineConfig::default()
+        });
+        for (account_id, tx_id) in [(2, 1), (1, 2)] {
+            let deposit = Transaction {
+                tx_type: TransactionType::Deposit,
+                account_id: ClientId(account_id),
+                tx_id: TxId(tx_id),
+                amount: dec!(7.5),
+                currency: None,
+                disputed_amount: Decimal::ZERO,
+                sequence: 0,
+                timestamp: None,
+                status: TransactionStatus::Completed,
+                history: StatusHistory::default(),
+            };
+            engine.process_transaction(deposit).unwrap();
+        }
+
+        let mut output = Vec::new();
+        engine.write_json(&mut output, None).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            r#"[{"client":1,"available":"7.5000","held":"0.0000","total":"7.5000","locked":false},{"client":2,"available":"7.50","held":"0.00","total":"7.50","locked":false}]"#
+        );
+    }
+
+    #[test]
+    fn test_format_amount() {
+        assert_eq!(format_amount(dec!(50), 4), "50.0000");
+        assert_eq!(format_amount(dec!(50.50), 4), "50.5000");
+        assert_eq!(format_amount(dec!(1.23455), 4), "1.2346");
+        assert_eq!(format_amount(dec!(1.23465), 4), "1.2346");
+        assert_eq!(format_amount(dec!(0.000049), 4), "0.0000");
+        assert_eq!(format_amount(dec!(-5), 4), "-5.0000");
+        assert_eq!(format_amount(dec!(-2.50005), 4), "-2.5000");
+        assert_eq!(format_amount(dec!(-0.00001), 4), "0.0000");
+        assert_eq!(format_amount(dec!(7.5), 0), "8");
+        assert_eq!(
+            format_amount(Decimal::MAX, 4),
+            "79228162514264337593543950335.0000"
+        );
+        assert_eq!(
+            format_amount(Decimal::MIN, 2),
+            "-79228162514264337593543950335.00"
+        );
+        assert_eq!(
+            format_amount(dec!(7922816251426433759354395.0335), 2),
+            "7922816251426433759354395.03"
+        );
+        assert_eq!(
+            format_amount(dec!(7922816251426433759354395.0335), 10),
+            "7922816251426433759354395.0335000000"
+        );
+    }
+
+    #[cfg(feature = "csv")]
+    #[test]
+    fn test_into_csv_iter() {
+        let mut engine = PaymentEngine::new();
+        for client in 1..=3 {
+            engine
+                .apply(TransactionEntry::deposit(
+                    ClientId(client),
+                    TxId(client.into()),
+                    dec!(1.5),
+                ))
+                .unwrap();
+        }
+        let expected: Vec<String> = engine.to_string().lines().map(str::to_owned).collect();
+
+        let mut lines = engine.into_csv_iter();
+        assert_eq!(
+            lines.next().as_deref(),
+            Some("client, available, held, total, locked")
+        );
+        let rest: Vec<String> = lines.collect();
+        assert_eq!(rest.len(), 3);
+        assert_eq!(rest, expected[1..]);
+        assert!(rest.contains(&"2, 1.5000, 0.0000, 1.5000, false".to_string()));
+    }
+
+    #[cfg(feature = "csv")]
+    #[test]
+    fn test_rounding_strategies() {
+        let cases = [
+            (RoundingStrategy::HalfEven, "0.0000", "0.0002", "0.0000"),
+            (RoundingStrategy::HalfUp, "0.0001", "0.0002", "-0.0001"),
+            (RoundingStrategy::HalfDown, "0.0000", "0.0001", "0.0000"),
+            (RoundingStrategy::TowardZero, "0.0000", "0.0001", "0.0000"),
+            (
+                RoundingStrategy::AwayFromZero,
+                "0.0001",
+                "0.0002",
+                "-0.0001",
+            ),
+        ];
+        for (rounding, half, odd_half, negative) in cases {
+            assert_eq!(
+                format_amount_with(dec!(0.00005), 4, rounding),
+                half,
+                "{rounding:?}"
+            );
+            assert_eq!(
+                format_amount_with(dec!(0.00015), 4, rounding),
+                odd_half,
+                "{rounding:?}"
+            );
+            assert_eq!(
+                format_amount_with(dec!(-0.00005), 4, rounding),
+                negative,
+                "{rounding:?}"
+            );
+        }
+        assert_eq!(
+            format_amount_with(dec!(0.00001), 4, RoundingStrategy::AwayFromZero),
+            "0.0001"
+        );
+
+        let mut engine = PaymentEngine::with_config(EngineConfig {
+            rounding: RoundingStrategy::HalfUp,
+            max_amount_scale: None,
+            ..EngineConfig::default()
+        });
+        engine
+            .apply(TransactionEntry::deposit(
+                ClientId(1),
+                TxId(1),
+                dec!(0.00005),
+            ))
+            .unwrap();
+        let mut output = Vec::new();
+        engine.write_csv(&mut output, None).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client, available, held, total, locked\n1, 0.0001, 0.0000, 0.0001, false\n"
+        );
+        assert!(
+            engine
+                .to_string()
+                .ends_with("1, 0.0001, 0.0000, 0.0001, false\n")
+        );
+    }
+
+    #[test]
+    fn test_report_precision() {
+        let mut engine = PaymentEngine::with_config(EngineConfig {
+            report_precision: Some(2),
+            account_scales: HashMap::from([(ClientId(1), 0)]),
+            ..EngineConfig::default()
+        });
+        let deposit = Transaction {
+            tx_type: TransactionType::Deposit,
+            account_id: ClientId(1),
+            tx_id: TxId(1),
+            amount: dec!(50),
+            currency: None,
+            disputed_amount: Decimal::ZERO,
+            sequence: 0,
+            timestamp: None,
+            status: TransactionStatus::Completed,
+            history: StatusHistory::default(),
+        };
+        engine.process_transaction(deposit).unwrap();
+
+        assert_eq!(
+            engine.to_string(),
+            "client, available, held, total, locked\n1, 50.00, 0.00, 50.00, false\n"
+        );
+        let mut output = Vec::new();
+        engine.write_csv(&mut output, Some(4)).unwrap();
+        assert!(
+            String::from_utf8(output)
+                .unwrap()
+                .contains("1, 50.0000, 0.0000")
+        );
+    }
+
+    #[test]
+    fn test_partial_resolves() {
+        let mut engine = PaymentEngine::new();
+        let deposit = Transaction {
+            tx_type: TransactionType::Deposit,
+            account_id: ClientId(1),
+            tx_id: TxId(1),
+            amount: dec!(100.0),
+            currency: None,
+            disputed_amount: Decimal::ZERO,
+            sequence: 0,
+            timestamp: None,
+            status: TransactionStatus::Completed,
+            history: StatusHistory::default(),
+        };
+        engine.process_transaction(deposit).unwrap();
+        engine.process_dispute(ClientId(1), TxId(1)).unwrap();
+
+        engine
+            .process_partial_resolve(ClientId(1), TxId(1), dec!(30.0))
+            .unwrap();
+        let transaction = &engine.transactions[&ClientId(1)][&TxId(1)];
+        assert_eq!(transaction.status, TransactionStatus::PartiallyResolved);
+        assert_eq!(transaction.disputed_amount, dec!(70.0));
+        assert_eq!(engine.accounts[&ClientId(1)].available, dec!(30.0));
+        assert_eq!(engine.accounts[&ClientId(1)].held, dec!(70.0));
+
+        engine
+            .process_partial_resolve(ClientId(1), TxId(1), dec!(50.0))
+            .unwrap();
+        assert_eq!(
+            engine.transactions[&ClientId(1)][&TxId(1)].status,
+            TransactionStatus::PartiallyResolved
+        );
+        assert_eq!(engine.accounts[&ClientId(1)].held, dec!(20.0));
+
+        assert!(matches!(
+            engine.process_partial_resolve(ClientId(1), TxId(1), dec!(25.0)),
+            Err(PaymentError::InsufficientHoldFunds { .. })
+        ));
+        assert!(matches!(
+            engine.process_partial_resolve(ClientId(1), TxId(1), dec!(0.0)),
+            Err(PaymentError::InvalidAmount(_))
+        ));
+        assert!(matches!(
+            engine.process_dispute(ClientId(1), TxId(1)),
+            Err(PaymentError::TransactionAlreadyDisputed(_))
+        ));
+
+        engine
+            .process_partial_resolve(ClientId(1), TxId(1), dec!(20.0))
+            .unwrap();
+        let transaction = &engine.transactions[&ClientId(1)][&TxId(1)];
+        assert_eq!(transaction.status, TransactionStatus::Resolved);
+        assert_eq!(transaction.disputed_amount, Decimal::ZERO);
+        assert_eq!(engine.accounts[&ClientId(1)].available, dec!(100.0));
+        assert_eq!(engine.accounts[&ClientId(1)].held, Decimal::ZERO);
+        assert_eq!(engine.verify_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn test_chargeback_after_partial_resolve() {
+        let mut engine = PaymentEngine::new();
+        let deposit = Transaction {
+            tx_type: TransactionType::Deposit,
+            account_id: ClientId(1),
+            tx_id: TxId(1),
+            amount: dec!(100.0),
+            currency: None,
+            disputed_amount: Decimal::ZERO,
+            sequence: 0,
+            timestamp: None,
+            status: TransactionStatus::Completed,
+            history: StatusHistory::default(),
+        };
+        engine.process_transaction(deposit).unwrap();
+        engine.process_dispute(ClientId(1), TxId(1)).unwrap();
+        engine
+            .process_partial_resolve(ClientId(1), TxId(1), dec!(40.0))
+            .unwrap();
+
+        engine.process_chargeback(ClientId(1), TxId(1)).unwrap();
+
+        let account = &engine.accounts[&ClientId(1)];
+        assert_eq!(account.available, dec!(40.0));
+        assert_eq!(account.held, Decimal::ZERO);
+        assert_eq!(account.total, dec!(40.0));
+        assert!(account.locked);
+        assert_eq!(
+            engine.transactions[&ClientId(1)][&TxId(1)].status,
+            TransactionStatus::Chargebacked
+        );
+    }
+
+    #[test]
+    fn test_merge_unchecked() {
+        let deposit = |account_id, tx_id, amount| Transaction {
+            tx_type: TransactionType::Deposit,
+            account_id,
+            tx_id,
+            amount,
+            currency: None,
+            disputed_amount: Decimal::ZERO,
+            sequence: 0,
+            timestamp: None,
+            status: TransactionStatus::Completed,
+            history: StatusHistory::default(),
+        };
+
+        let mut engine = PaymentEngine::new();
+        engine
+            .process_transaction(deposit(ClientId(1), TxId(1), dec!(10.0)))
+            .unwrap();
+        engine
+            .process_transaction(deposit(ClientId(2), TxId(2), dec!(5.0)))
+            .unwrap();
+
+        let mut shard = PaymentEngine::new();
+        shard
+            .process_transaction(deposit(ClientId(1), TxId(3), dec!(2.5)))
+            .unwrap();
+        shard
+            .process_transaction(deposit(ClientId(3), TxId(4), dec!(7.0)))
+            .unwrap();
+        shard.process_dispute(ClientId(3), TxId(4)).unwrap();
+        shard.process_chargeback(ClientId(3), TxId(4)).unwrap();
+
+        engine.merge_unchecked(shard).unwrap();
+
+        assert_eq!(engine.accounts[&ClientId(1)].total, dec!(12.5));
+        assert_eq!(engine.accounts[&ClientId(2)].total, dec!(5.0));
+        assert!(engine.accounts[&ClientId(3)].locked);
+        let history: Vec<u32> = engine.history(ClientId(1)).map(|t| t.tx_id.0).collect();
+        assert_eq!(history, vec![1, 3]);
+        assert_eq!(
+            engine.transactions[&ClientId(3)][&TxId(4)].status,
+            TransactionStatus::Chargebacked
+        );
+        assert_eq!(engine.verify_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn test_merge_colliding_tx_id() {
+        let deposit = |tx_id| Transaction {
+            tx_type: TransactionType::Deposit,
+            account_id: ClientId(1),
+            tx_id: TxId(tx_id),
+            amount: dec!(1.0),
+            currency: None,
+            disputed_amount: Decimal::ZERO,
+            sequence: 0,
+            timestamp: None,
+            status: TransactionStatus::Completed,
+            history: StatusHistory::default(),
+        };
+
+        let mut engine = PaymentEngine::new();
+        engine.process_transaction(deposit(1)).unwrap();
+        let mut shard = PaymentEngine::new();
+        shard.process_transaction(deposit(2)).unwrap();
+        shard.process_transaction(deposit(1)).unwrap();
+
+        assert_eq!(
+            engine.merge_unchecked(shard),
+            Err(MergeError::TransactionOverlap {
+                client: ClientId(1),
+                tx_id: TxId(1),
+            })
+        );
+        assert_eq!(engine.accounts[&ClientId(1)].total, dec!(1.0));
+        assert_eq!(engine.transactions[&ClientId(1)].len(), 1);
+    }
+
+    #[cfg(all(feature = "csv", feature = "digest"))]
+    #[test]
+    fn test_merge() {
+        let entries = vec![
+            TransactionEntry::deposit(ClientId(1), TxId(1), dec!(10.0)),
+            TransactionEntry::deposit(ClientId(2), TxId(2), dec!(5.0)),
+            TransactionEntry::withdrawal(ClientId(1), TxId(3), dec!(2.5)),
+            TransactionEntry::dispute(ClientId(2), TxId(2)),
+            TransactionEntry::deposit(ClientId(3), TxId(4), dec!(1.0)),
+            TransactionEntry::chargeback(ClientId(2), TxId(2)),
+            TransactionEntry::withdrawal(ClientId(3), TxId(5), dec!(4.0)),
+        ];
+        let process = |entries: Vec<TransactionEntry>| {
+            let mut engine = PaymentEngine::new();
+            process_stream_with_sink(&mut engine, entries.into_iter(), &mut NullSink);
+            engine
+        };
+        let (odd, even): (Vec<_>, Vec<_>) = entries
+            .iter()
+            .cloned()
+            .partition(|entry| entry.account_id.0 % 2 == 1);
+
+        let mut merged = process(odd);
+        merged.merge(process(even)).unwrap();
 
+        let concatenated = process(entries);
+        assert_eq!(merged.state_digest(), concatenated.state_digest());
+        assert_eq!(merged.verify_invariants(), Ok(()));
+
+        let before = merged.state_digest();
+        let overlapping = process(vec![
+            TransactionEntry::deposit(ClientId(4), TxId(6), dec!(1.0)),
+            TransactionEntry::deposit(ClientId(3), TxId(7), dec!(1.0)),
+        ]);
+        assert_eq!(
+            merged.merge(overlapping),
+            Err(MergeError::ClientOverlap(ClientId(3)))
+        );
+        assert_eq!(merged.state_digest(), before);
+        assert!(!merged.accounts.contains_key(&ClientId(4)));
+
+        // A withdrawal refused to a new client leaves no state to overlap.
+        let refused = process(vec![TransactionEntry::withdrawal(
+            ClientId(3),
+            TxId(7),
+            dec!(1.0),
+        )]);
+        assert!(refused.failed.is_empty());
+        merged.merge(refused).unwrap();
+        assert_eq!(merged.state_digest(), before);
+    }
+
+    #[test]
+    fn test_write_csv_precision() {
+        let mut engine = PaymentEngine::new();
         engine.accounts.insert(
-            3,
+            ClientId(1),
             Account {
-                client: 3,
-                available: dec!(999.9999),
-                held: dec!(0.0001),
-                total: dec!(1000.0),
-                locked: false,
+                available: dec!(2.125),
+                held: dec!(2.135),
+                total: dec!(4.26),
+                ..Account::new(ClientId(1))
+            },
+        );
+
+        let write = |precision| {
+            let mut output = Vec::new();
+            engine.write_csv(&mut output, precision).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+
+        assert_eq!(
+            write(Some(2)),
+            "client, available, held, total, locked\n1, 2.12, 2.14, 4.26, false\n"
+        );
+        assert_eq!(
+            write(None),
+            "client, available, held, total, locked\n1, 2.1250, 2.1350, 4.2600, false\n"
+        );
+        assert_eq!(
+            write(Some(20)),
+            "client, available, held, total, locked\n\
+             1, 2.1250000000, 2.1350000000, 4.2600000000, false\n"
+        );
+        assert_eq!(write(None), format!("{}", engine));
+    }
+
+    #[test]
+    fn test_chargebacks_before_lock() {
+        let mut engine = PaymentEngine::with_config(EngineConfig {
+            chargebacks_before_lock: 2,
+            ..EngineConfig::default()
+        });
+        for tx_id in 1..=3 {
+            let deposit = Transaction {
+                tx_type: TransactionType::Deposit,
+                account_id: ClientId(1),
+                tx_id: TxId(tx_id),
+                amount: dec!(10.0),
+                currency: None,
+                disputed_amount: Decimal::ZERO,
+                sequence: 0,
+                timestamp: None,
+                status: TransactionStatus::Completed,
+                history: StatusHistory::default(),
+            };
+            engine.process_transaction(deposit).unwrap();
+        }
+
+        engine.process_dispute(ClientId(1), TxId(1)).unwrap();
+        engine.process_chargeback(ClientId(1), TxId(1)).unwrap();
+        assert_eq!(engine.accounts[&ClientId(1)].chargebacks, 1);
+        assert!(!engine.accounts[&ClientId(1)].locked);
+
+        engine.process_dispute(ClientId(1), TxId(2)).unwrap();
+        engine.process_chargeback(ClientId(1), TxId(2)).unwrap();
+        assert_eq!(engine.accounts[&ClientId(1)].chargebacks, 2);
+        assert!(engine.accounts[&ClientId(1)].locked);
+        assert_eq!(engine.accounts[&ClientId(1)].total, dec!(10.0));
+        assert!(matches!(
+            engine.process_dispute(ClientId(1), TxId(3)),
+            Err(PaymentError::AccountLocked {
+                client: ClientId(1),
+                ..
+            })
+        ));
+    }
+
+    #[cfg(feature = "csv")]
+    #[test]
+    fn test_apply() {
+        let mut engine = PaymentEngine::new();
+        let view = |available, held| AccountView {
+            client: ClientId(1),
+            available,
+            held,
+            total: dec!(10.0),
+            locked: false,
+        };
+
+        let deposit = engine.apply(TransactionEntry::deposit(ClientId(1), TxId(1), dec!(10.0)));
+        assert_eq!(deposit.unwrap(), view(dec!(10.0), dec!(0.0)));
+
+        let dispute = engine.apply(TransactionEntry::dispute(ClientId(1), TxId(1)));
+        assert_eq!(dispute.unwrap(), view(dec!(0.0), dec!(10.0)));
+
+        let resolve = engine.apply(TransactionEntry::resolve(ClientId(1), TxId(1)));
+        assert_eq!(resolve.unwrap(), view(dec!(10.0), dec!(0.0)));
+
+        assert!(matches!(
+            engine.apply(TransactionEntry::resolve(ClientId(1), TxId(1))),
+            Err(PaymentError::TransactionAlreadyResolved(TxId(1)))
+        ));
+    }
+
+    #[test]
+    fn test_rejected_transaction_creates_no_account() {
+        let mut engine = PaymentEngine::new();
+        let withdrawal = Transaction {
+            tx_type: TransactionType::Withdrawal,
+            account_id: ClientId(9999),
+            tx_id: TxId(1),
+            amount: dec!(5.0),
+            currency: None,
+            disputed_amount: Decimal::ZERO,
+            sequence: 0,
+            timestamp: None,
+            status: TransactionStatus::Completed,
+            history: StatusHistory::default(),
+        };
+
+        assert!(matches!(
+            engine.process_transaction(withdrawal),
+            Err(PaymentError::InsufficientFunds { .. })
+        ));
+        assert!(engine.accounts.is_empty());
+        assert!(engine.transactions.is_empty());
+        assert_eq!(
+            engine.to_string(),
+            "client, available, held, total, locked\n"
+        );
+
+        // Nor does a rejected deposit, which would also take a `max_accounts`
+        // slot.
+        let mut engine = PaymentEngine::with_config(EngineConfig {
+            max_accounts: Some(1),
+            ..EngineConfig::default()
+        });
+        let deposit = |account_id, amount| Transaction {
+            tx_type: TransactionType::Deposit,
+            account_id,
+            tx_id: TxId(3),
+            amount,
+            currency: None,
+            disputed_amount: Decimal::ZERO,
+            sequence: 0,
+            timestamp: None,
+            status: TransactionStatus::Completed,
+            history: StatusHistory::default(),
+        };
+        assert!(
+            engine
+                .process_transaction(deposit(ClientId(2), dec!(-3)))
+                .is_err()
+        );
+        assert!(engine.accounts.is_empty());
+        engine
+            .process_transaction(deposit(ClientId(1), dec!(3)))
+            .unwrap();
+        assert_eq!(engine.accounts.len(), 1);
+    }
+
+    #[test]
+    fn test_chargeback_fee() {
+        let mut engine = PaymentEngine::with_config(EngineConfig {
+            chargeback_fee: dec!(2.5),
+            chargebacks_before_lock: 2,
+            ..EngineConfig::default()
+        });
+        for (tx_id, amount) in [(1, dec!(10.0)), (2, dec!(1.0))] {
+            let deposit = Transaction {
+                tx_type: TransactionType::Deposit,
+                account_id: ClientId(1),
+                tx_id: TxId(tx_id),
+                amount,
+                currency: None,
+                disputed_amount: Decimal::ZERO,
+                sequence: 0,
+                timestamp: None,
+                status: TransactionStatus::Completed,
+                history: StatusHistory::default(),
+            };
+            engine.process_transaction(deposit).unwrap();
+        }
+
+        engine.process_dispute(ClientId(1), TxId(1)).unwrap();
+        engine.process_dispute(ClientId(1), TxId(2)).unwrap();
+        engine.process_chargeback(ClientId(1), TxId(2)).unwrap();
+        let account = &engine.accounts[&ClientId(1)];
+        assert_eq!(account.available, dec!(-2.5));
+        assert_eq!(account.held, dec!(10.0));
+        assert_eq!(account.total, dec!(7.5));
+        assert!(!account.locked);
+
+        engine.process_chargeback(ClientId(1), TxId(1)).unwrap();
+        let account = &engine.accounts[&ClientId(1)];
+        assert_eq!(account.available, dec!(-5.0));
+        assert_eq!(account.held, dec!(0.0));
+        assert_eq!(account.total, dec!(-5.0));
+        assert!(account.locked);
+
+        let fees: Vec<(u32, Decimal)> = engine.fees[&ClientId(1)]
+            .iter()
+            .map(|fee| (fee.tx_id.0, fee.amount))
+            .collect();
+        assert_eq!(fees, vec![(2, dec!(2.5)), (1, dec!(2.5))]);
+        assert!(
+            engine.fees[&ClientId(1)]
+                .iter()
+                .all(|fee| fee.tx_type == TransactionType::Fee)
+        );
+    }
+
+    #[cfg(feature = "csv")]
+    #[test]
+    fn test_settled_retention() {
+        let entries = vec![
+            TransactionEntry::deposit(ClientId(1), TxId(1), dec!(10.0)),
+            TransactionEntry::deposit(ClientId(1), TxId(2), dec!(20.0)),
+            TransactionEntry::deposit(ClientId(1), TxId(3), dec!(30.0)),
+            TransactionEntry::deposit(ClientId(1), TxId(4), dec!(40.0)),
+            TransactionEntry::withdrawal(ClientId(1), TxId(5), dec!(5.0)),
+            TransactionEntry::dispute(ClientId(1), TxId(1)),
+            TransactionEntry::resolve(ClientId(1), TxId(1)),
+            TransactionEntry::dispute(ClientId(1), TxId(2)),
+            TransactionEntry::partial_resolve(ClientId(1), TxId(2), dec!(5.0)),
+            TransactionEntry::resolve(ClientId(1), TxId(2)),
+            TransactionEntry::dispute(ClientId(1), TxId(4)),
+            TransactionEntry::dispute(ClientId(1), TxId(3)),
+            TransactionEntry::chargeback(ClientId(1), TxId(3)),
+            TransactionEntry::dispute(ClientId(1), TxId(1)),
+            TransactionEntry::chargeback(ClientId(1), TxId(1)),
+        ];
+        let run = |settled_retention| {
+            let mut engine = PaymentEngine::with_config(EngineConfig {
+                settled_retention,
+                chargebacks_before_lock: 2,
+                ..EngineConfig::default()
+            });
+            for entry in entries.clone() {
+                engine.apply(entry).unwrap();
+            }
+            engine
+        };
+
+        let kept = run(None);
+        let evicting = run(Some(1));
+
+        assert_eq!(evicting.accounts, kept.accounts);
+        let mut remaining: Vec<u32> = evicting.transactions[&ClientId(1)]
+            .keys()
+            .map(|tx_id| tx_id.0)
+            .collect();
+        remaining.sort_unstable();
+        // Resolved transactions may be disputed again, so only the charged
+        // back ones are evicted.
+        assert_eq!(remaining, vec![1, 2, 4, 5]);
+        assert_eq!(kept.transactions[&ClientId(1)].len(), 5);
+        assert_eq!(evicting.audit(), vec![]);
+        assert_eq!(evicting.verify_invariants(), Ok(()));
+    }
+
+    #[cfg(feature = "csv")]
+    #[test]
+    fn test_settled_retention_rejects_reuse() {
+        let mut engine = PaymentEngine::with_config(EngineConfig {
+            settled_retention: Some(0),
+            ..EngineConfig::default()
+        });
+        let deposit = || Transaction {
+            tx_type: TransactionType::Deposit,
+            account_id: ClientId(1),
+            tx_id: TxId(1),
+            amount: dec!(10),
+            currency: None,
+            disputed_amount: Decimal::ZERO,
+            sequence: 0,
+            timestamp: None,
+            status: TransactionStatus::Completed,
+            history: StatusHistory::default(),
+        };
+        engine.process_transaction(deposit()).unwrap();
+        engine.process_dispute(ClientId(1), TxId(1)).unwrap();
+        engine.process_resolve(ClientId(1), TxId(1)).unwrap();
+
+        assert_eq!(
+            engine.process_transaction(deposit()),
+            Err(PaymentError::TransactionAlreadyExists {
+                client: ClientId(1),
+                tx_id: TxId(1),
+            })
+        );
+        assert_eq!(engine.accounts[&ClientId(1)].available, dec!(10));
+
+        // Only a charged back transaction is evicted, and its tx id is taken.
+        let mut engine = PaymentEngine::with_config(EngineConfig {
+            settled_retention: Some(0),
+            chargebacks_before_lock: 2,
+            ..EngineConfig::default()
+        });
+        engine.process_transaction(deposit()).unwrap();
+        engine.process_dispute(ClientId(1), TxId(1)).unwrap();
+        engine.process_chargeback(ClientId(1), TxId(1)).unwrap();
+        assert!(engine.transactions[&ClientId(1)].is_empty());
+        assert_eq!(
+            engine.process_transaction(deposit()),
+            Err(PaymentError::TransactionAlreadyExists {
+                client: ClientId(1),
+                tx_id: TxId(1),
+            })
+        );
+        assert_eq!(
+            engine.process_dispute(ClientId(1), TxId(1)),
+            Err(PaymentError::TransactionEvicted(TxId(1)))
+        );
+        assert_eq!(engine.accounts[&ClientId(1)].total, dec!(0));
+    }
+
+    #[cfg(feature = "csv")]
+    #[test]
+    fn test_redispute_after_resolve() {
+        let mut engine = PaymentEngine::with_config(EngineConfig {
+            settled_retention: Some(1),
+            ..EngineConfig::default()
+        });
+        let entries = vec![
+            TransactionEntry::deposit(ClientId(1), TxId(1), dec!(10.0)),
+            TransactionEntry::dispute(ClientId(1), TxId(1)),
+            TransactionEntry::resolve(ClientId(1), TxId(1)),
+            TransactionEntry::dispute(ClientId(1), TxId(1)),
+            TransactionEntry::partial_resolve(ClientId(1), TxId(1), dec!(4.0)),
+            TransactionEntry::resolve(ClientId(1), TxId(1)),
+            TransactionEntry::dispute(ClientId(1), TxId(1)),
+        ];
+        for entry in entries {
+            engine.apply(entry).unwrap();
+        }
+
+        let account = &engine.accounts[&ClientId(1)];
+        assert_eq!(account.available, dec!(0.0));
+        assert_eq!(account.held, dec!(10.0));
+        assert_eq!(
+            engine.transactions[&ClientId(1)][&TxId(1)].status,
+            TransactionStatus::Disputed
+        );
+
+        // Resolved transactions are never evicted, so tx 2 outlives the
+        // chargeback of tx 1.
+        engine
+            .apply(TransactionEntry::deposit(ClientId(1), TxId(2), dec!(5.0)))
+            .unwrap();
+        engine
+            .apply(TransactionEntry::dispute(ClientId(1), TxId(2)))
+            .unwrap();
+        engine
+            .apply(TransactionEntry::resolve(ClientId(1), TxId(2)))
+            .unwrap();
+        engine
+            .apply(TransactionEntry::chargeback(ClientId(1), TxId(1)))
+            .unwrap();
+
+        let account = &engine.accounts[&ClientId(1)];
+        assert_eq!(account.available, dec!(5.0));
+        assert_eq!(account.held, dec!(0.0));
+        assert_eq!(account.total, dec!(5.0));
+        assert!(account.locked);
+        assert_eq!(
+            engine.transactions[&ClientId(1)][&TxId(2)].status,
+            TransactionStatus::Resolved
+        );
+        assert_eq!(engine.audit(), vec![]);
+        assert_eq!(engine.verify_invariants(), Ok(()));
+        assert!(matches!(
+            engine.process_dispute(ClientId(1), TxId(1)),
+            Err(PaymentError::AccountLocked { .. })
+        ));
+
+        let mut engine = PaymentEngine::with_config(EngineConfig {
+            settled_retention: Some(0),
+            ..EngineConfig::default()
+        });
+        for entry in [
+            TransactionEntry::deposit(ClientId(1), TxId(1), dec!(10.0)),
+            TransactionEntry::dispute(ClientId(1), TxId(1)),
+            TransactionEntry::resolve(ClientId(1), TxId(1)),
+            TransactionEntry::dispute(ClientId(1), TxId(1)),
+        ] {
+            engine.apply(entry).unwrap();
+        }
+        assert_eq!(engine.accounts[&ClientId(1)].held, dec!(10.0));
+    }
+
+    #[cfg(feature = "csv")]
+    #[test]
+    fn test_redispute_checks_available_balance() {
+        let mut engine = PaymentEngine::new();
+        let entries = vec![
+            TransactionEntry::deposit(ClientId(1), TxId(1), dec!(10.0)),
+            TransactionEntry::dispute(ClientId(1), TxId(1)),
+            TransactionEntry::resolve(ClientId(1), TxId(1)),
+            TransactionEntry::withdrawal(ClientId(1), TxId(2), dec!(6.0)),
+        ];
+        for entry in entries {
+            engine.apply(entry).unwrap();
+        }
+
+        assert!(matches!(
+            engine.process_dispute(ClientId(1), TxId(1)),
+            Err(PaymentError::InsufficientHoldFunds { .. })
+        ));
+        assert_eq!(
+            engine.transactions[&ClientId(1)][&TxId(1)].status,
+            TransactionStatus::Resolved
+        );
+        assert_eq!(engine.accounts[&ClientId(1)].available, dec!(4.0));
+    }
+
+    #[cfg(feature = "csv")]
+    #[test]
+    fn test_retention_window() {
+        let mut engine = PaymentEngine::with_config(EngineConfig {
+            retention_policy: RetentionPolicy::Window(2),
+            ..EngineConfig::default()
+        });
+        let entries = vec![
+            TransactionEntry::deposit(ClientId(1), TxId(1), dec!(10.0)),
+            TransactionEntry::withdrawal(ClientId(1), TxId(2), dec!(1.0)),
+            TransactionEntry::deposit(ClientId(1), TxId(3), dec!(5.0)),
+            TransactionEntry::deposit(ClientId(2), TxId(4), dec!(2.0)),
+        ];
+        for entry in entries {
+            engine.apply(entry).unwrap();
+        }
+
+        let mut kept: Vec<u32> = engine
+            .transactions
+            .values()
+            .flat_map(|transactions| transactions.keys())
+            .map(|tx_id| tx_id.0)
+            .collect();
+        kept.sort_unstable();
+        assert_eq!(kept, vec![3, 4]);
+
+        // Two operations were applied after tx 3: still inside the window.
+        engine
+            .apply(TransactionEntry::dispute(ClientId(1), TxId(3)))
+            .unwrap();
+        // Tx 1 fell out of it.
+        assert_eq!(
+            engine.process_dispute(ClientId(1), TxId(1)),
+            Err(PaymentError::TransactionEvicted(TxId(1)))
+        );
+        assert_eq!(
+            engine.process_transaction(
+                TransactionEntry::deposit(ClientId(1), TxId(1), dec!(10.0))
+                    .try_into()
+                    .unwrap()
+            ),
+            Err(PaymentError::TransactionAlreadyExists {
+                client: ClientId(1),
+                tx_id: TxId(1),
+            })
+        );
+
+        // The open dispute outlives the window and can still be settled.
+        for tx in 5..=8 {
+            engine
+                .apply(TransactionEntry::deposit(ClientId(2), TxId(tx), dec!(1.0)))
+                .unwrap();
+        }
+        engine
+            .apply(TransactionEntry::chargeback(ClientId(1), TxId(3)))
+            .unwrap();
+
+        let account = &engine.accounts[&ClientId(1)];
+        assert_eq!(account.total, dec!(9.0));
+        assert_eq!(account.held, dec!(0.0));
+        assert!(!engine.transactions[&ClientId(2)].contains_key(&TxId(4)));
+        assert_eq!(engine.audit(), vec![]);
+        assert_eq!(engine.verify_invariants(), Ok(()));
+    }
+
+    #[cfg(feature = "csv")]
+    #[test]
+    fn test_retention_window_stays_bounded() {
+        let mut engine = PaymentEngine::with_config(EngineConfig {
+            retention_policy: RetentionPolicy::Window(4),
+            ..EngineConfig::default()
+        });
+        for tx in 1..=10_000 {
+            let client = ClientId((tx % 3) as u16);
+            engine
+                .apply(TransactionEntry::deposit(client, TxId(tx), dec!(1.0)))
+                .unwrap();
+            if tx % 5 == 0 {
+                engine
+                    .apply(TransactionEntry::dispute(client, TxId(tx)))
+                    .unwrap();
+                engine
+                    .apply(TransactionEntry::resolve(client, TxId(tx)))
+                    .unwrap();
+            }
+        }
+
+        let stored: usize = engine.transactions.values().map(HashMap::len).sum();
+        assert!(stored <= 4, "{stored} transactions kept");
+        assert!(engine.window.len() <= 4);
+        assert_eq!(engine.evicted_up_to.len(), 3);
+        assert!(engine.ledger.is_empty());
+        assert_eq!(
+            engine.process_dispute(ClientId(1), TxId(1)),
+            Err(PaymentError::TransactionEvicted(TxId(1)))
+        );
+        assert_eq!(engine.audit(), vec![]);
+    }
+
+    #[cfg(feature = "csv")]
+    #[test]
+    fn test_retry_of_failed_tx() {
+        let run = |allow_retry_of_failed_tx| {
+            let mut engine = PaymentEngine::with_config(EngineConfig {
+                allow_retry_of_failed_tx,
+                ..EngineConfig::default()
+            });
+            let withdrawal = TransactionEntry::withdrawal(ClientId(1), TxId(2), dec!(8.0));
+            engine
+                .apply(TransactionEntry::deposit(ClientId(1), TxId(1), dec!(5.0)))
+                .unwrap();
+            assert_eq!(
+                engine.apply(withdrawal.clone()).unwrap_err().code(),
+                "INSUFFICIENT_FUNDS"
+            );
+            engine
+                .apply(TransactionEntry::deposit(ClientId(1), TxId(3), dec!(5.0)))
+                .unwrap();
+            let retry = engine.apply(withdrawal);
+            (engine, retry)
+        };
+
+        let (engine, retry) = run(true);
+        assert_eq!(retry.unwrap().available, dec!(2.0));
+        assert!(engine.transactions[&ClientId(1)].contains_key(&TxId(2)));
+
+        let (engine, retry) = run(false);
+        assert_eq!(
+            retry,
+            Err(PaymentError::TransactionAlreadyExists {
+                client: ClientId(1),
+                tx_id: TxId(2),
+            })
+        );
+        assert_eq!(engine.accounts[&ClientId(1)].available, dec!(10.0));
+
+        // A retry must repeat the failed attempt.
+        let (mut engine, _) = run(true);
+        engine
+            .apply(TransactionEntry::withdrawal(
+                ClientId(1),
+                TxId(4),
+                dec!(9.0),
+            ))
+            .unwrap_err();
+        assert_eq!(
+            engine
+                .apply(TransactionEntry::withdrawal(
+                    ClientId(1),
+                    TxId(4),
+                    dec!(1.0)
+                ))
+                .unwrap_err()
+                .code(),
+            "TX_DUPLICATE_MISMATCH"
+        );
+        assert_eq!(engine.audit(), vec![]);
+
+        // Only withdrawals refused for lack of funds to a known client are
+        // kept, at most `settled_retention` of them per client.
+        let mut engine = PaymentEngine::with_config(EngineConfig {
+            max_accounts: Some(1),
+            settled_retention: Some(2),
+            ..EngineConfig::default()
+        });
+        engine
+            .apply(TransactionEntry::deposit(ClientId(1), TxId(1), dec!(1.0)))
+            .unwrap();
+        for entry in [
+            TransactionEntry::deposit(ClientId(2), TxId(2), dec!(1.0)),
+            TransactionEntry::withdrawal(ClientId(3), TxId(3), dec!(1.0)),
+            TransactionEntry::deposit(ClientId(1), TxId(1), dec!(2.0)),
+        ] {
+            engine.apply(entry).unwrap_err();
+        }
+        assert!(engine.failed.is_empty());
+        for tx_id in 5..=7 {
+            engine
+                .apply(TransactionEntry::withdrawal(
+                    ClientId(1),
+                    TxId(tx_id),
+                    dec!(5.0),
+                ))
+                .unwrap_err();
+        }
+        let failed = &engine.failed[&ClientId(1)];
+        assert_eq!(failed.order, [TxId(6), TxId(7)]);
+        assert_eq!(failed.attempts.len(), 2);
+        assert!(failed.get(TxId(5)).is_none());
+    }
+
+    #[cfg(feature = "csv")]
+    #[test]
+    fn test_concurrent_disputes() {
+        let open = || {
+            let mut engine = PaymentEngine::new();
+            let entries = vec![
+                TransactionEntry::deposit(ClientId(1), TxId(1), dec!(10.0)),
+                TransactionEntry::deposit(ClientId(1), TxId(2), dec!(4.0)),
+                TransactionEntry::dispute(ClientId(1), TxId(1)),
+                TransactionEntry::dispute(ClientId(1), TxId(2)),
+            ];
+            process_stream_with_sink(&mut engine, entries.into_iter(), &mut NullSink);
+            assert_eq!(engine.accounts[&ClientId(1)].held, dec!(14.0));
+            engine
+        };
+        let held = |engine: &PaymentEngine| engine.accounts[&ClientId(1)].held;
+
+        let mut engine = open();
+        engine.process_resolve(ClientId(1), TxId(1)).unwrap();
+        assert_eq!(held(&engine), dec!(4.0));
+        engine.process_chargeback(ClientId(1), TxId(2)).unwrap();
+        assert_eq!(held(&engine), Decimal::ZERO);
+        assert_eq!(engine.accounts[&ClientId(1)].total, dec!(10.0));
+        assert_eq!(engine.verify_invariants(), Ok(()));
+
+        let mut engine = open();
+        engine
+            .process_partial_resolve(ClientId(1), TxId(2), dec!(1.5))
+            .unwrap();
+        engine.process_resolve(ClientId(1), TxId(2)).unwrap();
+        assert_eq!(held(&engine), dec!(10.0));
+        engine.process_chargeback(ClientId(1), TxId(1)).unwrap();
+        assert_eq!(held(&engine), Decimal::ZERO);
+        assert_eq!(engine.accounts[&ClientId(1)].total, dec!(4.0));
+        assert_eq!(engine.verify_invariants(), Ok(()));
+
+        // A dispute whose hold went missing is not settled from the pool.
+        let mut engine = open();
+        engine
+            .transactions
+            .get_mut(&ClientId(1))
+            .unwrap()
+            .get_mut(&TxId(2))
+            .unwrap()
+            .disputed_amount = Decimal::ZERO;
+        assert!(engine.verify_invariants().is_err());
+        for result in [
+            engine.process_resolve(ClientId(1), TxId(2)),
+            engine.process_chargeback(ClientId(1), TxId(2)),
+        ] {
+            assert_eq!(
+                result,
+                Err(PaymentError::MissingHold {
+                    client: ClientId(1),
+                    tx_id: TxId(2),
+                })
+            );
+        }
+        assert_eq!(held(&engine), dec!(14.0));
+    }
+
+    #[cfg(feature = "csv")]
+    #[test]
+    fn test_reject_unknown_clients() {
+        let withdrawal = || TransactionEntry::withdrawal(ClientId(1), TxId(1), dec!(5.0));
+
+        let mut engine = PaymentEngine::new();
+        assert!(matches!(
+            engine.apply(withdrawal()),
+            Err(PaymentError::InsufficientFunds { .. })
+        ));
+        assert!(engine.accounts.is_empty());
+
+        let mut engine = PaymentEngine::with_config(EngineConfig {
+            reject_unknown_clients: true,
+            withdrawal_policy: WithdrawalPolicy::PartialClamp,
+            ..EngineConfig::default()
+        });
+        assert!(matches!(
+            engine.apply(withdrawal()),
+            Err(PaymentError::AccountNotFound(ClientId(1)))
+        ));
+        for entry in [
+            TransactionEntry::dispute(ClientId(1), TxId(1)),
+            TransactionEntry::resolve(ClientId(1), TxId(1)),
+            TransactionEntry::chargeback(ClientId(1), TxId(1)),
+        ] {
+            assert!(matches!(
+                engine.apply(entry),
+                Err(PaymentError::AccountNotFound(ClientId(1)))
+            ));
+        }
+        assert!(engine.accounts.is_empty());
+
+        // A deposit opens the account, after which the other operations apply.
+        engine
+            .apply(TransactionEntry::deposit(ClientId(1), TxId(2), dec!(3.0)))
+            .unwrap();
+        engine
+            .apply(TransactionEntry::dispute(ClientId(1), TxId(2)))
+            .unwrap();
+        // Known now, the client only lacks available funds to clamp to.
+        assert!(matches!(
+            engine.apply(withdrawal()),
+            Err(PaymentError::InsufficientFunds { .. })
+        ));
+        assert_eq!(engine.accounts.len(), 1);
+    }
+
+    #[cfg(feature = "csv")]
+    #[test]
+    fn test_aggregate_balances() {
+        let mut engine = PaymentEngine::new();
+        assert_eq!(engine.total_balance(), Some(Decimal::ZERO));
+
+        let entries = vec![
+            TransactionEntry::deposit(ClientId(1), TxId(1), dec!(100.0)),
+            TransactionEntry::deposit(ClientId(2), TxId(2), dec!(20.5)),
+            TransactionEntry::deposit(ClientId(3), TxId(3), dec!(7.25)),
+            TransactionEntry::withdrawal(ClientId(1), TxId(4), dec!(30.0)),
+            TransactionEntry::dispute(ClientId(2), TxId(2)),
+            TransactionEntry::deposit(ClientId(3), TxId(5), dec!(1.0)),
+            TransactionEntry::dispute(ClientId(3), TxId(5)),
+        ];
+        process_stream_with_sink(&mut engine, entries.into_iter(), &mut NullSink);
+
+        assert_eq!(engine.total_available(), Some(dec!(77.25)));
+        assert_eq!(engine.total_held(), Some(dec!(21.5)));
+        assert_eq!(engine.total_balance(), Some(dec!(98.75)));
+
+        // A sum beyond `Decimal::MAX` is reported instead of panicking.
+        for client in [ClientId(4), ClientId(5)] {
+            engine.get_or_create_account(client).unwrap();
+            engine.accounts.get_mut(&client).unwrap().available = Decimal::MAX;
+        }
+        assert_eq!(engine.total_available(), None);
+        assert_eq!(engine.total_held(), Some(dec!(21.5)));
+    }
+
+    #[cfg(feature = "csv")]
+    #[test]
+    fn test_transaction_history() {
+        use TransactionStatus::*;
+
+        let mut engine = PaymentEngine::with_config(EngineConfig {
+            freeze_disputes_on_lock: true,
+            ..EngineConfig::default()
+        });
+        let entries = vec![
+            TransactionEntry::deposit(ClientId(1), TxId(1), dec!(10.0)),
+            TransactionEntry::deposit(ClientId(1), TxId(2), dec!(5.0)),
+            TransactionEntry::dispute(ClientId(1), TxId(1)),
+            TransactionEntry::resolve(ClientId(1), TxId(1)),
+            TransactionEntry::dispute(ClientId(1), TxId(1)),
+            TransactionEntry::partial_resolve(ClientId(1), TxId(1), dec!(4.0)),
+            TransactionEntry::dispute(ClientId(1), TxId(2)),
+            TransactionEntry::chargeback(ClientId(1), TxId(2)),
+        ];
+        process_stream_with_sink(&mut engine, entries.into_iter(), &mut NullSink);
+
+        let history = engine.transaction_history(ClientId(1), TxId(1)).unwrap();
+        assert_eq!(
+            history.statuses(),
+            [Disputed, Resolved, Disputed, PartiallyResolved, Frozen]
+        );
+        assert_eq!(history.count(&Disputed), 2);
+        assert_eq!(history.count(&Resolved), 1);
+        assert_eq!(history.count(&Chargebacked), 0);
+        assert_eq!(
+            engine
+                .transaction_history(ClientId(1), TxId(2))
+                .unwrap()
+                .statuses(),
+            [Disputed, Chargebacked]
+        );
+        assert!(engine.transaction_history(ClientId(1), TxId(3)).is_none());
+
+        // Only the latest statuses are kept, the counts cover every move.
+        let mut engine = PaymentEngine::new();
+        engine
+            .apply(TransactionEntry::deposit(ClientId(1), TxId(1), dec!(1.0)))
+            .unwrap();
+        for _ in 0..20 {
+            engine
+                .apply(TransactionEntry::dispute(ClientId(1), TxId(1)))
+                .unwrap();
+            engine
+                .apply(TransactionEntry::resolve(ClientId(1), TxId(1)))
+                .unwrap();
+        }
+        let history = engine.transaction_history(ClientId(1), TxId(1)).unwrap();
+        assert_eq!(history.statuses().len(), MAX_STATUS_HISTORY);
+        assert_eq!(history.statuses().last(), Some(&Resolved));
+        assert_eq!(history.count(&Disputed), 20);
+        assert_eq!(history.count(&Resolved), 20);
+    }
+
+    #[cfg(feature = "csv")]
+    #[test]
+    fn test_withdrawal_dispute() {
+        let mut engine = PaymentEngine::new();
+        engine
+            .apply(TransactionEntry::deposit(ClientId(1), TxId(1), dec!(10.0)))
+            .unwrap();
+        engine
+            .apply(TransactionEntry::withdrawal(
+                ClientId(1),
+                TxId(2),
+                dec!(8.0),
+            ))
+            .unwrap();
+        assert_eq!(
+            engine.apply(TransactionEntry::dispute(ClientId(1), TxId(2))),
+            Err(PaymentError::InvalidTransactionType(TxId(2)))
+        );
+
+        let run = |last: TransactionEntry| {
+            let mut engine = PaymentEngine::with_config(EngineConfig {
+                allow_withdrawal_disputes: true,
+                ..EngineConfig::default()
+            });
+            engine
+                .apply(TransactionEntry::deposit(ClientId(1), TxId(1), dec!(10.0)))
+                .unwrap();
+            engine
+                .apply(TransactionEntry::withdrawal(
+                    ClientId(1),
+                    TxId(2),
+                    dec!(8.0),
+                ))
+                .unwrap();
+            // Disputing does not need the withdrawn funds to be available.
+            let disputed = engine
+                .apply(TransactionEntry::dispute(ClientId(1), TxId(2)))
+                .unwrap();
+            assert_eq!(
+                (disputed.available, disputed.held, disputed.total),
+                (dec!(2.0), dec!(8.0), dec!(10.0))
+            );
+            assert_eq!(engine.verify_invariants(), Ok(()));
+            assert_eq!(engine.audit(), vec![]);
+            let account = engine.apply(last).unwrap();
+            assert_eq!(engine.verify_invariants(), Ok(()));
+            assert_eq!(engine.audit(), vec![]);
+            (
+                account.available,
+                account.held,
+                account.total,
+                account.locked,
+            )
+        };
+
+        assert_eq!(
+            run(TransactionEntry::resolve(ClientId(1), TxId(2))),
+            (dec!(2.0), dec!(0.0), dec!(2.0), false)
+        );
+        assert_eq!(
+            run(TransactionEntry::partial_resolve(
+                ClientId(1),
+                TxId(2),
+                dec!(3.0)
+            )),
+            (dec!(2.0), dec!(5.0), dec!(7.0), false)
+        );
+        assert_eq!(
+            run(TransactionEntry::chargeback(ClientId(1), TxId(2))),
+            (dec!(10.0), dec!(0.0), dec!(10.0), true)
+        );
+    }
+
+    #[cfg(feature = "csv")]
+    #[test]
+    fn test_zero_amount_dispute() {
+        let mut engine = PaymentEngine::with_config(EngineConfig {
+            max_amount_scale: None,
+            ..EngineConfig::default()
+        });
+        // A zero deposit is rejected outright.
+        assert_eq!(
+            engine.apply(TransactionEntry::deposit(
+                ClientId(1),
+                TxId(1),
+                dec!(0.0000),
+            )),
+            Err(PaymentError::InvalidAmount(dec!(0.0000)))
+        );
+        engine
+            .apply(TransactionEntry::deposit(
+                ClientId(1),
+                TxId(2),
+                dec!(0.00004),
+            ))
+            .unwrap();
+        engine
+            .apply(TransactionEntry::deposit(
+                ClientId(1),
+                TxId(3),
+                dec!(0.0001),
+            ))
+            .unwrap();
+
+        let error = engine
+            .apply(TransactionEntry::dispute(ClientId(1), TxId(2)))
+            .unwrap_err();
+        assert_eq!(error, PaymentError::ZeroAmountDispute(TxId(2)));
+        assert_eq!(error.code(), "ZERO_AMOUNT_DISPUTE");
+        assert_eq!(
+            engine.transactions[&ClientId(1)][&TxId(2)].status,
+            TransactionStatus::Completed
+        );
+        let account = engine
+            .apply(TransactionEntry::dispute(ClientId(1), TxId(3)))
+            .unwrap();
+        assert_eq!(account.held, dec!(0.0001));
+    }
+
+    #[cfg(feature = "csv")]
+    #[test]
+    fn test_limits() {
+        let mut engine = PaymentEngine::with_config(EngineConfig {
+            limits: Limits {
+                max_tx_amount: Some(dec!(100.00)),
+                max_total_withdrawal_per_client: Some(dec!(50.00)),
+                ..Limits::default()
             },
+            ..EngineConfig::default()
+        });
+        engine
+            .apply(TransactionEntry::deposit(
+                ClientId(1),
+                TxId(1),
+                dec!(100.00),
+            ))
+            .unwrap();
+        assert_eq!(
+            engine.apply(TransactionEntry::deposit(
+                ClientId(1),
+                TxId(2),
+                dec!(100.01)
+            )),
+            Err(PaymentError::LimitExceeded {
+                tx_id: TxId(2),
+                kind: LimitKind::TransactionAmount,
+                limit: dec!(100.00),
+                attempted: dec!(100.01),
+            })
         );
 
-        let output = format!("{}", engine);
+        engine
+            .apply(TransactionEntry::withdrawal(
+                ClientId(1),
+                TxId(3),
+                dec!(30.00),
+            ))
+            .unwrap();
+        engine
+            .apply(TransactionEntry::withdrawal(
+                ClientId(1),
+                TxId(4),
+                dec!(20.00),
+            ))
+            .unwrap();
+        let rejected = engine
+            .apply(TransactionEntry::withdrawal(
+                ClientId(1),
+                TxId(5),
+                dec!(0.01),
+            ))
+            .unwrap_err();
+        assert_eq!(
+            rejected,
+            PaymentError::LimitExceeded {
+                tx_id: TxId(5),
+                kind: LimitKind::TotalWithdrawal,
+                limit: dec!(50.00),
+                attempted: dec!(50.01),
+            }
+        );
+        assert_eq!(rejected.code(), "LIMIT_EXCEEDED");
+        assert_eq!(engine.accounts[&ClientId(1)].available, dec!(50.00));
 
-        assert!(output.contains("client, available, held, total, locked"));
-        assert!(output.contains("1, 100.1234, 50.5678, 150.6912, false"));
-        assert!(output.contains("2, 0.0000, 25.0000, 25.0000, true"));
-        assert!(output.contains("3, 999.9999, 0.0001, 1000.0000, false"));
+        // The total is kept per client.
+        engine
+            .apply(TransactionEntry::deposit(ClientId(2), TxId(6), dec!(60.00)))
+            .unwrap();
+        engine
+            .apply(TransactionEntry::withdrawal(
+                ClientId(2),
+                TxId(7),
+                dec!(50.00),
+            ))
+            .unwrap();
+        assert_eq!(engine.audit(), vec![]);
+    }
 
-        let lines: Vec<&str> = output.trim().split('\n').collect();
-        assert_eq!(lines.len(), 4);
+    #[cfg(feature = "csv")]
+    #[test]
+    fn test_process_entries() {
+        let mut engine = PaymentEngine::new();
+        let entries = [
+            TransactionEntry::deposit(ClientId(1), TxId(1), dec!(10.0)),
+            TransactionEntry::withdrawal(ClientId(1), TxId(2), dec!(20.0)),
+            TransactionEntry::dispute(ClientId(1), TxId(1)),
+            TransactionEntry::dispute(ClientId(1), TxId(9)),
+            TransactionEntry::chargeback(ClientId(1), TxId(1)),
+            TransactionEntry::deposit(ClientId(1), TxId(3), dec!(1.0)),
+        ];
+        let outcomes = engine.process_entries(&entries);
 
-        for line in &lines[1..] {
-            let values: Vec<&str> = line.split(", ").collect();
-            assert_eq!(values.len(), 5);
-        }
+        assert_eq!(outcomes.len(), entries.len());
+        let codes: Vec<Option<&str>> = outcomes
+            .iter()
+            .map(|outcome| outcome.as_ref().err().map(PaymentError::code))
+            .collect();
+        assert_eq!(
+            codes,
+            vec![
+                None,
+                Some("INSUFFICIENT_FUNDS"),
+                None,
+                Some("TX_NOT_FOUND"),
+                None,
+                Some("ACCOUNT_LOCKED"),
+            ]
+        );
+        assert_eq!(engine.accounts[&ClientId(1)].total, Decimal::ZERO);
     }
 
+    #[cfg(feature = "csv")]
     #[test]
-    fn test_payment_engine_display_empty() {
-        let engine = PaymentEngine::new();
-        let output = format!("{}", engine);
-        assert_eq!(output.trim(), "client, available, held, total, locked");
+    fn test_rejected_transactions_leave_accounts_untouched() {
+        let mut engine = PaymentEngine::with_config(EngineConfig {
+            max_accounts: Some(2),
+            ..EngineConfig::default()
+        });
+        engine
+            .apply(TransactionEntry::deposit(ClientId(1), TxId(1), dec!(10.0)))
+            .unwrap();
+        engine.freeze_account(ClientId(1)).unwrap();
+        let locked = engine.accounts.clone();
+
+        // A locked account is rejected before anything else is looked at, and
+        // is not modified.
+        assert_eq!(
+            engine
+                .apply(TransactionEntry::deposit(ClientId(1), TxId(2), dec!(5.0)))
+                .unwrap_err()
+                .code(),
+            "ACCOUNT_LOCKED"
+        );
+        assert_eq!(engine.accounts, locked);
+
+        // A new client's rejected withdrawal neither creates its account nor
+        // takes one of the remaining slots.
+        assert!(matches!(
+            engine.apply(TransactionEntry::withdrawal(
+                ClientId(3),
+                TxId(3),
+                dec!(1.0)
+            )),
+            Err(PaymentError::InsufficientFunds { .. })
+        ));
+        assert_eq!(engine.accounts, locked);
+        engine
+            .apply(TransactionEntry::deposit(ClientId(4), TxId(4), dec!(1.0)))
+            .unwrap();
+        assert_eq!(
+            engine.apply(TransactionEntry::deposit(ClientId(3), TxId(5), dec!(1.0))),
+            Err(PaymentError::AccountLimitExceeded(ClientId(3)))
+        );
+        assert!(!engine.accounts.contains_key(&ClientId(3)));
     }
 
+    #[cfg(feature = "csv")]
     #[test]
-    fn test_withdrawal_insufficient_funds() {
+    fn test_transaction_error_precedence() {
         let mut engine = PaymentEngine::new();
+        engine
+            .apply(TransactionEntry::deposit(ClientId(1), TxId(1), dec!(10.0)))
+            .unwrap();
+        // A reused tx id withdrawing more than available fails all three checks.
+        let withdrawal = || TransactionEntry::withdrawal(ClientId(1), TxId(1), dec!(50.0));
 
-        let deposit = Transaction {
-            tx_type: TransactionType::Deposit,
-            account_id: 1,
-            tx_id: 1,
-            amount: dec!(50.0),
-            status: TransactionStatus::Completed,
-        };
+        engine.freeze_account(ClientId(1)).unwrap();
+        assert_eq!(
+            engine.apply(withdrawal()).unwrap_err().code(),
+            "ACCOUNT_LOCKED"
+        );
+        engine.unfreeze_account(ClientId(1)).unwrap();
+        assert_eq!(
+            engine.apply(withdrawal()),
+            Err(PaymentError::TransactionAlreadyExists {
+                client: ClientId(1),
+                tx_id: TxId(1),
+            })
+        );
+        assert_eq!(
+            engine.apply(TransactionEntry::withdrawal(
+                ClientId(1),
+                TxId(2),
+                dec!(50.0)
+            )),
+            Err(PaymentError::InsufficientFunds {
+                client: ClientId(1),
+                tx_id: TxId(2),
+            })
+        );
+        let account = &engine.accounts[&ClientId(1)];
+        assert_eq!((account.available, account.total), (dec!(10.0), dec!(10.0)));
+    }
 
-        engine.get_or_create_account(1);
+    #[cfg(feature = "csv")]
+    #[test]
+    fn test_max_withdrawal() {
+        let mut engine = PaymentEngine::with_config(EngineConfig {
+            limits: Limits {
+                max_withdrawal: Some(dec!(25.00)),
+                ..Limits::default()
+            },
+            withdrawal_policy: WithdrawalPolicy::PartialClamp,
+            ..EngineConfig::default()
+        });
         engine
-            .update_account_balance(1, dec!(50.0), dec!(0.0), dec!(50.0))
+            .apply(TransactionEntry::deposit(
+                ClientId(1),
+                TxId(1),
+                dec!(100.00),
+            ))
+            .unwrap();
+        engine
+            .apply(TransactionEntry::deposit(ClientId(2), TxId(2), dec!(10.00)))
+            .unwrap();
+        engine
+            .apply(TransactionEntry::withdrawal(
+                ClientId(1),
+                TxId(3),
+                dec!(25.00),
+            ))
             .unwrap();
-        engine.insert_transaction(deposit);
-
-        assert_eq!(engine.accounts.get(&1).unwrap().available, dec!(50.0));
-        assert_eq!(engine.accounts.get(&1).unwrap().total, dec!(50.0));
 
-        let withdrawal = Transaction {
-            tx_type: TransactionType::Withdrawal,
-            account_id: 1,
-            tx_id: 2,
-            amount: dec!(100.0),
-            status: TransactionStatus::Completed,
-        };
+        let rejected = engine
+            .apply(TransactionEntry::withdrawal(
+                ClientId(1),
+                TxId(4),
+                dec!(25.01),
+            ))
+            .unwrap_err();
+        assert_eq!(
+            rejected,
+            PaymentError::WithdrawalLimitExceeded {
+                client: ClientId(1),
+                tx_id: TxId(4),
+                limit: dec!(25.00),
+                attempted: dec!(25.01),
+            }
+        );
+        assert_eq!(rejected.code(), "WITHDRAWAL_LIMIT_EXCEEDED");
+        let account = &engine.accounts[&ClientId(1)];
+        assert_eq!(
+            (account.available, account.total),
+            (dec!(75.00), dec!(75.00))
+        );
 
-        let should_fail = engine.process_transaction(withdrawal);
-        assert!(should_fail.is_err(), "Should detect insufficient funds");
+        // The cap applies to the requested amount, before any clamping.
+        assert!(matches!(
+            engine.apply(TransactionEntry::withdrawal(
+                ClientId(2),
+                TxId(5),
+                dec!(30.00)
+            )),
+            Err(PaymentError::WithdrawalLimitExceeded { .. })
+        ));
+        assert_eq!(engine.accounts[&ClientId(2)].available, dec!(10.00));
 
-        assert_eq!(engine.accounts.get(&1).unwrap().available, dec!(50.0));
-        assert_eq!(engine.accounts.get(&1).unwrap().total, dec!(50.0));
-        assert_eq!(engine.accounts.get(&1).unwrap().held, dec!(0.0));
+        // Deposits are not capped.
+        engine
+            .apply(TransactionEntry::deposit(
+                ClientId(1),
+                TxId(6),
+                dec!(500.00),
+            ))
+            .unwrap();
     }
 
+    #[cfg(feature = "csv")]
     #[test]
-    fn test_dispute_insufficient_available_balance() {
-        let mut engine = PaymentEngine::new();
+    fn test_clear_and_reset_account() {
+        let mut engine = PaymentEngine::with_config(EngineConfig {
+            account_scales: HashMap::from([(ClientId(1), 2)]),
+            chargeback_fee: dec!(1.0),
+            ..EngineConfig::default()
+        });
+        assert!(!engine.reset_account(ClientId(1)));
+        engine.clear();
 
-        let deposit = Transaction {
-            tx_type: TransactionType::Deposit,
-            account_id: 1,
-            tx_id: 1,
-            amount: dec!(100.0),
-            status: TransactionStatus::Completed,
-        };
+        let entries = vec![
+            TransactionEntry::deposit(ClientId(1), TxId(1), dec!(10.0)),
+            TransactionEntry::deposit(ClientId(1), TxId(2), dec!(5.0)),
+            TransactionEntry::dispute(ClientId(1), TxId(2)),
+            TransactionEntry::chargeback(ClientId(1), TxId(2)),
+            TransactionEntry::deposit(ClientId(2), TxId(3), dec!(3.0)),
+        ];
+        for entry in entries {
+            engine.apply(entry).unwrap();
+        }
 
-        engine.get_or_create_account(1);
-        engine
-            .update_account_balance(1, dec!(100.0), dec!(0.0), dec!(100.0))
-            .unwrap();
-        engine.insert_transaction(deposit);
+        assert!(engine.reset_account(ClientId(1)));
+        let account = &engine.accounts[&ClientId(1)];
+        assert_eq!(account.total, dec!(0));
+        assert!(!account.locked);
+        assert_eq!(account.scale, 2);
+        assert!(engine.history(ClientId(1)).next().is_none());
+        assert!(engine.statement(ClientId(1)).unwrap().lines.is_empty());
+        assert_eq!(engine.accounts[&ClientId(2)].total, dec!(3.0));
+        assert_eq!(engine.audit(), vec![]);
 
-        let withdrawal = Transaction {
-            tx_type: TransactionType::Withdrawal,
-            account_id: 1,
-            tx_id: 2,
-            amount: dec!(80.0),
-            status: TransactionStatus::Completed,
-        };
+        // The tx ids of the reset account are free again.
         engine
-            .process_transaction(withdrawal)
-            .expect("Withdrawal should succeed");
-
-        assert_eq!(engine.accounts.get(&1).unwrap().available, dec!(20.0));
-        assert_eq!(engine.accounts.get(&1).unwrap().total, dec!(20.0));
+            .apply(TransactionEntry::deposit(ClientId(1), TxId(1), dec!(1.0)))
+            .unwrap();
 
-        let result = engine.process_dispute(1, 1);
-        assert!(
-            result.is_err(),
-            "Dispute should fail due to insufficient available funds"
-        );
+        engine.clear();
+        assert!(engine.accounts.is_empty());
+        assert!(engine.transactions.is_empty());
+        #[cfg(feature = "digest")]
+        assert_eq!(engine.state_digest(), PaymentEngine::new().state_digest());
+    }
 
-        assert_eq!(engine.accounts.get(&1).unwrap().available, dec!(20.0));
-        assert_eq!(engine.accounts.get(&1).unwrap().total, dec!(20.0));
-        assert_eq!(engine.accounts.get(&1).unwrap().held, dec!(0.0));
+    #[test]
+    fn test_find_transaction() {
+        let mut engine = PaymentEngine::new();
+        for (account_id, tx_id) in [(1, 10), (2, 20)] {
+            let deposit = Transaction {
+                tx_type: TransactionType::Deposit,
+                account_id: ClientId(account_id),
+                tx_id: TxId(tx_id),
+                amount: dec!(1.0),
+                currency: None,
+                disputed_amount: Decimal::ZERO,
+                sequence: 0,
+                timestamp: None,
+                status: TransactionStatus::Completed,
+                history: StatusHistory::default(),
+            };
+            engine.process_transaction(deposit).unwrap();
+        }
 
+        let (client, transaction) = engine.find_transaction(TxId(20)).unwrap();
+        assert_eq!(*client, ClientId(2));
+        assert_eq!(transaction.tx_id, TxId(20));
         assert_eq!(
-            engine.transactions.get(&1).unwrap().get(&1).unwrap().status,
-            TransactionStatus::Completed
+            engine.find_transaction(TxId(10)).map(|(client, _)| *client),
+            Some(ClientId(1))
         );
+        assert!(engine.find_transaction(TxId(30)).is_none());
     }
 
+    #[cfg(feature = "csv")]
     #[test]
-    fn test_withdrawal_exact_balance() {
-        let mut engine = PaymentEngine::new();
+    fn test_rejected_transitions_leave_balances() {
+        let mut engine = PaymentEngine::with_config(EngineConfig {
+            chargebacks_before_lock: 2,
+            freeze_disputes_on_lock: true,
+            ..EngineConfig::default()
+        });
+        let mut entries = Vec::new();
+        for client in [ClientId(1), ClientId(2)] {
+            let tx = |n: u32| TxId(u32::from(client.0) * 10 + n);
+            for n in 1..=6 {
+                entries.push(TransactionEntry::deposit(client, tx(n), dec!(10.0)));
+            }
+            entries.extend([
+                TransactionEntry::dispute(client, tx(2)),
+                TransactionEntry::dispute(client, tx(3)),
+                TransactionEntry::partial_resolve(client, tx(3), dec!(4.0)),
+                TransactionEntry::dispute(client, tx(4)),
+                TransactionEntry::resolve(client, tx(4)),
+                TransactionEntry::dispute(client, tx(5)),
+                TransactionEntry::chargeback(client, tx(5)),
+            ]);
+        }
+        // Client 2 reaches the lock, freezing its open disputes.
+        entries.extend([
+            TransactionEntry::dispute(ClientId(2), TxId(26)),
+            TransactionEntry::chargeback(ClientId(2), TxId(26)),
+        ]);
+        for entry in entries {
+            engine.apply(entry).unwrap();
+        }
 
-        let deposit = Transaction {
-            tx_type: TransactionType::Deposit,
-            account_id: 1,
-            tx_id: 1,
-            amount: dec!(50.0),
-            status: TransactionStatus::Completed,
+        let mut rejected = 0;
+        for client in [ClientId(1), ClientId(2)] {
+            for n in 1..=6 {
+                let tx_id = TxId(u32::from(client.0) * 10 + n);
+                for entry in [
+                    TransactionEntry::dispute(client, tx_id),
+                    TransactionEntry::partial_resolve(client, tx_id, dec!(1.0)),
+                    TransactionEntry::resolve(client, tx_id),
+                    TransactionEntry::chargeback(client, tx_id),
+                ] {
+                    let accounts = engine.accounts.clone();
+                    let status = engine.transactions[&client][&tx_id].status.clone();
+                    if engine.apply(entry).is_err() {
+                        rejected += 1;
+                        assert_eq!(engine.accounts, accounts);
+                        assert_eq!(engine.transactions[&client][&tx_id].status, status);
+                    }
+                }
+            }
+        }
+        assert!(rejected > 0);
+        assert_eq!(engine.verify_invariants(), Ok(()));
+    }
+
+    #[cfg(feature = "csv")]
+    #[test]
+    fn test_locked_account_deposits() {
+        let entries = vec![
+            TransactionEntry::deposit(ClientId(1), TxId(1), dec!(10.0)),
+            TransactionEntry::deposit(ClientId(1), TxId(2), dec!(5.0)),
+            TransactionEntry::dispute(ClientId(1), TxId(1)),
+            TransactionEntry::chargeback(ClientId(1), TxId(1)),
+        ];
+        let run = |locked_account_deposits| {
+            let mut engine = PaymentEngine::with_config(EngineConfig {
+                locked_account_deposits,
+                ..EngineConfig::default()
+            });
+            for entry in entries.clone() {
+                engine.apply(entry).unwrap();
+            }
+            engine
         };
 
-        engine.get_or_create_account(1);
-        engine
-            .update_account_balance(1, dec!(50.0), dec!(0.0), dec!(50.0))
+        let mut locked = run(LockedAccountDeposits::Reject);
+        assert_eq!(
+            locked
+                .apply(TransactionEntry::deposit(ClientId(1), TxId(3), dec!(1.0)))
+                .unwrap_err()
+                .code(),
+            "ACCOUNT_LOCKED"
+        );
+        assert_eq!(locked.accounts[&ClientId(1)].available, dec!(5.0));
+
+        let mut accepting = run(LockedAccountDeposits::Allow);
+        let account = accepting
+            .apply(TransactionEntry::deposit(ClientId(1), TxId(3), dec!(1.0)))
             .unwrap();
-        engine.insert_transaction(deposit);
+        assert_eq!(account.available, dec!(6.0));
+        assert!(account.locked);
+        for entry in [
+            TransactionEntry::withdrawal(ClientId(1), TxId(4), dec!(1.0)),
+            TransactionEntry::dispute(ClientId(1), TxId(3)),
+        ] {
+            assert_eq!(accepting.apply(entry).unwrap_err().code(), "ACCOUNT_LOCKED");
+        }
+        assert_eq!(accepting.accounts[&ClientId(1)].total, dec!(6.0));
+        assert_eq!(accepting.audit(), vec![]);
 
-        let withdrawal = Transaction {
-            tx_type: TransactionType::Withdrawal,
-            account_id: 1,
-            tx_id: 2,
-            amount: dec!(50.0),
-            status: TransactionStatus::Completed,
+        // The deprecated flag still works.
+        #[allow(deprecated)]
+        let config = EngineConfig {
+            allow_deposits_when_locked: true,
+            ..EngineConfig::default()
         };
-        engine
-            .process_transaction(withdrawal)
-            .expect("Withdrawal should succeed");
-
-        assert_eq!(engine.accounts.get(&1).unwrap().available, dec!(0.0));
-        assert_eq!(engine.accounts.get(&1).unwrap().total, dec!(0.0));
-        assert_eq!(engine.accounts.get(&1).unwrap().held, dec!(0.0));
+        assert!(config.accepts_locked_deposits());
+        assert!(!EngineConfig::default().accepts_locked_deposits());
     }
 
+    #[cfg(feature = "tracing")]
+    #[tracing_test::traced_test]
     #[test]
-    fn test_withdrawal_process_with_insufficient_funds() {
+    fn test_tracing_rejection() {
         let mut engine = PaymentEngine::new();
+        let deposit = Transaction::new(TransactionType::Deposit, ClientId(1), TxId(1), dec!(1.0));
+        engine.process_transaction(deposit).unwrap();
+        let withdrawal =
+            Transaction::new(TransactionType::Withdrawal, ClientId(1), TxId(7), dec!(5.0));
+        engine.process_transaction(withdrawal).unwrap_err();
 
-        engine.get_or_create_account(1);
-        engine
-            .update_account_balance(1, dec!(50.0), dec!(0.0), dec!(50.0))
-            .unwrap();
+        assert!(logs_contain(
+            "process_transaction{client=1 tx=7 kind=Withdrawal}"
+        ));
+        assert!(logs_contain("WARN"));
+        assert!(logs_contain("code=\"INSUFFICIENT_FUNDS\""));
+    }
 
-        let withdrawal = Transaction {
-            tx_type: TransactionType::Withdrawal,
-            account_id: 1,
-            tx_id: 2,
-            amount: dec!(100.0),
-            status: TransactionStatus::Completed,
+    #[test]
+    fn test_precision_loss() {
+        let mut engine = PaymentEngine::new();
+        let mut apply = |tx_type, tx_id, amount| {
+            engine.process_transaction(Transaction::new(tx_type, ClientId(1), TxId(tx_id), amount))
         };
-
-        let result = engine.process_transaction(withdrawal);
-
-        assert!(
-            result.is_err(),
-            "Should not have sufficient funds for withdrawal"
+        // 28 significant digits, as many as a `Decimal` holds.
+        let large = dec!(7000000000000000000000000000);
+        apply(TransactionType::Deposit, 1, large).unwrap();
+        // Rounded to a smaller scale, but nothing is lost.
+        apply(TransactionType::Withdrawal, 2, dec!(1.00)).unwrap();
+        let precision_loss = |tx_id| {
+            Err(PaymentError::PrecisionLoss {
+                client: ClientId(1),
+                tx_id: TxId(tx_id),
+            })
+        };
+        assert_eq!(
+            apply(TransactionType::Deposit, 3, dec!(0.0001)),
+            precision_loss(3)
         );
-        assert_eq!(engine.accounts.get(&1).unwrap().available, dec!(50.0));
-        assert_eq!(engine.accounts.get(&1).unwrap().total, dec!(50.0));
+        assert_eq!(
+            apply(TransactionType::Deposit, 4, Decimal::MAX),
+            precision_loss(4)
+        );
+        let account = &engine.accounts[&ClientId(1)];
+        assert_eq!(account.available, large - dec!(1));
+        assert_eq!(account.total, large - dec!(1));
+        assert_eq!(engine.get_transaction(ClientId(1), TxId(3)), None);
+        assert!(engine.verify_invariants().is_ok());
     }
 
     #[test]
-    fn test_dispute_process_with_insufficient_available_balance() {
-        let mut engine = PaymentEngine::new();
-
-        let deposit = Transaction {
-            tx_type: TransactionType::Deposit,
-            account_id: 1,
-            tx_id: 1,
-            amount: dec!(100.0),
-            status: TransactionStatus::Completed,
+    fn test_fee_precision_loss() {
+        let disputed = |fee| {
+            let mut engine = PaymentEngine::with_config(EngineConfig {
+                chargeback_fee: fee,
+                ..EngineConfig::default()
+            });
+            for (tx_id, amount) in [(1, dec!(7000000000000000000000000000)), (2, dec!(1))] {
+                engine
+                    .process_transaction(Transaction::new(
+                        TransactionType::Deposit,
+                        ClientId(1),
+                        TxId(tx_id),
+                        amount,
+                    ))
+                    .unwrap();
+            }
+            engine.process_dispute(ClientId(1), TxId(2)).unwrap();
+            engine
         };
 
-        engine.get_or_create_account(1);
-        engine
-            .update_account_balance(1, dec!(100.0), dec!(0.0), dec!(100.0))
+        // The reversal itself is exact, but the fee would be rounded away.
+        let mut engine = disputed(dec!(0.0001));
+        let before = (engine.accounts.clone(), engine.transactions.clone());
+        assert_eq!(
+            engine.process_chargeback(ClientId(1), TxId(2)),
+            Err(PaymentError::PrecisionLoss {
+                client: ClientId(1),
+                tx_id: TxId(2),
+            })
+        );
+        assert_eq!(
+            (engine.accounts.clone(), engine.transactions.clone()),
+            before
+        );
+        assert_eq!(
+            engine.get_transaction(ClientId(1), TxId(2)).unwrap().status,
+            TransactionStatus::Disputed
+        );
+        assert!(engine.fees.is_empty());
+
+        // A fee of the balance's scale is fine.
+        let mut engine = disputed(dec!(1));
+        engine.process_chargeback(ClientId(1), TxId(2)).unwrap();
+        assert_eq!(
+            engine.accounts[&ClientId(1)].total,
+            dec!(6999999999999999999999999999)
+        );
+
+        // Adding up the balances of a client in both engines would round too.
+        let mut merged = disputed(dec!(1));
+        let before = (merged.accounts.clone(), merged.transactions.clone());
+        let mut other = PaymentEngine::new();
+        other
+            .process_transaction(Transaction::new(
+                TransactionType::Deposit,
+                ClientId(1),
+                TxId(3),
+                dec!(0.0001),
+            ))
             .unwrap();
-        engine.insert_transaction(deposit);
+        assert_eq!(
+            merged.merge_unchecked(other),
+            Err(MergeError::PrecisionLoss(ClientId(1)))
+        );
+        assert_eq!(
+            (merged.accounts.clone(), merged.transactions.clone()),
+            before
+        );
+    }
 
-        let withdrawal = Transaction {
-            tx_type: TransactionType::Withdrawal,
-            account_id: 1,
-            tx_id: 2,
-            amount: dec!(80.0),
-            status: TransactionStatus::Completed,
+    #[test]
+    fn test_dispute_balance_deltas() {
+        let amount = dec!(2.5);
+        let sum = |a: BalanceDelta, b: BalanceDelta| BalanceDelta {
+            available: a.available + b.available,
+            held: a.held + b.held,
+            total: a.total + b.total,
         };
-        engine
-            .process_transaction(withdrawal)
-            .expect("Withdrawal should succeed");
+        for withdrawal in [false, true] {
+            let delta = |step| BalanceDelta::dispute(step, withdrawal, amount);
+            for step in [
+                DisputeStep::Hold,
+                DisputeStep::Release,
+                DisputeStep::Reverse,
+            ] {
+                assert_eq!(delta(step).available + delta(step).held, delta(step).total);
+            }
+            let zero = BalanceDelta::dispute(DisputeStep::Hold, withdrawal, Decimal::ZERO);
+            // A resolved dispute leaves the balances as they were.
+            assert_eq!(
+                sum(delta(DisputeStep::Hold), delta(DisputeStep::Release)),
+                zero
+            );
+            // A chargeback undoes the transaction itself.
+            let undone = if withdrawal { amount } else { -amount };
+            assert_eq!(
+                sum(delta(DisputeStep::Hold), delta(DisputeStep::Reverse)),
+                BalanceDelta {
+                    available: undone,
+                    held: Decimal::ZERO,
+                    total: undone,
+                }
+            );
+        }
+    }
 
-        assert_eq!(engine.accounts.get(&1).unwrap().available, dec!(20.0));
-        assert_eq!(engine.accounts.get(&1).unwrap().total, dec!(20.0));
+    #[cfg(feature = "csv")]
+    #[test]
+    fn test_lock_reason() {
+        let mut engine = PaymentEngine::new();
+        for tx_id in 1..=2 {
+            let deposit = Transaction {
+                tx_type: TransactionType::Deposit,
+                account_id: ClientId(1),
+                tx_id: TxId(tx_id),
+                amount: dec!(10.0),
+                currency: None,
+                disputed_amount: Decimal::ZERO,
+                sequence: 0,
+                timestamp: None,
+                status: TransactionStatus::Completed,
+                history: StatusHistory::default(),
+            };
+            engine.process_transaction(deposit).unwrap();
+        }
+        assert_eq!(engine.account(ClientId(1)).unwrap().lock_reason, None);
 
-        let result = engine.process_dispute(1, 1);
+        engine.process_dispute(ClientId(1), TxId(2)).unwrap();
+        engine.process_chargeback(ClientId(1), TxId(2)).unwrap();
 
-        assert!(
-            result.is_err(),
-            "Should not have sufficient available balance for dispute"
+        let reason = LockReason::Chargeback {
+            tx_id: TxId(2),
+            sequence: 3,
+        };
+        assert_eq!(
+            engine.account(ClientId(1)).unwrap().lock_reason,
+            Some(reason)
+        );
+        let error = engine.process_dispute(ClientId(1), TxId(1)).unwrap_err();
+        assert!(matches!(
+            error,
+            PaymentError::AccountLocked {
+                client: ClientId(1),
+                reason: Some(LockReason::Chargeback { tx_id: TxId(2), .. })
+            }
+        ));
+        assert_eq!(
+            error.to_string(),
+            "Account is locked: 1 by chargeback of tx 2 (sequence 3)"
         );
-        assert_eq!(engine.accounts.get(&1).unwrap().available, dec!(20.0));
-        assert_eq!(engine.accounts.get(&1).unwrap().total, dec!(20.0));
-        assert_eq!(engine.accounts.get(&1).unwrap().held, dec!(0.0));
 
+        let mut output = Vec::new();
+        engine.write_json(&mut output, None).unwrap();
         assert_eq!(
-            engine.transactions.get(&1).unwrap().get(&1).unwrap().status,
-            TransactionStatus::Completed
+            String::from_utf8(output).unwrap(),
+            r#"[{"client":1,"available":"10.0000","held":"0.0000","total":"10.0000","locked":true,"lock_reason":{"type":"chargeback","tx_id":2,"sequence":3}}]"#
+        );
+        assert_eq!(
+            engine.to_string(),
+            "client, available, held, total, locked\n1, 10.0000, 0.0000, 10.0000, true\n"
         );
     }
 
+    #[cfg(feature = "csv")]
     #[test]
-    fn test_successful_dispute_after_partial_withdrawal() {
+    fn test_freeze_and_unfreeze() {
         let mut engine = PaymentEngine::new();
+        engine.apply(TransactionEntry::freeze(ClientId(1))).unwrap();
+        let account = engine.account(ClientId(1)).unwrap();
+        assert!(account.locked);
+        assert_eq!(account.lock_reason, Some(LockReason::Manual));
+        assert_eq!(account.total, Decimal::ZERO);
 
-        let deposit = Transaction {
-            tx_type: TransactionType::Deposit,
-            account_id: 1,
-            tx_id: 1,
-            amount: dec!(30.0),
-            status: TransactionStatus::Completed,
-        };
-
-        engine.get_or_create_account(1);
+        let deposit = TransactionEntry::deposit(ClientId(1), TxId(1), dec!(10.0));
+        assert_eq!(
+            engine.apply(deposit.clone()).unwrap_err().to_string(),
+            "Account is locked: 1 by manual freeze"
+        );
         engine
-            .update_account_balance(1, dec!(100.0), dec!(0.0), dec!(100.0))
+            .apply(TransactionEntry::unfreeze(ClientId(1)))
             .unwrap();
-        engine.insert_transaction(deposit);
+        assert_eq!(engine.apply(deposit).unwrap().available, dec!(10.0));
+        assert_eq!(
+            engine.unfreeze_account(ClientId(1)),
+            Err(PaymentError::AccountNotFrozen(ClientId(1)))
+        );
+        assert_eq!(
+            engine.unfreeze_account(ClientId(2)),
+            Err(PaymentError::AccountNotFound(ClientId(2)))
+        );
 
-        let withdrawal = Transaction {
-            tx_type: TransactionType::Withdrawal,
-            account_id: 1,
-            tx_id: 2,
-            amount: dec!(50.0),
-            status: TransactionStatus::Completed,
-        };
+        // A chargeback lock is neither replaced by a freeze nor lifted.
+        engine.process_dispute(ClientId(1), TxId(1)).unwrap();
+        engine.process_chargeback(ClientId(1), TxId(1)).unwrap();
+        engine.freeze_account(ClientId(1)).unwrap();
+        assert!(matches!(
+            engine.unfreeze_account(ClientId(1)),
+            Err(PaymentError::AccountLocked {
+                reason: Some(LockReason::Chargeback { .. }),
+                ..
+            })
+        ));
+        assert!(engine.account(ClientId(1)).unwrap().locked);
 
+        // A chargeback turns a freeze into a chargeback lock.
         engine
-            .process_transaction(withdrawal)
-            .expect("Withdrawal should succeed");
+            .apply(TransactionEntry::deposit(ClientId(3), TxId(3), dec!(5.0)))
+            .unwrap();
+        engine.process_dispute(ClientId(3), TxId(3)).unwrap();
+        engine.freeze_account(ClientId(3)).unwrap();
+        assert_eq!(
+            engine.account(ClientId(3)).unwrap().lock_state(),
+            LockState::AdminLocked
+        );
+        engine.process_chargeback(ClientId(3), TxId(3)).unwrap();
+        assert_eq!(
+            engine.account(ClientId(3)).unwrap().lock_state(),
+            LockState::ChargebackLocked
+        );
+        assert!(engine.unfreeze_account(ClientId(3)).is_err());
+    }
 
-        assert_eq!(engine.accounts.get(&1).unwrap().available, dec!(50.0));
+    #[cfg(feature = "csv")]
+    #[test]
+    fn test_currencies() {
+        let eur: Currency = "EUR".parse().unwrap();
+        let mut engine = PaymentEngine::new();
+        for entry in [
+            TransactionEntry::deposit(ClientId(1), TxId(1), dec!(10.0)),
+            TransactionEntry::deposit(ClientId(1), TxId(2), dec!(5.0)).in_currency(eur),
+            TransactionEntry::deposit(ClientId(1), TxId(3), dec!(1.0)).in_currency(Currency::USD),
+            TransactionEntry::deposit(ClientId(2), TxId(4), dec!(2.0)),
+        ] {
+            engine.apply(entry).unwrap();
+        }
+        assert!(matches!(
+            engine.apply(
+                TransactionEntry::withdrawal(ClientId(1), TxId(5), dec!(6.0)).in_currency(eur)
+            ),
+            Err(PaymentError::InsufficientFunds { .. })
+        ));
+        assert!(matches!(
+            engine.apply(TransactionEntry::dispute(ClientId(1), TxId(2)).in_currency(Currency::USD)),
+            Err(PaymentError::CurrencyMismatch { expected, .. }) if expected == eur
+        ));
+        engine
+            .apply(TransactionEntry::dispute(ClientId(1), TxId(2)))
+            .unwrap();
 
-        let result = engine.process_dispute(1, 1);
-        assert!(
-            result.is_ok(),
-            "Dispute should succeed when sufficient available balance"
+        let account = &engine.accounts[&ClientId(1)];
+        assert_eq!(account.total, dec!(11.0));
+        assert_eq!(
+            account.balances(Some(eur)),
+            Balances {
+                available: dec!(0.0),
+                held: dec!(5.0),
+                total: dec!(5.0),
+            }
         );
+        assert_eq!(engine.transactions[&ClientId(1)][&TxId(3)].currency, None);
 
-        assert_eq!(engine.accounts.get(&1).unwrap().available, dec!(20.0));
-        assert_eq!(engine.accounts.get(&1).unwrap().held, dec!(30.0));
-        assert_eq!(engine.accounts.get(&1).unwrap().total, dec!(50.0));
+        engine
+            .apply(TransactionEntry::chargeback(ClientId(1), TxId(2)).in_currency(eur))
+            .unwrap();
+        assert_eq!(engine.accounts[&ClientId(1)].total, dec!(11.0));
+        assert_eq!(engine.verify_invariants(), Ok(()));
+        assert_eq!(engine.audit(), vec![]);
 
+        let mut output = Vec::new();
+        engine.write_csv(&mut output, Some(2)).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let mut lines: Vec<&str> = output.lines().collect();
+        lines.sort_unstable();
         assert_eq!(
-            engine.transactions.get(&1).unwrap().get(&1).unwrap().status,
-            TransactionStatus::Disputed
+            lines,
+            vec![
+                "1, EUR, 0.00, 0.00, 0.00, true",
+                "1, USD, 11.00, 0.00, 11.00, true",
+                "2, USD, 2.00, 0.00, 2.00, false",
+                "client, currency, available, held, total, locked",
+            ]
         );
+
+        let mut output = Vec::new();
+        engine.write_json(&mut output, Some(0)).unwrap();
+        assert!(String::from_utf8(output).unwrap().starts_with(
+            r#"[{"client":1,"currency":"USD","available":"11","held":"0","total":"11","locked":true"#
+        ));
     }
 }
@@ -1,62 +1,806 @@
-use crate::entry::{TransactionEntry, TransactionEntryType};
-use crate::error::PaymentError;
+use crate::config::CsvOptions;
+use crate::entry::{AmountField, SUPPORTED_VERSIONS, TransactionEntry, TransactionEntryType};
+use crate::error::{ContextualError, PaymentError, ProcessorError, RejectionReason};
+use crate::ids::ClientId;
+use crate::input::open_input;
 use crate::payments_engine::PaymentEngine;
+use crate::stats::Stats;
+use crate::transaction::{ConvertionError, Transaction};
 
-use std::io::Read;
+use std::collections::HashSet;
+use std::fmt;
+use std::fmt::Display;
+use std::io::{self, Read};
 use std::iter::Iterator;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 
-use csv::{ReaderBuilder, Trim};
+use csv::{ReaderBuilder, StringRecord, Trim};
+use rust_decimal::Decimal;
 
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ProcessingSummary {
+    pub processed: u64,
+    pub rejected_locked: u64,
+    pub rejected: u64,
+    pub parse_errors: u64,
+    /// Rows left out by an `EntryFilter`.
+    pub skipped: u64,
+    /// Processing stopped early because the cancellation token was set; the
+    /// remaining rows were not read.
+    pub interrupted: bool,
+    /// Processing stopped early because the errors exceeded
+    /// `CsvOptions::max_errors`; the remaining rows were not read.
+    pub aborted_due_to_errors: bool,
+    /// Breakdown of the rows by type, rejection code and amount.
+    pub stats: Stats,
+}
+
+impl ProcessingSummary {
+    /// Rows read, whatever became of them; after an interrupted run, the
+    /// `CsvOptions::skip_rows` that resumes right after them.
+    #[inline]
+    pub fn rows(&self) -> u64 {
+        self.processed + self.rejected + self.rejected_locked + self.parse_errors + self.skipped
+    }
+
+    /// Rows that failed to parse or were rejected.
+    #[inline]
+    pub fn errors(&self) -> u64 {
+        self.rejected + self.rejected_locked + self.parse_errors
+    }
+
+    #[inline]
+    pub(crate) fn record_parse_error(&mut self) {
+        self.parse_errors += 1;
+        self.stats.parse_failures += 1;
+    }
+
+    #[inline]
+    fn record(&mut self, result: &Result<(), PaymentError>) {
+        match result {
+            Ok(()) => self.processed += 1,
+            Err(e) => match e.rejection_reason() {
+                RejectionReason::AccountLocked => self.rejected_locked += 1,
+                RejectionReason::Invalid => self.rejected += 1,
+            },
+        }
+    }
+}
+
+impl Display for ProcessingSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "processed {}, rejected {} ({} on locked accounts), parse errors {}",
+            self.processed,
+            self.rejected + self.rejected_locked,
+            self.rejected_locked,
+            self.parse_errors
+        )?;
+        if self.skipped > 0 {
+            write!(f, ", skipped {}", self.skipped)?;
+        }
+        if self.interrupted {
+            f.write_str(", interrupted")?;
+        }
+        if self.aborted_due_to_errors {
+            f.write_str(", aborted after too many errors")?;
+        }
+        Ok(())
+    }
+}
+
+/// Selects the rows to apply by client. Rows of other clients are skipped
+/// before any engine work, so their accounts never appear in the output; the
+/// default filter keeps every row.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct EntryFilter {
+    clients: Option<HashSet<ClientId>>,
+}
+
+impl EntryFilter {
+    /// Keeps only the rows of these clients.
+    pub fn clients(clients: impl IntoIterator<Item = ClientId>) -> Self {
+        EntryFilter {
+            clients: Some(clients.into_iter().collect()),
+        }
+    }
+
+    #[inline]
+    pub fn matches(&self, entry: &TransactionEntry) -> bool {
+        self.clients
+            .as_ref()
+            .is_none_or(|clients| clients.contains(&entry.account_id))
+    }
+}
+
+/// Processing result of a single input file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileSummary {
+    pub path: PathBuf,
+    pub summary: ProcessingSummary,
+}
+
+/// Receives the rows that could not be applied.
+pub trait ErrorSink {
+    /// `record` holds the raw fields when the row itself could be read.
+    fn parse_error(&mut self, record: Option<&StringRecord>, error: &dyn Display);
+    fn rejected(&mut self, entry: &TransactionEntry, error: &PaymentError);
+
+    /// Called with the number of each data row, counting from 1, before the
+    /// row is applied or reported.
+    fn set_row(&mut self, _row: u64) {}
+
+    /// Called by `process_csv_files` before each file is read.
+    fn set_file(&mut self, _path: &Path) {}
+}
+
+impl<S: ErrorSink + ?Sized> ErrorSink for &mut S {
+    fn parse_error(&mut self, record: Option<&StringRecord>, error: &dyn Display) {
+        (**self).parse_error(record, error);
+    }
+
+    fn rejected(&mut self, entry: &TransactionEntry, error: &PaymentError) {
+        (**self).rejected(entry, error);
+    }
+
+    fn set_row(&mut self, row: u64) {
+        (**self).set_row(row);
+    }
+
+    fn set_file(&mut self, path: &Path) {
+        (**self).set_file(path);
+    }
+}
+
+/// Reports every failure to both sinks.
+impl<A: ErrorSink, B: ErrorSink> ErrorSink for (A, B) {
+    fn parse_error(&mut self, record: Option<&StringRecord>, error: &dyn Display) {
+        self.0.parse_error(record, error);
+        self.1.parse_error(record, error);
+    }
+
+    fn rejected(&mut self, entry: &TransactionEntry, error: &PaymentError) {
+        self.0.rejected(entry, error);
+        self.1.rejected(entry, error);
+    }
+
+    fn set_row(&mut self, row: u64) {
+        self.0.set_row(row);
+        self.1.set_row(row);
+    }
+
+    fn set_file(&mut self, path: &Path) {
+        self.0.set_file(path);
+        self.1.set_file(path);
+    }
+}
+
+/// Reports every failure on stderr, a rejection as a `ContextualError` naming
+/// its row and entry.
+#[derive(Debug, Default)]
+pub struct StderrSink {
+    row: u64,
+}
+
+impl ErrorSink for StderrSink {
+    fn parse_error(&mut self, _record: Option<&StringRecord>, error: &dyn Display) {
+        eprintln!("Error parsing transaction: {}", error);
+    }
+
+    fn rejected(&mut self, entry: &TransactionEntry, error: &PaymentError) {
+        let error = ContextualError {
+            row: self.row,
+            entry: entry.clone(),
+            source: error.clone(),
+        };
+        match error.source.rejection_reason() {
+            RejectionReason::AccountLocked => {
+                eprintln!("Warning: transaction rejected: {}", error)
+            }
+            RejectionReason::Invalid => eprintln!("Error processing transaction: {}", error),
+        }
+    }
+
+    fn set_row(&mut self, row: u64) {
+        self.row = row;
+    }
+}
+
+/// Reports every failure on stderr like `StderrSink`, prefixed with the file
+/// and line, e.g. `part-0003.csv:7: `, for runs over several files. The file
+/// follows `ErrorSink::set_file`.
+pub struct FileStderrSink {
+    path: PathBuf,
+    header_lines: u64,
+    inner: StderrSink,
+}
+
+impl FileStderrSink {
+    pub fn new(path: impl Into<PathBuf>, has_headers: bool) -> Self {
+        FileStderrSink {
+            path: path.into(),
+            header_lines: u64::from(has_headers),
+            inner: StderrSink::default(),
+        }
+    }
+
+    /// Line of the current row, assuming no quoted field spans lines and no
+    /// comment or blank line comes before it.
+    #[inline]
+    fn line(&self) -> u64 {
+        self.inner.row + self.header_lines
+    }
+}
+
+impl ErrorSink for FileStderrSink {
+    fn parse_error(&mut self, record: Option<&StringRecord>, error: &dyn Display) {
+        let _stderr = io::stderr().lock();
+        eprint!("{}:{}: ", self.path.display(), self.line());
+        self.inner.parse_error(record, error);
+    }
+
+    fn rejected(&mut self, entry: &TransactionEntry, error: &PaymentError) {
+        let _stderr = io::stderr().lock();
+        eprint!("{}:{}: ", self.path.display(), self.line());
+        self.inner.rejected(entry, error);
+    }
+
+    fn set_row(&mut self, row: u64) {
+        self.inner.set_row(row);
+    }
+
+    fn set_file(&mut self, path: &Path) {
+        self.path = path.to_path_buf();
+    }
+}
+
+/// Discards every failure; the counts are still kept in the summary.
+pub struct NullSink;
+
+impl ErrorSink for NullSink {
+    fn parse_error(&mut self, _record: Option<&StringRecord>, _error: &dyn Display) {}
+
+    fn rejected(&mut self, _entry: &TransactionEntry, _error: &PaymentError) {}
+}
+
+/// Reader for the transaction CSV format: unquoted, trimmed fields with an
+/// optional trailing amount.
 #[inline]
-pub fn process_csv_stream(engine: &mut PaymentEngine, reader: impl Read) {
-    let mut binding = ReaderBuilder::new()
-        .has_headers(true)
-        .quoting(false)
+pub(crate) fn csv_reader<R: Read>(reader: R, options: &CsvOptions) -> csv::Reader<R> {
+    ReaderBuilder::new()
+        .has_headers(options.has_headers)
+        .delimiter(options.delimiter)
+        .quoting(options.quoting)
         .trim(Trim::All)
         .flexible(true)
-        .from_reader(reader);
+        .comment(options.comment_char)
+        .from_reader(reader)
+}
+
+/// Whether every field of the row is empty once trimmed, as for a line of
+/// whitespace or bare delimiters.
+#[inline]
+pub(crate) fn is_blank(record: &StringRecord) -> bool {
+    record.iter().all(str::is_empty)
+}
 
-    let stream = binding
-        .deserialize()
-        .inspect(|result: &Result<TransactionEntry, csv::Error>| {
-            if let Err(e) = result {
-                eprintln!("Error parsing transaction: {}", e);
+/// Columns every header row must name; `amount` may be omitted.
+const REQUIRED_COLUMNS: [&str; 3] = ["type", "client", "tx"];
+
+/// Rejects a header row missing one of the required columns, naming it, since
+/// every row would otherwise fail to deserialize.
+pub(crate) fn check_headers(headers: &StringRecord) -> Result<(), ProcessorError> {
+    match REQUIRED_COLUMNS
+        .iter()
+        .find(|column| !headers.iter().any(|header| header == **column))
+    {
+        Some(column) => Err(ProcessorError::BadHeader(format!(
+            "missing column `{}`, found `{}`",
+            column,
+            headers.iter().collect::<Vec<_>>().join(", ")
+        ))),
+        None => Ok(()),
+    }
+}
+
+#[inline]
+pub fn process_csv_stream(
+    engine: &mut PaymentEngine,
+    reader: impl Read,
+) -> Result<ProcessingSummary, ProcessorError> {
+    process_csv_stream_with_sink(engine, reader, &mut StderrSink::default())
+}
+
+pub fn process_csv_stream_with_sink(
+    engine: &mut PaymentEngine,
+    reader: impl Read,
+    sink: &mut (impl ErrorSink + ?Sized),
+) -> Result<ProcessingSummary, ProcessorError> {
+    process_csv_stream_with_options(engine, reader, &CsvOptions::default(), sink)
+}
+
+/// Reads the input with `options`, e.g. another delimiter, reporting failed
+/// rows on stderr.
+#[inline]
+pub fn process_csv_stream_with(
+    engine: &mut PaymentEngine,
+    reader: impl Read,
+    options: &CsvOptions,
+) -> Result<ProcessingSummary, ProcessorError> {
+    process_csv_stream_with_options(engine, reader, options, &mut StderrSink::default())
+}
+
+/// Fails with `BadHeader` before applying anything if the header row lacks a
+/// required column.
+pub fn process_csv_stream_with_options(
+    engine: &mut PaymentEngine,
+    reader: impl Read,
+    options: &CsvOptions,
+    sink: &mut (impl ErrorSink + ?Sized),
+) -> Result<ProcessingSummary, ProcessorError> {
+    process_results(
+        engine,
+        csv_entries(reader, options)?,
+        &EntryFilter::default(),
+        None,
+        options,
+        sink,
+    )
+}
+
+/// Stops before the next row once `cancel` is set, e.g. from a signal
+/// handler, and marks the summary `interrupted`. The rows applied until then
+/// stay applied, so a report can still be written for them.
+pub fn process_csv_stream_cancellable(
+    engine: &mut PaymentEngine,
+    reader: impl Read,
+    cancel: &AtomicBool,
+    sink: &mut (impl ErrorSink + ?Sized),
+) -> Result<ProcessingSummary, ProcessorError> {
+    let options = CsvOptions::default();
+    process_results(
+        engine,
+        csv_entries(reader, &options)?,
+        &EntryFilter::default(),
+        Some(cancel),
+        &options,
+        sink,
+    )
+}
+
+/// Processes only the rows selected by `filter`, counting the others as
+/// skipped. Rows that fail to parse are still reported, whatever their client.
+#[inline]
+pub fn process_csv_stream_filtered(
+    engine: &mut PaymentEngine,
+    reader: impl Read,
+    filter: &EntryFilter,
+) -> Result<ProcessingSummary, ProcessorError> {
+    process_csv_stream_filtered_with_sink(engine, reader, filter, &mut StderrSink::default())
+}
+
+pub fn process_csv_stream_filtered_with_sink(
+    engine: &mut PaymentEngine,
+    reader: impl Read,
+    filter: &EntryFilter,
+    sink: &mut (impl ErrorSink + ?Sized),
+) -> Result<ProcessingSummary, ProcessorError> {
+    let options = CsvOptions::default();
+    process_results(
+        engine,
+        csv_entries(reader, &options)?,
+        filter,
+        None,
+        &options,
+        sink,
+    )
+}
+
+/// Deserializes the rows of a transaction CSV. A row that fails to deserialize
+/// is yielded with its raw fields; the iterator ends after an io error, an
+/// unreadable header row or a row of an unsupported version, yielded as
+/// `RowFailure::Fatal`.
+fn csv_entries(
+    reader: impl Read,
+    options: &CsvOptions,
+) -> Result<impl Iterator<Item = Result<TransactionEntry, RowFailure<csv::Error>>>, ProcessorError>
+{
+    let mut binding = csv_reader(reader, options);
+    let mut header_error = None;
+    let headers = if options.has_headers {
+        match binding.headers() {
+            Ok(headers) => {
+                check_headers(headers)?;
+                Some(headers.clone())
             }
-        })
-        .filter_map(Result::ok);
+            Err(e) => {
+                header_error = Some(e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let mut record = StringRecord::new();
+    let mut done = false;
+    let amount_format = options.amount_format;
+    let skip_blank_lines = options.skip_blank_lines;
+    let version_column = headers.as_ref().and_then(version_column);
+
+    Ok(std::iter::from_fn(move || {
+        if let Some(e) = header_error.take() {
+            done = true;
+            return Some(Err(RowFailure::Parse(None, e)));
+        }
+        if done {
+            return None;
+        }
+        let mut read = binding.read_record(&mut record);
+        while skip_blank_lines && matches!(read, Ok(true)) && is_blank(&record) {
+            read = binding.read_record(&mut record);
+        }
+        match read {
+            Ok(true) => {
+                if let Some(version) = unsupported_version(&record, version_column) {
+                    done = true;
+                    return Some(Err(RowFailure::Fatal(ProcessorError::UnsupportedVersion(
+                        version,
+                    ))));
+                }
+                Some(
+                    record
+                        .deserialize::<TransactionEntry>(headers.as_ref())
+                        .map(|mut entry| {
+                            if let Some(format) = &amount_format {
+                                entry.amount.reparse(format);
+                            }
+                            entry
+                        })
+                        .map_err(|e| RowFailure::Parse(Some(record.clone()), e)),
+                )
+            }
+            Ok(false) => None,
+            Err(e) => {
+                done = e.is_io_error();
+                Some(Err(RowFailure::Parse(None, e)))
+            }
+        }
+    }))
+}
 
-    process_stream(engine, stream);
+/// Why an item of the row stream was not applied.
+enum RowFailure<E> {
+    /// The row failed to parse, with its raw fields when available; reported
+    /// and counted, and processing goes on.
+    Parse(Option<StringRecord>, E),
+    /// Processing stops with this error, keeping the rows applied before it.
+    Fatal(ProcessorError),
 }
 
 #[inline]
-pub fn process_stream(engine: &mut PaymentEngine, stream: impl Iterator<Item = TransactionEntry>) {
-    for transaction in stream {
-        let result = process_entry(engine, transaction);
+pub(crate) fn version_column(headers: &StringRecord) -> Option<usize> {
+    headers.iter().position(|header| header == "version")
+}
 
-        result.unwrap_or_else(|e| {
-            eprintln!("Error processing transaction: {}", e);
-        });
+/// The version of the row if it is missing from `SUPPORTED_VERSIONS`. A
+/// version that is not a number is left to fail with the row.
+pub(crate) fn unsupported_version(record: &StringRecord, column: Option<usize>) -> Option<u32> {
+    column
+        .and_then(|column| record.get(column))
+        .and_then(|version| version.parse::<u32>().ok())
+        .filter(|version| !SUPPORTED_VERSIONS.contains(version))
+}
+
+/// Opens the file with `open_input` and processes it against the engine.
+pub fn process_csv_file(
+    engine: &mut PaymentEngine,
+    path: &Path,
+    sink: &mut (impl ErrorSink + ?Sized),
+) -> Result<ProcessingSummary, ProcessorError> {
+    process_csv_file_filtered(engine, path, &EntryFilter::default(), sink)
+}
+
+/// Like `process_csv_file`, applying only the rows selected by `filter`.
+pub fn process_csv_file_filtered(
+    engine: &mut PaymentEngine,
+    path: &Path,
+    filter: &EntryFilter,
+    sink: &mut (impl ErrorSink + ?Sized),
+) -> Result<ProcessingSummary, ProcessorError> {
+    let reader = open_input(path)?;
+    process_csv_stream_filtered_with_sink(engine, reader, filter, sink)
+}
+
+/// Like `process_csv_file_filtered`, reading the file with `options` and
+/// stopping early once `cancel` is set; see `process_csv_stream_cancellable`.
+pub fn process_csv_file_cancellable(
+    engine: &mut PaymentEngine,
+    path: &Path,
+    options: &CsvOptions,
+    filter: &EntryFilter,
+    cancel: &AtomicBool,
+    sink: &mut (impl ErrorSink + ?Sized),
+) -> Result<ProcessingSummary, ProcessorError> {
+    let reader = open_input(path)?;
+    process_results(
+        engine,
+        csv_entries(reader, options)?,
+        filter,
+        Some(cancel),
+        options,
+        sink,
+    )
+}
+
+/// Processes the files in order against the same engine, each with its own
+/// header row, and returns the result of each file processed, that of
+/// `paths[i]` at index `i`. A file that fails, e.g. because it cannot be
+/// opened or has a row of an unsupported version, keeps the rows applied
+/// before the failure, and processing goes on with the next file unless
+/// `strict` is set. Only the rows selected by `filter` are applied, and
+/// processing stops after a file that was cancelled or aborted:
+/// `options.max_errors` counts the failed rows of the whole run, each failed
+/// file counting as one.
+pub fn process_csv_files(
+    engine: &mut PaymentEngine,
+    paths: &[PathBuf],
+    options: &CsvOptions,
+    filter: &EntryFilter,
+    cancel: &AtomicBool,
+    strict: bool,
+    sink: &mut (impl ErrorSink + ?Sized),
+) -> Vec<Result<FileSummary, ProcessorError>> {
+    let mut results = Vec::with_capacity(paths.len());
+    let mut failed = 0;
+    for path in paths {
+        sink.set_file(path);
+        let options = CsvOptions {
+            max_errors: options.max_errors.map(|max| max.saturating_sub(failed)),
+            ..*options
+        };
+        let result = process_csv_file_cancellable(engine, path, &options, filter, cancel, sink)
+            .map(|summary| FileSummary {
+                path: path.clone(),
+                summary,
+            });
+        let stop = match &result {
+            Ok(file) => {
+                failed += file.summary.errors();
+                file.summary.interrupted || file.summary.aborted_due_to_errors
+            }
+            Err(_) => {
+                failed += 1;
+                strict
+            }
+        };
+        results.push(result);
+        if stop {
+            break;
+        }
     }
+    results
 }
 
 #[inline]
-fn process_entry(
+pub fn process_stream(
+    engine: &mut PaymentEngine,
+    stream: impl Iterator<Item = TransactionEntry>,
+) -> ProcessingSummary {
+    process_stream_with_sink(engine, stream, &mut StderrSink::default())
+}
+
+/// Processes entries parsed by the caller from any format. Items that failed to
+/// parse are reported to the sink and counted as parse errors; processing
+/// continues with the next item.
+pub fn process_fallible_stream<E: Display>(
+    engine: &mut PaymentEngine,
+    stream: impl Iterator<Item = Result<TransactionEntry, E>>,
+    sink: &mut (impl ErrorSink + ?Sized),
+) -> ProcessingSummary {
+    process_results(
+        engine,
+        stream.map(|item| item.map_err(|e| RowFailure::Parse(None, e))),
+        &EntryFilter::default(),
+        None,
+        &CsvOptions::default(),
+        sink,
+    )
+    // Only strict processing fails.
+    .unwrap_or_default()
+}
+
+/// Processes records of the caller's own type, e.g. rows read from JSON or a
+/// database cursor, converting each with `TryInto<TransactionEntry>`. Records
+/// that fail to convert are reported to the sink and counted as parse errors;
+/// types implementing `Into<TransactionEntry>` never fail.
+pub fn process_records<R>(
+    engine: &mut PaymentEngine,
+    records: impl IntoIterator<Item = R>,
+    sink: &mut (impl ErrorSink + ?Sized),
+) -> ProcessingSummary
+where
+    R: TryInto<TransactionEntry>,
+    R::Error: Display,
+{
+    process_fallible_stream(engine, records.into_iter().map(R::try_into), sink)
+}
+
+/// Shared by the CSV and the custom format paths; a failed item carries the raw
+/// fields of the row when they are known. Only `strict`, `skip_rows` and
+/// `max_errors` of `options` apply here. With `strict`, the first rejected
+/// row is still reported to the sink, then returned as an error. The first
+/// `skip_rows` items are read but neither applied, reported nor counted, and
+/// the rows keep their numbers.
+fn process_results<E: Display>(
+    engine: &mut PaymentEngine,
+    stream: impl Iterator<Item = Result<TransactionEntry, RowFailure<E>>>,
+    filter: &EntryFilter,
+    cancel: Option<&AtomicBool>,
+    options: &CsvOptions,
+    sink: &mut (impl ErrorSink + ?Sized),
+) -> Result<ProcessingSummary, ProcessorError> {
+    #[cfg(feature = "tracing")]
+    let span = tracing::info_span!(
+        "process_csv",
+        rows = tracing::field::Empty,
+        processed = tracing::field::Empty,
+        failed = tracing::field::Empty,
+    )
+    .entered();
+    let result = process_rows(engine, stream, filter, cancel, options, sink);
+    #[cfg(feature = "tracing")]
+    if let Ok(summary) = &result {
+        span.record("rows", summary.rows());
+        span.record("processed", summary.processed);
+        span.record("failed", summary.errors());
+    }
+    result
+}
+
+fn process_rows<E: Display>(
+    engine: &mut PaymentEngine,
+    stream: impl Iterator<Item = Result<TransactionEntry, RowFailure<E>>>,
+    filter: &EntryFilter,
+    cancel: Option<&AtomicBool>,
+    options: &CsvOptions,
+    sink: &mut (impl ErrorSink + ?Sized),
+) -> Result<ProcessingSummary, ProcessorError> {
+    let mut summary = ProcessingSummary::default();
+    for (index, item) in stream.enumerate().skip(options.skip_rows) {
+        if cancel.is_some_and(|cancel| cancel.load(Ordering::Relaxed)) {
+            summary.interrupted = true;
+            break;
+        }
+        sink.set_row(index as u64 + 1);
+        match item {
+            Ok(entry) if !filter.matches(&entry) => summary.skipped += 1,
+            Ok(entry) => {
+                if let Some(error) = process_and_record(engine, entry, &mut summary, sink)
+                    && options.strict
+                {
+                    return Err(ProcessorError::Rejected {
+                        row: index as u64 + 1,
+                        error,
+                    });
+                }
+            }
+            Err(RowFailure::Parse(record, e)) => {
+                sink.parse_error(record.as_ref(), &e);
+                summary.record_parse_error();
+            }
+            Err(RowFailure::Fatal(error)) => return Err(error),
+        }
+        if options
+            .max_errors
+            .is_some_and(|max_errors| summary.errors() > max_errors)
+        {
+            summary.aborted_due_to_errors = true;
+            break;
+        }
+    }
+    Ok(summary)
+}
+
+pub fn process_stream_with_sink(
+    engine: &mut PaymentEngine,
+    stream: impl Iterator<Item = TransactionEntry>,
+    sink: &mut (impl ErrorSink + ?Sized),
+) -> ProcessingSummary {
+    let mut summary = ProcessingSummary::default();
+    for (index, transaction) in stream.enumerate() {
+        sink.set_row(index as u64 + 1);
+        process_and_record(engine, transaction, &mut summary, sink);
+    }
+    summary
+}
+
+/// Returns the rejection, already reported to the sink, if any.
+#[inline]
+pub(crate) fn process_and_record(
+    engine: &mut PaymentEngine,
+    transaction: TransactionEntry,
+    summary: &mut ProcessingSummary,
+    sink: &mut (impl ErrorSink + ?Sized),
+) -> Option<PaymentError> {
+    let reused = engine.reused_tx_ids().len();
+    let result = process_entry(engine, transaction.clone());
+    summary.record(&result);
+    summary.stats.reused_tx_ids += (engine.reused_tx_ids().len() - reused) as u64;
+    summary.stats.record(
+        transaction.entry_type.clone(),
+        applied_amount(engine, &transaction),
+        &result,
+    );
+
+    let e = result.err()?;
+    sink.rejected(&transaction, &e);
+    Some(e)
+}
+
+/// Amount stored for a deposit or withdrawal, which may be less than the
+/// entry's for a clamped withdrawal; the entry's once the transaction was
+/// evicted.
+#[inline]
+fn applied_amount(engine: &PaymentEngine, entry: &TransactionEntry) -> Option<Decimal> {
+    engine
+        .transactions
+        .get(&entry.account_id)
+        .and_then(|transactions| transactions.get(&entry.tx_id))
+        .map(|transaction| transaction.amount)
+        .or(match entry.amount {
+            AmountField::Value(amount) => Some(amount),
+            _ => None,
+        })
+}
+
+#[inline]
+pub(crate) fn process_entry(
     engine: &mut PaymentEngine,
     transaction: TransactionEntry,
 ) -> Result<(), PaymentError> {
+    if !matches!(
+        transaction.entry_type,
+        TransactionEntryType::Deposit
+            | TransactionEntryType::Withdrawal
+            | TransactionEntryType::Freeze
+            | TransactionEntryType::Unfreeze
+    ) {
+        engine
+            .check_currency(
+                transaction.account_id,
+                transaction.tx_id,
+                transaction.currency,
+            )
+            .map_err(|error| engine.rejected(error))?;
+    }
     let result: Result<(), PaymentError> = match transaction.entry_type {
         TransactionEntryType::Withdrawal | TransactionEntryType::Deposit => {
-            engine.process_transaction(transaction.try_into()?)
+            let max_scale = engine.config.max_amount_scale;
+            let transaction = Transaction::from_entry(transaction, max_scale)
+                .map_err(|error| engine.rejected(error.into()))?;
+            engine.process_transaction(transaction)
         }
         TransactionEntryType::Dispute => {
             engine.process_dispute(transaction.account_id, transaction.tx_id)
         }
-        TransactionEntryType::Resolve => {
-            engine.process_resolve(transaction.account_id, transaction.tx_id)
-        }
+        TransactionEntryType::Resolve => match transaction.amount {
+            AmountField::Missing => {
+                engine.process_resolve(transaction.account_id, transaction.tx_id)
+            }
+            AmountField::Value(amount) => {
+                engine.process_partial_resolve(transaction.account_id, transaction.tx_id, amount)
+            }
+            AmountField::Invalid(raw) => {
+                Err(engine.rejected(ConvertionError::UnparseableAmount(raw).into()))
+            }
+        },
         TransactionEntryType::Chargeback => {
             engine.process_chargeback(transaction.account_id, transaction.tx_id)
         }
+        TransactionEntryType::Freeze => engine.freeze_account(transaction.account_id),
+        TransactionEntryType::Unfreeze => engine.unfreeze_account(transaction.account_id),
     };
     result
 }
@@ -64,8 +808,11 @@ fn process_entry(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::{AmountFormat, CrossClientTxIdPolicy, EngineConfig};
+    use crate::ids::{ClientId, TxId};
+    use crate::payments_engine::TxIdReuse;
     use crate::transaction::TransactionStatus;
-    use rust_decimal::dec;
+    use rust_decimal::{Decimal, dec};
 
     #[test]
     fn test_process_csv_stream() {
@@ -78,137 +825,288 @@ mod tests {
                     chargeback, 1, 2";
         let reader = data.as_bytes();
 
-        process_csv_stream(&mut engine, reader);
+        let summary = process_csv_stream(&mut engine, reader).unwrap();
 
         assert_eq!(engine.accounts.len(), 1);
         assert_eq!(engine.transactions.len(), 1);
+        assert_eq!(summary.processed, 2);
+        assert_eq!(summary.rejected, 3);
+    }
+
+    #[test]
+    fn test_write_transactions_csv() {
+        let mut engine = PaymentEngine::new();
+        let data = "type, client, tx, amount\n\
+                    deposit, 2, 3, 7.5\n\
+                    deposit, 1, 1, 100.0\n\
+                    withdrawal, 1, 2, 50.0\n\
+                    deposit, 1, 4, 10.0\n\
+                    dispute, 1, 4\n\
+                    resolve, 1, 4\n\
+                    dispute, 2, 3\n\
+                    chargeback, 2, 3";
+        process_csv_stream(&mut engine, data.as_bytes()).unwrap();
+
+        let mut output = Vec::new();
+        engine.write_transactions_csv(&mut output).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,tx,type,amount,status\n\
+             1,1,deposit,100.0000,completed\n\
+             1,2,withdrawal,50.0000,completed\n\
+             1,4,deposit,10.0000,resolved\n\
+             2,3,deposit,7.5000,chargebacked\n"
+        );
     }
 
     #[test]
     fn test_process_stream() {
         let mut engine = PaymentEngine::new();
         let transactions = vec![
-            TransactionEntry {
-                entry_type: TransactionEntryType::Deposit,
-                account_id: 1,
-                tx_id: 1,
-                amount: Some(dec!(100.0)),
-            },
-            TransactionEntry {
-                entry_type: TransactionEntryType::Withdrawal,
-                account_id: 1,
-                tx_id: 2,
-                amount: Some(dec!(50.0)),
-            },
-            TransactionEntry {
-                entry_type: TransactionEntryType::Dispute,
-                account_id: 1,
-                tx_id: 1,
-                amount: None,
-            },
+            TransactionEntry::deposit(ClientId(1), TxId(1), dec!(100.0)),
+            TransactionEntry::withdrawal(ClientId(1), TxId(2), dec!(50.0)),
+            TransactionEntry::dispute(ClientId(1), TxId(1)),
         ];
 
         process_stream(&mut engine, transactions.into_iter());
 
         assert_eq!(engine.accounts.len(), 1);
         assert_eq!(engine.transactions.len(), 1);
-        assert_eq!(engine.accounts.get(&1).unwrap().total, dec!(50.0));
-        assert_eq!(engine.transactions.get(&1).unwrap().len(), 2);
+        assert_eq!(engine.accounts.get(&ClientId(1)).unwrap().total, dec!(50.0));
+        assert_eq!(engine.transactions.get(&ClientId(1)).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_process_stream_classifies_locked_rejections() {
+        let mut engine = PaymentEngine::new();
+        let data = "type, client, tx, amount\n\
+                    deposit, 1, 1, 100.0\n\
+                    dispute, 1, 1\n\
+                    chargeback, 1, 1\n\
+                    deposit, 1, 2, 10.0\n\
+                    withdrawal, 1, 3, 5.0\n\
+                    withdrawal, 2, 4, 5.0\n\
+                    deposit, x, 5, 5.0";
+
+        let summary = process_csv_stream(&mut engine, data.as_bytes()).unwrap();
+
+        assert_eq!(
+            summary,
+            ProcessingSummary {
+                processed: 3,
+                rejected_locked: 2,
+                rejected: 1,
+                parse_errors: 1,
+                skipped: 0,
+                interrupted: false,
+                aborted_due_to_errors: false,
+                stats: Stats {
+                    applied: [
+                        (TransactionEntryType::Deposit, 1),
+                        (TransactionEntryType::Dispute, 1),
+                        (TransactionEntryType::Chargeback, 1),
+                    ]
+                    .into_iter()
+                    .collect(),
+                    rejected: [("ACCOUNT_LOCKED", 2), ("INSUFFICIENT_FUNDS", 1)]
+                        .into_iter()
+                        .collect(),
+                    parse_failures: 1,
+                    reused_tx_ids: 0,
+                    deposited: dec!(100.0),
+                    withdrawn: Decimal::ZERO,
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn test_process_csv_stream_filtered() {
+        let mut engine = PaymentEngine::new();
+        let data = "type, client, tx, amount\n\
+                    deposit, 1, 1, 100.0\n\
+                    deposit, 2, 2, 20.0\n\
+                    withdrawal, 1, 3, 25.5\n\
+                    deposit, 2, 4, 5.0\n\
+                    dispute, 2, 2\n\
+                    dispute, 1, 1";
+        let filter = EntryFilter::clients([ClientId(2)]);
+
+        let summary = process_csv_stream_filtered(&mut engine, data.as_bytes(), &filter).unwrap();
+
+        assert_eq!(summary.processed, 3);
+        assert_eq!(summary.skipped, 3);
+        assert_eq!(
+            summary.to_string(),
+            "processed 3, rejected 0 (0 on locked accounts), parse errors 0, skipped 3"
+        );
+        assert_eq!(engine.accounts.len(), 1);
+        let account = engine.accounts.get(&ClientId(2)).unwrap();
+        assert_eq!(account.available, dec!(5.0));
+        assert_eq!(account.held, dec!(20.0));
+        assert!(EntryFilter::default().matches(&TransactionEntry::dispute(ClientId(1), TxId(1))));
+    }
+
+    /// Sets the token on the first rejected row, as a signal handler would.
+    struct CancelOnReject<'a>(&'a AtomicBool);
+
+    impl ErrorSink for CancelOnReject<'_> {
+        fn parse_error(&mut self, _record: Option<&StringRecord>, _error: &dyn Display) {}
+
+        fn rejected(&mut self, _entry: &TransactionEntry, _error: &PaymentError) {
+            self.0.store(true, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn test_process_csv_stream_cancellable() {
+        let mut engine = PaymentEngine::new();
+        let data = "type, client, tx, amount\n\
+                    deposit, 1, 1, 10.0\n\
+                    withdrawal, 1, 2, 50.0\n\
+                    deposit, 1, 3, 5.0\n\
+                    deposit, 2, 4, 5.0";
+        let cancel = AtomicBool::new(false);
+
+        let summary = process_csv_stream_cancellable(
+            &mut engine,
+            data.as_bytes(),
+            &cancel,
+            &mut CancelOnReject(&cancel),
+        )
+        .unwrap();
+
+        assert!(summary.interrupted);
+        assert_eq!((summary.processed, summary.rejected), (1, 1));
+        assert!(summary.to_string().ends_with(", interrupted"));
+        assert_eq!(engine.accounts[&ClientId(1)].available, dec!(10.0));
+        assert!(!engine.accounts.contains_key(&ClientId(2)));
     }
 
     #[test]
     fn test_process_entry_duplicate() {
         let mut engine = PaymentEngine::new();
-        let entry = TransactionEntry {
-            entry_type: TransactionEntryType::Deposit,
-            account_id: 1,
-            tx_id: 1,
-            amount: Some(dec!(100.0)),
-        };
+        let entry = TransactionEntry::deposit(ClientId(1), TxId(1), dec!(100.0));
 
         let result = process_entry(&mut engine, entry.clone());
         assert!(result.is_ok());
 
         let result = process_entry(&mut engine, entry);
-        assert!(result.is_err(), "Should not allow duplicate transactions");
+        assert_eq!(
+            result.unwrap_err().code(),
+            "TX_ALREADY_EXISTS",
+            "Should not allow duplicate transactions"
+        );
 
-        let entry = TransactionEntry {
-            entry_type: TransactionEntryType::Withdrawal,
-            account_id: 1,
-            tx_id: 2,
-            amount: Some(dec!(1.0)),
-        };
+        let entry = TransactionEntry::withdrawal(ClientId(1), TxId(2), dec!(1.0));
 
         let result = process_entry(&mut engine, entry.clone());
         assert!(result.is_ok());
 
         let result = process_entry(&mut engine, entry);
-        assert!(result.is_err(), "Should not allow duplicate transactions");
-        assert_eq!(engine.accounts.get(&1).unwrap().available, dec!(99.0));
-
-        let entry = TransactionEntry {
-            entry_type: TransactionEntryType::Deposit,
-            account_id: 1,
-            tx_id: 3,
-            amount: Some(dec!(50.0)),
-        };
+        assert_eq!(
+            result.unwrap_err().code(),
+            "TX_ALREADY_EXISTS",
+            "Should not allow duplicate transactions"
+        );
+        assert_eq!(
+            engine.accounts.get(&ClientId(1)).unwrap().available,
+            dec!(99.0)
+        );
+
+        let entry = TransactionEntry::deposit(ClientId(1), TxId(3), dec!(50.0));
         process_entry(&mut engine, entry).unwrap();
-        let entry = TransactionEntry {
-            entry_type: TransactionEntryType::Dispute,
-            account_id: 1,
-            tx_id: 3,
-            amount: None,
-        };
+        let entry = TransactionEntry::dispute(ClientId(1), TxId(3));
         let result = process_entry(&mut engine, entry.clone());
         assert!(result.is_ok(), "Dispute should be processed successfully");
-        assert_eq!(engine.accounts.get(&1).unwrap().held, dec!(50.0));
-        assert_eq!(engine.accounts.get(&1).unwrap().total, dec!(149.0));
-        assert_eq!(engine.accounts.get(&1).unwrap().available, dec!(99.0));
+        assert_eq!(engine.accounts.get(&ClientId(1)).unwrap().held, dec!(50.0));
         assert_eq!(
-            engine.transactions.get(&1).unwrap().get(&3).unwrap().status,
+            engine.accounts.get(&ClientId(1)).unwrap().total,
+            dec!(149.0)
+        );
+        assert_eq!(
+            engine.accounts.get(&ClientId(1)).unwrap().available,
+            dec!(99.0)
+        );
+        assert_eq!(
+            engine
+                .transactions
+                .get(&ClientId(1))
+                .unwrap()
+                .get(&TxId(3))
+                .unwrap()
+                .status,
             TransactionStatus::Disputed
         );
 
         let result = process_entry(&mut engine, entry);
-        assert!(result.is_err(), "Should not allow duplicate disputes");
+        assert_eq!(
+            result.unwrap_err().code(),
+            "TX_ALREADY_DISPUTED",
+            "Should not allow duplicate disputes"
+        );
 
-        let entry = TransactionEntry {
-            entry_type: TransactionEntryType::Resolve,
-            account_id: 1,
-            tx_id: 3,
-            amount: None,
-        };
+        let entry = TransactionEntry::resolve(ClientId(1), TxId(3));
         let result = process_entry(&mut engine, entry.clone());
 
         assert!(result.is_ok(), "Resolve should be processed successfully");
-        assert_eq!(engine.accounts.get(&1).unwrap().held, dec!(0.0));
-        assert_eq!(engine.accounts.get(&1).unwrap().total, dec!(149.0));
-        assert_eq!(engine.accounts.get(&1).unwrap().available, dec!(149.0));
+        assert_eq!(engine.accounts.get(&ClientId(1)).unwrap().held, dec!(0.0));
+        assert_eq!(
+            engine.accounts.get(&ClientId(1)).unwrap().total,
+            dec!(149.0)
+        );
         assert_eq!(
-            engine.transactions.get(&1).unwrap().get(&3).unwrap().status,
+            engine.accounts.get(&ClientId(1)).unwrap().available,
+            dec!(149.0)
+        );
+        assert_eq!(
+            engine
+                .transactions
+                .get(&ClientId(1))
+                .unwrap()
+                .get(&TxId(3))
+                .unwrap()
+                .status,
             TransactionStatus::Resolved
         );
 
         let result = process_entry(&mut engine, entry);
-        assert!(result.is_err(), "Should not allow duplicate resolves");
+        assert!(
+            matches!(
+                result,
+                Err(PaymentError::TransactionAlreadyResolved(TxId(3)))
+            ),
+            "Should not allow duplicate resolves"
+        );
 
-        let entry = TransactionEntry {
-            entry_type: TransactionEntryType::Chargeback,
-            account_id: 1,
-            tx_id: 3,
-            amount: None,
-        };
+        let entry = TransactionEntry::chargeback(ClientId(1), TxId(3));
         let result = process_entry(&mut engine, entry.clone());
 
         assert!(
-            result.is_err(),
+            matches!(
+                result,
+                Err(PaymentError::TransactionAlreadyResolved(TxId(3)))
+            ),
             "Chargeback should not be allowed after resolve"
         );
-        assert_eq!(engine.accounts.get(&1).unwrap().held, dec!(0.0));
-        assert_eq!(engine.accounts.get(&1).unwrap().total, dec!(149.0));
-        assert_eq!(engine.accounts.get(&1).unwrap().available, dec!(149.0));
+        assert_eq!(engine.accounts.get(&ClientId(1)).unwrap().held, dec!(0.0));
+        assert_eq!(
+            engine.accounts.get(&ClientId(1)).unwrap().total,
+            dec!(149.0)
+        );
+        assert_eq!(
+            engine.accounts.get(&ClientId(1)).unwrap().available,
+            dec!(149.0)
+        );
         assert_eq!(
-            engine.transactions.get(&1).unwrap().get(&3).unwrap().status,
+            engine
+                .transactions
+                .get(&ClientId(1))
+                .unwrap()
+                .get(&TxId(3))
+                .unwrap()
+                .status,
             TransactionStatus::Resolved
         );
     }
@@ -216,141 +1114,172 @@ mod tests {
     #[test]
     fn test_process_entry_duplicate_cachback() {
         let mut engine = PaymentEngine::new();
-        let entry = TransactionEntry {
-            entry_type: TransactionEntryType::Deposit,
-            account_id: 1,
-            tx_id: 1,
-            amount: Some(dec!(100.0)),
-        };
+        let entry = TransactionEntry::deposit(ClientId(1), TxId(1), dec!(100.0));
 
         let result = process_entry(&mut engine, entry);
         assert!(result.is_ok());
 
-        let entry = TransactionEntry {
-            entry_type: TransactionEntryType::Deposit,
-            account_id: 1,
-            tx_id: 2,
-            amount: Some(dec!(1.0)),
-        };
+        let entry = TransactionEntry::deposit(ClientId(1), TxId(2), dec!(1.0));
 
         let result = process_entry(&mut engine, entry);
         assert!(result.is_ok());
-        assert_eq!(engine.accounts.get(&1).unwrap().available, dec!(101.0));
+        assert_eq!(
+            engine.accounts.get(&ClientId(1)).unwrap().available,
+            dec!(101.0)
+        );
 
-        let entry = TransactionEntry {
-            entry_type: TransactionEntryType::Dispute,
-            account_id: 1,
-            tx_id: 2,
-            amount: None,
-        };
+        let entry = TransactionEntry::dispute(ClientId(1), TxId(2));
 
         let result = process_entry(&mut engine, entry.clone());
         assert!(result.is_ok(), "Dispute should be processed successfully");
-        assert_eq!(engine.accounts.get(&1).unwrap().held, dec!(1.0));
-        assert_eq!(engine.accounts.get(&1).unwrap().total, dec!(101.0));
-        assert_eq!(engine.accounts.get(&1).unwrap().available, dec!(100.0));
+        assert_eq!(engine.accounts.get(&ClientId(1)).unwrap().held, dec!(1.0));
+        assert_eq!(
+            engine.accounts.get(&ClientId(1)).unwrap().total,
+            dec!(101.0)
+        );
+        assert_eq!(
+            engine.accounts.get(&ClientId(1)).unwrap().available,
+            dec!(100.0)
+        );
         assert_eq!(
-            engine.transactions.get(&1).unwrap().get(&2).unwrap().status,
+            engine
+                .transactions
+                .get(&ClientId(1))
+                .unwrap()
+                .get(&TxId(2))
+                .unwrap()
+                .status,
             TransactionStatus::Disputed
         );
 
-        let entry = TransactionEntry {
-            entry_type: TransactionEntryType::Chargeback,
-            account_id: 1,
-            tx_id: 2,
-            amount: None,
-        };
+        let entry = TransactionEntry::chargeback(ClientId(1), TxId(2));
         let result = process_entry(&mut engine, entry.clone());
         assert!(
             result.is_ok(),
             "Chargeback should be processed successfully"
         );
-        assert_eq!(engine.accounts.get(&1).unwrap().held, dec!(0.0));
-        assert_eq!(engine.accounts.get(&1).unwrap().total, dec!(100.0));
-        assert_eq!(engine.accounts.get(&1).unwrap().available, dec!(100.0));
-        assert!(engine.accounts.get(&1).unwrap().locked);
+        assert_eq!(engine.accounts.get(&ClientId(1)).unwrap().held, dec!(0.0));
+        assert_eq!(
+            engine.accounts.get(&ClientId(1)).unwrap().total,
+            dec!(100.0)
+        );
+        assert_eq!(
+            engine.accounts.get(&ClientId(1)).unwrap().available,
+            dec!(100.0)
+        );
+        assert!(engine.accounts.get(&ClientId(1)).unwrap().locked);
 
         assert_eq!(
-            engine.transactions.get(&1).unwrap().get(&2).unwrap().status,
+            engine
+                .transactions
+                .get(&ClientId(1))
+                .unwrap()
+                .get(&TxId(2))
+                .unwrap()
+                .status,
             TransactionStatus::Chargebacked
         );
 
         let result = process_entry(&mut engine, entry);
-        assert!(result.is_err(), "Should not allow duplicate resolves");
+        assert_eq!(
+            result.unwrap_err().code(),
+            "ACCOUNT_LOCKED",
+            "Should not allow duplicate chargebacks"
+        );
+    }
+
+    #[test]
+    fn test_resolve_and_chargeback_of_undisputed_transaction() {
+        let mut engine = PaymentEngine::new();
+        let entry = TransactionEntry::deposit(ClientId(1), TxId(1), dec!(10.0));
+        process_entry(&mut engine, entry).unwrap();
+
+        for entry in [
+            TransactionEntry::resolve(ClientId(1), TxId(1)),
+            TransactionEntry::chargeback(ClientId(1), TxId(1)),
+        ] {
+            let result = process_entry(&mut engine, entry);
+            assert!(matches!(
+                result,
+                Err(PaymentError::TransactionIsNotDisputed(_))
+            ));
+        }
     }
 
     #[test]
     fn process_dispute_for_absent_transactions() {
         let mut engine = PaymentEngine::new();
 
-        let entry = TransactionEntry {
-            entry_type: TransactionEntryType::Deposit,
-            account_id: 1,
-            tx_id: 1,
-            amount: Some(dec!(100.0)),
-        };
+        let entry = TransactionEntry::deposit(ClientId(1), TxId(1), dec!(100.0));
 
         process_entry(&mut engine, entry.clone()).unwrap();
 
-        let entry = TransactionEntry {
-            entry_type: TransactionEntryType::Dispute,
-            account_id: 1,
-            tx_id: 999,
-            amount: None,
-        };
+        let entry = TransactionEntry::dispute(ClientId(1), TxId(999));
 
-        assert!(!engine.transactions.get(&1).unwrap().contains_key(&999));
+        assert!(
+            !engine
+                .transactions
+                .get(&ClientId(1))
+                .unwrap()
+                .contains_key(&TxId(999))
+        );
 
         let result = process_entry(&mut engine, entry);
-        assert!(
-            result.is_err(),
+        assert_eq!(
+            result.unwrap_err().code(),
+            "TX_NOT_FOUND",
             "Should return error for absent transactions"
         );
-        assert!(!engine.transactions.get(&1).unwrap().contains_key(&999));
+        assert!(
+            !engine
+                .transactions
+                .get(&ClientId(1))
+                .unwrap()
+                .contains_key(&TxId(999))
+        );
 
-        let entry = TransactionEntry {
-            entry_type: TransactionEntryType::Resolve,
-            account_id: 1,
-            tx_id: 999,
-            amount: None,
-        };
+        let entry = TransactionEntry::resolve(ClientId(1), TxId(999));
 
         let result = process_entry(&mut engine, entry);
-        assert!(
-            result.is_err(),
+        assert_eq!(
+            result.unwrap_err().code(),
+            "TX_NOT_FOUND",
             "Should return error for absent transactions"
         );
-        assert!(!engine.transactions.get(&1).unwrap().contains_key(&999));
+        assert!(
+            !engine
+                .transactions
+                .get(&ClientId(1))
+                .unwrap()
+                .contains_key(&TxId(999))
+        );
 
-        let entry = TransactionEntry {
-            entry_type: TransactionEntryType::Chargeback,
-            account_id: 1,
-            tx_id: 999,
-            amount: None,
-        };
+        let entry = TransactionEntry::chargeback(ClientId(1), TxId(999));
 
         let result = process_entry(&mut engine, entry);
-        assert!(
-            result.is_err(),
+        assert_eq!(
+            result.unwrap_err().code(),
+            "TX_NOT_FOUND",
             "Should return error for absent transactions"
         );
-        assert!(!engine.transactions.get(&1).unwrap().contains_key(&999));
+        assert!(
+            !engine
+                .transactions
+                .get(&ClientId(1))
+                .unwrap()
+                .contains_key(&TxId(999))
+        );
     }
 
     #[test]
     fn test_dispute_with_incorrect_account_id() {
         let mut engine = PaymentEngine::new();
 
-        let correct_account_id = 1;
-        let incorrect_account_id = 2;
-        let tx_id = 1;
+        let correct_account_id = ClientId(1);
+        let incorrect_account_id = ClientId(2);
+        let tx_id = TxId(1);
 
-        let entry = TransactionEntry {
-            entry_type: TransactionEntryType::Deposit,
-            account_id: correct_account_id,
-            tx_id,
-            amount: Some(dec!(100.0)),
-        };
+        let entry = TransactionEntry::deposit(correct_account_id, tx_id, dec!(100.0));
 
         let result = process_entry(&mut engine, entry);
         assert!(result.is_ok(), "Deposit should be processed successfully");
@@ -363,16 +1292,17 @@ mod tests {
             dec!(100.0)
         );
 
-        let incorrect_disput = TransactionEntry {
-            entry_type: TransactionEntryType::Dispute,
-            account_id: incorrect_account_id,
-            tx_id,
-            amount: None,
-        };
+        let incorrect_disput = TransactionEntry::dispute(incorrect_account_id, tx_id);
 
         let result = process_entry(&mut engine, incorrect_disput);
         assert!(
-            result.is_err(),
+            matches!(
+                result,
+                Err(PaymentError::AccountMismatch {
+                    expected: ClientId(1),
+                    actual: ClientId(2)
+                })
+            ),
             "Dispute should fail when account_id doesn't match transaction's account"
         );
 
@@ -391,18 +1321,28 @@ mod tests {
         assert!(!engine.accounts.get(&correct_account_id).unwrap().locked);
 
         assert_eq!(
-            engine.transactions.get(&1).unwrap().get(&1).unwrap().status,
+            engine
+                .transactions
+                .get(&ClientId(1))
+                .unwrap()
+                .get(&TxId(1))
+                .unwrap()
+                .status,
             TransactionStatus::Completed
         );
 
         assert!(!engine.accounts.contains_key(&incorrect_account_id));
 
-        let correct_disput = TransactionEntry {
-            entry_type: TransactionEntryType::Dispute,
-            account_id: correct_account_id,
-            tx_id,
-            amount: None,
-        };
+        let result = process_entry(
+            &mut engine,
+            TransactionEntry::dispute(incorrect_account_id, TxId(99)),
+        );
+        assert!(matches!(
+            result,
+            Err(PaymentError::TransactionNotFound { .. })
+        ));
+
+        let correct_disput = TransactionEntry::dispute(correct_account_id, tx_id);
 
         let result = process_entry(&mut engine, correct_disput);
         assert!(
@@ -422,4 +1362,732 @@ mod tests {
             dec!(100.0)
         );
     }
+
+    #[derive(Default)]
+    struct RecordingSink {
+        parse_errors: usize,
+        rejected: Vec<(u32, String)>,
+    }
+
+    impl ErrorSink for RecordingSink {
+        fn parse_error(&mut self, _record: Option<&StringRecord>, _error: &dyn Display) {
+            self.parse_errors += 1;
+        }
+
+        fn rejected(&mut self, entry: &TransactionEntry, error: &PaymentError) {
+            self.rejected.push((entry.tx_id.0, error.to_string()));
+        }
+    }
+
+    #[test]
+    fn test_process_csv_stream_with_sink() {
+        let mut engine = PaymentEngine::new();
+        let mut sink = RecordingSink::default();
+        let data = "type, client, tx, amount\n\
+                    deposit, 1, 1, 10.0\n\
+                    withdrawal, 1, 2, 50.0\n\
+                    deposit, x, 3, 5.0\n\
+                    dispute, 1, 9";
+
+        let summary =
+            process_csv_stream_with_sink(&mut engine, data.as_bytes(), &mut sink).unwrap();
+
+        assert_eq!(summary.rejected, 2);
+        assert_eq!(sink.parse_errors, 1);
+        assert_eq!(
+            sink.rejected,
+            vec![
+                (2, "Insufficient funds for transaction 2".to_string()),
+                (9, "Transaction not found: 9".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_process_partial_resolve_rows() {
+        let mut engine = PaymentEngine::new();
+        let data = "type, client, tx, amount\n\
+                    deposit, 1, 1, 100.0\n\
+                    dispute, 1, 1,\n\
+                    resolve, 1, 1, 60.0\n\
+                    resolve, 1, 1, abc\n\
+                    resolve, 1, 1,";
+
+        let summary = process_csv_stream(&mut engine, data.as_bytes()).unwrap();
+
+        assert_eq!(summary.processed, 4);
+        assert_eq!(summary.rejected, 1);
+        assert_eq!(
+            engine.transactions[&ClientId(1)][&TxId(1)].status,
+            TransactionStatus::Resolved
+        );
+        assert_eq!(engine.accounts[&ClientId(1)].available, dec!(100.0));
+
+        let mut engine = PaymentEngine::new();
+        let entries = vec![
+            TransactionEntry::deposit(ClientId(1), TxId(1), dec!(100.0)),
+            TransactionEntry::dispute(ClientId(1), TxId(1)),
+            TransactionEntry::partial_resolve(ClientId(1), TxId(1), dec!(60.0)),
+        ];
+        process_stream(&mut engine, entries.into_iter());
+        assert_eq!(
+            engine.transactions[&ClientId(1)][&TxId(1)].status,
+            TransactionStatus::PartiallyResolved
+        );
+        assert_eq!(engine.accounts[&ClientId(1)].held, dec!(40.0));
+    }
+
+    #[test]
+    fn test_bad_header() {
+        let mut engine = PaymentEngine::new();
+        let data = "typ, client, tx, amount\n\
+                    deposit, 1, 1, 10.0";
+
+        let error = process_csv_stream(&mut engine, data.as_bytes()).unwrap_err();
+
+        assert!(matches!(error, ProcessorError::BadHeader(_)));
+        assert_eq!(
+            error.to_string(),
+            "Unusable CSV header: missing column `type`, found `typ, client, tx, amount`"
+        );
+        assert!(engine.accounts.is_empty());
+
+        let data = "type, client, tx\ndispute, 1, 1";
+        assert!(process_csv_stream(&mut engine, data.as_bytes()).is_ok());
+    }
+
+    #[test]
+    fn test_process_csv_without_headers() {
+        let mut engine = PaymentEngine::new();
+        let data = "deposit, 1, 1, 10.0\n\
+                    deposit, 2, 2, 5.0\n\
+                    withdrawal, 1, 3, 2.5\n\
+                    dispute, 2, 2,\n\
+                    resolve, 2, 2";
+        let options = CsvOptions {
+            has_headers: false,
+            ..CsvOptions::default()
+        };
+
+        let summary =
+            process_csv_stream_with_options(&mut engine, data.as_bytes(), &options, &mut NullSink)
+                .unwrap();
+
+        assert_eq!(summary.processed, 5);
+        assert_eq!(summary.parse_errors, 0);
+        assert_eq!(engine.accounts[&ClientId(1)].total, dec!(7.5));
+        assert_eq!(engine.accounts[&ClientId(2)].available, dec!(5.0));
+    }
+
+    #[test]
+    fn test_process_csv_skip_rows() {
+        let data = "type, client, tx, amount\n\
+                    deposit, 1, 1, 10.0\n\
+                    deposit, 1, x, 1.0\n\
+                    dispute, 1, 1,\n\
+                    resolve, 1, 1,\n\
+                    withdrawal, 1, 2, 50.0\n\
+                    deposit, 1, 3, 5.0";
+
+        let mut expected = PaymentEngine::new();
+        process_csv_stream_with_sink(&mut expected, data.as_bytes(), &mut NullSink).unwrap();
+
+        // The first run stops after three rows, the second resumes after them.
+        let mut engine = PaymentEngine::new();
+        let head: Vec<&str> = data.lines().take(4).collect();
+        let first =
+            process_csv_stream_with_sink(&mut engine, head.join("\n").as_bytes(), &mut NullSink)
+                .unwrap();
+        assert_eq!(first.rows(), 3);
+
+        let options = CsvOptions {
+            skip_rows: first.rows() as usize,
+            strict: true,
+            ..CsvOptions::default()
+        };
+        let error =
+            process_csv_stream_with_options(&mut engine, data.as_bytes(), &options, &mut NullSink)
+                .unwrap_err();
+        assert!(matches!(error, ProcessorError::Rejected { row: 5, .. }));
+
+        let options = CsvOptions {
+            strict: false,
+            ..options
+        };
+        let mut engine = PaymentEngine::new();
+        process_csv_stream_with_sink(&mut engine, head.join("\n").as_bytes(), &mut NullSink)
+            .unwrap();
+        let second =
+            process_csv_stream_with_options(&mut engine, data.as_bytes(), &options, &mut NullSink)
+                .unwrap();
+        assert_eq!(
+            (second.processed, second.rejected, second.rows()),
+            (2, 1, 3)
+        );
+        assert_eq!(engine.accounts, expected.accounts);
+    }
+
+    #[test]
+    fn test_process_csv_tx_ids_reused_across_clients() {
+        let data = "type, client, tx, amount\n\
+                    deposit, 1, 1, 10.0\n\
+                    deposit, 2, 1, 20.0\n\
+                    withdrawal, 3, 1, 5.0\n\
+                    deposit, 1, 2, 1.0\n\
+                    deposit, 2, 2, 1.0";
+        let run = |policy| {
+            let mut engine = PaymentEngine::with_config(EngineConfig {
+                cross_client_tx_ids: policy,
+                ..EngineConfig::default()
+            });
+            let summary =
+                process_csv_stream_with_sink(&mut engine, data.as_bytes(), &mut NullSink).unwrap();
+            (engine, summary)
+        };
+
+        let (engine, summary) = run(CrossClientTxIdPolicy::Allow);
+        assert_eq!((summary.processed, summary.rejected), (4, 1));
+        assert!(engine.reused_tx_ids().is_empty());
+
+        let (engine, summary) = run(CrossClientTxIdPolicy::Report);
+        assert_eq!((summary.processed, summary.rejected), (4, 1));
+        assert_eq!(summary.stats.reused_tx_ids, 2);
+        assert_eq!(
+            engine.reused_tx_ids(),
+            [
+                TxIdReuse {
+                    tx_id: TxId(1),
+                    first_client: ClientId(1),
+                    second_client: ClientId(2),
+                },
+                TxIdReuse {
+                    tx_id: TxId(2),
+                    first_client: ClientId(1),
+                    second_client: ClientId(2),
+                },
+            ]
+        );
+        let table = summary.stats.to_string();
+        let line = table
+            .lines()
+            .find(|line| line.starts_with("tx ids reused across clients"))
+            .unwrap();
+        assert!(line.ends_with(" 2"));
+
+        let (mut engine, summary) = run(CrossClientTxIdPolicy::Reject);
+        assert_eq!((summary.processed, summary.rejected), (2, 3));
+        assert_eq!(summary.stats.rejected["TX_ID_REUSED"], 3);
+        assert!(engine.reused_tx_ids().is_empty());
+        assert!(!engine.accounts.contains_key(&ClientId(2)));
+        assert_eq!(
+            engine.apply(TransactionEntry::deposit(ClientId(3), TxId(2), dec!(1.0))),
+            Err(PaymentError::TxIdReusedAcrossClients {
+                tx_id: TxId(2),
+                first_client: ClientId(1),
+                second_client: ClientId(3),
+            })
+        );
+    }
+
+    #[test]
+    fn test_process_csv_comments_and_blank_lines() {
+        let data = "# fixture for client 1\n\
+                    # generated by hand\n\
+                    type, client, tx, amount\n\
+                    deposit, 1, 1, 10.0\n\
+                    # the withdrawal below must fail\n\
+                    withdrawal, 1, 2, 50.0\n\
+                    \x20  \n\
+                    ,,,\n\
+                    deposit, 1, 3, 5.0\n\
+                    \n\
+                    \x20\n";
+
+        let mut engine = PaymentEngine::new();
+        let summary = process_csv_stream(&mut engine, data.as_bytes()).unwrap();
+        assert_eq!(
+            (summary.processed, summary.rejected, summary.parse_errors),
+            (2, 1, 0)
+        );
+        assert_eq!(engine.accounts[&ClientId(1)].available, dec!(15.0));
+
+        // Without `skip_blank_lines` the three blank rows are parse errors;
+        // without `comment_char` the first comment is read as the header.
+        let options = CsvOptions {
+            skip_blank_lines: false,
+            ..CsvOptions::default()
+        };
+        let mut engine = PaymentEngine::new();
+        let summary =
+            process_csv_stream_with_options(&mut engine, data.as_bytes(), &options, &mut NullSink)
+                .unwrap();
+        assert_eq!(
+            (summary.processed, summary.rejected, summary.parse_errors),
+            (2, 1, 3)
+        );
+        let options = CsvOptions {
+            comment_char: None,
+            ..CsvOptions::default()
+        };
+        assert!(matches!(
+            process_csv_stream_with_options(&mut engine, data.as_bytes(), &options, &mut NullSink),
+            Err(ProcessorError::BadHeader(_))
+        ));
+    }
+
+    #[test]
+    fn test_process_csv_version() {
+        let run = |data: &str| {
+            let mut engine = PaymentEngine::new();
+            let result = process_csv_stream_with_sink(&mut engine, data.as_bytes(), &mut NullSink);
+            (engine, result)
+        };
+
+        // Without the column, rows are version 1.
+        let (engine, summary) = run("type, client, tx, amount\ndeposit, 1, 1, 10.0\n");
+        assert_eq!(summary.unwrap().processed, 1);
+        assert_eq!(engine.accounts[&ClientId(1)].available, dec!(10.0));
+
+        let (engine, summary) = run("version, type, client, tx, amount\n\
+                                     1, deposit, 1, 1, 10.0\n\
+                                     , withdrawal, 1, 2, 2.5\n");
+        assert_eq!(summary.unwrap().processed, 2);
+        assert_eq!(engine.accounts[&ClientId(1)].available, dec!(7.5));
+
+        // An unsupported version on any row stops processing there.
+        let (engine, result) = run("version, type, client, tx, amount\n\
+                                    1, deposit, 1, 1, 10.0\n\
+                                    2, deposit, 1, 2, 5.0\n\
+                                    1, deposit, 1, 3, 1.0\n");
+        assert!(matches!(result, Err(ProcessorError::UnsupportedVersion(2))));
+        assert_eq!(engine.accounts[&ClientId(1)].available, dec!(10.0));
+
+        let (engine, result) = run("version, type, client, tx, amount\n\
+                                    \n\
+                                    2, deposit, 1, 1, 10.0\n\
+                                    1, deposit, 1, 2, 5.0\n");
+        assert!(matches!(result, Err(ProcessorError::UnsupportedVersion(2))));
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Unsupported format version: 2"
+        );
+        assert!(engine.accounts.is_empty());
+    }
+
+    #[test]
+    fn test_process_csv_max_errors() {
+        let data = "type, client, tx, amount\n\
+                    deposit, 1, 1, 10.0\n\
+                    withdrawal, 1, 2, 50.0\n\
+                    deposit, x, 3, 1.0\n\
+                    dispute, 1, 99,\n\
+                    deposit, 1, 4, 5.0\n\
+                    withdrawal, 2, 5, 1.0\n\
+                    deposit, 1, 6, 100.0\n\
+                    deposit, y, 7, 1.0\n\
+                    dispute, 1, 1,";
+        let options = CsvOptions {
+            max_errors: Some(3),
+            ..CsvOptions::default()
+        };
+
+        let mut engine = PaymentEngine::new();
+        let summary =
+            process_csv_stream_with_options(&mut engine, data.as_bytes(), &options, &mut NullSink)
+                .unwrap();
+        // The fourth error, on row 6, stops the run.
+        assert!(summary.aborted_due_to_errors);
+        assert_eq!((summary.rows(), summary.errors()), (6, 4));
+        assert_eq!(summary.processed, 2);
+        assert!(
+            summary
+                .to_string()
+                .ends_with(", aborted after too many errors")
+        );
+        assert_eq!(engine.accounts[&ClientId(1)].available, dec!(15.0));
+        assert!(!engine.transactions[&ClientId(1)].contains_key(&TxId(6)));
+
+        let options = CsvOptions {
+            max_errors: Some(5),
+            ..options
+        };
+        let mut engine = PaymentEngine::new();
+        let summary =
+            process_csv_stream_with_options(&mut engine, data.as_bytes(), &options, &mut NullSink)
+                .unwrap();
+        assert!(!summary.aborted_due_to_errors);
+        assert_eq!(summary.errors(), 5);
+    }
+
+    #[test]
+    fn test_process_csv_decorated_amounts() {
+        let data = "type, client, tx, amount\n\
+                    deposit, 1, 1,\"$1,234.56\"\n\
+                    deposit, 1, 2, 1000\n\
+                    withdrawal, 1, 3, $34.56\n\
+                    deposit, 2, 4,\"1,000.50\"\n\
+                    deposit, 2, 5,\"1,00\"\n\
+                    deposit, 2, 6, €5";
+
+        let mut strict = PaymentEngine::new();
+        let summary = process_csv_stream_with_options(
+            &mut strict,
+            data.as_bytes(),
+            &CsvOptions::default(),
+            &mut NullSink,
+        )
+        .unwrap();
+        assert_eq!((summary.processed, summary.rejected), (1, 5));
+
+        let mut engine = PaymentEngine::new();
+        let options = CsvOptions {
+            amount_format: Some(AmountFormat::default()),
+            ..CsvOptions::default()
+        };
+        let summary =
+            process_csv_stream_with_options(&mut engine, data.as_bytes(), &options, &mut NullSink)
+                .unwrap();
+        assert_eq!((summary.processed, summary.rejected), (4, 2));
+        assert_eq!(
+            engine.transactions[&ClientId(1)][&TxId(1)].amount,
+            dec!(1234.56)
+        );
+        assert_eq!(engine.accounts[&ClientId(1)].available, dec!(2200.00));
+        assert_eq!(engine.accounts[&ClientId(2)].available, dec!(1000.50));
+
+        let format = AmountFormat {
+            currency_symbol: Some('€'),
+            thousands_separator: Some(' '),
+        };
+        assert_eq!(format.parse("-€12 345.6"), Some(dec!(-12345.6)));
+        assert_eq!(format.parse("12 34"), None);
+        assert_eq!(format.parse("$1"), None);
+    }
+
+    #[test]
+    fn test_process_csv_strict() {
+        let data = "type, client, tx, amount\n\
+                    deposit, 1, 1, 10.0\n\
+                    deposit, 1, x, 1.0\n\
+                    withdrawal, 1, 2, 50.0\n\
+                    deposit, 1, 3, 5.0";
+
+        let mut lenient = PaymentEngine::new();
+        let summary = process_csv_stream_with_options(
+            &mut lenient,
+            data.as_bytes(),
+            &CsvOptions::default(),
+            &mut NullSink,
+        )
+        .unwrap();
+        assert_eq!((summary.processed, summary.rejected), (2, 1));
+
+        let mut engine = PaymentEngine::new();
+        let options = CsvOptions {
+            strict: true,
+            ..CsvOptions::default()
+        };
+        let error =
+            process_csv_stream_with_options(&mut engine, data.as_bytes(), &options, &mut NullSink)
+                .unwrap_err();
+        assert!(matches!(
+            error,
+            ProcessorError::Rejected {
+                row: 3,
+                error: PaymentError::InsufficientFunds { .. }
+            }
+        ));
+        assert!(error.to_string().starts_with("Row 3 rejected: "));
+        // Rows before the rejected one stay applied, later ones are not read.
+        assert_eq!(
+            engine.accounts.get(&ClientId(1)).unwrap().available,
+            dec!(10.0)
+        );
+    }
+
+    #[test]
+    fn test_process_csv_quoted() {
+        let data = "type,client,tx,amount,description\n\
+                    \"deposit\",\"1\",\"1\",\"10.0\",\"refund, partial\"\n\
+                    \" deposit \", 1, 2, 5.0, cash\n\
+                    \"dispute\",\"1\",\"2\",\"\",\"said \"\"never\"\"\"";
+
+        let mut engine = PaymentEngine::new();
+        let summary =
+            process_csv_stream_with_sink(&mut engine, data.as_bytes(), &mut NullSink).unwrap();
+        assert_eq!((summary.processed, summary.parse_errors), (3, 0));
+        let account = engine.accounts.get(&ClientId(1)).unwrap();
+        assert_eq!((account.available, account.held), (dec!(10.0), dec!(5.0)));
+
+        // Without quoting, the quotes are part of the values.
+        let mut engine = PaymentEngine::new();
+        let options = CsvOptions {
+            quoting: false,
+            ..CsvOptions::default()
+        };
+        let summary =
+            process_csv_stream_with_options(&mut engine, data.as_bytes(), &options, &mut NullSink)
+                .unwrap();
+        assert_eq!((summary.processed, summary.parse_errors), (0, 3));
+    }
+
+    #[cfg(feature = "digest")]
+    #[test]
+    fn test_process_csv_with_delimiters() {
+        let process = |data: &str, delimiter| {
+            let mut engine = PaymentEngine::new();
+            let options = CsvOptions {
+                delimiter,
+                ..CsvOptions::default()
+            };
+            let summary = process_csv_stream_with(&mut engine, data.as_bytes(), &options).unwrap();
+            assert_eq!((summary.processed, summary.parse_errors), (4, 0));
+            engine.state_digest()
+        };
+        let comma = "type, client, tx, amount\n\
+                     deposit, 1, 1, 10.5\n\
+                     deposit, 2, 2, 5.0\n\
+                     withdrawal, 1, 3, 2.5\n\
+                     dispute, 2, 2,";
+
+        let digest = process(comma, b',');
+        assert_eq!(process(&comma.replace(',', "\t"), b'\t'), digest);
+        assert_eq!(process(&comma.replace(',', ";"), b';'), digest);
+    }
+
+    #[test]
+    fn test_process_csv_with_timestamp() {
+        let mut engine = PaymentEngine::new();
+        let data = "type, client, tx, amount, timestamp\n\
+                    deposit, 1, 1, 10.0, 1700000100\n\
+                    withdrawal, 1, 2, 4.0, 1700000000\n\
+                    deposit, 2, 3, 1.0,\n\
+                    deposit, 1, 4, 1.0, 1700000200";
+
+        let summary = process_csv_stream(&mut engine, data.as_bytes()).unwrap();
+
+        assert_eq!(summary.processed, 4);
+        assert_eq!(
+            engine.transactions[&ClientId(1)][&TxId(2)].timestamp,
+            Some(1700000000)
+        );
+        assert_eq!(engine.last_activity(ClientId(1)), Some(1700000200));
+        assert_eq!(engine.last_activity(ClientId(2)), None);
+
+        let mut output = Vec::new();
+        engine.write_json(&mut output, None).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(
+            output,
+            r#"[{"client":1,"available":"7.0000","held":"0.0000","total":"7.0000","locked":false,"last_activity":1700000200},{"client":2,"available":"1.0000","held":"0.0000","total":"1.0000","locked":false}]"#
+        );
+
+        // Rows without the column still parse.
+        let mut engine = PaymentEngine::new();
+        process_csv_stream(
+            &mut engine,
+            "type, client, tx, amount\ndeposit, 1, 1, 1.0".as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(engine.transactions[&ClientId(1)][&TxId(1)].timestamp, None);
+    }
+
+    #[test]
+    fn test_process_csv_with_currency() {
+        let mut engine = PaymentEngine::new();
+        let data = "type, client, tx, amount, currency\n\
+                    deposit, 1, 1, 10.0,\n\
+                    deposit, 1, 2, 5.0, eur\n\
+                    dispute, 1, 2,, USD\n\
+                    dispute, 1, 2,, EUR\n\
+                    deposit, 1, 3, 1.0, EURO";
+        let mut sink = RecordingSink::default();
+
+        let summary =
+            process_csv_stream_with_sink(&mut engine, data.as_bytes(), &mut sink).unwrap();
+
+        assert_eq!(summary.processed, 3);
+        assert_eq!(summary.parse_errors, 1);
+        assert_eq!(
+            sink.rejected,
+            vec![(2, "Transaction 2 is in EUR, not USD".to_string())]
+        );
+        let account = &engine.accounts[&ClientId(1)];
+        assert_eq!(account.available, dec!(10.0));
+        assert_eq!(
+            account.balances(Some("EUR".parse().unwrap())).held,
+            dec!(5.0)
+        );
+    }
+
+    #[test]
+    fn test_process_fallible_stream() {
+        let mut engine = PaymentEngine::new();
+        let mut sink = RecordingSink::default();
+        let entries = vec![
+            Ok(TransactionEntry::deposit(ClientId(1), TxId(1), dec!(10.0))),
+            Err("unreadable row"),
+            Ok(TransactionEntry::withdrawal(
+                ClientId(1),
+                TxId(2),
+                dec!(4.0),
+            )),
+            Ok(TransactionEntry::withdrawal(
+                ClientId(1),
+                TxId(3),
+                dec!(40.0),
+            )),
+        ];
+
+        let summary = process_fallible_stream(&mut engine, entries.into_iter(), &mut sink);
+
+        assert_eq!(summary.processed, 2);
+        assert_eq!(summary.rejected, 1);
+        assert_eq!(summary.parse_errors, 1);
+        assert_eq!(sink.parse_errors, 1);
+        assert_eq!(engine.accounts[&ClientId(1)].total, dec!(6.0));
+    }
+
+    /// A caller's own row type, with the operation as free text.
+    struct Row {
+        kind: &'static str,
+        client: u16,
+        tx: u32,
+        amount: Decimal,
+    }
+
+    impl TryFrom<Row> for TransactionEntry {
+        type Error = String;
+
+        fn try_from(row: Row) -> Result<Self, Self::Error> {
+            let (client, tx) = (ClientId(row.client), TxId(row.tx));
+            match row.kind {
+                "credit" => Ok(TransactionEntry::deposit(client, tx, row.amount)),
+                "debit" => Ok(TransactionEntry::withdrawal(client, tx, row.amount)),
+                kind => Err(format!("unknown kind `{}`", kind)),
+            }
+        }
+    }
+
+    #[test]
+    fn test_process_records() {
+        let mut engine = PaymentEngine::new();
+        let mut sink = RecordingSink::default();
+        let rows = vec![
+            Row {
+                kind: "credit",
+                client: 1,
+                tx: 1,
+                amount: dec!(10.0),
+            },
+            Row {
+                kind: "refund",
+                client: 1,
+                tx: 2,
+                amount: dec!(1.0),
+            },
+            Row {
+                kind: "debit",
+                client: 1,
+                tx: 3,
+                amount: dec!(4.0),
+            },
+        ];
+
+        let summary = process_records(&mut engine, rows, &mut sink);
+
+        assert_eq!(summary.processed, 2);
+        assert_eq!(summary.parse_errors, 1);
+        assert_eq!(engine.accounts[&ClientId(1)].total, dec!(6.0));
+
+        // Entries themselves convert infallibly.
+        let entries = [TransactionEntry::deposit(ClientId(2), TxId(4), dec!(1.0))];
+        let summary = process_records(&mut engine, entries, &mut sink);
+        assert_eq!(summary.processed, 1);
+        assert_eq!(sink.parse_errors, 1);
+    }
+
+    #[test]
+    fn test_process_csv_files() {
+        let dir = std::env::temp_dir();
+        let first = dir.join(format!("transaction-files-{}-1.csv", std::process::id()));
+        let second = dir.join(format!("transaction-files-{}-2.csv", std::process::id()));
+        std::fs::write(&first, "type, client, tx, amount\ndeposit, 1, 1, 10.0\n").unwrap();
+        std::fs::write(
+            &second,
+            "type, client, tx, amount\nwithdrawal, 1, 2, 4.0\nwithdrawal, 1, 3, 40.0\n",
+        )
+        .unwrap();
+        let mut engine = PaymentEngine::new();
+        let process = |engine: &mut PaymentEngine, paths: &[PathBuf], strict| {
+            let mut sink = RecordingSink::default();
+            let results = process_csv_files(
+                engine,
+                paths,
+                &CsvOptions::default(),
+                &EntryFilter::default(),
+                &AtomicBool::new(false),
+                strict,
+                &mut sink,
+            );
+            (results, sink)
+        };
+
+        let (results, sink) = process(&mut engine, &[first.clone(), second.clone()], false);
+        let summaries: Vec<FileSummary> = results.into_iter().map(Result::unwrap).collect();
+        assert_eq!(summaries.len(), 2);
+        assert_eq!(summaries[0].path, first);
+        assert_eq!(summaries[0].summary.processed, 1);
+        assert_eq!(summaries[1].summary.processed, 1);
+        assert_eq!(summaries[1].summary.rejected, 1);
+        assert_eq!(sink.rejected.len(), 1);
+        assert_eq!(engine.accounts[&ClientId(1)].available, dec!(6.0));
+
+        // An unreadable file keeps the summaries around it, unless strict.
+        let missing = dir.join("transaction-files-missing.csv");
+        let third = dir.join(format!("transaction-files-{}-3.csv", std::process::id()));
+        std::fs::write(&third, "type, client, tx, amount\ndeposit, 1, 4, 6.0\n").unwrap();
+        let (results, _) = process(&mut engine, &[missing.clone(), third.clone()], false);
+        assert_eq!(results.len(), 2);
+        assert!(matches!(
+            &results[0],
+            Err(ProcessorError::Io(e)) if e.kind() == std::io::ErrorKind::NotFound
+        ));
+        assert_eq!(results[1].as_ref().unwrap().path, third);
+        assert_eq!(results[1].as_ref().unwrap().summary.processed, 1);
+        assert_eq!(engine.accounts[&ClientId(1)].available, dec!(12.0));
+
+        let (results, _) = process(&mut engine, &[missing, third.clone()], true);
+        assert_eq!(results.len(), 1);
+        assert!(results[0].is_err());
+        assert_eq!(engine.accounts[&ClientId(1)].available, dec!(12.0));
+
+        std::fs::remove_file(first).unwrap();
+        std::fs::remove_file(second).unwrap();
+        std::fs::remove_file(third).unwrap();
+    }
+
+    #[test]
+    fn test_max_amount_scale() {
+        let entries = || {
+            vec![
+                TransactionEntry::deposit(ClientId(1), TxId(1), dec!(1.0)),
+                TransactionEntry::deposit(ClientId(1), TxId(2), dec!(0.00001)),
+            ]
+            .into_iter()
+        };
+
+        let mut engine = PaymentEngine::new();
+        let summary = process_stream(&mut engine, entries());
+        assert_eq!(summary.rejected, 1);
+        assert_eq!(engine.accounts[&ClientId(1)].total, dec!(1.0));
+
+        let mut engine = PaymentEngine::with_config(EngineConfig {
+            max_amount_scale: None,
+            ..EngineConfig::default()
+        });
+        let summary = process_stream(&mut engine, entries());
+        assert_eq!(summary.processed, 2);
+        assert_eq!(engine.accounts[&ClientId(1)].total, dec!(1.00001));
+    }
 }
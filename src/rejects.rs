@@ -0,0 +1,114 @@
+use std::fmt::Display;
+use std::io::Write;
+
+use csv::{StringRecord, Writer};
+
+use crate::entry::TransactionEntry;
+use crate::error::PaymentError;
+use crate::processor::ErrorSink;
+
+const COLUMNS: usize = 5;
+
+/// Writes every failed row to a CSV file with an extra `error` column, so the
+/// rows can be fixed and processed again.
+///
+/// Rows that failed to parse are written as read, with empty fields if the row
+/// itself could not be read; rejected rows are written back from their
+/// `TransactionEntry`.
+pub struct CsvRejectsSink<W: Write> {
+    writer: Writer<W>,
+    error: Option<csv::Error>,
+}
+
+impl<W: Write> CsvRejectsSink<W> {
+    pub fn new(writer: W) -> csv::Result<Self> {
+        let mut writer = Writer::from_writer(writer);
+        writer.write_record(["type", "client", "tx", "amount", "currency", "error"])?;
+        Ok(CsvRejectsSink {
+            writer,
+            error: None,
+        })
+    }
+
+    /// Flushes the file, returning the first write error if any occurred.
+    pub fn finish(mut self) -> csv::Result<()> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        self.writer.flush().map_err(csv::Error::from)
+    }
+
+    #[inline]
+    fn write(&mut self, fields: [&str; COLUMNS], error: &dyn Display) {
+        if self.error.is_some() {
+            return;
+        }
+        let error = error.to_string();
+        let [entry_type, client, tx, amount, currency] = fields;
+        if let Err(e) =
+            self.writer
+                .write_record([entry_type, client, tx, amount, currency, error.as_str()])
+        {
+            self.error = Some(e);
+        }
+    }
+}
+
+impl<W: Write> ErrorSink for CsvRejectsSink<W> {
+    fn parse_error(&mut self, record: Option<&StringRecord>, error: &dyn Display) {
+        let mut fields = [""; COLUMNS];
+        for (field, value) in fields.iter_mut().zip(record.into_iter().flatten()) {
+            *field = value;
+        }
+        self.write(fields, error);
+    }
+
+    fn rejected(&mut self, entry: &TransactionEntry, error: &PaymentError) {
+        let entry_type = entry.entry_type.to_string();
+        let client = entry.account_id.to_string();
+        let tx = entry.tx_id.to_string();
+        let amount = entry.amount.to_string();
+        let currency = entry
+            .currency
+            .map(|currency| currency.to_string())
+            .unwrap_or_default();
+        self.write([&entry_type, &client, &tx, &amount, &currency], error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::payments_engine::PaymentEngine;
+    use crate::processor::process_csv_stream_with_sink;
+
+    #[test]
+    fn test_rejects_csv() {
+        let mut engine = PaymentEngine::new();
+        let data = "type, client, tx, amount\n\
+                    deposit, 1, 1, 10.0\n\
+                    withdrawal, 1, 2, 50.0\n\
+                    deposit, x, 3, 5.0\n\
+                    deposit, 1, 4, abc\n\
+                    dispute, 1, 1";
+        let mut output = Vec::new();
+        let mut sink = CsvRejectsSink::new(&mut output).unwrap();
+
+        process_csv_stream_with_sink(&mut engine, data.as_bytes(), &mut sink).unwrap();
+        sink.finish().unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], "type,client,tx,amount,currency,error");
+        assert_eq!(
+            lines[1],
+            "withdrawal,1,2,50.0,,Insufficient funds for transaction 2"
+        );
+        assert!(lines[2].starts_with("deposit,x,3,5.0,,\"CSV deserialize error"));
+        assert_eq!(
+            lines[3],
+            "deposit,1,4,abc,,Invalid entry for transaction conversion: Unparseable amount for transaction: abc"
+        );
+    }
+}
@@ -0,0 +1,99 @@
+use std::fmt;
+use std::str::FromStr;
+
+use rust_decimal::Decimal;
+use serde::de::{self, Unexpected, Visitor};
+use serde::{Deserializer, Serializer};
+
+/// Writes an amount as a string, so no precision is lost to floats, whatever
+/// `rust_decimal` serde features are enabled elsewhere in the dependency
+/// graph. Every crate-owned amount goes through it, with
+/// `#[serde(with = "crate::serde_decimal")]`.
+pub fn serialize<S: Serializer>(value: &Decimal, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(value)
+}
+
+/// Reads an amount from a string or, for hand-written files, a number.
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Decimal, D::Error> {
+    deserializer.deserialize_any(DecimalVisitor)
+}
+
+struct DecimalVisitor;
+
+impl Visitor<'_> for DecimalVisitor {
+    type Value = Decimal;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a decimal amount as a string or a number")
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Decimal, E> {
+        Decimal::from_str(value)
+            .or_else(|_| Decimal::from_scientific(value))
+            .map_err(|_| E::invalid_value(Unexpected::Str(value), &self))
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<Decimal, E> {
+        Ok(Decimal::from(value))
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<Decimal, E> {
+        Ok(Decimal::from(value))
+    }
+
+    /// Goes through the shortest decimal rendering of the float, so `0.1`
+    /// reads as exactly `0.1`.
+    fn visit_f64<E: de::Error>(self, value: f64) -> Result<Decimal, E> {
+        Decimal::from_str(&value.to_string())
+            .map_err(|_| E::invalid_value(Unexpected::Float(value), &self))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::account::{Account, Balances};
+    use crate::entry::TransactionEntry;
+    use crate::ids::{ClientId, TxId};
+    use crate::transaction::Transaction;
+    use rust_decimal::dec;
+
+    #[test]
+    fn test_round_trip_is_exact() {
+        let amount = dec!(0.1) + dec!(0.2);
+        let mut account = Account::new(ClientId(1));
+        account.available = amount;
+        account.held = dec!(0.0001);
+        account.total = amount + dec!(0.0001);
+        let json = serde_json::to_string(&account).unwrap();
+        assert!(json.contains(r#""available":"0.3","held":"0.0001","total":"0.3001""#));
+        assert_eq!(serde_json::from_str::<Account>(&json).unwrap(), account);
+
+        let mut transaction =
+            Transaction::try_from(TransactionEntry::deposit(ClientId(1), TxId(1), amount)).unwrap();
+        transaction.disputed_amount = dec!(79228162514264337593543950335);
+        let json = serde_json::to_string(&transaction).unwrap();
+        assert!(json.contains(r#""disputed_amount":"79228162514264337593543950335""#));
+        assert_eq!(
+            serde_json::from_str::<Transaction>(&json).unwrap(),
+            transaction
+        );
+    }
+
+    #[test]
+    fn test_accepts_numbers() {
+        let balances: Balances =
+            serde_json::from_str(r#"{"available": 0.3, "held": 2, "total": "2.30"}"#).unwrap();
+        assert_eq!(balances.available, dec!(0.3));
+        assert_eq!(balances.held, dec!(2));
+        assert_eq!(balances.total.to_string(), "2.30");
+
+        assert!(
+            serde_json::from_str::<Balances>(r#"{"available": "x", "held": 0, "total": 0}"#)
+                .is_err()
+        );
+        assert!(
+            serde_json::from_str::<Balances>(r#"{"available": true, "held": 0, "total": 0}"#)
+                .is_err()
+        );
+    }
+}
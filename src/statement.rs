@@ -0,0 +1,342 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fmt::Display;
+use std::io;
+use std::io::Write;
+
+use rust_decimal::Decimal;
+
+use crate::account::Balances;
+use crate::config::RoundingStrategy;
+use crate::currency::Currency;
+use crate::ids::{ClientId, TxId};
+use crate::payments_engine::{PaymentEngine, format_amount_with};
+use crate::transaction::TransactionStatus;
+
+/// Operation behind a ledger line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LedgerKind {
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Resolve,
+    Chargeback,
+    /// Fee charged along with a chargeback.
+    Fee,
+}
+
+impl Display for LedgerKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            LedgerKind::Deposit => "deposit",
+            LedgerKind::Withdrawal => "withdrawal",
+            LedgerKind::Dispute => "dispute",
+            LedgerKind::Resolve => "resolve",
+            LedgerKind::Chargeback => "chargeback",
+            LedgerKind::Fee => "fee",
+        })
+    }
+}
+
+/// A balance movement applied to an account, recorded for statements.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct LedgerEntry {
+    pub(crate) sequence: u64,
+    pub(crate) kind: LedgerKind,
+    pub(crate) tx_id: TxId,
+    pub(crate) currency: Option<Currency>,
+    /// Amount moved: deposited, withdrawn, held, released, charged back or
+    /// charged as a fee.
+    pub(crate) amount: Decimal,
+    pub(crate) available_delta: Decimal,
+}
+
+/// One applied operation with the available balance, in the same currency,
+/// after it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatementLine {
+    pub sequence: u64,
+    pub kind: LedgerKind,
+    pub tx_id: TxId,
+    pub currency: Currency,
+    pub amount: Decimal,
+    /// Current status of the referenced transaction, `None` once it was
+    /// evicted under `settled_retention` or the retention window.
+    pub status: Option<TransactionStatus>,
+    pub available: Decimal,
+}
+
+/// `available`, `held` and `total` are the final balances in the base
+/// currency, `currencies` those in any other currency.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Statement {
+    pub client: ClientId,
+    pub lines: Vec<StatementLine>,
+    pub base_currency: Currency,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub currencies: BTreeMap<Currency, Balances>,
+    pub locked: bool,
+    /// Decimal places used when rendering the amounts.
+    pub scale: u32,
+    /// How the amounts are rounded to `scale` when rendered.
+    pub rounding: RoundingStrategy,
+}
+
+impl PaymentEngine {
+    /// Lists the client's operations in the order they were applied, with the
+    /// running available balance of their currency, followed by the final
+    /// balances. Returns `None` for an unknown client. Operations are only
+    /// listed when `EngineConfig::record_statements` is set.
+    pub fn statement(&self, client: ClientId) -> Option<Statement> {
+        let account = self.accounts.get(&client)?;
+
+        let mut entries: Vec<&LedgerEntry> =
+            self.ledger.get(&client).into_iter().flatten().collect();
+        entries.sort_unstable_by_key(|entry| entry.sequence);
+
+        let mut available: HashMap<Option<Currency>, Decimal> = HashMap::new();
+        let lines = entries
+            .into_iter()
+            .map(|entry| {
+                let available = available.entry(entry.currency).or_default();
+                *available += entry.available_delta;
+                StatementLine {
+                    sequence: entry.sequence,
+                    kind: entry.kind,
+                    tx_id: entry.tx_id,
+                    currency: self.currency_or_base(entry.currency),
+                    amount: entry.amount,
+                    status: self
+                        .transactions
+                        .get(&client)
+                        .and_then(|transactions| transactions.get(&entry.tx_id))
+                        .map(|transaction| transaction.status.clone()),
+                    available: *available,
+                }
+            })
+            .collect();
+
+        Some(Statement {
+            client,
+            lines,
+            base_currency: self.config.base_currency,
+            available: account.available,
+            held: account.held,
+            total: account.total,
+            currencies: account.currencies.clone(),
+            locked: account.locked,
+            scale: self.output_scale(account, None),
+            rounding: self.config.rounding,
+        })
+    }
+}
+
+impl StatementLine {
+    #[inline]
+    fn status_label(&self) -> String {
+        self.status
+            .as_ref()
+            .map_or_else(|| "evicted".to_string(), |status| status.to_string())
+    }
+}
+
+impl Statement {
+    #[inline]
+    fn format(&self, amount: Decimal) -> String {
+        format_amount_with(amount, self.scale, self.rounding)
+    }
+
+    /// Writes the lines as CSV, without the final balances. A `currency`
+    /// column follows the amount when foreign currencies are held.
+    pub fn write_csv<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let currencies = !self.currencies.is_empty();
+        if currencies {
+            writeln!(writer, "sequence,type,tx,amount,currency,status,available")?;
+        } else {
+            writeln!(writer, "sequence,type,tx,amount,status,available")?;
+        }
+        for line in &self.lines {
+            write!(
+                writer,
+                "{},{},{},{},",
+                line.sequence,
+                line.kind,
+                line.tx_id,
+                self.format(line.amount),
+            )?;
+            if currencies {
+                write!(writer, "{},", line.currency)?;
+            }
+            writeln!(
+                writer,
+                "{},{}",
+                line.status_label(),
+                self.format(line.available)
+            )?;
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn write_balances(&self, f: &mut fmt::Formatter, balances: Balances) -> fmt::Result {
+        write!(
+            f,
+            "available {}, held {}, total {}",
+            self.format(balances.available),
+            self.format(balances.held),
+            self.format(balances.total),
+        )
+    }
+}
+
+impl Display for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Statement for client {}", self.client)?;
+        writeln!(
+            f,
+            "{:>8}  {:<10}  {:>10}  {:>14}  {:<18}  {:>14}",
+            "sequence", "type", "tx", "amount", "status", "available"
+        )?;
+        for line in &self.lines {
+            let mut amount = self.format(line.amount);
+            if !self.currencies.is_empty() {
+                amount = format!("{} {}", amount, line.currency);
+            }
+            writeln!(
+                f,
+                "{:>8}  {:<10}  {:>10}  {:>14}  {:<18}  {:>14}",
+                line.sequence,
+                line.kind.to_string(),
+                line.tx_id,
+                amount,
+                line.status_label(),
+                self.format(line.available)
+            )?;
+        }
+        let base = Balances {
+            available: self.available,
+            held: self.held,
+            total: self.total,
+        };
+        if self.currencies.is_empty() {
+            self.write_balances(f, base)?;
+        } else {
+            write!(f, "{}: ", self.base_currency)?;
+            self.write_balances(f, base)?;
+            for (currency, &balances) in &self.currencies {
+                write!(f, "\n{}: ", currency)?;
+                self.write_balances(f, balances)?;
+            }
+        }
+        if self.locked {
+            f.write_str(", locked")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "csv"))]
+mod tests {
+    use super::*;
+    use crate::config::EngineConfig;
+    use crate::entry::TransactionEntry;
+    use crate::processor::{NullSink, process_stream_with_sink};
+    use rust_decimal::dec;
+
+    #[test]
+    fn test_statement_running_balance() {
+        let mut engine = PaymentEngine::with_config(EngineConfig {
+            record_statements: true,
+            ..EngineConfig::default()
+        });
+        let entries = vec![
+            TransactionEntry::deposit(ClientId(1), TxId(1), dec!(100.0)),
+            TransactionEntry::deposit(ClientId(1), TxId(2), dec!(20.0)),
+            TransactionEntry::withdrawal(ClientId(1), TxId(3), dec!(10.0)),
+            TransactionEntry::dispute(ClientId(1), TxId(1)),
+            TransactionEntry::partial_resolve(ClientId(1), TxId(1), dec!(40.0)),
+            TransactionEntry::dispute(ClientId(1), TxId(2)),
+            TransactionEntry::resolve(ClientId(1), TxId(2)),
+            TransactionEntry::chargeback(ClientId(1), TxId(1)),
+            TransactionEntry::deposit(ClientId(1), TxId(4), dec!(5.0)),
+            TransactionEntry::deposit(ClientId(2), TxId(5), dec!(1.0)),
+        ];
+        process_stream_with_sink(&mut engine, entries.into_iter(), &mut NullSink);
+
+        let statement = engine.statement(ClientId(1)).unwrap();
+
+        let lines: Vec<(String, u32, Decimal, Decimal)> = statement
+            .lines
+            .iter()
+            .map(|line| {
+                (
+                    line.kind.to_string(),
+                    line.tx_id.0,
+                    line.amount,
+                    line.available,
+                )
+            })
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                ("deposit".to_string(), 1, dec!(100.0), dec!(100.0)),
+                ("deposit".to_string(), 2, dec!(20.0), dec!(120.0)),
+                ("withdrawal".to_string(), 3, dec!(10.0), dec!(110.0)),
+                ("dispute".to_string(), 1, dec!(100.0), dec!(10.0)),
+                ("resolve".to_string(), 1, dec!(40.0), dec!(50.0)),
+                ("dispute".to_string(), 2, dec!(20.0), dec!(30.0)),
+                ("resolve".to_string(), 2, dec!(20.0), dec!(50.0)),
+                ("chargeback".to_string(), 1, dec!(60.0), dec!(50.0)),
+            ]
+        );
+        assert!(
+            statement
+                .lines
+                .windows(2)
+                .all(|pair| pair[0].sequence < pair[1].sequence)
+        );
+        assert_eq!(
+            statement.lines[0].status,
+            Some(TransactionStatus::Chargebacked)
+        );
+        assert_eq!(
+            statement.available,
+            statement.lines.last().unwrap().available
+        );
+        assert_eq!(statement.held, dec!(0.0));
+        assert_eq!(statement.total, dec!(50.0));
+        assert!(statement.locked);
+        assert!(engine.statement(ClientId(3)).is_none());
+    }
+
+    #[test]
+    fn test_statement_rendering() {
+        let mut engine = PaymentEngine::with_config(EngineConfig {
+            record_statements: true,
+            ..EngineConfig::default()
+        });
+        let entries = vec![
+            TransactionEntry::deposit(ClientId(1), TxId(1), dec!(10.0)),
+            TransactionEntry::dispute(ClientId(1), TxId(1)),
+        ];
+        process_stream_with_sink(&mut engine, entries.into_iter(), &mut NullSink);
+        let statement = engine.statement(ClientId(1)).unwrap();
+
+        let mut output = Vec::new();
+        statement.write_csv(&mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "sequence,type,tx,amount,status,available\n\
+             0,deposit,1,10.0000,disputed,10.0000\n\
+             1,dispute,1,10.0000,disputed,0.0000\n"
+        );
+        assert!(
+            statement
+                .to_string()
+                .ends_with("available 0.0000, held 10.0000, total 10.0000")
+        );
+    }
+}
@@ -0,0 +1,164 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fmt::Display;
+
+use rust_decimal::Decimal;
+
+use crate::account::DEFAULT_SCALE;
+use crate::entry::TransactionEntryType;
+use crate::error::PaymentError;
+use crate::payments_engine::format_amount;
+
+/// Breakdown of a run, accumulated by the processor along with the
+/// `ProcessingSummary` counts.
+///
+/// The amount sums add deposits and withdrawals of every currency together and
+/// saturate at `Decimal::MAX` rather than overflow.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Stats {
+    /// Rows applied, by type.
+    pub applied: BTreeMap<TransactionEntryType, u64>,
+    /// Rows rejected, by `PaymentError::code`.
+    pub rejected: BTreeMap<&'static str, u64>,
+    pub parse_failures: u64,
+    /// Applied rows reusing another client's tx id, counted under
+    /// `CrossClientTxIdPolicy::Report`.
+    pub reused_tx_ids: u64,
+    pub deposited: Decimal,
+    /// Amount actually withdrawn, after any clamping.
+    pub withdrawn: Decimal,
+}
+
+impl Stats {
+    /// Counts a row of `entry_type`; `amount` is the amount moved by an
+    /// applied deposit or withdrawal.
+    pub(crate) fn record(
+        &mut self,
+        entry_type: TransactionEntryType,
+        amount: Option<Decimal>,
+        result: &Result<(), PaymentError>,
+    ) {
+        match result {
+            Ok(()) => {
+                *self.applied.entry(entry_type.clone()).or_default() += 1;
+                let sum = match entry_type {
+                    TransactionEntryType::Deposit => &mut self.deposited,
+                    TransactionEntryType::Withdrawal => &mut self.withdrawn,
+                    _ => return,
+                };
+                if let Some(amount) = amount {
+                    *sum = sum.checked_add(amount).unwrap_or(Decimal::MAX);
+                }
+            }
+            Err(e) => *self.rejected.entry(e.code()).or_default() += 1,
+        }
+    }
+
+    /// Adds the counts and sums of `other`, e.g. of the next input file.
+    pub fn merge(&mut self, other: &Stats) {
+        for (entry_type, count) in &other.applied {
+            *self.applied.entry(entry_type.clone()).or_default() += count;
+        }
+        for (code, count) in &other.rejected {
+            *self.rejected.entry(code).or_default() += count;
+        }
+        self.parse_failures += other.parse_failures;
+        self.reused_tx_ids += other.reused_tx_ids;
+        self.deposited = self
+            .deposited
+            .checked_add(other.deposited)
+            .unwrap_or(Decimal::MAX);
+        self.withdrawn = self
+            .withdrawn
+            .checked_add(other.withdrawn)
+            .unwrap_or(Decimal::MAX);
+    }
+}
+
+/// One row per applied type and rejection code, then the parse failures, the
+/// reused tx ids if any and the amount sums, with the labels and values
+/// aligned.
+impl Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut rows: Vec<(String, String)> = self
+            .applied
+            .iter()
+            .map(|(entry_type, count)| (format!("applied {}", entry_type), count.to_string()))
+            .collect();
+        rows.extend(
+            self.rejected
+                .iter()
+                .map(|(code, count)| (format!("rejected {}", code), count.to_string())),
+        );
+        rows.push((
+            "parse failures".to_string(),
+            self.parse_failures.to_string(),
+        ));
+        if self.reused_tx_ids > 0 {
+            rows.push((
+                "tx ids reused across clients".to_string(),
+                self.reused_tx_ids.to_string(),
+            ));
+        }
+        rows.push((
+            "deposited".to_string(),
+            format_amount(self.deposited, DEFAULT_SCALE),
+        ));
+        rows.push((
+            "withdrawn".to_string(),
+            format_amount(self.withdrawn, DEFAULT_SCALE),
+        ));
+
+        let label_width = rows.iter().map(|(label, _)| label.len()).max().unwrap_or(0);
+        let value_width = rows.iter().map(|(_, value)| value.len()).max().unwrap_or(0);
+        for (index, (label, value)) in rows.iter().enumerate() {
+            if index > 0 {
+                f.write_str("\n")?;
+            }
+            write!(f, "{:<label_width$}  {:>value_width$}", label, value)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ids::{ClientId, TxId};
+    use rust_decimal::dec;
+
+    #[test]
+    fn test_record_and_render() {
+        let mut stats = Stats::default();
+        stats.record(TransactionEntryType::Deposit, Some(dec!(100.0)), &Ok(()));
+        stats.record(TransactionEntryType::Deposit, Some(dec!(2.5)), &Ok(()));
+        stats.record(TransactionEntryType::Withdrawal, Some(dec!(10)), &Ok(()));
+        stats.record(
+            TransactionEntryType::Dispute,
+            None,
+            &Err(PaymentError::TransactionNotFound {
+                client: ClientId(1),
+                tx_id: TxId(9),
+            }),
+        );
+        stats.parse_failures = 3;
+
+        let mut total = stats.clone();
+        total.merge(&stats);
+        assert_eq!(total.applied[&TransactionEntryType::Deposit], 4);
+        assert_eq!(total.deposited, dec!(205.0));
+
+        assert_eq!(
+            stats.to_string(),
+            "applied deposit               2\n\
+             applied withdrawal            1\n\
+             rejected TX_NOT_FOUND         1\n\
+             parse failures                3\n\
+             deposited              102.5000\n\
+             withdrawn               10.0000"
+        );
+
+        stats.record(TransactionEntryType::Deposit, Some(Decimal::MAX), &Ok(()));
+        assert_eq!(stats.deposited, Decimal::MAX);
+    }
+}
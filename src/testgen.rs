@@ -0,0 +1,204 @@
+use std::collections::VecDeque;
+use std::io;
+use std::io::Write;
+
+use rust_decimal::Decimal;
+
+use crate::entry::{TransactionEntry, TransactionEntryType};
+use crate::ids::{ClientId, TxId};
+
+/// Number of recent deposits kept as candidates for disputes and duplicates,
+/// so that generating stays in constant memory.
+const RECENT_DEPOSITS: usize = 4096;
+
+/// Shape of a synthetic input, e.g. for benchmarks. The same configuration
+/// always produces the same rows.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeneratorConfig {
+    pub rows: u64,
+    /// Clients are numbered from 1 to `clients`.
+    pub clients: u16,
+    /// Share of deposits among new deposits and withdrawals, from 0 to 1.
+    pub deposit_ratio: f64,
+    /// Probability of a row disputing a recent deposit or settling an open
+    /// dispute, with a resolve or, one time in four, a chargeback.
+    pub dispute_probability: f64,
+    /// Probability of a row repeating a recent deposit with its tx id.
+    pub duplicate_probability: f64,
+    pub seed: u64,
+}
+
+impl Default for GeneratorConfig {
+    fn default() -> Self {
+        GeneratorConfig {
+            rows: 1000,
+            clients: 100,
+            deposit_ratio: 0.7,
+            dispute_probability: 0.05,
+            duplicate_probability: 0.01,
+            seed: 1,
+        }
+    }
+}
+
+/// Endless stream of generated rows; take `config.rows` of them, or use
+/// `write_csv`.
+pub struct Generator {
+    config: GeneratorConfig,
+    state: u64,
+    next_tx_id: u32,
+    deposits: VecDeque<(ClientId, TxId, Decimal)>,
+    open_disputes: Vec<(ClientId, TxId)>,
+}
+
+impl Generator {
+    pub fn new(config: GeneratorConfig) -> Self {
+        // Xorshift gets stuck on a zero state.
+        let state = config.seed ^ 0x9e37_79b9_7f4a_7c15;
+        Generator {
+            config,
+            state: if state == 0 { 1 } else { state },
+            next_tx_id: 1,
+            deposits: VecDeque::new(),
+            open_disputes: Vec::new(),
+        }
+    }
+
+    #[inline]
+    fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+
+    /// Uniform in `[0, 1)`.
+    #[inline]
+    fn next_unit(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    #[inline]
+    fn pick(&mut self, len: usize) -> usize {
+        (self.next_u64() % len as u64) as usize
+    }
+
+    fn dispute_or_settle(&mut self) -> Option<TransactionEntry> {
+        if !self.open_disputes.is_empty() && self.next_unit() < 0.5 {
+            let index = self.pick(self.open_disputes.len());
+            let (client, tx_id) = self.open_disputes.swap_remove(index);
+            return Some(if self.next_u64().is_multiple_of(4) {
+                TransactionEntry::chargeback(client, tx_id)
+            } else {
+                TransactionEntry::resolve(client, tx_id)
+            });
+        }
+        if self.deposits.is_empty() {
+            return None;
+        }
+        let index = self.pick(self.deposits.len());
+        let (client, tx_id, _) = self.deposits.remove(index)?;
+        self.open_disputes.push((client, tx_id));
+        Some(TransactionEntry::dispute(client, tx_id))
+    }
+
+    fn duplicate(&mut self) -> Option<TransactionEntry> {
+        if self.deposits.is_empty() {
+            return None;
+        }
+        let index = self.pick(self.deposits.len());
+        let (client, tx_id, amount) = self.deposits[index];
+        Some(TransactionEntry::deposit(client, tx_id, amount))
+    }
+
+    fn transaction(&mut self) -> TransactionEntry {
+        let client = ClientId((self.next_u64() % u64::from(self.config.clients.max(1))) as u16 + 1);
+        let tx_id = TxId(self.next_tx_id);
+        self.next_tx_id = self.next_tx_id.wrapping_add(1);
+        let amount = Decimal::new((self.next_u64() % 100_000) as i64 + 1, 2);
+
+        if self.next_unit() < self.config.deposit_ratio {
+            if self.deposits.len() == RECENT_DEPOSITS {
+                self.deposits.pop_front();
+            }
+            self.deposits.push_back((client, tx_id, amount));
+            TransactionEntry::deposit(client, tx_id, amount)
+        } else {
+            TransactionEntry::withdrawal(client, tx_id, amount)
+        }
+    }
+}
+
+impl Iterator for Generator {
+    type Item = TransactionEntry;
+
+    fn next(&mut self) -> Option<TransactionEntry> {
+        let roll = self.next_unit();
+        let entry = if roll < self.config.dispute_probability {
+            self.dispute_or_settle()
+        } else if roll < self.config.dispute_probability + self.config.duplicate_probability {
+            self.duplicate()
+        } else {
+            None
+        };
+        Some(entry.unwrap_or_else(|| self.transaction()))
+    }
+}
+
+/// Writes `config.rows` generated rows as a transaction CSV with a header
+/// row, one row at a time.
+pub fn write_csv<W: Write>(config: &GeneratorConfig, mut writer: W) -> io::Result<()> {
+    writeln!(writer, "type,client,tx,amount")?;
+    for entry in Generator::new(config.clone()).take(config.rows as usize) {
+        match entry.entry_type {
+            TransactionEntryType::Deposit | TransactionEntryType::Withdrawal => writeln!(
+                writer,
+                "{},{},{},{}",
+                entry.entry_type, entry.account_id, entry.tx_id, entry.amount
+            )?,
+            _ => writeln!(
+                writer,
+                "{},{},{},",
+                entry.entry_type, entry.account_id, entry.tx_id
+            )?,
+        }
+    }
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generator_is_deterministic() {
+        let config = GeneratorConfig {
+            rows: 2000,
+            dispute_probability: 0.2,
+            duplicate_probability: 0.1,
+            ..GeneratorConfig::default()
+        };
+        let generate = |config: &GeneratorConfig| {
+            let mut output = Vec::new();
+            write_csv(config, &mut output).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+
+        let first = generate(&config);
+        assert_eq!(first, generate(&config));
+        assert_ne!(
+            first,
+            generate(&GeneratorConfig {
+                seed: 2,
+                ..config.clone()
+            })
+        );
+        assert_eq!(first.lines().count(), 2001);
+        for entry_type in ["deposit", "withdrawal", "dispute", "resolve", "chargeback"] {
+            assert!(
+                first.lines().any(|line| line.starts_with(entry_type)),
+                "{entry_type}"
+            );
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use rust_decimal::Decimal;
+
+use crate::entry::TransactionEntry;
+use crate::ids::ClientId;
+use crate::payments_engine::PaymentEngine;
+use crate::processor::process_entry;
+use crate::transaction::Transaction;
+
+type AccountState = (ClientId, Decimal, Decimal, Decimal, bool);
+
+impl PaymentEngine {
+    /// Builds a fresh engine by applying `entries` in order. Rejected entries are
+    /// skipped silently, exactly as they would leave the state untouched.
+    pub fn replay(entries: &[TransactionEntry]) -> PaymentEngine {
+        let mut engine = PaymentEngine::new();
+        for entry in entries {
+            let _ = process_entry(&mut engine, entry.clone());
+        }
+        engine
+    }
+}
+
+/// Asserts that replaying `entries` twice produces identical accounts and
+/// transactions, and returns the replayed engine.
+pub fn assert_replay_deterministic(entries: &[TransactionEntry]) -> PaymentEngine {
+    let first = PaymentEngine::replay(entries);
+    let second = PaymentEngine::replay(entries);
+
+    assert_eq!(
+        account_states(&first),
+        account_states(&second),
+        "Replay produced different account state"
+    );
+    assert_eq!(
+        transaction_states(&first),
+        transaction_states(&second),
+        "Replay produced different transactions"
+    );
+    first
+}
+
+fn account_states(engine: &PaymentEngine) -> Vec<AccountState> {
+    let mut states: Vec<AccountState> = engine
+        .accounts
+        .values()
+        .map(|a| (a.client, a.available, a.held, a.total, a.locked))
+        .collect();
+    states.sort_unstable_by_key(|state| state.0);
+    states
+}
+
+fn transaction_states(engine: &PaymentEngine) -> Vec<&Transaction> {
+    let mut transactions: Vec<&Transaction> = engine
+        .transactions
+        .values()
+        .flat_map(|account_transactions| account_transactions.values())
+        .collect();
+    transactions.sort_unstable_by_key(|t| (t.account_id, t.tx_id));
+    transactions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ids::TxId;
+    use rust_decimal::dec;
+
+    #[test]
+    fn test_replay_is_deterministic() {
+        let entries = vec![
+            TransactionEntry::deposit(ClientId(1), TxId(1), dec!(10.0)),
+            TransactionEntry::deposit(ClientId(2), TxId(2), dec!(20.0)),
+            TransactionEntry::deposit(ClientId(1), TxId(3), dec!(5.0)),
+            TransactionEntry::dispute(ClientId(1), TxId(1)),
+            TransactionEntry::dispute(ClientId(1), TxId(3)),
+            TransactionEntry::chargeback(ClientId(1), TxId(1)),
+            TransactionEntry::resolve(ClientId(1), TxId(3)),
+            TransactionEntry::withdrawal(ClientId(2), TxId(4), dec!(50.0)),
+            TransactionEntry::withdrawal(ClientId(2), TxId(5), dec!(5.0)),
+        ];
+
+        let engine = assert_replay_deterministic(&entries);
+
+        assert_eq!(
+            engine.accounts.get(&ClientId(1)).unwrap().available,
+            dec!(5.0)
+        );
+        assert!(engine.accounts.get(&ClientId(1)).unwrap().locked);
+        assert_eq!(engine.accounts.get(&ClientId(2)).unwrap().total, dec!(15.0));
+    }
+}
@@ -1,64 +1,454 @@
 use std::default::Default;
+use std::fmt;
+use std::fmt::Display;
 
 use rust_decimal::Decimal;
+#[cfg(feature = "csv")]
+use serde::{Deserialize, Serialize};
 
-use crate::entry::{TransactionEntry, TransactionEntryType};
+use crate::currency::Currency;
+use crate::ids::{ClientId, TxId};
 use thiserror::Error;
 
+/// Journal record of a deposit, withdrawal or fee. Serializes with the same
+/// field names as `TransactionEntry`, e.g. to dump the journal as JSON.
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "csv", derive(Serialize, Deserialize))]
 pub struct Transaction {
+    #[cfg_attr(feature = "csv", serde(rename = "type"))]
     pub tx_type: TransactionType,
-    pub account_id: u16,
-    pub tx_id: u32,
+    #[cfg_attr(feature = "csv", serde(rename = "client"))]
+    pub account_id: ClientId,
+    #[cfg_attr(feature = "csv", serde(rename = "tx"))]
+    pub tx_id: TxId,
+    #[cfg_attr(feature = "csv", serde(with = "crate::serde_decimal"))]
     pub amount: Decimal,
+    /// Currency of the amount, `None` for the engine's base currency.
+    pub currency: Option<Currency>,
+    /// Amount currently held by a dispute; for a charged back transaction, the
+    /// amount that was charged back.
+    #[cfg_attr(feature = "csv", serde(with = "crate::serde_decimal"))]
+    pub disputed_amount: Decimal,
+    /// Order in which the transaction was applied, assigned by the engine.
+    pub sequence: u64,
+    /// Time of the transaction as given in the input, in seconds since the
+    /// Unix epoch; not used by the balance logic.
+    pub timestamp: Option<u64>,
     pub status: TransactionStatus,
+    /// Statuses the transaction moved through after `Completed`.
+    #[cfg_attr(
+        feature = "csv",
+        serde(default, skip_serializing_if = "StatusHistory::is_empty")
+    )]
+    pub history: StatusHistory,
 }
 
-impl TryFrom<TransactionEntry> for Transaction {
-    type Error = ConvertionError;
+/// Maximum number of decimal places accepted for an amount unless configured
+/// otherwise.
+pub const MAX_AMOUNT_SCALE: u32 = 4;
 
-    fn try_from(value: TransactionEntry) -> Result<Self, Self::Error> {
-        Ok(Transaction {
-            tx_type: value.entry_type.try_into()?,
-            account_id: value.account_id,
-            tx_id: value.tx_id,
-            amount: value.amount.ok_or(ConvertionError::MissingAmount)?,
+impl Transaction {
+    /// A completed transaction, as applied by `PaymentEngine::process_transaction`,
+    /// which assigns its sequence number.
+    pub fn new(
+        tx_type: TransactionType,
+        account_id: ClientId,
+        tx_id: TxId,
+        amount: Decimal,
+    ) -> Self {
+        Transaction {
+            tx_type,
+            account_id,
+            tx_id,
+            amount,
+            currency: None,
+            disputed_amount: Decimal::ZERO,
+            sequence: 0,
+            timestamp: None,
             status: TransactionStatus::Completed,
-        })
+            history: StatusHistory::default(),
+        }
+    }
+
+    /// Moves to `status`, recording it in the history; the move must have been
+    /// validated with `TransactionStatus::transition`.
+    #[inline]
+    pub(crate) fn set_status(&mut self, status: TransactionStatus) {
+        self.history.record(&status);
+        self.status = status;
+    }
+
+    /// Net effect on the account's total once no dispute is open: deposits
+    /// add, withdrawals and fees subtract, and a chargeback reverses the
+    /// charged back amount.
+    pub(crate) fn net_amount(&self) -> Decimal {
+        let charged_back = match self.status {
+            TransactionStatus::Chargebacked => self.disputed_amount,
+            _ => Decimal::ZERO,
+        };
+        match self.tx_type {
+            TransactionType::Deposit => self.amount - charged_back,
+            TransactionType::Withdrawal | TransactionType::Fee => charged_back - self.amount,
+        }
     }
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "csv",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "lowercase")
+)]
 pub enum TransactionType {
     Deposit,
     Withdrawal,
+    /// Charged by the engine itself, never read from input; see
+    /// `EngineConfig::chargeback_fee`.
+    Fee,
 }
 
-impl TryFrom<TransactionEntryType> for TransactionType {
-    type Error = ConvertionError;
-
-    fn try_from(value: TransactionEntryType) -> Result<Self, Self::Error> {
-        match value {
-            TransactionEntryType::Deposit => Ok(TransactionType::Deposit),
-            TransactionEntryType::Withdrawal => Ok(TransactionType::Withdrawal),
-            _ => Err(ConvertionError::InvalidTransactionType),
-        }
+impl Display for TransactionType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            TransactionType::Deposit => "deposit",
+            TransactionType::Withdrawal => "withdrawal",
+            TransactionType::Fee => "fee",
+        })
     }
 }
 
+/// Serialized as rendered by `Display`, e.g. `partially_resolved`.
 #[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(
+    feature = "csv",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "snake_case")
+)]
 pub enum TransactionStatus {
     #[default]
     Completed,
     Disputed,
+    /// Part of the disputed amount was released, the rest is still held.
+    PartiallyResolved,
     Resolved,
     Chargebacked,
+    /// Still disputed when the account was locked by another chargeback.
+    Frozen,
+}
+
+/// Maximum number of statuses kept in a `StatusHistory`; older ones are
+/// dropped, while the counts keep growing.
+pub const MAX_STATUS_HISTORY: usize = 32;
+
+/// Statuses a transaction entered, e.g. to tell how many times it was disputed
+/// and resolved. Takes a single pointer, without allocation, until the first
+/// dispute, since most transactions are never disputed.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "csv", derive(Serialize, Deserialize))]
+pub struct StatusHistory(Option<Box<StatusRecord>>);
+
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "csv", derive(Serialize, Deserialize))]
+struct StatusRecord {
+    /// The latest statuses entered, oldest first.
+    statuses: Vec<TransactionStatus>,
+    /// Times each status was entered, in `TransactionStatus` declaration order.
+    counts: [u32; 6],
+}
+
+impl StatusHistory {
+    #[inline]
+    fn record(&mut self, status: &TransactionStatus) {
+        let record = self.0.get_or_insert_default();
+        if record.statuses.len() == MAX_STATUS_HISTORY {
+            record.statuses.remove(0);
+        }
+        record.statuses.push(status.clone());
+        let count = &mut record.counts[status.index()];
+        *count = count.saturating_add(1);
+    }
+
+    /// The latest statuses entered, oldest first, at most
+    /// `MAX_STATUS_HISTORY` of them.
+    pub fn statuses(&self) -> &[TransactionStatus] {
+        self.0.as_ref().map_or(&[], |record| &record.statuses)
+    }
+
+    /// Times the transaction entered `status`, including moves no longer in
+    /// `statuses`; `Completed` is never entered again.
+    pub fn count(&self, status: &TransactionStatus) -> u32 {
+        self.0
+            .as_ref()
+            .map_or(0, |record| record.counts[status.index()])
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_none()
+    }
+}
+
+/// Why a transaction cannot move from one status to another; see
+/// `TransactionStatus::transition`.
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum TransitionError {
+    #[error("Transaction already disputed")]
+    AlreadyDisputed,
+    #[error("Transaction is not disputed")]
+    NotDisputed,
+    #[error("Transaction already resolved")]
+    AlreadyResolved,
+    #[error("Transaction already charged back")]
+    AlreadyChargedBack,
+    #[error("Transaction frozen by account lock")]
+    Frozen,
+    /// A move the engine never requests, such as back to `Completed`.
+    #[error("Transaction cannot go from {from} to {to}")]
+    Illegal {
+        from: TransactionStatus,
+        to: TransactionStatus,
+    },
 }
 
-#[derive(Error, Debug)]
+impl TransactionStatus {
+    #[inline]
+    fn index(&self) -> usize {
+        match self {
+            TransactionStatus::Completed => 0,
+            TransactionStatus::Disputed => 1,
+            TransactionStatus::PartiallyResolved => 2,
+            TransactionStatus::Resolved => 3,
+            TransactionStatus::Chargebacked => 4,
+            TransactionStatus::Frozen => 5,
+        }
+    }
+
+    /// Validates a move to `to` and returns the new status.
+    ///
+    /// A completed or resolved transaction may be disputed. An open dispute,
+    /// disputed or partially resolved, may be partially or fully resolved,
+    /// charged back, or frozen by an account lock. Charged back and frozen
+    /// transactions are final.
+    pub fn transition(self, to: TransactionStatus) -> Result<TransactionStatus, TransitionError> {
+        use TransactionStatus::*;
+
+        match (self, to) {
+            (Completed | Resolved, Disputed) => Ok(Disputed),
+            (
+                Disputed | PartiallyResolved,
+                to @ (PartiallyResolved | Resolved | Chargebacked | Frozen),
+            ) => Ok(to),
+            (Chargebacked, Disputed | PartiallyResolved | Resolved | Chargebacked) => {
+                Err(TransitionError::AlreadyChargedBack)
+            }
+            (Frozen, Disputed | PartiallyResolved | Resolved | Chargebacked) => {
+                Err(TransitionError::Frozen)
+            }
+            (Disputed | PartiallyResolved, Disputed) => Err(TransitionError::AlreadyDisputed),
+            (Resolved, PartiallyResolved | Resolved | Chargebacked) => {
+                Err(TransitionError::AlreadyResolved)
+            }
+            (Completed, PartiallyResolved | Resolved | Chargebacked) => {
+                Err(TransitionError::NotDisputed)
+            }
+            (from, to) => Err(TransitionError::Illegal { from, to }),
+        }
+    }
+}
+
+impl Display for TransactionStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            TransactionStatus::Completed => "completed",
+            TransactionStatus::Disputed => "disputed",
+            TransactionStatus::PartiallyResolved => "partially_resolved",
+            TransactionStatus::Resolved => "resolved",
+            TransactionStatus::Chargebacked => "chargebacked",
+            TransactionStatus::Frozen => "frozen",
+        })
+    }
+}
+
+#[derive(Error, Debug, Clone, PartialEq)]
 pub enum ConvertionError {
     #[error("Invalid transaction type for conversion")]
     InvalidTransactionType,
     #[error("Missing amount for transaction")]
     MissingAmount,
+    #[error("Unparseable amount for transaction: {0}")]
+    UnparseableAmount(String),
+    #[error("Amount has too many decimal places: {0}")]
+    TooManyDecimals(Decimal),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "csv")]
+    use rust_decimal::dec;
+
+    #[test]
+    fn test_transitions() {
+        use TransactionStatus::*;
+        use TransitionError::{
+            AlreadyChargedBack, AlreadyDisputed, AlreadyResolved, Illegal, NotDisputed,
+        };
+
+        let statuses = [
+            Completed,
+            Disputed,
+            PartiallyResolved,
+            Resolved,
+            Chargebacked,
+            Frozen,
+        ];
+        // Expected outcome for every (from, to) pair, rows in `statuses` order.
+        let expected: [[Result<(), TransitionError>; 6]; 6] = [
+            // Completed
+            [
+                Err(Illegal {
+                    from: Completed,
+                    to: Completed,
+                }),
+                Ok(()),
+                Err(NotDisputed),
+                Err(NotDisputed),
+                Err(NotDisputed),
+                Err(Illegal {
+                    from: Completed,
+                    to: Frozen,
+                }),
+            ],
+            // Disputed
+            [
+                Err(Illegal {
+                    from: Disputed,
+                    to: Completed,
+                }),
+                Err(AlreadyDisputed),
+                Ok(()),
+                Ok(()),
+                Ok(()),
+                Ok(()),
+            ],
+            // PartiallyResolved
+            [
+                Err(Illegal {
+                    from: PartiallyResolved,
+                    to: Completed,
+                }),
+                Err(AlreadyDisputed),
+                Ok(()),
+                Ok(()),
+                Ok(()),
+                Ok(()),
+            ],
+            // Resolved
+            [
+                Err(Illegal {
+                    from: Resolved,
+                    to: Completed,
+                }),
+                Ok(()),
+                Err(AlreadyResolved),
+                Err(AlreadyResolved),
+                Err(AlreadyResolved),
+                Err(Illegal {
+                    from: Resolved,
+                    to: Frozen,
+                }),
+            ],
+            // Chargebacked
+            [
+                Err(Illegal {
+                    from: Chargebacked,
+                    to: Completed,
+                }),
+                Err(AlreadyChargedBack),
+                Err(AlreadyChargedBack),
+                Err(AlreadyChargedBack),
+                Err(AlreadyChargedBack),
+                Err(Illegal {
+                    from: Chargebacked,
+                    to: Frozen,
+                }),
+            ],
+            // Frozen
+            [
+                Err(Illegal {
+                    from: Frozen,
+                    to: Completed,
+                }),
+                Err(TransitionError::Frozen),
+                Err(TransitionError::Frozen),
+                Err(TransitionError::Frozen),
+                Err(TransitionError::Frozen),
+                Err(Illegal {
+                    from: Frozen,
+                    to: Frozen,
+                }),
+            ],
+        ];
+
+        for (from, row) in statuses.iter().zip(expected) {
+            for (to, expected) in statuses.iter().zip(row) {
+                let expected = expected.map(|()| to.clone());
+                assert_eq!(
+                    from.clone().transition(to.clone()),
+                    expected,
+                    "{from} -> {to}"
+                );
+            }
+        }
+    }
+
+    #[cfg(feature = "csv")]
+    #[test]
+    fn test_serde_round_trip() {
+        let mut transaction = Transaction::new(
+            TransactionType::Deposit,
+            ClientId(2),
+            TxId(9),
+            dec!(12.3400),
+        );
+        transaction.currency = Some("eur".parse().unwrap());
+        transaction.disputed_amount = dec!(2.5);
+        transaction.sequence = 4;
+        transaction.timestamp = Some(1_700_000_000);
+        transaction.status = TransactionStatus::PartiallyResolved;
+
+        let json = serde_json::to_string(&transaction).unwrap();
+        assert_eq!(
+            json,
+            r#"{"type":"deposit","client":2,"tx":9,"amount":"12.3400","currency":"EUR","disputed_amount":"2.5","sequence":4,"timestamp":1700000000,"status":"partially_resolved"}"#
+        );
+        assert_eq!(
+            serde_json::from_str::<Transaction>(&json).unwrap(),
+            transaction
+        );
+
+        transaction.set_status(TransactionStatus::Resolved);
+        let json = serde_json::to_string(&transaction).unwrap();
+        assert!(json.ends_with(r#""history":{"statuses":["resolved"],"counts":[0,0,0,1,0,0]}}"#));
+        assert_eq!(
+            serde_json::from_str::<Transaction>(&json).unwrap(),
+            transaction
+        );
+
+        for status in [
+            TransactionStatus::Completed,
+            TransactionStatus::Disputed,
+            TransactionStatus::Resolved,
+            TransactionStatus::Chargebacked,
+            TransactionStatus::Frozen,
+        ] {
+            assert_eq!(
+                serde_json::to_string(&status).unwrap(),
+                format!("\"{status}\"")
+            );
+        }
+        assert_eq!(
+            serde_json::to_string(&TransactionType::Fee).unwrap(),
+            r#""fee""#
+        );
+    }
 }
@@ -0,0 +1,319 @@
+use std::fmt;
+use std::fmt::Display;
+use std::io::Read;
+
+use csv::StringRecord;
+
+use crate::config::CsvOptions;
+use crate::entry::TransactionEntry;
+use crate::error::PaymentError;
+use crate::payments_engine::PaymentEngine;
+use crate::processor::{check_headers, csv_reader, is_blank, process_entry};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FindingKind {
+    /// The row could not be parsed or converted into a transaction.
+    Malformed,
+    /// A deposit or withdrawal of zero or a negative amount, which the engine
+    /// rejects with `InvalidAmount`.
+    NegativeAmount,
+    DuplicateTransaction,
+    /// The row references a transaction or account that does not exist.
+    UnknownTransaction,
+    InsufficientFunds,
+    /// The row targets an account locked by an earlier chargeback.
+    AccountLocked,
+    /// The referenced transaction is not in a state allowing the operation.
+    InvalidState,
+    AccountLimitExceeded,
+    /// The amount is above one of the configured `Limits`.
+    LimitExceeded,
+}
+
+impl FindingKind {
+    pub fn severity(self) -> Severity {
+        match self {
+            FindingKind::AccountLocked => Severity::Warning,
+            _ => Severity::Error,
+        }
+    }
+}
+
+impl From<&PaymentError> for FindingKind {
+    fn from(error: &PaymentError) -> Self {
+        match error {
+            PaymentError::InsufficientFunds { .. } | PaymentError::InsufficientHoldFunds { .. } => {
+                FindingKind::InsufficientFunds
+            }
+            PaymentError::InvalidAmount(_) => FindingKind::NegativeAmount,
+            PaymentError::AccountLocked { .. } => FindingKind::AccountLocked,
+            PaymentError::AccountNotFound(_)
+            | PaymentError::TransactionNotFound { .. }
+            | PaymentError::TransactionEvicted(_)
+            | PaymentError::AccountMismatch { .. }
+            | PaymentError::CurrencyMismatch { .. } => FindingKind::UnknownTransaction,
+            PaymentError::AccountLimitExceeded(_) => FindingKind::AccountLimitExceeded,
+            PaymentError::LimitExceeded { .. }
+            | PaymentError::WithdrawalLimitExceeded { .. }
+            | PaymentError::PrecisionLoss { .. } => FindingKind::LimitExceeded,
+            PaymentError::TransactionAlreadyExists { .. }
+            | PaymentError::DuplicateWithDifferentAmount { .. }
+            | PaymentError::TxIdReusedAcrossClients { .. } => FindingKind::DuplicateTransaction,
+            PaymentError::InvalidTransactionType(_)
+            | PaymentError::MissingHold { .. }
+            | PaymentError::ZeroAmountDispute(_)
+            | PaymentError::TransactionAlreadyDisputed(_)
+            | PaymentError::TransactionIsNotDisputed(_)
+            | PaymentError::TransactionAlreadyResolved(_)
+            | PaymentError::TransactionAlreadyChargedBack(_)
+            | PaymentError::TransactionFrozen(_)
+            | PaymentError::InvalidTransition { .. }
+            | PaymentError::AccountNotFrozen(_) => FindingKind::InvalidState,
+            PaymentError::InvalidEntryForConversion(_) => FindingKind::Malformed,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+    /// Line of the row in the input, when known.
+    pub line: Option<u64>,
+    pub severity: Severity,
+    pub kind: FindingKind,
+    pub message: String,
+}
+
+impl Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(line) = self.line {
+            write!(f, "line {}: ", line)?;
+        }
+        write!(f, "{}: {}", self.severity, self.message)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ValidationReport {
+    pub rows: u64,
+    pub findings: Vec<Finding>,
+}
+
+impl ValidationReport {
+    pub fn is_clean(&self) -> bool {
+        self.findings.is_empty()
+    }
+
+    pub fn count(&self, severity: Severity) -> usize {
+        self.findings
+            .iter()
+            .filter(|finding| finding.severity == severity)
+            .count()
+    }
+
+    pub fn count_kind(&self, kind: FindingKind) -> usize {
+        self.findings
+            .iter()
+            .filter(|finding| finding.kind == kind)
+            .count()
+    }
+
+    #[inline]
+    fn push(&mut self, line: Option<u64>, kind: FindingKind, message: impl Display) {
+        self.findings.push(Finding {
+            line,
+            severity: kind.severity(),
+            kind,
+            message: message.to_string(),
+        });
+    }
+}
+
+/// Lists every finding followed by the counts.
+impl Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for finding in &self.findings {
+            writeln!(f, "{}", finding)?;
+        }
+        write!(
+            f,
+            "{} rows checked, {} errors, {} warnings",
+            self.rows,
+            self.count(Severity::Error),
+            self.count(Severity::Warning)
+        )
+    }
+}
+
+/// Runs the input through a fresh engine with the default configuration and
+/// reports the problems found, without producing balances. Rows with a negative
+/// amount are reported and not applied.
+pub fn process_csv_validate(reader: impl Read) -> ValidationReport {
+    validate_csv_stream(&mut PaymentEngine::new(), reader)
+}
+
+/// Like `process_csv_validate`, against an existing engine so that stateful
+/// checks carry over between inputs.
+pub fn validate_csv_stream(engine: &mut PaymentEngine, reader: impl Read) -> ValidationReport {
+    validate_csv_stream_with_options(engine, reader, &CsvOptions::default())
+}
+
+/// Like `validate_csv_stream`, reading the input with `options`.
+pub fn validate_csv_stream_with_options(
+    engine: &mut PaymentEngine,
+    reader: impl Read,
+    options: &CsvOptions,
+) -> ValidationReport {
+    let mut binding = csv_reader(reader, options);
+    let mut report = ValidationReport::default();
+
+    let headers = match binding.headers() {
+        Ok(headers) => headers.clone(),
+        Err(e) => {
+            report.push(None, FindingKind::Malformed, e);
+            return report;
+        }
+    };
+    if let Err(e) = check_headers(&headers) {
+        report.push(Some(1), FindingKind::Malformed, e);
+        return report;
+    }
+
+    let mut record = StringRecord::new();
+    loop {
+        match binding.read_record(&mut record) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(e) => {
+                let line = e.position().map(|position| position.line());
+                let stop = e.is_io_error();
+                report.push(line, FindingKind::Malformed, e);
+                if stop {
+                    break;
+                }
+                continue;
+            }
+        }
+        if options.skip_blank_lines && is_blank(&record) {
+            continue;
+        }
+        report.rows += 1;
+        let line = record.position().map(|position| position.line());
+
+        let mut entry = match record.deserialize::<TransactionEntry>(Some(&headers)) {
+            Ok(entry) => entry,
+            Err(e) => {
+                report.push(line, FindingKind::Malformed, e);
+                continue;
+            }
+        };
+        if let Some(format) = &options.amount_format {
+            entry.amount.reparse(format);
+        }
+        if let Err(e) = process_entry(engine, entry) {
+            report.push(line, FindingKind::from(&e), e);
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ids::ClientId;
+    use crate::processor::{NullSink, process_csv_stream_with_sink};
+    use rust_decimal::dec;
+
+    #[test]
+    fn test_clean_input() {
+        let data = "type, client, tx, amount\n\
+                    deposit, 1, 1, 10.0\n\
+                    dispute, 1, 1,\n\
+                    resolve, 1, 1,";
+
+        let report = process_csv_validate(data.as_bytes());
+
+        assert!(report.is_clean());
+        assert_eq!(report.rows, 3);
+    }
+
+    #[test]
+    fn test_findings() {
+        let data = "type, client, tx, amount\n\
+                    deposit, 1, 1, 10.0\n\
+                    deposit, x, 2, 1.0\n\
+                    deposit, 1, 1, 10.0\n\
+                    dispute, 1, 9,\n\
+                    withdrawal, 1, 3, 50.0\n\
+                    deposit, 1, 4, -1.0\n\
+                    resolve, 1, 1,\n\
+                    dispute, 1, 1,\n\
+                    chargeback, 1, 1,\n\
+                    deposit, 1, 5, 1.0";
+
+        let report = process_csv_validate(data.as_bytes());
+
+        let kinds: Vec<(Option<u64>, FindingKind)> = report
+            .findings
+            .iter()
+            .map(|finding| (finding.line, finding.kind))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (Some(3), FindingKind::Malformed),
+                (Some(4), FindingKind::DuplicateTransaction),
+                (Some(5), FindingKind::UnknownTransaction),
+                (Some(6), FindingKind::InsufficientFunds),
+                (Some(7), FindingKind::NegativeAmount),
+                (Some(8), FindingKind::InvalidState),
+                (Some(11), FindingKind::AccountLocked),
+            ]
+        );
+        assert_eq!(report.rows, 10);
+        assert_eq!(report.count(Severity::Error), 6);
+        assert_eq!(report.count(Severity::Warning), 1);
+        assert_eq!(report.count_kind(FindingKind::Malformed), 1);
+        assert!(
+            report
+                .to_string()
+                .ends_with("10 rows checked, 6 errors, 1 warnings")
+        );
+    }
+
+    #[test]
+    fn test_negative_amount_is_not_applied() {
+        let data = "type, client, tx, amount\n\
+                    deposit, 1, 1, 10.0\n\
+                    withdrawal, 1, 2, -5.0\n\
+                    deposit, 1, 3, 0.0";
+
+        let report = process_csv_validate(data.as_bytes());
+        let kinds: Vec<FindingKind> = report.findings.iter().map(|finding| finding.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![FindingKind::NegativeAmount, FindingKind::NegativeAmount]
+        );
+
+        // The real run rejects the same rows.
+        let mut engine = PaymentEngine::new();
+        let summary =
+            process_csv_stream_with_sink(&mut engine, data.as_bytes(), &mut NullSink).unwrap();
+        assert_eq!(summary.rejected, 2);
+        assert_eq!(engine.accounts[&ClientId(1)].available, dec!(10.0));
+    }
+}
@@ -0,0 +1,366 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("fixtures")
+        .join(name)
+}
+
+fn run(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_transaction"))
+        .args(args)
+        .output()
+        .expect("Failed to run binary")
+}
+
+fn run_with_stdin(args: &[&str], input: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_transaction"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to run binary");
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    child.wait_with_output().expect("Failed to wait for binary")
+}
+
+fn sorted_lines(output: &[u8]) -> Vec<String> {
+    let mut lines: Vec<String> = String::from_utf8_lossy(output)
+        .lines()
+        .map(str::to_owned)
+        .collect();
+    lines.sort();
+    lines
+}
+
+#[test]
+fn test_plain_file() {
+    let output = run(&[fixture("transactions.csv").to_str().unwrap()]);
+
+    assert!(output.status.success());
+    assert_eq!(
+        sorted_lines(&output.stdout),
+        vec![
+            "",
+            "1, 74.5000, 0.0000, 74.5000, false",
+            "2, 0.0000, 0.0000, 0.0000, true",
+            "client, available, held, total, locked",
+        ]
+    );
+}
+
+#[test]
+fn test_client_filter() {
+    let output = run(&[
+        "--client",
+        "2",
+        fixture("transactions.csv").to_str().unwrap(),
+    ]);
+
+    assert!(output.status.success());
+    assert_eq!(
+        sorted_lines(&output.stdout),
+        vec![
+            "",
+            "2, 0.0000, 0.0000, 0.0000, true",
+            "client, available, held, total, locked",
+        ]
+    );
+}
+
+#[test]
+fn test_delimiters_match_comma_file() {
+    let plain = run(&[fixture("transactions.csv").to_str().unwrap()]);
+    for (delimiter, name) in [
+        ("\\t", "transactions.tsv"),
+        (";", "transactions_semicolon.csv"),
+    ] {
+        let output = run(&["--delimiter", delimiter, fixture(name).to_str().unwrap()]);
+
+        assert!(output.status.success(), "{name}");
+        assert_eq!(sorted_lines(&output.stdout), sorted_lines(&plain.stdout));
+    }
+
+    let output = run(&[
+        "--delimiter",
+        "|",
+        fixture("transactions.csv").to_str().unwrap(),
+    ]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn test_stats() {
+    let output = run(&["--stats", fixture("transactions.csv").to_str().unwrap()]);
+
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "applied deposit            2\n\
+         applied withdrawal         1\n\
+         applied dispute            1\n\
+         applied chargeback         1\n\
+         parse failures             0\n\
+         deposited           120.0000\n\
+         withdrawn            25.5000\n"
+    );
+}
+
+#[test]
+fn test_quoted_file_matches_plain_file() {
+    let plain = run(&[fixture("transactions.csv").to_str().unwrap()]);
+    let quoted = run(&[fixture("transactions_quoted.csv").to_str().unwrap()]);
+
+    assert!(quoted.status.success());
+    assert!(quoted.stderr.is_empty());
+    assert_eq!(sorted_lines(&quoted.stdout), sorted_lines(&plain.stdout));
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn test_gzip_file_matches_plain_file() {
+    let plain = run(&[fixture("transactions.csv").to_str().unwrap()]);
+    let gzip = run(&[fixture("transactions.csv.gz").to_str().unwrap()]);
+
+    assert!(gzip.status.success());
+    assert_eq!(sorted_lines(&gzip.stdout), sorted_lines(&plain.stdout));
+}
+
+#[test]
+fn test_stdin_matches_plain_file() {
+    let plain = run(&[fixture("transactions.csv").to_str().unwrap()]);
+    let input = std::fs::read(fixture("transactions.csv")).unwrap();
+    let stdin = run_with_stdin(&["-"], &input);
+
+    assert!(stdin.status.success());
+    assert_eq!(sorted_lines(&stdin.stdout), sorted_lines(&plain.stdout));
+}
+
+#[test]
+fn test_quiet_strict() {
+    let input = b"type, client, tx, amount\ndeposit, 1, 1, 1.0\nwithdrawal, 1, 2, 5.0\n";
+
+    let output = run_with_stdin(&["-", "--quiet", "--strict"], input);
+
+    // Only the error stopping the run is printed, and no report.
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(stderr.lines().count(), 1, "{stderr}");
+    assert!(
+        stderr.starts_with("Error: failed to process -: Row 2 rejected: "),
+        "{stderr}"
+    );
+
+    let lenient = run_with_stdin(&["-", "--quiet"], input);
+    assert!(lenient.status.success());
+    assert_eq!(
+        sorted_lines(&lenient.stdout),
+        vec![
+            "",
+            "1, 1.0000, 0.0000, 1.0000, false",
+            "client, available, held, total, locked",
+        ]
+    );
+}
+
+#[test]
+fn test_rejects_file() {
+    let rejects =
+        std::env::temp_dir().join(format!("transaction-rejects-{}.csv", std::process::id()));
+
+    let output = run(&[
+        "--quiet",
+        "--rejects",
+        rejects.to_str().unwrap(),
+        fixture("rejects.csv").to_str().unwrap(),
+    ]);
+
+    assert!(output.status.success());
+    let contents = std::fs::read_to_string(&rejects).unwrap();
+    std::fs::remove_file(&rejects).unwrap();
+    let lines: Vec<&str> = contents.lines().collect();
+    assert_eq!(
+        lines,
+        vec![
+            "type,client,tx,amount,currency,error",
+            "withdrawal,1,3,500.0,,Insufficient funds for transaction 3",
+            "deposit,1,4,abc,,Invalid entry for transaction conversion: Unparseable amount for transaction: abc",
+            "dispute,3,9,,,Transaction not found: 9",
+            "deposit,x,5,1.0,,\"CSV deserialize error: record 6 (line: 7, byte: 124): field 1: invalid digit found in string\"",
+        ]
+    );
+}
+
+#[test]
+fn test_precision() {
+    let output = run(&[
+        "--precision",
+        "2",
+        fixture("transactions.csv").to_str().unwrap(),
+    ]);
+
+    assert!(output.status.success());
+    assert_eq!(
+        sorted_lines(&output.stdout),
+        vec![
+            "",
+            "1, 74.50, 0.00, 74.50, false",
+            "2, 0.00, 0.00, 0.00, true",
+            "client, available, held, total, locked",
+        ]
+    );
+}
+
+#[test]
+fn test_check() {
+    let clean = run(&["--check", fixture("transactions.csv").to_str().unwrap()]);
+
+    assert_eq!(clean.status.code(), Some(0));
+    assert_eq!(
+        String::from_utf8_lossy(&clean.stdout),
+        "5 rows checked, 0 errors, 0 warnings\n"
+    );
+
+    let findings = run(&["--check", fixture("rejects.csv").to_str().unwrap()]);
+
+    assert_eq!(findings.status.code(), Some(2));
+    let stdout = String::from_utf8_lossy(&findings.stdout);
+    assert!(stdout.contains("line 4: error: Insufficient funds for transaction 3"));
+    assert!(stdout.ends_with("8 rows checked, 4 errors, 0 warnings\n"));
+}
+
+#[test]
+fn test_exit_codes() {
+    let usage = run(&[
+        "--format",
+        "xml",
+        fixture("transactions.csv").to_str().unwrap(),
+    ]);
+    assert_eq!(usage.status.code(), Some(2));
+
+    let missing = run(&["/nonexistent/transactions.csv"]);
+    assert_eq!(missing.status.code(), Some(1));
+    assert!(missing.stdout.is_empty());
+    let stderr = String::from_utf8_lossy(&missing.stderr);
+    assert!(stderr.starts_with("Error: failed to open /nonexistent/transactions.csv: "));
+    assert!(!stderr.contains("panicked"));
+}
+
+#[test]
+fn test_diff() {
+    let transactions = fixture("transactions.csv");
+    let transactions = transactions.to_str().unwrap();
+    let same = run(&[transactions, "--diff", transactions]);
+    assert!(same.status.success());
+    assert!(same.stdout.is_empty());
+
+    let other = std::env::temp_dir().join(format!("transaction-diff-{}.csv", std::process::id()));
+    std::fs::write(
+        &other,
+        "type, client, tx, amount\n\
+         deposit, 1, 1, 100.0\n\
+         withdrawal, 1, 3, 20.0\n\
+         deposit, 3, 4, 1.0\n",
+    )
+    .unwrap();
+    let changed = run(&[transactions, "--diff", other.to_str().unwrap()]);
+    std::fs::remove_file(&other).unwrap();
+
+    assert_eq!(changed.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8(changed.stdout).unwrap(),
+        "client 1: available 74.5 -> 80.0, total 74.5 -> 80.0\n\
+         client 2: removed (available 0, held 0.0, total 0.0, locked true)\n\
+         client 3: added (available 1.0, held 0, total 1.0, locked false)\n"
+    );
+}
+
+#[test]
+fn test_digest() {
+    let digest = |output: Output| {
+        assert!(output.status.success());
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        stderr
+            .lines()
+            .find_map(|line| line.strip_prefix("digest: "))
+            .expect("digest line")
+            .to_string()
+    };
+
+    let plain = digest(run(&[
+        "--digest",
+        fixture("transactions.csv").to_str().unwrap(),
+    ]));
+    assert_eq!(plain.len(), 64);
+    assert!(plain.chars().all(|c| c.is_ascii_hexdigit()));
+
+    let input = std::fs::read(fixture("transactions.csv")).unwrap();
+    assert_eq!(digest(run_with_stdin(&["--digest", "-"], &input)), plain);
+}
+
+#[test]
+fn test_directory_of_part_files() {
+    let dir = std::env::temp_dir().join(format!("transaction-parts-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let parts = [
+        (
+            "part-0002.csv",
+            "type, client, tx, amount\nwithdrawal, 1, 4, 500.0\n",
+        ),
+        (
+            "part-0000.csv",
+            "type, client, tx, amount\ndeposit, 1, 1, 10.0\ndeposit, 2, 2, 5.0\n",
+        ),
+        (
+            "part-0001.csv",
+            "type, client, tx, amount\ndeposit, 1, 3, 2.5\ndispute, 2, 2,\nchargeback, 2, 2,\n",
+        ),
+        (
+            ".part-0003.csv",
+            "type, client, tx, amount\ndeposit, 1, 5, 99.0\n",
+        ),
+        ("notes.txt", "not a transaction file\n"),
+    ];
+    for (name, data) in parts {
+        std::fs::write(dir.join(name), data).unwrap();
+    }
+
+    let output = run(&[dir.to_str().unwrap()]);
+    let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(
+        sorted_lines(&output.stdout),
+        vec![
+            "",
+            "1, 12.5000, 0.0000, 12.5000, false",
+            "2, 0.0000, 0.0000, 0.0000, true",
+            "client, available, held, total, locked",
+        ]
+    );
+    let position = |needle: &str| {
+        stderr
+            .find(needle)
+            .unwrap_or_else(|| panic!("{needle} in {stderr}"))
+    };
+    assert!(position("part-0000.csv: processed 2") < position("part-0001.csv: processed 3"));
+    assert!(position("part-0001.csv: processed 3") < position("part-0002.csv: processed 0"));
+    position(
+        "part-0002.csv:2: Error processing transaction: row 1: withdrawal tx 4 client 1: Insufficient funds",
+    );
+    position(".part-0003.csv: hidden file");
+    position("notes.txt: not a CSV file");
+
+    let empty = std::env::temp_dir().join(format!("transaction-empty-{}", std::process::id()));
+    std::fs::create_dir_all(&empty).unwrap();
+    let output = run(&[empty.to_str().unwrap()]);
+    std::fs::remove_dir_all(&empty).unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("no CSV files in"));
+}
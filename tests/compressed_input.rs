@@ -0,0 +1,63 @@
+use std::path::{Path, PathBuf};
+
+use rust_decimal::dec;
+use transaction::ids::ClientId;
+use transaction::input::open_input;
+use transaction::payments_engine::PaymentEngine;
+use transaction::processor::process_csv_stream;
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("fixtures")
+        .join(name)
+}
+
+fn process_file(path: &Path) -> PaymentEngine {
+    let mut engine = PaymentEngine::new();
+    process_csv_stream(&mut engine, open_input(path).unwrap()).unwrap();
+    engine
+}
+
+fn assert_fixture_state(engine: &PaymentEngine) {
+    assert_eq!(engine.accounts.len(), 2);
+    assert_eq!(
+        engine.accounts.get(&ClientId(1)).unwrap().available,
+        dec!(74.5)
+    );
+    assert_eq!(engine.accounts.get(&ClientId(2)).unwrap().total, dec!(0.0));
+    assert!(engine.accounts.get(&ClientId(2)).unwrap().locked);
+}
+
+#[test]
+fn test_plain_input() {
+    assert_fixture_state(&process_file(&fixture("transactions.csv")));
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn test_gzip_input() {
+    assert_fixture_state(&process_file(&fixture("transactions.csv.gz")));
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn test_zstd_input() {
+    let plain = std::fs::read(fixture("transactions.csv")).unwrap();
+    let path = std::env::temp_dir().join(format!("transactions-{}.csv.zst", std::process::id()));
+    std::fs::write(&path, zstd::encode_all(plain.as_slice(), 3).unwrap()).unwrap();
+
+    let engine = process_file(&path);
+    std::fs::remove_file(&path).unwrap();
+
+    assert_fixture_state(&engine);
+}
+
+#[cfg(not(feature = "gzip"))]
+#[test]
+fn test_gzip_input_without_feature() {
+    let error = open_input(&fixture("transactions.csv.gz"))
+        .err()
+        .expect("gzip input must be rejected without the feature");
+    assert_eq!(error.kind(), std::io::ErrorKind::Unsupported);
+}
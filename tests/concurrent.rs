@@ -0,0 +1,85 @@
+use std::num::NonZeroUsize;
+use std::thread;
+
+use rust_decimal::Decimal;
+use transaction::account::AccountView;
+use transaction::concurrent::ConcurrentPaymentEngine;
+use transaction::entry::TransactionEntry;
+use transaction::ids::{ClientId, TxId};
+use transaction::payments_engine::PaymentEngine;
+
+const THREADS: u32 = 8;
+const OPS_PER_THREAD: u32 = 10_000;
+const CLIENTS_PER_THREAD: u32 = 4;
+
+/// Deterministic mix of operations on the thread's own clients, including
+/// disputes, resolves and chargebacks of its earlier deposits.
+fn operations(thread: u32) -> Vec<TransactionEntry> {
+    let mut state = 0x2545_f491_u64 ^ u64::from(thread).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+
+    let mut issued: Vec<(ClientId, TxId)> = Vec::new();
+    (0..OPS_PER_THREAD)
+        .map(|op| {
+            let client = ClientId((thread * CLIENTS_PER_THREAD + (next() % 4) as u32) as u16);
+            let tx_id = TxId(thread * OPS_PER_THREAD + op);
+            let amount = Decimal::new((next() % 10_000) as i64 + 1, 2);
+            let earlier = (!issued.is_empty()).then(|| issued[next() as usize % issued.len()]);
+
+            match (next() % 100, earlier) {
+                (0..50, _) | (_, None) => {
+                    issued.push((client, tx_id));
+                    TransactionEntry::deposit(client, tx_id, amount)
+                }
+                (50..75, _) => TransactionEntry::withdrawal(client, tx_id, amount),
+                (75..88, Some((client, tx_id))) => TransactionEntry::dispute(client, tx_id),
+                (88..99, Some((client, tx_id))) => TransactionEntry::resolve(client, tx_id),
+                (_, Some((client, tx_id))) => TransactionEntry::chargeback(client, tx_id),
+            }
+        })
+        .collect()
+}
+
+fn views(engine: &PaymentEngine) -> Vec<AccountView> {
+    let mut views: Vec<AccountView> = engine.accounts.values().map(AccountView::from).collect();
+    views.sort_unstable_by_key(|view| view.client);
+    views
+}
+
+#[test]
+fn test_concurrent_matches_sequential_replay() {
+    let operations: Vec<Vec<TransactionEntry>> = (0..THREADS).map(operations).collect();
+
+    let concurrent = ConcurrentPaymentEngine::new(NonZeroUsize::new(4).unwrap());
+    thread::scope(|scope| {
+        for thread_operations in &operations {
+            let concurrent = &concurrent;
+            scope.spawn(move || {
+                for entry in thread_operations {
+                    let _ = concurrent.apply(entry.clone());
+                }
+            });
+        }
+    });
+
+    let mut sequential = PaymentEngine::new();
+    for entry in operations.into_iter().flatten() {
+        let _ = sequential.apply(entry);
+    }
+
+    let concurrent = concurrent.into_engine();
+    assert_eq!(views(&concurrent), views(&sequential));
+    assert_eq!(concurrent.verify_invariants(), Ok(()));
+    assert!(
+        sequential
+            .accounts
+            .values()
+            .any(|account| account.held > Decimal::ZERO)
+            || sequential.locked_accounts().next().is_some()
+    );
+}
@@ -0,0 +1,30 @@
+use transaction::payments_engine::PaymentEngine;
+use transaction::processor::{NullSink, process_csv_stream_with_sink};
+use transaction::testgen::{GeneratorConfig, write_csv};
+
+#[test]
+fn test_generated_input_keeps_invariants() {
+    let config = GeneratorConfig {
+        rows: 50_000,
+        clients: 500,
+        dispute_probability: 0.1,
+        duplicate_probability: 0.02,
+        seed: 42,
+        ..GeneratorConfig::default()
+    };
+    let mut input = Vec::new();
+    write_csv(&config, &mut input).unwrap();
+
+    let mut engine = PaymentEngine::new();
+    let summary =
+        process_csv_stream_with_sink(&mut engine, input.as_slice(), &mut NullSink).unwrap();
+
+    assert_eq!(summary.parse_errors, 0);
+    assert_eq!(
+        summary.processed + summary.rejected + summary.rejected_locked,
+        50_000
+    );
+    assert!(engine.accounts.values().any(|account| account.locked));
+    assert_eq!(engine.verify_invariants(), Ok(()));
+    assert_eq!(engine.audit(), vec![]);
+}
@@ -0,0 +1,109 @@
+use proptest::prelude::*;
+use rust_decimal::Decimal;
+use transaction::config::EngineConfig;
+use transaction::ids::{ClientId, TxId};
+use transaction::payments_engine::PaymentEngine;
+use transaction::transaction::{Transaction, TransactionType};
+
+/// An operation on one of a few clients and tx ids, so that disputes,
+/// resolves and chargebacks often hit an earlier deposit or withdrawal.
+#[derive(Debug, Clone)]
+enum Op {
+    Deposit(u16, u32, Decimal),
+    Withdrawal(u16, u32, Decimal),
+    Dispute(u16, u32),
+    Resolve(u16, u32),
+    PartialResolve(u16, u32, Decimal),
+    Chargeback(u16, u32),
+}
+
+fn amount() -> impl Strategy<Value = Decimal> {
+    (0i64..=100_000).prop_map(|cents| Decimal::new(cents, 2))
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    let client = 1u16..=3;
+    let tx_id = 1u32..=20;
+    prop_oneof![
+        3 => (client.clone(), tx_id.clone(), amount()).prop_map(|(c, t, a)| Op::Deposit(c, t, a)),
+        2 => (client.clone(), tx_id.clone(), amount()).prop_map(|(c, t, a)| Op::Withdrawal(c, t, a)),
+        2 => (client.clone(), tx_id.clone()).prop_map(|(c, t)| Op::Dispute(c, t)),
+        1 => (client.clone(), tx_id.clone()).prop_map(|(c, t)| Op::Resolve(c, t)),
+        1 => (client.clone(), tx_id.clone(), amount())
+            .prop_map(|(c, t, a)| Op::PartialResolve(c, t, a)),
+        1 => (client, tx_id).prop_map(|(c, t)| Op::Chargeback(c, t)),
+    ]
+}
+
+/// Applies the operation; rejections are expected and ignored.
+fn apply(engine: &mut PaymentEngine, op: &Op) {
+    let _ = match *op {
+        Op::Deposit(client, tx_id, amount) => engine.process_transaction(Transaction::new(
+            TransactionType::Deposit,
+            ClientId(client),
+            TxId(tx_id),
+            amount,
+        )),
+        Op::Withdrawal(client, tx_id, amount) => engine.process_transaction(Transaction::new(
+            TransactionType::Withdrawal,
+            ClientId(client),
+            TxId(tx_id),
+            amount,
+        )),
+        Op::Dispute(client, tx_id) => engine.process_dispute(ClientId(client), TxId(tx_id)),
+        Op::Resolve(client, tx_id) => engine.process_resolve(ClientId(client), TxId(tx_id)),
+        Op::PartialResolve(client, tx_id, amount) => {
+            engine.process_partial_resolve(ClientId(client), TxId(tx_id), amount)
+        }
+        Op::Chargeback(client, tx_id) => engine.process_chargeback(ClientId(client), TxId(tx_id)),
+    };
+}
+
+/// Applies the operations one by one, failing on the first step that breaks
+/// `available + held == total` or leaves a balance negative.
+fn check_balances(mut engine: PaymentEngine, ops: &[Op]) -> Result<(), TestCaseError> {
+    for (step, op) in ops.iter().enumerate() {
+        apply(&mut engine, op);
+        for account in engine.accounts.values() {
+            prop_assert_eq!(
+                account.available + account.held,
+                account.total,
+                "step {} ({:?}): {:?}",
+                step,
+                op,
+                account
+            );
+            prop_assert!(
+                account.available >= Decimal::ZERO
+                    && account.held >= Decimal::ZERO
+                    && account.total >= Decimal::ZERO,
+                "step {} ({:?}): {:?}",
+                step,
+                op,
+                account
+            );
+        }
+    }
+    prop_assert!(engine.verify_invariants().is_ok());
+    Ok(())
+}
+
+proptest! {
+    #[test]
+    fn balances_stay_consistent(ops in prop::collection::vec(op(), 0..200)) {
+        check_balances(PaymentEngine::new(), &ops)?;
+    }
+
+    /// Disputed withdrawals move the balances the other way: resolving one
+    /// removes the hold from the total, charging it back returns the funds.
+    #[test]
+    fn balances_stay_consistent_with_withdrawal_disputes(
+        ops in prop::collection::vec(op(), 0..200)
+    ) {
+        let engine = PaymentEngine::with_config(EngineConfig {
+            allow_withdrawal_disputes: true,
+            ..EngineConfig::default()
+        });
+        check_balances(engine, &ops)?;
+    }
+}